@@ -0,0 +1,13 @@
+# Writes the Opcodes package (one constant per major opcode) into $DOLLY_OUT_DIR.
+import os
+
+OPCODES = {"load": 0x03, "op_imm": 0x13, "store": 0x23, "op": 0x33, "branch": 0x63}
+
+lines = ["package Opcodes;", ""]
+for name, value in OPCODES.items():
+    camel = "".join(part.capitalize() for part in name.split("_"))
+    lines.append("Bit#(7) opcode%s = 7'h%02x;" % (camel, value))
+lines += ["", "endpackage", ""]
+
+with open(os.path.join(os.environ["DOLLY_OUT_DIR"], "Opcodes.bsv"), "w") as f:
+    f.write("\n".join(lines))
@@ -0,0 +1,321 @@
+//! `dolly init --existing`: writes a dolly.toml for a tree of BSV sources that wasn't laid out
+//! for dolly, inferring what it can from the sources and the usual conventions.
+use super::bsv;
+use super::inspect;
+use convert_case::{Case, Casing};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, path,
+};
+
+/// What was inferred about a tree, with paths relative to its root.
+#[derive(Debug, Default, PartialEq)]
+pub struct Adoption {
+    pub src_dirs: Vec<path::PathBuf>, // Directories with design (non-testbench) sources
+    pub top_file: Option<path::PathBuf>,
+    pub top_modules: Vec<String>, // Modules of the top file nothing instantiates
+    pub top_candidates: Vec<path::PathBuf>, // Sources nothing imports, when there are several
+    pub testbenches: Vec<path::PathBuf>, // Found by dolly where they are
+    pub misplaced: Vec<path::PathBuf>, // Testbenches dolly won't find without renaming
+}
+
+/// Whether a file name follows one of the common testbench naming conventions.
+fn testbench_name(stem: &str) -> bool {
+    let lower = stem.to_lowercase();
+    lower.ends_with("_tb")
+        || lower.ends_with("tb")
+        || lower.ends_with("_test")
+        || lower.starts_with("tb")
+        || lower.starts_with("test")
+}
+
+/// Whether a source is a testbench: by its name, or because its modules are all Empty and it
+/// calls $finish.
+fn is_testbench(path: &path::Path, modules: &[bsv::Module], source: &str) -> bool {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    testbench_name(&stem)
+        || (!modules.is_empty()
+            && modules.iter().all(|module| module.interface == "Empty")
+            && source.contains("$finish"))
+}
+
+/// Infers the layout of a tree from its sources (by path relative to the root, with their
+/// contents).
+pub fn infer(sources: &BTreeMap<path::PathBuf, String>) -> Adoption {
+    let mut adoption = Adoption::default();
+    let mut design = BTreeMap::<&path::PathBuf, Vec<bsv::Module>>::new();
+    let mut imported = BTreeSet::<String>::new();
+    let mut instantiated = BTreeSet::<String>::new();
+    for (path, source) in sources {
+        let modules = bsv::parse_source_modules(path, source);
+        for module in &modules {
+            instantiated.extend(
+                inspect::parse_instances(source, &module.name)
+                    .into_iter()
+                    .map(|instance| instance.module),
+            );
+        }
+        if is_testbench(path, &modules, source) {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            if path.starts_with("tests") || stem.ends_with("_tb") {
+                adoption.testbenches.push(path.clone());
+            } else {
+                adoption.misplaced.push(path.clone());
+            }
+        } else {
+            imported.extend(bsv::parse_source_imports(path, source));
+            design.insert(path, modules);
+        }
+    }
+
+    let src_dirs: BTreeSet<path::PathBuf> = design
+        .keys()
+        .map(|path| path.parent().unwrap().to_path_buf())
+        .collect();
+    adoption.src_dirs = src_dirs
+        .into_iter()
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                path::PathBuf::from(".")
+            } else {
+                dir
+            }
+        })
+        .collect();
+    // Unit testbenches are only found in source directories.
+    let src_dirs = &adoption.src_dirs;
+    adoption.testbenches.retain(|path| {
+        let parent = path.parent().unwrap();
+        let parent = if parent.as_os_str().is_empty() {
+            path::Path::new(".")
+        } else {
+            parent
+        };
+        let found = path.starts_with("tests") || src_dirs.iter().any(|dir| dir == parent);
+        if !found {
+            adoption.misplaced.push(path.clone());
+        }
+        found
+    });
+    adoption.misplaced.sort();
+
+    let roots: Vec<(&path::PathBuf, Vec<String>)> = design
+        .iter()
+        .filter(|(path, _)| !imported.contains(&*path.file_stem().unwrap().to_string_lossy()))
+        .map(|(path, modules)| {
+            let tops = modules
+                .iter()
+                .filter(|module| !instantiated.contains(&module.name))
+                .map(|module| module.name.clone())
+                .collect();
+            (*path, tops)
+        })
+        .filter(|(_, tops): &(_, Vec<String>)| !tops.is_empty())
+        .collect();
+    match roots.as_slice() {
+        [(path, tops)] => {
+            adoption.top_file = Some(path.to_path_buf());
+            adoption.top_modules = tops.clone();
+        }
+        roots => {
+            adoption.top_candidates = roots.iter().map(|(path, _)| path.to_path_buf()).collect()
+        }
+    }
+    adoption
+}
+
+/// The sources below a tree's root, skipping hidden directories and dolly's target.
+fn read_sources(
+    root: &path::Path,
+) -> Result<BTreeMap<path::PathBuf, String>, Box<dyn std::error::Error>> {
+    let mut sources = BTreeMap::<path::PathBuf, String>::new();
+    for path in bsv::find_packages(root).into_values().flatten() {
+        let relative = path.strip_prefix(root)?.to_path_buf();
+        let hidden = relative.components().any(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            name.starts_with('.') || name == "target"
+        });
+        if !hidden {
+            sources.insert(relative, fs::read_to_string(&path)?);
+        }
+    }
+    Ok(sources)
+}
+
+fn display(path: &path::Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// The dolly.toml for an adopted tree.
+pub fn manifest(name: &str, adoption: &Adoption) -> String {
+    let src_dirs: Vec<toml::Value> = adoption
+        .src_dirs
+        .iter()
+        .map(|dir| toml::Value::String(display(dir)))
+        .collect();
+    let mut manifest = format!(
+        "[package]\nname = {:?}\nversion = \"0.1.0\"\nsrc-dirs = {}\n",
+        name,
+        toml::Value::Array(src_dirs)
+    );
+    match &adoption.top_file {
+        Some(top_file) => manifest.push_str(&format!("top-file = {:?}\n", display(top_file))),
+        None => {
+            for candidate in &adoption.top_candidates {
+                manifest.push_str(&format!("# top-file = {:?}\n", display(candidate)));
+            }
+        }
+    }
+    manifest
+}
+
+/// Implements `dolly init --existing`: writes dolly.toml for the tree and reports what it
+/// inferred and what is left to do.
+pub fn adopt(root: &path::Path) -> Result<String, Box<dyn std::error::Error>> {
+    let manifest_path = root.join("dolly.toml");
+    if manifest_path.exists() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} is already a dolly project",
+            display(root)
+        ))));
+    }
+    let sources = read_sources(root)?;
+    if sources.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No .bsv or .bs sources found in {}",
+            display(root)
+        ))));
+    }
+    let name = root
+        .canonicalize()?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_case(Case::UpperCamel))
+        .unwrap_or_else(|| "Project".to_string());
+    let adoption = infer(&sources);
+    fs::write(&manifest_path, manifest(&name, &adoption))?;
+
+    let mut report = format!("Wrote {}\n", display(&manifest_path));
+    report.push_str(&format!(
+        "  name: {} (from the directory name)\n  src-dirs: {}\n",
+        name,
+        adoption
+            .src_dirs
+            .iter()
+            .map(|dir| display(dir))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    if let Some(top_file) = &adoption.top_file {
+        report.push_str(&format!("  top-file: {}\n", display(top_file)));
+    }
+    for testbench in &adoption.testbenches {
+        report.push_str(&format!("  testbench: {}\n", display(testbench)));
+    }
+
+    let mut todo = Vec::<String>::new();
+    match &adoption.top_file {
+        Some(top_file) if !sources[top_file].contains(&format!("{}topmodule", bsv::DIRECTIVE)) => {
+            for module in &adoption.top_modules {
+                todo.push(format!(
+                    "add '//!topmodule {}' to {} to build it",
+                    module,
+                    display(top_file)
+                ));
+            }
+        }
+        Some(_) => {}
+        None if adoption.top_candidates.is_empty() => {
+            todo.push("no top module found; set top-file in dolly.toml".to_string())
+        }
+        None => todo.push(format!(
+            "no single top file; uncomment one of the top-file lines in dolly.toml ({})",
+            adoption
+                .top_candidates
+                .iter()
+                .map(|candidate| display(candidate))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+    for testbench in &adoption.misplaced {
+        let stem = testbench.file_stem().unwrap().to_string_lossy();
+        todo.push(format!(
+            "{} looks like a testbench; rename it to {}_tb.{} in a source directory, or move it \
+             to tests/",
+            display(testbench),
+            stem,
+            testbench.extension().unwrap().to_string_lossy()
+        ));
+    }
+    for testbench in adoption.testbenches.iter().chain(&adoption.misplaced) {
+        if !sources[testbench].contains(&format!("{}topmodule", bsv::DIRECTIVE)) {
+            todo.push(format!(
+                "add a //!topmodule directive naming {}'s testbench module",
+                display(testbench)
+            ));
+        }
+    }
+    if !todo.is_empty() {
+        report.push_str("Not inferred:\n");
+        for item in todo {
+            report.push_str(&format!("  {}\n", item));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adopted_layout() {
+        let sources = BTreeMap::from([
+            (
+                path::PathBuf::from("rtl/Top.bsv"),
+                "import Alu::*;\nmodule mkTop(Top);\n    Alu alu <- mkAlu;\nendmodule\n"
+                    .to_string(),
+            ),
+            (
+                path::PathBuf::from("rtl/alu/Alu.bsv"),
+                "module mkAlu(Alu);\nendmodule\n".to_string(),
+            ),
+            (
+                path::PathBuf::from("rtl/alu/Alu_tb.bsv"),
+                "import Alu::*;\nmodule mkAlu_tb(Empty);\nendmodule\n".to_string(),
+            ),
+            (
+                path::PathBuf::from("sim/TbTop.bsv"),
+                "import Top::*;\nmodule mkTbTop(Empty);\nendmodule\n".to_string(),
+            ),
+            (
+                path::PathBuf::from("sim/Checker.bsv"),
+                "module mkChecker(Empty);\n    rule done; $finish; endrule\nendmodule\n"
+                    .to_string(),
+            ),
+        ]);
+        let adoption = infer(&sources);
+        assert_eq!(
+            adoption.src_dirs,
+            [path::PathBuf::from("rtl"), path::PathBuf::from("rtl/alu")]
+        );
+        assert_eq!(adoption.top_file, Some(path::PathBuf::from("rtl/Top.bsv")));
+        assert_eq!(adoption.top_modules, ["mkTop"]);
+        assert_eq!(
+            adoption.testbenches,
+            [path::PathBuf::from("rtl/alu/Alu_tb.bsv")]
+        );
+        assert_eq!(
+            adoption.misplaced,
+            [
+                path::PathBuf::from("sim/Checker.bsv"),
+                path::PathBuf::from("sim/TbTop.bsv")
+            ]
+        );
+        assert_eq!(
+            manifest("Cpu", &adoption),
+            "[package]\nname = \"Cpu\"\nversion = \"0.1.0\"\nsrc-dirs = [\"rtl\", \"rtl/alu\"]\n\
+             top-file = \"rtl/Top.bsv\"\n"
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Lightweight parsing of BSV declarations (interfaces and modules) for code generation and
+//! introspection.  This is not a full BSV parser; it understands the common declaration forms.
+//! Bluespec classic (.bs) sources are understood well enough to find their imports and modules.
+use regex::Regex;
+use std::{collections::HashMap, fs, path};
+
+/// Dolly directives start with `//!` in BSV sources and `--!` in Bluespec classic sources.
+pub const DIRECTIVE: &str = r"(?://|--)!";
+
+/// Whether `path` is a BSV (.bsv) or Bluespec classic (.bs) source.
+pub fn is_source(path: &path::Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "bsv" || ext == "bs")
+}
+
+fn is_classic(path: &path::Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "bs")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MethodKind {
+    Value,
+    Action,
+    ActionValue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Argument {
+    pub ty: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Method {
+    pub kind: MethodKind,
+    pub name: String,
+    pub result: String, // The full result type, e.g. ActionValue#(Bit#(8))
+    pub arguments: Vec<Argument>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub parameters: Option<String>, // Type parameters, e.g. "type t" for interface Foo#(type t)
+    pub methods: Vec<Method>,
+    pub subinterfaces: Vec<Argument>, // `interface Type name;` members
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub interface: String, // The interface type the module provides
+    pub parameters: Option<String>,
+}
+
+/// Removes `//` and `/* */` comments, keeping line structure.
+pub fn strip_comments(source: &str) -> String {
+    let block_re = Regex::new(r"(?s)/\*.*?\*/").unwrap();
+    let line_re = Regex::new(r"//[^\n]*").unwrap();
+    let without_blocks = block_re.replace_all(source, |capture: &regex::Captures| {
+        capture[0]
+            .chars()
+            .filter(|c| *c == '\n')
+            .collect::<String>()
+    });
+    line_re.replace_all(&without_blocks, "").into_owned()
+}
+
+/// Removes Bluespec classic `--` and `{- -}` comments, keeping line structure.
+pub fn strip_classic_comments(source: &str) -> String {
+    let block_re = Regex::new(r"(?s)\{-.*?-\}").unwrap();
+    let line_re = Regex::new(r"--[^\n]*").unwrap();
+    let without_blocks = block_re.replace_all(source, |capture: &regex::Captures| {
+        capture[0]
+            .chars()
+            .filter(|c| *c == '\n')
+            .collect::<String>()
+    });
+    line_re.replace_all(&without_blocks, "").into_owned()
+}
+
+/// Splits `text` on commas that aren't nested inside parentheses.
+fn split_top_level(text: &str) -> Vec<String> {
+    let mut parts = Vec::<String>::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// Splits a declaration such as `Bit#(8) value` into its type and trailing name.
+fn split_type_and_name(declaration: &str) -> Option<(String, String)> {
+    let declaration = declaration.trim();
+    let name_start = declaration
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |index| index + 1);
+    let name = &declaration[name_start..];
+    let ty = declaration[..name_start].trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some((ty.to_string(), name.to_string()))
+    }
+}
+
+/// Parses the text between `method` and `;` of an interface method prototype.
+fn parse_method(prototype: &str) -> Option<Method> {
+    // The argument list starts at the first '(' that isn't part of a type parameter list.
+    let bytes = prototype.as_bytes();
+    let arguments_start = (0..bytes.len())
+        .find(|index| bytes[*index] == b'(' && !prototype[..*index].trim_end().ends_with('#'));
+
+    let (head, arguments) = match arguments_start {
+        Some(start) => {
+            let end = prototype.rfind(')')?;
+            (&prototype[..start], &prototype[start + 1..end])
+        }
+        None => (prototype, ""),
+    };
+
+    let (result, name) = split_type_and_name(head)?;
+    let kind = if result == "Action" {
+        MethodKind::Action
+    } else if result.starts_with("ActionValue") {
+        MethodKind::ActionValue
+    } else {
+        MethodKind::Value
+    };
+
+    let arguments = split_top_level(arguments)
+        .iter()
+        .filter_map(|argument| split_type_and_name(argument))
+        .map(|(ty, name)| Argument { ty, name })
+        .collect();
+
+    Some(Method {
+        kind,
+        name,
+        result,
+        arguments,
+    })
+}
+
+/// Parses the interface declarations in a BSV source.
+pub fn parse_interfaces(source: &str) -> Vec<Interface> {
+    let source = strip_comments(source);
+    let interface_re = Regex::new(
+        r"(?s)(?:^|\n)\s*interface\s+(\w+)\s*(?:#\s*\(([^;]*?)\))?\s*;(.*?)\bendinterface\b",
+    )
+    .unwrap();
+    let method_re = Regex::new(r"(?s)\bmethod\s+([^;]*);").unwrap();
+    let subinterface_re = Regex::new(r"(?s)\binterface\s+([^;]*);").unwrap();
+
+    interface_re
+        .captures_iter(&source)
+        .map(|capture| Interface {
+            name: capture[1].to_string(),
+            parameters: capture
+                .get(2)
+                .map(|parameters| parameters.as_str().to_string()),
+            methods: method_re
+                .captures_iter(&capture[3])
+                .filter_map(|method| parse_method(&method[1]))
+                .collect(),
+            subinterfaces: subinterface_re
+                .captures_iter(&capture[3])
+                .filter_map(|subinterface| split_type_and_name(&subinterface[1]))
+                .map(|(ty, name)| Argument { ty, name })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Parses the module definitions in a BSV source.
+pub fn parse_modules(source: &str) -> Vec<Module> {
+    let source = strip_comments(source);
+    let module_re = Regex::new(
+        r"(?:^|\n)\s*module\s+(?:\[\s*\w+\s*\]\s*)?(\w+)\s*(?:#\s*\(([^;]*?)\))?\s*\(\s*(\w+)[^;]*\)\s*;",
+    )
+    .unwrap();
+
+    module_re
+        .captures_iter(&source)
+        .map(|capture| Module {
+            name: capture[1].to_string(),
+            parameters: capture
+                .get(2)
+                .map(|parameters| parameters.as_str().to_string()),
+            interface: capture[3].to_string(),
+        })
+        .collect()
+}
+
+/// Parses the module definitions in a Bluespec classic source, which are recognized by their
+/// type signature, e.g. `mkCounter :: Integer -> Module Counter`.
+pub fn parse_classic_modules(source: &str) -> Vec<Module> {
+    let source = strip_classic_comments(source);
+    let module_re =
+        Regex::new(r"(?m)^(\w+)\s*::\s*((?:[^\n=]*->)?)\s*Module\s+\(?\s*(\w+)").unwrap();
+
+    module_re
+        .captures_iter(&source)
+        .map(|capture| Module {
+            name: capture[1].to_string(),
+            parameters: Some(capture[2].trim().trim_end_matches("->").trim().to_string())
+                .filter(|parameters| !parameters.is_empty()),
+            interface: capture[3].to_string(),
+        })
+        .collect()
+}
+
+/// Parses the packages a Bluespec classic source imports (`import Name`).
+pub fn parse_classic_imports(source: &str) -> Vec<String> {
+    let source = strip_classic_comments(source);
+    let import_re = Regex::new(r"(?m)^\s*import\s+(?:qualified\s+)?(\w+)").unwrap();
+    import_re
+        .captures_iter(&source)
+        .map(|capture| capture[1].to_string())
+        .collect()
+}
+
+/// Parses the modules defined by a source in either syntax.
+pub fn parse_source_modules(path: &path::Path, source: &str) -> Vec<Module> {
+    if is_classic(path) {
+        parse_classic_modules(source)
+    } else {
+        parse_modules(source)
+    }
+}
+
+/// Parses the packages imported by a source in either syntax.
+pub fn parse_source_imports(path: &path::Path, source: &str) -> Vec<String> {
+    if is_classic(path) {
+        parse_classic_imports(source)
+    } else {
+        parse_imports(source)
+    }
+}
+
+/// Parses the packages a BSV source imports (`import Name::*;`).
+pub fn parse_imports(source: &str) -> Vec<String> {
+    let source = strip_comments(source);
+    let import_re = Regex::new(r"\bimport\s+(\w+)\s*::\s*\*\s*;").unwrap();
+    import_re
+        .captures_iter(&source)
+        .map(|capture| capture[1].to_string())
+        .collect()
+}
+
+/// Indexes the `.bsv` and `.bs` files below `root` by package (file stem).  A package defined by more
+/// than one file maps to all of them.
+pub fn find_packages(root: &path::Path) -> HashMap<String, Vec<path::PathBuf>> {
+    let mut packages = HashMap::<String, Vec<path::PathBuf>>::new();
+    let mut remaining = vec![root.to_path_buf()];
+    while let Some(directory) = remaining.pop() {
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.is_dir() {
+                remaining.push(path);
+            } else if is_source(&path) {
+                let package = path.file_stem().unwrap().to_string_lossy().to_string();
+                packages.entry(package).or_default().push(path);
+            }
+        }
+    }
+    for paths in packages.values_mut() {
+        paths.sort();
+    }
+    packages
+}
+
+/// A source and the sources of the packages it imports, transitively (in path order).
+pub fn imported_sources(
+    source: &path::Path,
+    packages: &HashMap<String, Vec<path::PathBuf>>,
+) -> Vec<path::PathBuf> {
+    let mut sources = std::collections::BTreeSet::<path::PathBuf>::new();
+    let mut remaining = vec![source.to_path_buf()];
+    while let Some(source) = remaining.pop() {
+        let Ok(contents) = fs::read_to_string(&source) else {
+            continue;
+        };
+        if !sources.insert(source.clone()) {
+            continue;
+        }
+        for import in parse_source_imports(&source, &contents) {
+            remaining.extend(packages.get(&import).into_iter().flatten().cloned());
+        }
+    }
+    sources.into_iter().collect()
+}
+
+/// A module definition along with the source file (and hence package) that defines it.
+#[derive(Clone, Debug)]
+pub struct ModuleLocation {
+    pub module: Module,
+    pub path: path::PathBuf,
+}
+
+impl ModuleLocation {
+    pub fn package(&self) -> String {
+        self.path.file_stem().unwrap().to_string_lossy().to_string()
+    }
+}
+
+/// Finds a module by name (either `mkName` or `Name`) in the `.bsv` and `.bs` files of the
+/// given directories.
+pub fn find_module<'a>(
+    directories: impl Iterator<Item = &'a path::PathBuf>,
+    name: &str,
+) -> Option<ModuleLocation> {
+    let candidates = [name.to_string(), format!("mk{}", name)];
+    for directory in directories {
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+        let mut sources: Vec<path::PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_source(path))
+            .collect();
+        sources.sort();
+
+        for path in sources {
+            let Ok(source) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Some(module) = parse_source_modules(&path, &source)
+                .into_iter()
+                .find(|module| candidates.contains(&module.name))
+            {
+                return Some(ModuleLocation { module, path });
+            }
+        }
+    }
+    None
+}
+
+/// Finds an interface declaration by name in the `.bsv` files of the given directories.
+pub fn find_interface<'a>(
+    directories: impl Iterator<Item = &'a path::PathBuf>,
+    name: &str,
+) -> Option<Interface> {
+    directories
+        .filter_map(|directory| directory.read_dir().ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bsv"))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|source| parse_interfaces(&source))
+        .find(|interface| interface.name == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = r#"
+//!topmodule mkCounter
+interface Counter;
+    method Action increment(Bit#(8) amount, Bool wrap); // comment
+    method ActionValue#(Bit#(8)) take;
+    method Bit#(8) value;
+    interface Put#(Bit#(8)) sink;
+endinterface
+
+/* module mkIgnored(Counter); */
+module mkCounter(Counter);
+endmodule
+
+module mkWrapper#(parameter Integer depth)(Counter ifc);
+endmodule
+"#;
+
+    #[test]
+    fn parse_interface_methods() {
+        let interfaces = parse_interfaces(SOURCE);
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces[0].name, "Counter");
+
+        let methods = &interfaces[0].methods;
+        assert_eq!(methods.len(), 3);
+        assert_eq!(methods[0].kind, MethodKind::Action);
+        assert_eq!(
+            methods[0].arguments,
+            vec![
+                Argument {
+                    ty: "Bit#(8)".to_string(),
+                    name: "amount".to_string()
+                },
+                Argument {
+                    ty: "Bool".to_string(),
+                    name: "wrap".to_string()
+                }
+            ]
+        );
+        assert_eq!(methods[1].kind, MethodKind::ActionValue);
+        assert_eq!(methods[1].result, "ActionValue#(Bit#(8))");
+        assert_eq!(methods[2].kind, MethodKind::Value);
+        assert_eq!(methods[2].name, "value");
+        assert!(methods[2].arguments.is_empty());
+        assert_eq!(
+            interfaces[0].subinterfaces,
+            vec![Argument {
+                ty: "Put#(Bit#(8))".to_string(),
+                name: "sink".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_import_statements() {
+        let source = "import FIFO::*;\n// import Ignored::*;\nimport \"BDPI\" function Bit#(32) f();\nimport Counter :: *;\n";
+        assert_eq!(parse_imports(source), vec!["FIFO", "Counter"]);
+    }
+
+    #[test]
+    fn parse_classic_source() {
+        let source = "package Counter where\n\nimport FIFO\nimport qualified List\n-- import Ignored\n\nmkCounter :: Module Counter\nmkCounter = module\n\nmkFifo :: Integer -> Module (FIFO a)\n";
+        assert_eq!(parse_classic_imports(source), vec!["FIFO", "List"]);
+        assert_eq!(
+            parse_classic_modules(source),
+            vec![
+                Module {
+                    name: "mkCounter".to_string(),
+                    interface: "Counter".to_string(),
+                    parameters: None,
+                },
+                Module {
+                    name: "mkFifo".to_string(),
+                    interface: "FIFO".to_string(),
+                    parameters: Some("Integer".to_string()),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_module_definitions() {
+        let modules = parse_modules(SOURCE);
+        assert_eq!(
+            modules,
+            vec![
+                Module {
+                    name: "mkCounter".to_string(),
+                    interface: "Counter".to_string(),
+                    parameters: None,
+                },
+                Module {
+                    name: "mkWrapper".to_string(),
+                    interface: "Counter".to_string(),
+                    parameters: Some("parameter Integer depth".to_string()),
+                }
+            ]
+        );
+    }
+}
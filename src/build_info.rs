@@ -0,0 +1,182 @@
+//! The BuildInfo package (`[build] build-info`): the package version, git commit and (with
+//! `[build] build-time`) build time as BSV constants, written into target/generated/src before
+//! each build so that the generated hardware can report where it came from.
+use super::changes;
+use super::project::Project;
+use std::fs;
+use tracing::warn;
+
+/// What a build was built from.
+#[derive(Debug, PartialEq)]
+pub struct BuildInfo {
+    pub version: String,
+    pub commit: Option<String>, // The full hash of HEAD, if the project is in a git repository
+    pub dirty: bool,            // Whether the work tree differs from HEAD
+    pub timestamp: Option<u64>, // Seconds since the Unix epoch, with [build] build-time
+}
+
+/// The leading number of a version component ("3" of "3-rc1"), or 0.
+fn version_number(component: Option<&str>) -> u64 {
+    let digits: String = component
+        .unwrap_or_default()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().unwrap_or(0)
+}
+
+/// A Unix time as an ISO 8601 UTC date and time (1970-01-01T00:00:00Z).
+fn iso_8601(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    // Civil date from days since the epoch, in 400-year eras of 146097 days from 0000-03-01.
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The BuildInfo package.
+pub fn generate_bsv(info: &BuildInfo) -> String {
+    let mut components = info.version.split('.');
+    let major = version_number(components.next());
+    let minor = version_number(components.next());
+    let patch = version_number(components.next());
+    let commit = info.commit.as_deref().unwrap_or_default();
+    let short = commit.get(..8).unwrap_or("0");
+    format!(
+        "// Written by dolly before each build ([build] build-info); don't edit.
+package BuildInfo;
+
+String version = \"{}\";
+Bit#(16) versionMajor = {};
+Bit#(16) versionMinor = {};
+Bit#(16) versionPatch = {};
+
+String gitCommit = \"{}\";
+Bit#(32) gitHash = 'h{};  // The first 8 hex digits of gitCommit
+Bool gitDirty = {};
+
+String buildTime = \"{}\";
+Bit#(64) buildTimestamp = {};  // Seconds since the Unix epoch (UTC), or 0 without build-time
+
+endpackage
+",
+        info.version,
+        major,
+        minor,
+        patch,
+        if commit.is_empty() { "unknown" } else { commit },
+        short,
+        if info.dirty { "True" } else { "False" },
+        info.timestamp.map_or("unknown".to_string(), iso_8601),
+        info.timestamp.unwrap_or(0)
+    )
+}
+
+/// Runs git in the project root, returning its output if it succeeded.
+fn git(project: &Project, args: &[&str]) -> Option<String> {
+    let output = changes::git(project.root_path(), args).ok()?;
+    Some(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+impl BuildInfo {
+    /// The project's build information, now.  The build time, which makes every build differ,
+    /// is only included with `[build] build-time`; SOURCE_DATE_EPOCH, when set, is the build
+    /// time then, for reproducible builds.
+    pub fn collect(project: &Project) -> Self {
+        let commit = git(project, &["rev-parse", "HEAD"]);
+        if commit.is_none() {
+            warn!(
+                "{} isn't in a git repository; BuildInfo has no commit",
+                project.package.name
+            );
+        }
+        let dirty = commit.is_some()
+            && git(project, &["status", "--porcelain"]).is_some_and(|status| !status.is_empty());
+        let timestamp = project.build.build_time.then(|| {
+            std::env::var("SOURCE_DATE_EPOCH")
+                .ok()
+                .and_then(|epoch| epoch.parse().ok())
+                .unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |duration| duration.as_secs())
+                })
+        });
+        Self {
+            version: project.package.version.clone(),
+            commit,
+            dirty,
+            timestamp,
+        }
+    }
+}
+
+/// Writes target/generated/src/BuildInfo.bsv, when the project asks for it.  An unchanged
+/// package is left alone, so that bsc doesn't recompile what imports it.
+pub fn write(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    if !project.build.build_info {
+        return Ok(());
+    }
+    let out_dir = project.generated_dir();
+    fs::create_dir_all(&out_dir)?;
+    let file = out_dir.join("BuildInfo.bsv");
+    let contents = generate_bsv(&BuildInfo::collect(project));
+    if fs::read_to_string(&file).ok().as_deref() != Some(contents.as_str()) {
+        fs::write(&file, contents)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_info_package() {
+        assert_eq!(iso_8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_8601(951782400 + 3723), "2000-02-29T01:02:03Z");
+        assert_eq!(iso_8601(1791979200), "2026-10-14T12:00:00Z");
+
+        let bsv = generate_bsv(&BuildInfo {
+            version: "1.12.3-rc1".to_string(),
+            commit: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+            dirty: true,
+            timestamp: Some(1791979200),
+        });
+        assert!(bsv.contains("\npackage BuildInfo;\n"));
+        assert!(bsv.contains("String version = \"1.12.3-rc1\";\n"));
+        assert!(bsv.contains("Bit#(16) versionMinor = 12;\nBit#(16) versionPatch = 3;\n"));
+        assert!(bsv.contains("Bit#(32) gitHash = 'h01234567;"));
+        assert!(bsv.contains("Bool gitDirty = True;\n"));
+        assert!(bsv.contains("String buildTime = \"2026-10-14T12:00:00Z\";\n"));
+
+        let bsv = generate_bsv(&BuildInfo {
+            version: "0.1.0".to_string(),
+            commit: None,
+            dirty: false,
+            timestamp: None,
+        });
+        assert!(bsv.contains("String gitCommit = \"unknown\";\nBit#(32) gitHash = 'h0;"));
+        assert!(bsv.contains("String buildTime = \"unknown\";\nBit#(64) buildTimestamp = 0;"));
+    }
+}
@@ -1,18 +1,350 @@
-use super::project::Project;
+use super::bsv;
+use super::changes::Changes;
+use super::codes;
+use super::config;
+use super::coverage::Coverage;
+use super::dashboard::Dashboard;
+use super::diagnostics::{self, Diagnostic};
+use super::distribute;
+use super::executor;
+use super::fingerprint::Fingerprint;
+use super::firmware::{self, Program};
+use super::generators;
+use super::harness;
+use super::history::{self, TestRun};
+use super::jobserver;
+use super::linters::Waivers;
+use super::mutate;
+use super::overrides::Overrides;
+use super::project::{Project, Simulator, TestConfig, WaveFormat};
+use super::runner;
+use super::shard::{self, Shard};
+use super::snapshot::{self, SnapshotResult, SnapshotSource};
+use super::suggest;
+use super::telemetry;
+use super::timings::{self, Timing};
+use super::verilog;
+use super::warnings::{self, Warnings};
+use super::waves;
 use colored::Colorize;
 use convert_case::{Case, Casing};
-use log::{error, trace, warn};
 use regex::Regex;
-use std::{collections::HashSet, fs, path, process, str};
-
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, path, process, str,
+    sync::Mutex,
+};
+use tracing::{error, trace, warn};
+
+#[derive(Clone)]
 struct BuildTarget {
     path: path::PathBuf,
     top_module: Option<String>, // Top module found in test (or the default top module name if None)
     extra_libraries: HashSet<path::PathBuf>,
+    snapshot: Option<SnapshotSource>, // Output compared against a golden file (if any)
+    waves: bool,                      // Dump a VCD waveform when the test runs
+    compare_signals: Vec<String>,     // Signals compared against a baseline waveform
+    program: Option<Program>,         // Program loaded into the test's memory image
+    env: BTreeMap<String, String>,    // Variables from //!env directives, over the project's
+    data: Option<path::PathBuf>,      // Fixture files copied into the test's run directory
+    requires: Vec<String>,            // Runner tags from //!requires directives
+    defines: Vec<(String, String)>,   // Macros defined for one //!matrix configuration
+    overrides: Overrides,             // Settings of the .dolly.toml files above the source
+    suite: Option<String>,            // Dotted path of the tests/ subdirectory holding it
+}
+
+impl BuildTarget {
+    /// A target building a module of a source (rather than running a test), with the settings
+    /// of the .dolly.toml files above the source.
+    fn module(
+        project: &Project,
+        path: &path::Path,
+        top_module: &str,
+        extra_libraries: &HashSet<path::PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let overrides = Overrides::for_file(project.root_path(), path)?;
+        Ok(BuildTarget {
+            path: path.to_path_buf(),
+            top_module: Some(top_module.to_string()),
+            extra_libraries: extra_libraries.clone(),
+            snapshot: None,
+            waves: false,
+            compare_signals: Vec::new(),
+            program: None,
+            env: overrides.env.clone(),
+            data: None,
+            requires: Vec::new(),
+            defines: Vec::new(),
+            overrides,
+            suite: None,
+        })
+    }
+
+    /// The name of the target's build directory and test: the file stem, after the suite of
+    /// a test in a subdirectory of tests/ and followed by the defines of its //!matrix
+    /// configuration (e.g. integration.cache.Cache_tb-XLEN=32-WAYS=2).
+    fn name(&self) -> String {
+        let mut name = self.path.file_stem().unwrap().to_string_lossy().to_string();
+        if let Some(suite) = &self.suite {
+            name = format!("{}.{}", suite, name);
+        }
+        for (macro_name, value) in &self.defines {
+            name.push_str(&format!("-{}={}", macro_name, value));
+        }
+        name
+    }
+
+    fn define_args(&self) -> Vec<std::ffi::OsString> {
+        self.overrides
+            .defines
+            .iter()
+            .chain(
+                self.defines
+                    .iter()
+                    .map(|(macro_name, value)| (macro_name, value)),
+            )
+            .flat_map(|(macro_name, value)| {
+                ["-D".into(), format!("{}={}", macro_name, value).into()]
+            })
+            .collect()
+    }
+}
+
+/// The directives configuring a testbench, compiled once for every source.
+struct DirectivePatterns {
+    top_module: Regex,
+    snapshot: Regex,
+    waves: Regex,
+    compare_signals: Regex,
+    program: Regex,
+    env: Regex,
+    data: Regex,
+    requires: Regex,
+    matrix: Regex,
+}
+
+impl DirectivePatterns {
+    fn new() -> Result<Self, regex::Error> {
+        let directive = |pattern: &str| Regex::new(&format!("{}{}", bsv::DIRECTIVE, pattern));
+        Ok(DirectivePatterns {
+            top_module: directive(r"topmodule\s+(\w*)\s*")?,
+            snapshot: directive(r"snapshot(?:\s+(\S+))?\s*$")?,
+            waves: directive(r"waves\s*$")?,
+            compare_signals: directive(r"compare_signals\s+(.*)$")?,
+            program: directive(r"(firmware|elf)\s+(\S+)")?,
+            env: directive(r"env\s+(\w+)=(.*?)\s*$")?,
+            data: directive(r"data\s+(\S+)")?,
+            requires: directive(r"requires\s+(.*)$")?,
+            matrix: directive(r"matrix\s+(.*)$")?,
+        })
+    }
+}
+
+/// The directives of a testbench's source, read in one pass.
+struct TestDirectives {
+    top_module: Option<String>, // The //!topmodule, unless several are given
+    snapshot: Option<SnapshotSource>, // The first //!snapshot
+    waves: bool,                // Whether there is a //!waves
+    compare_signals: Vec<String>, // The words of every //!compare_signals
+    program: Option<Program>,   // The first //!firmware or //!elf
+    env: BTreeMap<String, String>, // Every //!env NAME=value
+    data: Option<path::PathBuf>, // The first //!data, or else a <testbench>.data directory
+    requires: Vec<String>,      // The words of every //!requires
+    matrix: Vec<Vec<(String, String)>>, // The defines of each configuration of the first //!matrix
+}
+
+impl TestDirectives {
+    /// Reads the directives of the source at `path`, whose paths are relative to the project
+    /// `root`.  A source that can't be read has none.
+    fn parse(patterns: &DirectivePatterns, root: &path::Path, path: &path::Path) -> Self {
+        let mut directives = TestDirectives {
+            top_module: None,
+            snapshot: None,
+            waves: false,
+            compare_signals: Vec::new(),
+            program: None,
+            env: BTreeMap::new(),
+            data: None,
+            requires: Vec::new(),
+            matrix: vec![Vec::new()],
+        };
+        let Ok(contents) = fs::read_to_string(path) else {
+            return directives;
+        };
+        let words = |capture: regex::Captures| {
+            capture[1]
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        let mut top_modules = Vec::<String>::new();
+        let mut matrix = None;
+        for line in contents.lines() {
+            if let Some(capture) = patterns.top_module.captures(line) {
+                top_modules.push(capture[1].to_string());
+            }
+            if let Some(capture) = patterns
+                .snapshot
+                .captures(line)
+                .filter(|_| directives.snapshot.is_none())
+            {
+                directives.snapshot = Some(match capture.get(1) {
+                    Some(file) => SnapshotSource::File(path::PathBuf::from(file.as_str())),
+                    None => SnapshotSource::Stdout,
+                });
+            }
+            directives.waves |= patterns.waves.is_match(line);
+            if let Some(capture) = patterns.compare_signals.captures(line) {
+                directives.compare_signals.extend(words(capture));
+            }
+            if let Some(capture) = patterns
+                .program
+                .captures(line)
+                .filter(|_| directives.program.is_none())
+            {
+                let program_path = root.join(&capture[2]);
+                directives.program = Some(match &capture[1] {
+                    "firmware" => Program::Firmware(program_path),
+                    _ => Program::Elf(program_path),
+                });
+            }
+            if let Some(capture) = patterns.env.captures(line) {
+                directives
+                    .env
+                    .insert(capture[1].to_string(), capture[2].to_string());
+            }
+            if let Some(capture) = patterns
+                .data
+                .captures(line)
+                .filter(|_| directives.data.is_none())
+            {
+                directives.data = Some(root.join(&capture[1]));
+            }
+            if let Some(capture) = patterns.requires.captures(line) {
+                directives.requires.extend(words(capture));
+            }
+            if let Some(capture) = patterns.matrix.captures(line).filter(|_| matrix.is_none()) {
+                matrix = Some(capture[1].to_string());
+            }
+        }
+
+        match top_modules.as_slice() {
+            [] => {}
+            [top_module] => directives.top_module = Some(top_module.clone()),
+            _ => warn!("Warning: multiple top modules specified for {:?}", path),
+        }
+        if directives.data.is_none() {
+            directives.data = Some(path.with_extension("data")).filter(|dir| dir.is_dir());
+        }
+        if let Some(axes) = matrix {
+            directives.matrix = Self::matrix(&axes, path);
+        }
+        directives
+    }
+
+    /// Every combination of the values of `//!matrix NAME=value,value... ...` axes (one value
+    /// per name).
+    fn matrix(axes: &str, path: &path::Path) -> Vec<Vec<(String, String)>> {
+        let mut configurations = vec![Vec::new()];
+        for axis in axes.split_whitespace() {
+            let (name, values) = axis.split_once('=').unwrap_or_default();
+            let values: Vec<&str> = values
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .collect();
+            if name.is_empty() || values.is_empty() {
+                warn!("Ignoring malformed matrix axis '{}' in {:?}", axis, path);
+                continue;
+            }
+            configurations = configurations
+                .iter()
+                .flat_map(|defines| {
+                    values.iter().map(|value| {
+                        let mut defines = defines.clone();
+                        defines.push((name.to_string(), value.to_string()));
+                        defines
+                    })
+                })
+                .collect();
+        }
+        configurations
+    }
+}
+
+/// What `dolly build` emits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Emit {
+    #[default]
+    Verilog, // Verilog of each top module, in target/<top>/
+    Sv,      // SystemVerilog that passes strict lint
+    Bo,      // Only the compiled packages, in target/bo/
+    Bluesim, // Only a linked Bluesim binary of each top module, in target/bluesim/<top>/
+    All,     // Verilog, the compiled packages and Bluesim binaries
+}
+
+impl Emit {
+    /// Whether Verilog of the top modules is emitted.
+    pub fn verilog(&self) -> bool {
+        matches!(self, Emit::Verilog | Emit::Sv | Emit::All)
+    }
+
+    fn packages(&self) -> bool {
+        matches!(self, Emit::Bo | Emit::All)
+    }
+
+    fn bluesim(&self) -> bool {
+        matches!(self, Emit::Bluesim | Emit::All)
+    }
+}
+
+/// Lists the targets that failed (with --keep-going, once every target has been tried),
+/// returning the first failure.
+fn report_failures<E: std::fmt::Display>(
+    what: &str,
+    total: usize,
+    failures: Vec<(String, E)>,
+) -> Option<(String, E)> {
+    if failures.is_empty() {
+        return None;
+    }
+    println!(
+        "{}",
+        format!("{} of {} {} failed:", failures.len(), total, what).red()
+    );
+    for (name, e) in &failures {
+        println!("    {}: {}", name, e);
+    }
+    failures.into_iter().next()
+}
+
+/// Options controlling how the builder runs.
+#[derive(Clone, Default)]
+pub struct Options {
+    pub emit: Emit,
+    pub update_snapshots: bool,
+    pub waves: bool,
+    pub compare_waves: Option<path::PathBuf>, // Baseline target directory to compare waveforms with
+    pub coverage: bool,
+    pub deny_warnings: bool,  // Fail if bsc reports any warnings
+    pub show_commands: bool,  // Print each command line before running it
+    pub dry_run: bool,        // Print the command lines without running them
+    pub changed: bool,        // Only run the tests affected by files changed in git
+    pub no_cache: bool,       // Run even the tests that passed before with the same inputs
+    pub shard: Option<Shard>, // Only run this shard's share of the tests
+    pub timings: bool,        // Report how long each compile, link and run took
+    pub lint_verilog: bool,   // Lint the generated Verilog with the [verilog] linters
+    pub distribute: bool,     // Share the tests out among the [runners] machines
+    pub ui: bool,             // Show the test run as a dashboard
+    pub keep_going: bool,     // Carry on with the other targets after one fails
+    pub suites: Vec<String>,  // Only run the tests named by or in these suites
 }
 
 pub struct Builder {
     modules: HashSet<path::PathBuf>,
+    library_paths: Vec<path::PathBuf>, // Project-wide libraries, searched after the modules
+    precompiled: Vec<path::PathBuf>,   // Directories of precompiled .bo/.ba dependencies
+    support_dirs: Vec<path::PathBuf>,  // The project's testbench support packages
+    source_roots: Vec<path::PathBuf>,  // Of the project and its dependencies, for imports
     unit_tests: Vec<BuildTarget>,
     tests: Vec<BuildTarget>,
     top_modules: Vec<String>,
@@ -20,17 +352,38 @@ pub struct Builder {
     extra_libraries: HashSet<path::PathBuf>,
 
     all_tests_passed: bool,
+    warnings: Mutex<Warnings>, // Warnings reported by every bsc run so far
+    env: BTreeMap<String, String>, // The project's [env], set for bsc and the simulations
+    runs: Mutex<Vec<TestRun>>, // The tests built and run (rather than cached) so far
+    timings: Mutex<Vec<Timing>>, // The steps timed so far (with --timings)
+    start: std::time::Instant,
+
+    options: Options,
 }
 
 impl Builder {
     pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
         Self {
             modules: HashSet::<_>::new(),
+            library_paths: Vec::<_>::new(),
+            precompiled: Vec::<_>::new(),
+            support_dirs: Vec::<_>::new(),
+            source_roots: Vec::<_>::new(),
             unit_tests: Vec::<_>::new(),
             tests: Vec::<_>::new(),
             top_modules: Vec::<_>::new(),
             extra_libraries: HashSet::<_>::new(),
             all_tests_passed: false,
+            warnings: Mutex::new(Warnings::new()),
+            env: BTreeMap::new(),
+            runs: Mutex::new(Vec::new()),
+            timings: Mutex::new(Vec::new()),
+            start: std::time::Instant::now(),
+            options,
         }
     }
 
@@ -49,14 +402,193 @@ impl Builder {
         self.top_modules.len()
     }
 
+    /// The bsc search path (`-p`) containing the standard libraries and every module directory.
+    fn module_path_string(&self) -> std::ffi::OsString {
+        let mut module_path_string: std::ffi::OsString = "%/Libraries".into();
+        let colon: std::ffi::OsString = ":".into();
+        for module in self
+            .modules
+            .iter()
+            .chain(&self.precompiled)
+            .chain(&self.library_paths)
+        {
+            module_path_string.push(&colon);
+            module_path_string.push(module.as_os_str());
+        }
+        module_path_string
+    }
+
+    /// The bsc search path for testbenches: the module path, then the support directories.
+    fn test_module_path_string(&self) -> std::ffi::OsString {
+        let mut module_path_string = self.module_path_string();
+        for support_dir in &self.support_dirs {
+            module_path_string.push(":");
+            module_path_string.push(support_dir.as_os_str());
+        }
+        module_path_string
+    }
+
+    pub fn module_paths(&self) -> impl Iterator<Item = &path::PathBuf> {
+        self.modules.iter()
+    }
+
     pub fn all_tests_passed(&self) -> bool {
         self.all_tests_passed
     }
 
+    /// The number of unit and integration tests found.
+    pub fn total_test_count(&self) -> usize {
+        self.unit_tests.len() + self.tests.len()
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn top_modules(&self) -> &[String] {
+        &self.top_modules
+    }
+
+    /// Keeps only the named top modules (all of them if none are named), failing on names
+    /// that aren't top modules.
+    pub fn retain_top_modules(
+        &mut self,
+        names: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let (Some(name), true) = (names.first(), self.top_modules.is_empty()) {
+            return Err(Box::new(codes::error(
+                "D0203",
+                format!("--top {}: the top file has no //!topmodule directive", name),
+            )));
+        }
+        for name in names {
+            if !self.top_modules.contains(name) {
+                let suggestions =
+                    suggest::similar(name, self.top_modules.iter().map(String::as_str));
+                return Err(Box::new(codes::error(
+                    "D0204",
+                    format!(
+                        "'{}' isn't a top module{}; the top modules are {}",
+                        name,
+                        suggest::did_you_mean(&suggestions),
+                        self.top_modules.join(", ")
+                    ),
+                )));
+            }
+        }
+        if !names.is_empty() {
+            self.top_modules
+                .retain(|top_module| names.contains(top_module));
+        }
+        Ok(())
+    }
+
+    /// Runs a step, recording how long it took when timings were asked for.
+    fn timed<T>(&self, phase: &'static str, target: &str, step: impl FnOnce() -> T) -> T {
+        if !self.options.timings && !telemetry::enabled() {
+            return step();
+        }
+        let start = std::time::Instant::now();
+        let span = telemetry::step(phase, target);
+        let result = step();
+        drop(span);
+        if self.options.timings {
+            self.timings.lock().unwrap().push(Timing {
+                phase,
+                target: target.to_string(),
+                start: start.duration_since(self.start).as_secs_f64(),
+                duration: start.elapsed().as_secs_f64(),
+            });
+        }
+        result
+    }
+
+    /// Prints the timing summary and writes target/dolly-timings.html, when timings were
+    /// asked for.
+    pub fn report_timings(&self, project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.options.timings || self.options.dry_run {
+            return Ok(());
+        }
+        let timings = self.timings.lock().unwrap();
+        print!("{}", timings::summary(&timings));
+        let file = timings::timings_file(&project.target_dir());
+        timings::write_html(&file, &timings)?;
+        println!("Timing report: {}", file.to_string_lossy());
+        Ok(())
+    }
+
+    /// The names of the build directories of the tests and top modules found.
+    pub fn target_names(&self) -> Vec<String> {
+        self.unit_tests
+            .iter()
+            .chain(&self.tests)
+            .map(BuildTarget::name)
+            .chain(self.top_modules.iter().cloned())
+            .collect()
+    }
+
+    /// Prints the warning summary, failing if the project or options deny warnings.
+    fn check_warnings(&self, project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+        self.warnings
+            .lock()
+            .unwrap()
+            .check(project.build.deny_warnings || self.options.deny_warnings)
+    }
+
+    /// Runs a command for the named target, streaming its output and logging it to `log_file`
+    /// (see `executor::current`, which may run it on a build server).  The command line is
+    /// printed first with --show-commands; with --dry-run it is only printed, and `None` is
+    /// returned.
+    fn run_command(
+        options: &Options,
+        cmd: &mut process::Command,
+        name: &str,
+        log_file: &path::Path,
+    ) -> std::io::Result<Option<process::Output>> {
+        let executor = executor::current();
+        if options.show_commands || options.dry_run {
+            println!("{}", executor.command_line(cmd));
+        }
+        if options.dry_run {
+            return Ok(None);
+        }
+        executor.run(cmd, name, log_file).map(Some)
+    }
+
+    /// Runs bsc for the named target (see `run_command`), counting the warnings it reports.
+    fn run_bsc(
+        &self,
+        cmd: &mut process::Command,
+        name: &str,
+        log_file: &path::Path,
+    ) -> Result<Option<process::Output>, Box<dyn std::error::Error>> {
+        // Only spawning bsc fails with NotFound; not being able to create the log doesn't.
+        let output =
+            Self::run_command(&self.options, cmd, name, log_file).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    codes::error("D0101", "Unable to locate 'bsc' program.")
+                }
+                _ => e,
+            })?;
+        let Some(output) = output else {
+            return Ok(None);
+        };
+        let mut warnings = self.warnings.lock().unwrap();
+        warnings.add_output(&String::from_utf8_lossy(&output.stdout));
+        warnings.add_output(&String::from_utf8_lossy(&output.stderr));
+        Ok(Some(output))
+    }
+
+    /// Adds the module directories of the project's dependencies to the search path.
     pub fn find_dependencies(
-        _project: &Project,
+        project: &Project,
         build: Builder,
     ) -> Result<Builder, Box<dyn std::error::Error>> {
+        let _stage = telemetry::stage("find_dependencies");
+        let mut build = build;
+        for dependency in project.load_dependencies()? {
+            build = Builder::find_modules(&dependency, build)?;
+        }
         Ok(build)
     }
 
@@ -64,37 +596,66 @@ impl Builder {
         project: &Project,
         builder: Builder,
     ) -> Result<Builder, Box<dyn std::error::Error>> {
+        let _stage = telemetry::stage(&format!("find_modules {}", project.package.name));
         let mut builder = builder;
-        let re = Regex::new(r"//!submodule\s+(\w*)\s*")?;
-        let extra_library_re = Regex::new(r"//!extra_library\s+(\S*)\s*")?;
+        generators::run(project, &builder.options)?;
+        // The project itself is processed after its dependencies, so its [env] is the one used.
+        builder.env = project.env()?;
+        builder.support_dirs = project.support_dirs();
+        for library_path in project.library_paths()? {
+            if !builder.library_paths.contains(&library_path) {
+                builder.library_paths.push(library_path);
+            }
+        }
+        for src_dir in project.src_dirs() {
+            if !builder.source_roots.contains(&src_dir) {
+                builder.source_roots.push(src_dir);
+            }
+        }
+        for precompiled in project.precompiled_dependencies()? {
+            if !builder.precompiled.contains(&precompiled) {
+                builder.precompiled.push(precompiled);
+            }
+        }
 
-        let mut remaining_paths = Vec::<path::PathBuf>::new();
-        remaining_paths.push(project.root_path().join("src"));
+        let re = Regex::new(&format!(r"{}submodule\s+(\w*)\s*", bsv::DIRECTIVE))?;
+        let extra_library_re = Regex::new(&format!(r"{}extra_library\s+(\S*)\s*", bsv::DIRECTIVE))?;
+
+        // The top file's directory is processed first, starting from the top file itself.
+        let top_file = project.top_file();
+        let top_dir = top_file.parent().unwrap().to_path_buf();
+        let mut remaining_paths: Vec<path::PathBuf> = project
+            .src_dirs()
+            .into_iter()
+            .filter(|src_dir| *src_dir != top_dir)
+            .collect();
+        remaining_paths.push(top_dir);
 
         let mut first_path = true;
+        let mut module_paths = Vec::<path::PathBuf>::new();
 
         while let Some(current_module_path) = remaining_paths.pop() {
             trace!("Processing module {:?}", &current_module_path);
             builder.modules.insert(current_module_path.clone());
+            module_paths.push(current_module_path.clone());
 
-            let submodule_source = {
-                if first_path {
-                    first_path = false;
-                    format!("{}.bsv", project.package.name.to_case(Case::Pascal))
+            // Check for a <module>.bsv
+            let mod_dot_bsv = if first_path {
+                first_path = false;
+                top_file.clone()
+            } else {
+                let name = current_module_path
+                    .file_stem()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_case(Case::Pascal);
+                let classic = current_module_path.join(format!("{}.bs", name));
+                if classic.exists() {
+                    classic
                 } else {
-                    format!(
-                        "{}.bsv",
-                        current_module_path
-                            .file_stem()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_case(Case::Pascal)
-                    )
+                    current_module_path.join(format!("{}.bsv", name))
                 }
             };
-
-            // Check for a <module>.bsv
-            let mod_dot_bsv = current_module_path.join(submodule_source);
             if mod_dot_bsv.exists() {
                 // Open the file and look for modules that haven't been encountered
                 let submodules: HashSet<path::PathBuf> = fs::read_to_string(&mod_dot_bsv)?
@@ -110,6 +671,9 @@ impl Builder {
 
                 // Add submodules to the array of modules to be processed.
                 for submodule in submodules {
+                    if !submodule.is_dir() {
+                        return Err(Self::missing_submodule(project, &mod_dot_bsv, &submodule));
+                    }
                     remaining_paths.push(submodule);
                 }
 
@@ -133,43 +697,241 @@ impl Builder {
             }
         }
 
-        Ok(builder)
+        Self::find_imported_modules(project, builder, &module_paths)
+    }
+
+    /// The error for a //!submodule directive naming a directory that doesn't exist, with the
+    /// directories of the source roots that the name is close to.
+    fn missing_submodule(
+        project: &Project,
+        mod_file: &path::Path,
+        submodule: &path::Path,
+    ) -> Box<dyn std::error::Error> {
+        let name = submodule.file_name().unwrap().to_string_lossy().to_string();
+        let relative = |path: &path::Path| {
+            path.strip_prefix(project.root_path())
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        };
+        let mut directories = Vec::<path::PathBuf>::new();
+        let mut remaining = project.src_dirs();
+        while let Some(directory) = remaining.pop() {
+            for path in directory
+                .read_dir()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_dir())
+            {
+                directories.push(path.clone());
+                remaining.push(path);
+            }
+        }
+        directories.sort();
+        let file_names: Vec<String> = directories
+            .iter()
+            .map(|directory| directory.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        let close = suggest::similar(&name, file_names.iter().map(String::as_str));
+        let suggestions: Vec<String> = directories
+            .iter()
+            .zip(&file_names)
+            .filter(|(_, file_name)| close.contains(&file_name.as_str()))
+            .map(|(directory, _)| relative(directory))
+            .collect();
+        let suggested: Vec<&str> = suggestions.iter().map(String::as_str).collect();
+        Box::new(codes::error(
+            "D0201",
+            format!(
+                "{}: //!submodule {}: directory {} not found{}",
+                relative(mod_file),
+                name,
+                relative(submodule),
+                suggest::did_you_mean(&suggested)
+            ),
+        ))
     }
 
-    fn find_top_module(re: &Regex, path: &path::PathBuf) -> Option<String> {
-        let mut top_module = None;
+    /// Adds the directories of the packages imported (transitively) by the sources found so far
+    /// and by the integration tests, searching the source roots and the module directories of
+    /// the project and of its dependencies.  Packages defined by more than one file are left to
+    /// the //!submodule directives.
+    fn find_imported_modules(
+        project: &Project,
+        builder: Builder,
+        module_paths: &[path::PathBuf],
+    ) -> Result<Builder, Box<dyn std::error::Error>> {
+        let mut builder = builder;
+        let mut packages = std::collections::HashMap::<String, Vec<path::PathBuf>>::new();
+        let mut directories: Vec<&path::PathBuf> = builder
+            .source_roots
+            .iter()
+            .chain(&builder.modules)
+            .collect();
+        directories.sort();
+        directories.dedup();
+        for directory in directories {
+            for (package, package_paths) in bsv::find_packages(directory) {
+                let entry = packages.entry(package).or_default();
+                entry.extend(package_paths);
+                entry.sort();
+                entry.dedup();
+            }
+        }
 
-        if let Ok(contents) = fs::read_to_string(path) {
-            let top_modules: Vec<String> = contents
-                .lines()
-                .flat_map(|line| re.captures(line))
-                .map(|capture| capture[1].to_string())
-                .collect();
+        let mut remaining_sources: Vec<path::PathBuf> = module_paths
+            .iter()
+            .filter_map(|directory| directory.read_dir().ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| bsv::is_source(path))
+            .chain(
+                project
+                    .integration_tests()
+                    .into_iter()
+                    .map(|(_, path)| path),
+            )
+            .collect();
+        let mut visited = HashSet::<path::PathBuf>::new();
+        let mut unresolved = Vec::<(String, path::PathBuf)>::new();
 
-            if !top_modules.is_empty() {
-                if top_modules.len() > 1 {
-                    warn!("Warning: multiple top modules specified for {:?}", path);
-                } else {
-                    top_module = Some(top_modules[0].clone());
+        while let Some(source) = remaining_sources.pop() {
+            if !visited.insert(source.clone()) {
+                continue;
+            }
+            let Ok(contents) = fs::read_to_string(&source) else {
+                continue;
+            };
+            for import in bsv::parse_source_imports(&source, &contents) {
+                match packages.get(&import).map(Vec::as_slice) {
+                    Some([package_path]) => {
+                        let directory = package_path.parent().unwrap().to_path_buf();
+                        if builder.modules.insert(directory.clone()) {
+                            trace!("Found module {:?} imported by {:?}", directory, source);
+                        }
+                        remaining_sources.push(package_path.clone());
+                    }
+                    Some(package_paths) => trace!(
+                        "Package {} is defined by {:?}; relying on //!submodule directives",
+                        import,
+                        package_paths
+                    ),
+                    // A library package (or one from a dependency)
+                    None => unresolved.push((import, source.clone())),
                 }
             }
+        }
 
-            top_module
-        } else {
-            None
+        Self::check_imports(project, &builder, &packages, &unresolved)?;
+        Ok(builder)
+    }
+
+    /// Fails on imports of unknown packages that are close to a known one (likely typos).
+    fn check_imports(
+        project: &Project,
+        builder: &Builder,
+        packages: &HashMap<String, Vec<path::PathBuf>>,
+        unresolved: &[(String, path::PathBuf)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(libraries) = verilog::bluespec_dir()
+            .map(|dir| dir.join("Libraries"))
+            .filter(|dir| dir.is_dir() && !unresolved.is_empty())
+        else {
+            return Ok(());
+        };
+        let mut known: BTreeSet<String> = packages.keys().cloned().collect();
+        let flag_paths = project.flag_search_paths();
+        for directory in std::iter::once(&libraries)
+            .chain(&builder.modules)
+            .chain(&builder.precompiled)
+            .chain(&builder.library_paths)
+            .chain(&flag_paths)
+        {
+            let paths = directory
+                .read_dir()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()));
+            for path in paths {
+                if bsv::is_source(&path) || path.extension().is_some_and(|ext| ext == "bo") {
+                    known.insert(path.file_stem().unwrap().to_string_lossy().to_string());
+                }
+            }
+        }
+
+        for (import, source) in unresolved {
+            if known.contains(import) {
+                continue;
+            }
+            let suggestions = suggest::similar(import, known.iter().map(String::as_str));
+            if !suggestions.is_empty() {
+                return Err(Box::new(codes::error(
+                    "D0202",
+                    format!(
+                        "{}: import {}: package not found{}",
+                        source
+                            .strip_prefix(project.root_path())
+                            .unwrap_or(source)
+                            .to_string_lossy(),
+                        import,
+                        suggest::did_you_mean(&suggestions)
+                    ),
+                )));
+            }
         }
+        Ok(())
+    }
+
+    /// The targets of a testbench (one per //!matrix configuration), as the directives of
+    /// its source configure them.
+    fn test_targets(
+        project: &Project,
+        patterns: &DirectivePatterns,
+        extra_libraries: &HashSet<path::PathBuf>,
+        path: path::PathBuf,
+        suite: Option<String>,
+    ) -> Vec<BuildTarget> {
+        let directives = TestDirectives::parse(patterns, project.root_path(), &path);
+        let target = BuildTarget {
+            path,
+            top_module: directives.top_module,
+            extra_libraries: extra_libraries.clone(),
+            snapshot: directives.snapshot,
+            waves: directives.waves,
+            compare_signals: directives.compare_signals,
+            program: directives.program,
+            env: directives.env,
+            data: directives.data,
+            requires: directives.requires,
+            defines: Vec::new(),
+            overrides: Overrides::default(),
+            suite,
+        };
+        Self::expand_matrix(target, directives.matrix)
+    }
+
+    /// One target per matrix configuration.
+    fn expand_matrix(
+        target: BuildTarget,
+        configurations: Vec<Vec<(String, String)>>,
+    ) -> Vec<BuildTarget> {
+        configurations
+            .into_iter()
+            .map(|defines| BuildTarget {
+                defines,
+                ..target.clone()
+            })
+            .collect()
     }
 
     pub fn find_top_modules(
         project: &Project,
         builder: Builder,
     ) -> Result<Builder, Box<dyn std::error::Error>> {
-        let re = Regex::new(r"//!topmodule\s+(\w*)\s*")?;
+        let re = Regex::new(&format!(r"{}topmodule\s+(\w*)\s*", bsv::DIRECTIVE))?;
         let mut builder = builder;
-        let top_module_path = project.root_path().join("src").join(format!(
-            "{}.bsv",
-            project.package.name.to_case(Case::Pascal)
-        ));
+        let top_module_path = project.top_file();
 
         let contents = fs::read_to_string(top_module_path)?;
         builder.top_modules = contents
@@ -185,200 +947,622 @@ impl Builder {
         project: &Project,
         builder: Builder,
     ) -> Result<Builder, Box<dyn std::error::Error>> {
-        let top_module_path = project.root_path().join("src").join(format!(
-            "{}.bsv",
-            project.package.name.to_case(Case::Pascal)
-        ));
-        if builder.top_modules.is_empty() {
-            warn!("Warning - no top modules found in {:?}", top_module_path);
+        let _stage = telemetry::stage("build_verilog");
+        let top_module_path = project.top_file();
+        // Libraries have no top module, only packages (asking for one with --top fails in
+        // `retain_top_modules`).
+        if builder.top_modules.is_empty() && builder.options.emit != Emit::Bo {
+            warn!(
+                "Warning - no //!topmodule directive in {}",
+                top_module_path
+                    .strip_prefix(project.root_path())
+                    .unwrap_or(&top_module_path)
+                    .to_string_lossy()
+            );
         }
 
-        // Module path creation
-        let mut module_path_string: std::ffi::OsString = "%/Libraries".into();
-        let colon: std::ffi::OsString = ":".into();
-        for module in &builder.modules {
-            module_path_string.push(&colon);
-            module_path_string.push(module.as_os_str());
+        let mut bsc_args = warnings::bsc_flags(&project.build);
+        bsc_args.extend(
+            project
+                .verilog
+                .bsc_flags
+                .iter()
+                .map(std::ffi::OsString::from),
+        );
+        if builder.options.emit == Emit::Sv {
+            if project.verilog.remove_dollar {
+                bsc_args.push("-remove-dollar".into());
+            }
+            bsc_args.push("-unspecified-to".into());
+            bsc_args.push((&project.verilog.unspecified_to).into());
         }
 
-        let build_root = project.root_path().join("target");
+        let build_root = project.target_dir();
+        let emit = builder.options.emit;
+        if emit.packages() {
+            builder.compile_packages(&bsc_args, &top_module_path, &project.output_dir("bo"))?;
+        }
 
-        for top_module in &builder.top_modules {
-            let build_target = BuildTarget {
-                path: top_module_path.clone(),
-                top_module: Some(top_module.clone()),
-                extra_libraries: builder.extra_libraries.clone(),
-            };
+        // With --keep-going, the top modules that failed and why.
+        let mut failures = Vec::<(String, Box<dyn std::error::Error>)>::new();
+        let mut build_paths = Vec::<path::PathBuf>::new();
+        for top_module in builder.top_modules.iter().filter(|_| emit.verilog()) {
+            runner::check_interrupted()?;
+            let build_target = BuildTarget::module(
+                project,
+                &top_module_path,
+                top_module,
+                &builder.extra_libraries,
+            )?;
 
             // Create the path object inside the target directory that matches the test path stem.
             let build_path = build_root.join(top_module);
 
-            // Create the test build path if necessary.
-            if !build_path.exists() {
-                fs::create_dir_all(&build_path)?;
-            }
-
-            trace!("Compile current dir: {:?}", build_path.as_path());
-            trace!("Compile source: {:?}", &build_target.path);
-
-            let output = process::Command::new("bsc")
-                // output directory for .bo and .ba files
-                .arg("-bdir")
-                .arg(&build_path)
-                // output directory for .v files
-                .arg("-vdir")
-                .arg(&build_path)
-                // specify paths to modules/sources
-                .arg("-p")
-                .arg(&module_path_string)
-                // compile BSV generating Verilog
-                .arg("-verilog")
-                // check and recompile packages that are not up to date
-                .arg("-u")
-                // Specify a module to elaborate
-                .arg("-g")
-                .arg(top_module)
-                // Sshhhh
-                .arg("-quiet")
-                // The source file
-                .arg(&build_target.path)
-                .output();
-
-            if let Err(e) = output {
-                if let std::io::ErrorKind::NotFound = e.kind() {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Unable to locate 'bsc' program.",
-                    )));
-                } else {
-                    println!("ERROR: Attempting to locate 'bsc' failed.");
-                    return Err(Box::new(e));
-                }
+            match builder.compile_verilog(&bsc_args, &build_target, &build_path) {
+                Ok(()) => build_paths.push(build_path),
+                Err(e) if builder.options.keep_going => failures.push((top_module.clone(), e)),
+                Err(e) => return Err(e),
             }
+        }
 
-            let output = output.unwrap();
-            if !output.status.success() {
-                error!(
-                    "Compile failed {}",
-                    std::str::from_utf8(output.stdout.as_slice()).unwrap()
-                );
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Compile failed",
-                )));
+        if emit.verilog() && failures.is_empty() {
+            Self::post_process_verilog(project, &builder.options, &build_root, &build_paths)?;
+            if builder.options.lint_verilog {
+                builder.lint_verilog(project, &build_paths)?;
+            }
+        }
+
+        if emit.bluesim() {
+            let test_config = TestConfig {
+                simulator: Simulator::Bluesim,
+                ..Default::default()
+            };
+            for top_module in &builder.top_modules {
+                runner::check_interrupted()?;
+                let build_target = BuildTarget::module(
+                    project,
+                    &top_module_path,
+                    top_module,
+                    &builder.extra_libraries,
+                )?;
+                // One directory per top module, as they share the top file's name.
+                let bluesim_root = project.output_dir("bluesim").join(top_module);
+                let linked = builder
+                    .compile_build_target(&bsc_args, &bluesim_root, &build_target, &test_config)
+                    .and_then(|_| {
+                        builder.link_build_target(
+                            &bsc_args,
+                            &bluesim_root,
+                            &build_target,
+                            &test_config,
+                        )
+                    });
+                match linked {
+                    Ok(executable) => trace!("Linked {}: {:?}", top_module, executable),
+                    Err(e) if builder.options.keep_going => failures.push((top_module.clone(), e)),
+                    Err(e) => return Err(e),
+                }
             }
         }
+        if let Some((_, first)) =
+            report_failures("top modules", builder.top_modules.len(), failures)
+        {
+            return Err(first);
+        }
+        builder.check_warnings(project)?;
 
         Ok(builder)
     }
 
-    pub fn find_tests(
+    /// Applies the `[verilog]` module prefix and `--emit sv` conversion to the generated Verilog,
+    /// optionally concatenating it (with the primitives it uses) into a single file.
+    fn post_process_verilog(
         project: &Project,
-        builder: Builder,
-    ) -> Result<Builder, Box<dyn std::error::Error>> {
-        let mut builder = builder;
-        let re = Regex::new(r"//!topmodule\s+(\w*)\s*")?;
+        options: &Options,
+        build_root: &path::Path,
+        build_paths: &[path::PathBuf],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let config = &project.verilog;
+        let system_verilog = options.emit == Emit::Sv;
+        let header = project.package.verilog_header();
+        if options.dry_run
+            || (!system_verilog
+                && config.module_prefix.is_none()
+                && !config.single_file
+                && header.is_none())
+        {
+            return Ok(());
+        }
+        // Sources kept from an earlier build may already have the header.
+        let with_header = |source: String| match &header {
+            Some(header) if !source.starts_with(header.as_str()) => {
+                header.clone() + source.as_str()
+            }
+            _ => source,
+        };
 
-        // Find unit tests
-        for module in &builder.modules {
-            let mut unit_tests: Vec<BuildTarget> = module
+        // The same submodule may be generated for more than one top.
+        let mut files = Vec::<path::PathBuf>::new();
+        for build_path in build_paths {
+            let mut generated: Vec<path::PathBuf> = build_path
                 .read_dir()?
-                .filter(|dir_entry| dir_entry.is_ok())
-                // unwrap the paths inside the Ok variants (safe since Err variants were previously rejected)
-                .map(|dir_entry| dir_entry.unwrap().path())
-                // Filter out any paths that don't end in ".bsv"
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "v"))
                 .filter(|path| {
-                    let mut accept = false;
-                    if let Some(extension) = path.extension() {
-                        if extension == "bsv" {
-                            if let Some(stem) = path.file_stem() {
-                                if stem.to_string_lossy().ends_with("_tb") {
-                                    accept = true
-                                }
-                            }
-                        }
-                    }
-                    accept
+                    !files
+                        .iter()
+                        .any(|file| file.file_name() == path.file_name())
                 })
-                // Change from PathBuf to BuildTarget
-                .map(|path_buf| {
-                    // See if any top modules are defined in the file
-                    let top_module: Option<String> = Self::find_top_module(&re, &path_buf);
-
-                    BuildTarget {
-                        path: path_buf,
-                        top_module,
-                        extra_libraries: builder.extra_libraries.clone(),
-                    }
-                })
-                .inspect(|test_definition| trace!("Unit Test found: {:?}", &test_definition.path))
                 .collect();
-
-            builder.unit_tests.append(&mut unit_tests);
+            generated.sort();
+            files.append(&mut generated);
         }
 
-        // Find top level integration tests
-        builder.tests = project
-            .root_path()
-            .join("tests")
-            // read all files in the "tests" directory
-            .read_dir()?
-            // filter out any Err variants
-            .filter(|dir_entry| dir_entry.is_ok())
-            // unwrap the paths inside the Ok variants (safe since Err variants were previously rejected)
-            .map(|dir_entry| dir_entry.unwrap().path())
-            // Filter out any paths that don't end in ".bsv"
-            .filter(|path| {
-                if let Some(extension) = path.extension() {
-                    extension == "bsv"
-                } else {
-                    false
-                }
-            })
-            // Change from PathBuf to BuildTarget
-            .map(|path_buf| {
-                // See if any top modules are defined in the file
-                let top_module: Option<String> = Self::find_top_module(&re, &path_buf);
-
-                BuildTarget {
-                    path: path_buf,
-                    top_module,
-                    extra_libraries: builder.extra_libraries.clone(),
-                }
-            })
-            .inspect(|test_definition| trace!("Test found: {:?}", &test_definition.path))
-            .collect();
-
-        Ok(builder)
-    }
+        let mut sources = Vec::<(path::PathBuf, String)>::new();
+        for file in &files {
+            sources.push((file.clone(), fs::read_to_string(file)?));
+        }
+        if config.single_file {
+            for primitive in verilog::primitives(&files)? {
+                let source = fs::read_to_string(&primitive)?;
+                sources.push((primitive, source));
+            }
+        }
 
-    fn compile_build_target(
-        module_path_string: &std::ffi::OsStr,
-        build_root: &path::Path,
-        target: &BuildTarget,
-    ) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-        // Create the path object inside the target directory that matches the test path stem.
-        let test_build_path = build_root.join(target.path.file_stem().unwrap());
+        if let Some(prefix) = &config.module_prefix {
+            let modules: std::collections::BTreeSet<String> = sources
+                .iter()
+                .flat_map(|(_, source)| verilog::defined_modules(source))
+                .collect();
+            for (_, source) in &mut sources {
+                *source = verilog::prefix_modules(source, &modules, prefix);
+            }
+        }
 
-        // Create the test build path if necessary.
-        if !test_build_path.exists() {
-            fs::create_dir_all(&test_build_path)?;
+        let extension = if system_verilog { "sv" } else { "v" };
+        if config.single_file {
+            let contents: Vec<&str> = sources.iter().map(|(_, source)| source.as_str()).collect();
+            let mut contents = contents.join("\n");
+            if system_verilog {
+                contents = verilog::to_system_verilog(&contents).map_err(|e| {
+                    std::io::Error::other(format!("{}: {}", project.package.name, e))
+                })?;
+            }
+            let output = build_root.join(format!("{}.{}", project.package.name, extension));
+            fs::write(&output, with_header(contents))?;
+            println!("Wrote {}", output.to_string_lossy());
+            return Ok(());
         }
 
-        // Get the top module name from the test (or the default, if one wasn't found)
-        let top_module = target
-            .top_module
-            .clone()
-            .unwrap_or("mkTopModule".to_string());
+        for (file, source) in sources {
+            let file_name = file.file_name().unwrap().to_string_lossy();
+            let output = match &config.module_prefix {
+                Some(prefix) => file.with_file_name(format!("{}{}", prefix, file_name)),
+                None => file.clone(),
+            }
+            .with_extension(extension);
+            let source = if system_verilog {
+                verilog::to_system_verilog(&source).map_err(|e| {
+                    std::io::Error::other(format!("{}: {}", file.to_string_lossy(), e))
+                })?
+            } else {
+                source
+            };
+            fs::write(&output, with_header(source))?;
+            if output != file {
+                fs::remove_file(&file)?;
+            }
+        }
+        Ok(())
+    }
 
-        trace!(
-            "Top module for {}: {}",
-            target.path.to_string_lossy(),
-            top_module
+    /// Runs the `[verilog] linters` (`verilator --lint-only -Wall` by default) over the Verilog
+    /// generated for each top module and the primitives it uses, reporting their findings in
+    /// the generated files that aren't waived.  Errors fail the build, as warnings do with
+    /// --deny-warnings.  Findings in the primitives themselves are left out.
+    fn lint_verilog(
+        &self,
+        project: &Project,
+        build_paths: &[path::PathBuf],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let prefix = project.verilog.module_prefix.as_deref().unwrap_or_default();
+        let waivers = match &project.verilog.lint_waivers {
+            Some(file) => Waivers::load(&project.root_path().join(file))?,
+            None => Waivers::default(),
+        };
+        let mut diagnostics = Vec::<Diagnostic>::new();
+        for (top_module, build_path) in self.top_modules.iter().zip(build_paths) {
+            runner::check_interrupted()?;
+            // On a dry run nothing may have been generated yet.
+            let mut files: Vec<path::PathBuf> = build_path
+                .read_dir()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|ext| ext == "v" || ext == "sv")
+                })
+                .collect();
+            files.sort();
+            let primitives = verilog::primitives(&files)?;
+            let verilog_top = format!("{}{}", prefix, top_module);
+
+            for linter in &project.verilog.linters {
+                let mut cmd = linter.command(&project.verilog, &verilog_top, &files, &primitives);
+                if self.options.show_commands || self.options.dry_run {
+                    println!("{}", executor::current().command_line(&cmd));
+                }
+                if self.options.dry_run {
+                    continue;
+                }
+                let output = self
+                    .timed("lint", top_module, || executor::current().output(&mut cmd))
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => codes::error(
+                            "D0102",
+                            format!("Unable to locate '{}' program.", linter.name()),
+                        ),
+                        _ => e,
+                    })?;
+                let log = format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                let log_file = build_path.join(linter.log_file());
+                fs::write(&log_file, log.as_bytes())?;
+                let findings = linter.parse(&log);
+                // A linter that failed without a finding didn't lint (a bad flag, a crash).
+                if !output.status.success() && findings.is_empty() {
+                    return Err(Box::new(codes::error(
+                        "D0103",
+                        format!(
+                            "{} failed on {} ({}) without reporting any finding; see {}",
+                            linter.name(),
+                            verilog_top,
+                            output.status,
+                            log_file.to_string_lossy()
+                        ),
+                    )));
+                }
+                diagnostics.extend(
+                    findings
+                        .into_iter()
+                        .filter(|diagnostic| {
+                            diagnostic.file.as_ref().is_some_and(|file| {
+                                !primitives.iter().any(|primitive| primitive == file)
+                            })
+                        })
+                        .map(|mut diagnostic| {
+                            if let Some(relative) = diagnostic
+                                .file
+                                .as_ref()
+                                .and_then(|file| file.strip_prefix(project.root_path()).ok())
+                            {
+                                diagnostic.file = Some(relative.to_path_buf());
+                            }
+                            diagnostic
+                        })
+                        .filter(|diagnostic| !waivers.waives(*linter, diagnostic)),
+                );
+            }
+        }
+        diagnostics::report(
+            &diagnostics,
+            project.build.deny_warnings || self.options.deny_warnings,
+        )
+    }
+
+    fn compile_verilog(
+        &self,
+        bsc_args: &[std::ffi::OsString],
+        build_target: &BuildTarget,
+        build_path: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Create the build path if necessary.
+        if !build_path.exists() {
+            fs::create_dir_all(build_path)?;
+        }
+
+        trace!("Compile current dir: {:?}", build_path);
+        trace!("Compile source: {:?}", &build_target.path);
+
+        let mut cmd = process::Command::new(config::tool("bsc"));
+        let cmd = cmd
+            .envs(&self.env)
+            .envs(&build_target.env)
+            // output directory for .bo and .ba files
+            .arg("-bdir")
+            .arg(build_path)
+            // output directory for .v files
+            .arg("-vdir")
+            .arg(build_path)
+            // generate schedule file
+            .arg("-info-dir")
+            .arg(build_path)
+            .arg("-show-schedule")
+            // list the Verilog modules each module instantiates (<module>.use)
+            .arg("-show-module-use")
+            // specify paths to modules/sources
+            .arg("-p")
+            .arg(self.module_path_string())
+            // compile BSV generating Verilog
+            .arg("-verilog")
+            .args(bsc_args)
+            .args(build_target.define_args())
+            // check and recompile packages that are not up to date
+            .arg("-u")
+            // Specify a module to elaborate
+            .arg("-g")
+            .arg(build_target.top_module.as_deref().unwrap_or_default())
+            // Sshhhh
+            .arg("-quiet")
+            // The source file
+            .arg(&build_target.path);
+
+        let log_file = build_path.join("compile.log");
+        let name = build_target.top_module.as_deref().unwrap_or_default();
+        let output = self.timed("verilog", name, || self.run_bsc(cmd, name, &log_file))?;
+        if output.is_some_and(|output| !output.status.success()) {
+            error!("Compile failed (see {})", log_file.to_string_lossy());
+            return Err(Box::new(codes::error("D0301", "Compile failed")));
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the packages of the top file and those it imports into `bdir`, without
+    /// elaborating a module, for projects that use them as precompiled dependencies.
+    fn compile_packages(
+        &self,
+        bsc_args: &[std::ffi::OsString],
+        top_file: &path::Path,
+        bdir: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !bdir.exists() {
+            fs::create_dir_all(bdir)?;
+        }
+
+        let mut cmd = process::Command::new(config::tool("bsc"));
+        let cmd = cmd
+            .envs(&self.env)
+            // output directory for .bo files
+            .arg("-bdir")
+            .arg(bdir)
+            // specify paths to modules/sources
+            .arg("-p")
+            .arg(self.module_path_string())
+            .args(bsc_args)
+            // check and recompile packages that are not up to date
+            .arg("-u")
+            // Sshhhh
+            .arg("-quiet")
+            // The source file
+            .arg(top_file);
+
+        let log_file = bdir.join("compile.log");
+        let name = top_file.file_stem().unwrap_or_default().to_string_lossy();
+        let output = self.timed("bo", &name, || self.run_bsc(cmd, &name, &log_file))?;
+        if output.is_some_and(|output| !output.status.success()) {
+            error!("Compile failed (see {})", log_file.to_string_lossy());
+            return Err(Box::new(codes::error("D0301", "Compile failed")));
+        }
+
+        Ok(())
+    }
+
+    /// Generates Verilog for `top_module` (defined in `source`) into `build_path`, with extra
+    /// bsc flags.
+    pub fn generate_verilog(
+        &self,
+        project: &Project,
+        bsc_args: &[std::ffi::OsString],
+        source: &path::Path,
+        top_module: &str,
+        build_path: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let build_target = BuildTarget::module(project, source, top_module, &self.extra_libraries)?;
+
+        self.compile_verilog(bsc_args, &build_target, build_path)
+    }
+
+    pub fn find_tests(
+        project: &Project,
+        builder: Builder,
+    ) -> Result<Builder, Box<dyn std::error::Error>> {
+        let _stage = telemetry::stage("find_tests");
+        let mut builder = builder;
+        let patterns = DirectivePatterns::new()?;
+
+        // Find unit tests
+        for module in &builder.modules {
+            let mut unit_tests: Vec<BuildTarget> = module
+                .read_dir()?
+                .filter(|dir_entry| dir_entry.is_ok())
+                // unwrap the paths inside the Ok variants (safe since Err variants were previously rejected)
+                .map(|dir_entry| dir_entry.unwrap().path())
+                // Filter out any paths that aren't unit testbenches (<name>_tb.bsv by default)
+                .filter(|path| project.is_unit_test(path))
+                // Change from PathBuf to BuildTarget(s)
+                .flat_map(|path_buf| {
+                    Self::test_targets(project, &patterns, &builder.extra_libraries, path_buf, None)
+                })
+                .inspect(|test_definition| trace!("Unit Test found: {:?}", &test_definition.path))
+                .collect();
+
+            builder.unit_tests.append(&mut unit_tests);
+        }
+
+        // Find the integration tests, in tests/ and its subdirectories
+        builder.tests = project
+            .integration_tests()
+            .into_iter()
+            // Change from PathBuf to BuildTarget(s)
+            .flat_map(|(suite, path_buf)| {
+                Self::test_targets(
+                    project,
+                    &patterns,
+                    &builder.extra_libraries,
+                    path_buf,
+                    suite,
+                )
+            })
+            .inspect(|test_definition| trace!("Test found: {:?}", &test_definition.path))
+            .collect();
+
+        for test in builder
+            .unit_tests
+            .iter_mut()
+            .chain(builder.tests.iter_mut())
+        {
+            test.overrides = Overrides::for_file(project.root_path(), &test.path)?;
+            for (name, value) in &test.overrides.env {
+                test.env
+                    .entry(name.clone())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+
+        if !builder.options.suites.is_empty() {
+            builder.retain_suites();
+        }
+        if builder.options.changed {
+            builder.retain_changed_tests(project)?;
+        }
+        if let Some(shard) = builder.options.shard {
+            builder.retain_shard(project, shard);
+        }
+        Ok(builder)
+    }
+
+    /// Keeps only the tests of the --suite options: those a suite names, or that are in it or
+    /// in its subdirectories (integration or integration/cache, say).
+    fn retain_suites(&mut self) {
+        let suites: Vec<String> = self
+            .options
+            .suites
+            .iter()
+            .map(|suite| suite.trim_matches('/').replace('/', "."))
+            .collect();
+        let selected = |test: &BuildTarget| {
+            let name = test.name();
+            suites.iter().any(|suite| {
+                name == *suite
+                    || name
+                        .strip_prefix(suite.as_str())
+                        .is_some_and(|rest| rest.starts_with(['.', '-']))
+            })
+        };
+        self.unit_tests.retain(selected);
+        self.tests.retain(selected);
+    }
+
+    /// Drops the tests that belong to other shards (see `Shard::select`).
+    fn retain_shard(&mut self, project: &Project, shard: Shard) {
+        let names: Vec<String> = self
+            .unit_tests
+            .iter()
+            .chain(&self.tests)
+            .map(BuildTarget::name)
+            .collect();
+        let durations = match &project.test.shard_durations {
+            Some(file) => shard::load_durations(&project.root_path().join(file)),
+            None => BTreeMap::new(),
+        };
+        let selected = shard.select(&names, &durations);
+        self.unit_tests
+            .retain(|test| selected.contains(&test.name()));
+        self.tests.retain(|test| selected.contains(&test.name()));
+        println!(
+            "Running shard {}/{}: {} of {} tests",
+            shard.index,
+            shard.count,
+            selected.len(),
+            names.len()
+        );
+    }
+
+    /// Drops the tests that no file changed in git could affect.
+    fn retain_changed_tests(
+        &mut self,
+        project: &Project,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let changes = Changes::find(project)?;
+        let affected = |test: &BuildTarget| {
+            let mut inputs = Vec::<&path::Path>::new();
+            match &test.program {
+                Some(Program::Firmware(path) | Program::Elf(path)) => inputs.push(path),
+                None => {}
+            }
+            if let Some(data) = &test.data {
+                inputs.push(data);
+            }
+            changes.affect(&test.path, &inputs)
+        };
+        let count = self.unit_tests.len() + self.tests.len();
+        self.unit_tests.retain(affected);
+        self.tests.retain(affected);
+        println!(
+            "Running {} of {} tests affected by changed files",
+            self.unit_tests.len() + self.tests.len(),
+            count
+        );
+        Ok(())
+    }
+
+    /// The top module of a test: its //!topmodule directive, or else `[test] default-top`
+    /// (mkTopModule) or mk<FileStem>, preferring whichever the file defines.
+    fn test_top_module(target: &BuildTarget, test_config: &TestConfig) -> String {
+        if let Some(top_module) = &target.top_module {
+            return top_module.clone();
+        }
+
+        let default_top = test_config
+            .default_top
+            .clone()
+            .unwrap_or("mkTopModule".to_string());
+        let stem_top = format!("mk{}", target.path.file_stem().unwrap().to_string_lossy());
+        let modules = fs::read_to_string(&target.path)
+            .map(|source| bsv::parse_source_modules(&target.path, &source))
+            .unwrap_or_default();
+        let defines = |name: &str| modules.iter().any(|module| module.name == name);
+        if !defines(&default_top) && defines(&stem_top) {
+            stem_top
+        } else {
+            default_top
+        }
+    }
+
+    fn compile_build_target(
+        &self,
+        bsc_args: &[std::ffi::OsString],
+        build_root: &path::Path,
+        target: &BuildTarget,
+        test_config: &TestConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Create the path object inside the target directory that matches the test name.
+        let test_build_path = build_root.join(target.name());
+
+        // Create the test build path if necessary.
+        if !test_build_path.exists() {
+            fs::create_dir_all(&test_build_path)?;
+        }
+
+        // Get the top module name from the test (or the default, if one wasn't found)
+        let top_module = Self::test_top_module(target, test_config);
+
+        trace!(
+            "Top module for {}: {}",
+            target.path.to_string_lossy(),
+            top_module
         );
 
         // Compile
-        let cmd = process::Command::new("bsc")
+        let mut cmd = process::Command::new(config::tool("bsc"));
+        let cmd = cmd
+            .envs(&self.env)
+            .envs(&target.env)
             // output directory for .bo and .ba files
             .arg("-bdir")
             .arg(&test_build_path)
@@ -386,11 +1570,19 @@ impl Builder {
             .arg("-info-dir")
             .arg(&test_build_path)
             .arg("-show-schedule")
+            // list the Verilog modules each module instantiates (<module>.use)
+            .arg("-show-module-use")
             // specify paths to modules/sources
             .arg("-p")
-            .arg(module_path_string)
-            // compile BSV generating Bluesim object
-            .arg("-sim")
+            .arg(self.test_module_path_string())
+            // compile BSV generating a Bluesim object (or Verilog for Verilator)
+            .args(Self::simulator_compile_args(
+                test_config,
+                &test_build_path,
+                &self.options,
+            ))
+            .args(bsc_args)
+            .args(target.define_args())
             // check and recompile packages that are not up to date
             .arg("-u")
             // Specify a module to elaborate
@@ -402,58 +1594,75 @@ impl Builder {
             .arg("-check-assert")
             //                .arg("-print-flags")
             // The source file
-            .arg(&target.path)
-            .spawn();
-
-        if let Err(e) = cmd {
-            if let std::io::ErrorKind::NotFound = e.kind() {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Unable to locate 'bsc' program.",
-                )));
-            } else {
-                println!("ERROR: Attempting to locate 'bsc' failed.");
-                return Err(Box::new(e));
-            }
-        }
+            .arg(&target.path);
 
-        let cmd = cmd.unwrap();
         trace!("Compile current dir: {:?}", test_build_path.as_path());
         trace!("Compile source: {:?}", &target.path);
 
-        let output = cmd.wait_with_output()?;
-        if output.status.success() {
+        let log_file = test_build_path.join("compile.log");
+        let output = self.timed("compile", &target.name(), || {
+            self.run_bsc(cmd, &target.name(), &log_file)
+        })?;
+        if output.is_none_or(|output| output.status.success()) {
             trace!("Compilation succeeded: {:?}", &target.path);
-            Ok(output)
+            Ok(())
         } else {
-            error!(
-                "Compile failed {}",
-                std::str::from_utf8(output.stdout.as_slice()).unwrap()
-            );
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Compile failed",
-            )))
+            error!("Compile failed (see {})", log_file.to_string_lossy());
+            Err(Box::new(codes::error("D0301", "Compile failed")))
+        }
+    }
+
+    fn simulator_compile_args(
+        test_config: &TestConfig,
+        build_path: &path::Path,
+        options: &Options,
+    ) -> Vec<std::ffi::OsString> {
+        match test_config.simulator {
+            Simulator::Bluesim if options.coverage => {
+                // keep CAN_FIRE/WILL_FIRE signals so rule firings can be counted
+                vec!["-sim".into(), "-keep-fires".into()]
+            }
+            Simulator::Bluesim => vec!["-sim".into()],
+            Simulator::Verilator => vec!["-verilog".into(), "-vdir".into(), build_path.into()],
         }
     }
 
     fn link_build_target(
+        &self,
+        bsc_args: &[std::ffi::OsString],
         build_root: &path::Path,
         target: &BuildTarget,
+        test_config: &TestConfig,
     ) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
-        let test_build_path = build_root.join(target.path.file_stem().unwrap());
+        let test_build_path = build_root.join(target.name());
 
         // Get the top module name from the test (or the default, if one wasn't found)
-        let top_module = target
-            .top_module
-            .clone()
-            .unwrap_or("mkTopModule".to_string());
+        let top_module = Self::test_top_module(target, test_config);
 
         // Determine the name/path of the resulting output file.
-        let output_file = test_build_path.join(target.path.as_path().file_stem().unwrap());
+        let output_file = test_build_path.join(target.name());
 
-        let mut cmd = process::Command::new("bsc");
+        if test_config.simulator == Simulator::Verilator {
+            return self.link_harness(target, &top_module, &output_file, test_config);
+        }
+
+        // Bluesim compiles the C++ of a simulation in parallel, with tokens of make's
+        // jobserver when dolly is run by make.
+        let tokens = jobserver::acquire();
+        let parallel: Vec<std::ffi::OsString> =
+            if test_config.simulator == Simulator::Bluesim && tokens.jobs() > 1 {
+                vec![
+                    "-parallel-sim-link".into(),
+                    tokens.jobs().to_string().into(),
+                ]
+            } else {
+                Vec::new()
+            };
+
+        let mut cmd = process::Command::new(config::tool("bsc"));
         let cmd = cmd
+            .envs(&self.env)
+            .envs(&target.env)
             // output directory for .bo and .ba files
             .arg("-bdir")
             .arg(&test_build_path)
@@ -464,11 +1673,18 @@ impl Builder {
             .arg("-info-dir")
             .arg(&test_build_path)
             .arg("-show-schedule")
+            // specify paths to modules and precompiled packages
+            .arg("-p")
+            .arg(self.test_module_path_string())
             // output directory for Bluesim intermediate files
             .arg("-simdir")
             .arg(&test_build_path)
-            // compile BSV generating Bluesim object
+            // link a Bluesim simulation
             .arg("-sim")
+            .args(self.options.coverage.then_some("-keep-fires"))
+            .args(parallel)
+            .args(bsc_args)
+            .args(target.define_args())
             // check and recompile packages that are not up to date
             .arg("-u")
             .arg("-e")
@@ -493,121 +1709,1459 @@ impl Builder {
             cmd
         };
 
-        let child = cmd.spawn();
+        trace!("Linking: {:?}", &target.path);
 
-        if let Err(e) = child {
-            if let std::io::ErrorKind::NotFound = e.kind() {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Unable to locate 'bsc' program.",
-                )));
-            } else {
-                println!("ERROR: Attempting to locate 'bsc' failed.");
-                return Err(Box::new(e));
-            }
+        let log_file = test_build_path.join("link.log");
+        let output = self.timed("link", &target.name(), || {
+            self.run_bsc(cmd, &target.name(), &log_file)
+        })?;
+        drop(tokens);
+        if output.is_none_or(|output| output.status.success()) {
+            trace!("Link succeded: {:?}", &target.path);
+            Ok(output_file)
+        } else {
+            error!("Link failed (see {})", log_file.to_string_lossy());
+            Err(Box::new(codes::error("D0302", "Link failed")))
         }
+    }
+
+    /// Builds a Verilator simulation of a testbench's generated Verilog under the harness
+    /// driving its clock and reset (see `harness`).
+    fn link_harness(
+        &self,
+        target: &BuildTarget,
+        top_module: &str,
+        output_file: &path::Path,
+        test_config: &TestConfig,
+    ) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+        let test_build_path = output_file.parent().unwrap();
+        let harness_file = test_build_path.join(format!("{}.v", harness::MODULE));
+        let harness = harness::generate(top_module, &test_config.harness)?;
+        if !self.options.dry_run {
+            fs::write(&harness_file, harness)?;
+        }
+
+        let mut cmd = process::Command::new(config::tool("verilator"));
+        cmd.envs(&self.env)
+            .envs(&target.env)
+            .arg("--binary")
+            // bsc's Verilog and the Bluespec primitives aren't lint-clean
+            .arg("-Wno-fatal")
+            .arg("-Wno-lint")
+            .arg("--top-module")
+            .arg(harness::MODULE)
+            .arg("--Mdir")
+            .arg(test_build_path.join("obj_dir"))
+            .arg("-o")
+            .arg(output_file)
+            // the testbench's Verilog, then that of the precompiled dependencies and primitives
+            .arg("-y")
+            .arg(test_build_path);
+        for precompiled in &self.precompiled {
+            cmd.arg("-y").arg(precompiled);
+        }
+        if let Some(dir) = verilog::bluespec_dir() {
+            cmd.arg("-y").arg(dir.join("Verilog"));
+        }
+        if Self::waves_enabled(target, &self.options) {
+            cmd.arg(match test_config.wave_format {
+                WaveFormat::Vcd => "--trace",
+                WaveFormat::Fst => "--trace-fst",
+            });
+        }
+        if self.options.coverage {
+            // line and toggle coverage
+            cmd.arg("--coverage");
+        }
+        cmd.arg(&harness_file);
 
-        let child = child.unwrap();
         trace!("Linking: {:?}", &target.path);
 
-        let output = child.wait_with_output()?;
-        if output.status.success() {
+        let log_file = test_build_path.join("link.log");
+        let output = self
+            .timed("link", &target.name(), || {
+                Self::run_command(&self.options, &mut cmd, &target.name(), &log_file)
+            })
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    codes::error("D0102", "Unable to locate 'verilator' program.")
+                }
+                _ => e,
+            })?;
+        if output.is_none_or(|output| output.status.success()) {
             trace!("Link succeded: {:?}", &target.path);
-            Ok(output_file)
+            Ok(output_file.to_path_buf())
         } else {
-            error!(
-                "Link failed: {}",
-                std::str::from_utf8(output.stdout.as_slice()).unwrap()
-            );
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Link failed",
-            )))
+            error!("Link failed (see {})", log_file.to_string_lossy());
+            Err(Box::new(codes::error("D0302", "Link failed")))
+        }
+    }
+
+    fn check_snapshot(
+        run_dir: &path::Path,
+        snapshot_dir: &path::Path,
+        test_name: &str,
+        source: &SnapshotSource,
+        stdout: &str,
+        update: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let actual = match source {
+            SnapshotSource::Stdout => stdout.to_string(),
+            SnapshotSource::File(file) => match fs::read_to_string(run_dir.join(file)) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("Unable to read snapshot output {:?}: {}", file, e);
+                    return Ok(false);
+                }
+            },
+        };
+
+        let golden = snapshot::golden_path(snapshot_dir, test_name, source);
+        match snapshot::check(&golden, &actual, update)? {
+            SnapshotResult::Matched => Ok(true),
+            SnapshotResult::Updated => {
+                println!("Snapshot updated: {}", golden.to_string_lossy());
+                Ok(true)
+            }
+            SnapshotResult::Missing => {
+                println!(
+                    "Snapshot {} does not exist (run with --update-snapshots to create it)",
+                    golden.to_string_lossy()
+                );
+                Ok(false)
+            }
+            SnapshotResult::Mismatched(diff) => {
+                println!(
+                    "Snapshot {} does not match:\n{}",
+                    golden.to_string_lossy(),
+                    diff
+                );
+                Ok(false)
+            }
         }
     }
 
     fn test_build_target(
+        &self,
         target_executable: &path::Path,
+        target: &BuildTarget,
+        snapshot_dir: &path::Path,
+        test_config: &TestConfig,
     ) -> Result<bool, Box<dyn std::error::Error>> {
+        let options = &self.options;
         trace!("Testing: {:?}", &target_executable);
-        let output = if cfg!(target_os = "windows") {
-            std::process::Command::new("cmd")
-                .arg("/C")
-                .arg(target_executable)
-                .output()?
+        let test_name = target.name();
+
+        // Ask the simulator to dump a waveform next to the test executable if requested.
+        let waves = Self::waves_enabled(target, options);
+        let vcd_file = waves::wave_file(target_executable, WaveFormat::Vcd);
+        let coverage_file = target_executable.with_file_name("coverage.dat");
+        // A database left by an earlier run would otherwise be merged if this one writes none.
+        if options.coverage && !options.dry_run {
+            let _ = fs::remove_file(&coverage_file);
+        }
+        let mut sim_args = Vec::<std::ffi::OsString>::new();
+        match test_config.simulator {
+            // Bluesim rule coverage is extracted from the VCD.
+            Simulator::Bluesim if waves || options.coverage => {
+                sim_args.push("-V".into());
+                sim_args.push(vcd_file.clone().into());
+            }
+            Simulator::Bluesim => {}
+            Simulator::Verilator => {
+                if waves {
+                    sim_args.push("+bscvcd".into());
+                }
+                if options.coverage {
+                    let mut plusarg: std::ffi::OsString = "+verilator+coverage+file+".into();
+                    plusarg.push(&coverage_file);
+                    sim_args.push(plusarg);
+                }
+            }
+        }
+
+        let run_dir = Self::run_dir(target_executable);
+
+        let mut cmd = if cfg!(target_os = "windows") {
+            let mut cmd = std::process::Command::new("cmd");
+            cmd.arg("/C");
+            cmd
         } else {
-            std::process::Command::new("sh")
-                .arg("-c")
-                .arg(target_executable)
-                .output()?
+            let mut cmd = std::process::Command::new("sh");
+            cmd.arg("-c").arg(r#""$0" "$@""#);
+            cmd
+        };
+        cmd.envs(&self.env)
+            .envs(&target.env)
+            .arg(target_executable)
+            .args(&sim_args)
+            .current_dir(&run_dir);
+        let Some(output) = self.timed("run", &test_name, || {
+            Self::run_command(
+                options,
+                &mut cmd,
+                &test_name,
+                &target_executable.with_file_name("run.log"),
+            )
+        })?
+        else {
+            return Ok(true);
         };
 
-        if !output.status.success() {
+        if options.coverage && test_config.simulator == Simulator::Bluesim {
+            let mut coverage = Coverage::new();
+            coverage.add_rule_firings(&vcd_file)?;
+            coverage.write_dat(&coverage_file)?;
+            if !waves {
+                let _ = fs::remove_file(&vcd_file);
+            }
+        }
+
+        let wave_file = waves::wave_file(target_executable, test_config.wave_format);
+        let wave_file = match (waves, test_config.simulator, test_config.wave_format) {
+            (false, _, _) => wave_file,
+            // The harness always dumps to dump.vcd in the working directory, in
+            // whichever format Verilator's tracing was compiled for.
+            (true, Simulator::Verilator, _) => {
+                if let Err(e) = fs::rename(run_dir.join("dump.vcd"), &wave_file) {
+                    warn!("Unable to move dump.vcd to {:?}: {}", wave_file, e);
+                }
+                wave_file
+            }
+            // Bluesim can only dump VCD.
+            (true, Simulator::Bluesim, WaveFormat::Vcd) => wave_file,
+            (true, Simulator::Bluesim, WaveFormat::Fst) => {
+                waves::convert_to_fst(&vcd_file, &wave_file)
+            }
+        };
+
+        let mut passed =
+            Self::check_test_output(&output, target, &test_name, &run_dir, snapshot_dir, options)?;
+        if passed {
+            if let Some(baseline_dir) = Self::compare_baseline(target, options) {
+                passed = Self::compare_waves(&wave_file, baseline_dir, &test_name, target)?;
+            }
+        }
+
+        if !passed && waves {
+            println!("Waveform: {}", wave_file.to_string_lossy());
+        }
+
+        Ok(passed)
+    }
+
+    /// The working directory a test's simulation runs in, target/<test>/run.
+    fn run_dir(target_executable: &path::Path) -> path::PathBuf {
+        target_executable.with_file_name("run")
+    }
+
+    fn waves_enabled(target: &BuildTarget, options: &Options) -> bool {
+        options.waves || target.waves || Self::compare_baseline(target, options).is_some()
+    }
+
+    /// The baseline directory the test's waveform is compared against, if any.
+    fn compare_baseline<'a>(target: &BuildTarget, options: &'a Options) -> Option<&'a path::Path> {
+        if target.compare_signals.is_empty() {
+            None
+        } else {
+            options.compare_waves.as_deref()
+        }
+    }
+
+    fn compare_waves(
+        wave_file: &path::Path,
+        baseline_dir: &path::Path,
+        test_name: &str,
+        target: &BuildTarget,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if wave_file.extension().is_some_and(|ext| ext != "vcd") {
             error!(
-                "Test failed: {}",
-                std::str::from_utf8(output.stdout.as_slice()).unwrap()
+                "Waveform comparison requires VCD waveforms: {:?}",
+                wave_file
             );
+            return Ok(false);
+        }
+
+        let baseline = baseline_dir
+            .join(test_name)
+            .join(format!("{}.vcd", test_name));
+        trace!("Comparing {:?} with baseline {:?}", wave_file, baseline);
+
+        let mismatches = waves::compare(wave_file, &baseline, &target.compare_signals)?;
+        for mismatch in &mismatches {
+            println!("Waveform mismatch: {}", mismatch);
+        }
+
+        Ok(mismatches.is_empty())
+    }
+
+    fn check_test_output(
+        output: &process::Output,
+        target: &BuildTarget,
+        test_name: &str,
+        run_dir: &path::Path,
+        snapshot_dir: &path::Path,
+        options: &Options,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if !output.status.success() {
+            error!("Test failed: {}", test_name);
             Ok(false)
         } else {
-            // Search stdout for ">>>PASS" to see if the test succeeded.
             let stdout = str::from_utf8(output.stdout.as_slice())?;
-            if stdout.contains(">>>PASS") {
-                println!(
-                    "Test: {} -- {}.",
-                    target_executable.file_stem().unwrap().to_string_lossy(),
-                    "PASSED".green()
-                );
+            let passed = match &target.snapshot {
+                // Snapshot tests pass when their output matches the golden file.
+                Some(source) => Self::check_snapshot(
+                    run_dir,
+                    snapshot_dir,
+                    test_name,
+                    source,
+                    stdout,
+                    options.update_snapshots,
+                )?,
+                // Search stdout for ">>>PASS" to see if the test succeeded.
+                None => stdout.contains(">>>PASS"),
+            };
+
+            if passed {
+                println!("Test: {} -- {}.", test_name, "PASSED".green());
                 Ok(true)
             } else {
-                println!("{}", stdout);
-                println!(
-                    "Test: {} -- {}.",
-                    target_executable.file_stem().unwrap().to_string_lossy(),
-                    "FAILED".red().bold()
-                );
+                println!("Test: {} -- {}.", test_name, "FAILED".red().bold());
                 Ok(false)
             }
         }
     }
 
+    /// Whether tests that passed before with the same inputs are skipped.  They aren't when
+    /// outputs of the run (waveforms, coverage or snapshots) were asked for.
+    fn caching(&self) -> bool {
+        let options = &self.options;
+        !(options.no_cache
+            || options.dry_run
+            || options.waves
+            || options.coverage
+            || options.update_snapshots
+            || options.compare_waves.is_some())
+    }
+
+    /// The fingerprint of everything a test's result depends on: its sources and those they
+    /// import, its program and fixture data, golden snapshots, flags and environment.
+    fn test_fingerprint(
+        &self,
+        project: &Project,
+        bsc_args: &[std::ffi::OsString],
+        test: &BuildTarget,
+        packages: &HashMap<String, Vec<path::PathBuf>>,
+        snapshot_dir: &path::Path,
+    ) -> Fingerprint {
+        let mut fingerprint = Fingerprint::new();
+        fingerprint.add_str(env!("CARGO_PKG_VERSION"));
+        fingerprint.add_str(&config::tool("bsc"));
+        let test_config = test.overrides.test_config(&project.test);
+        fingerprint.add_str(&format!("{:?}", test_config));
+        fingerprint.add_str(&Self::test_top_module(test, &test_config));
+        for arg in bsc_args.iter().chain(&test.define_args()) {
+            fingerprint.add(arg.as_encoded_bytes());
+        }
+        for (name, value) in self.env.iter().chain(&test.env) {
+            fingerprint.add_str(name);
+            fingerprint.add_str(value);
+        }
+        for source in bsv::imported_sources(&test.path, packages) {
+            fingerprint.add_path(&source);
+        }
+        if let Some(Program::Firmware(path) | Program::Elf(path)) = &test.program {
+            fingerprint.add_str(&format!("{:?}", project.toolchain));
+            fingerprint.add_path(path);
+        }
+        if let Some(data) = &test.data {
+            fingerprint.add_path(data);
+        }
+        if let Some(source) = &test.snapshot {
+            fingerprint.add_path(&snapshot::golden_path(snapshot_dir, &test.name(), source));
+        }
+        let mut libraries: Vec<&path::PathBuf> = test
+            .extra_libraries
+            .iter()
+            .chain(&self.library_paths)
+            .chain(&self.precompiled)
+            .chain(&self.support_dirs)
+            .collect();
+        libraries.sort();
+        for library in libraries {
+            fingerprint.add_path(library);
+        }
+        fingerprint
+    }
+
     fn run_test(
-        module_path_string: &std::ffi::OsStr,
+        &self,
+        project: &Project,
         build_root: &path::Path,
+        snapshot_dir: &path::Path,
         test: &BuildTarget,
+        packages: &HashMap<String, Vec<path::PathBuf>>,
     ) -> Result<bool, Box<dyn std::error::Error>> {
-        Self::compile_build_target(module_path_string, build_root, test)?;
-        let test_executable = Self::link_build_target(build_root, test)?;
-        Self::test_build_target(test_executable.as_path())
+        let _span = telemetry::target("test", &test.name());
+        let start = std::time::Instant::now();
+        let bsc_args = warnings::bsc_flags(&project.build);
+        let fingerprint_file = build_root.join(test.name()).join("passed.fingerprint");
+        let fingerprint = self
+            .caching()
+            .then(|| self.test_fingerprint(project, &bsc_args, test, packages, snapshot_dir));
+        if fingerprint
+            .as_ref()
+            .is_some_and(|fingerprint| fingerprint.matches(&fingerprint_file))
+        {
+            println!("Test: {} -- {}.", test.name(), "cached pass".green());
+            return Ok(true);
+        }
+
+        let test_config = test.overrides.test_config(&project.test);
+        self.compile_build_target(&bsc_args, build_root, test, &test_config)?;
+        let test_executable = self.link_build_target(&bsc_args, build_root, test, &test_config)?;
+        runner::check_interrupted()?;
+        // Each run starts from an empty working directory, so files left by an earlier run
+        // can't make it pass.
+        let run_dir = Self::run_dir(&test_executable);
+        if !self.options.dry_run {
+            if run_dir.exists() {
+                fs::remove_dir_all(&run_dir)?;
+            }
+            fs::create_dir_all(&run_dir)?;
+            if let Some(program) = &test.program {
+                firmware::prepare(
+                    &project.toolchain.riscv,
+                    project.root_path(),
+                    program,
+                    &run_dir,
+                )?;
+            }
+            if let Some(data) = &test.data {
+                mutate::copy_tree(data, &run_dir, &[]).map_err(|e| {
+                    std::io::Error::other(format!(
+                        "Unable to copy fixture data {}: {}",
+                        data.to_string_lossy(),
+                        e
+                    ))
+                })?;
+            }
+        }
+        let passed =
+            self.test_build_target(test_executable.as_path(), test, snapshot_dir, &test_config)?;
+        if let Some(fingerprint) = fingerprint.filter(|_| passed) {
+            fingerprint.record(&fingerprint_file)?;
+        }
+        if !self.options.dry_run {
+            self.runs.lock().unwrap().push(TestRun::new(
+                test.name(),
+                passed,
+                start.elapsed().as_secs_f64(),
+            ));
+        }
+        Ok(passed)
+    }
+
+    /// Compiles and links a standalone simulation executable (such as a generated harness)
+    /// against the builder's modules, placing its build products in `build_root`.
+    pub fn build_executable(
+        project: &Project,
+        builder: &Builder,
+        build_root: &path::Path,
+        source: &path::Path,
+        top_module: &str,
+    ) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+        let target = BuildTarget::module(project, source, top_module, &builder.extra_libraries)?;
+
+        let bsc_args = warnings::bsc_flags(&project.build);
+        let test_config = target.overrides.test_config(&project.test);
+        builder.compile_build_target(&bsc_args, build_root, &target, &test_config)?;
+        builder.link_build_target(&bsc_args, build_root, &target, &test_config)
     }
 
     pub fn run_tests(
         project: &Project,
         builder: Builder,
     ) -> Result<Builder, Box<dyn std::error::Error>> {
+        let _stage = telemetry::stage("run_tests");
         let mut builder = builder;
-        let build_root = project.root_path().join("target");
-
-        // Module path creation
-        let mut module_path_string: std::ffi::OsString = "%/Libraries".into();
-        let colon: std::ffi::OsString = ":".into();
-        for module in &builder.modules {
-            module_path_string.push(&colon);
-            module_path_string.push(module.as_os_str());
+        // Waveforms are compared as VCD, which isn't kept when FST is asked for.
+        if builder.options.compare_waves.is_some() && project.test.wave_format == WaveFormat::Fst {
+            return Err(Box::new(std::io::Error::other(
+                "--compare-waves needs VCD waveforms, but [test] wave-format is \"fst\"",
+            )));
         }
+        let build_root = project.target_dir();
+        let snapshot_dir = project.root_path().join("tests").join("snapshots");
 
         //
         // For each test
         //
         builder.all_tests_passed = true;
-        for test in builder.unit_tests.iter().chain(builder.tests.iter()) {
-            let test_passed =
-                Self::run_test(module_path_string.as_os_str(), build_root.as_path(), test)?;
-            if !test_passed {
+        let packages = if builder.caching() {
+            project.packages()?
+        } else {
+            HashMap::new()
+        };
+        // With --keep-going, a test that fails to build counts as failing, and the others still
+        // run; the failures are listed at the end.
+        let failures = Mutex::new(Vec::<(String, String)>::new());
+        let keep_going = builder.options.keep_going;
+        let record =
+            |test: &BuildTarget, result: Result<bool, Box<dyn std::error::Error>>| match result {
+                Ok(true) => Ok(true),
+                Ok(false) => {
+                    failures
+                        .lock()
+                        .unwrap()
+                        .push((test.name(), "failed".to_string()));
+                    Ok(false)
+                }
+                // An interrupted run stops rather than going on to the next test.
+                Err(e) if keep_going && !runner::is_interrupted() => {
+                    failures.lock().unwrap().push((test.name(), e.to_string()));
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            };
+        let total = builder.unit_tests.len() + builder.tests.len();
+        if builder.options.distribute {
+            let tests: Vec<&BuildTarget> = builder
+                .unit_tests
+                .iter()
+                .chain(builder.tests.iter())
+                .collect();
+            let requirements: Vec<(String, Vec<String>)> = tests
+                .iter()
+                .map(|test| {
+                    let test_config = test.overrides.test_config(&project.test);
+                    (test.name(), Self::test_requirements(test, &test_config))
+                })
+                .collect();
+            builder.all_tests_passed = distribute::run_tests(
+                &project.config.runners,
+                project.root_path(),
+                &requirements,
+                keep_going,
+                |index| {
+                    record(
+                        tests[index],
+                        builder.run_test(
+                            project,
+                            build_root.as_path(),
+                            snapshot_dir.as_path(),
+                            tests[index],
+                            &packages,
+                        ),
+                    )
+                },
+            )?;
+        } else {
+            let tests = builder.unit_tests.iter().chain(builder.tests.iter());
+            let dashboard = if builder.options.ui {
+                Dashboard::start(tests.clone().map(BuildTarget::name).collect())
+                    .map_err(|e| warn!("Not showing the dashboard: {}", e))
+                    .ok()
+            } else {
+                None
+            };
+            for (index, test) in tests.enumerate() {
+                runner::check_interrupted()?;
+                if let Some(dashboard) = &dashboard {
+                    dashboard.started(index);
+                }
+                let test_passed = record(
+                    test,
+                    builder.run_test(
+                        project,
+                        build_root.as_path(),
+                        snapshot_dir.as_path(),
+                        test,
+                        &packages,
+                    ),
+                );
+                if let Some(dashboard) = &dashboard {
+                    dashboard.finished(index, matches!(test_passed, Ok(true)));
+                }
+                if !test_passed? {
+                    builder.all_tests_passed = false;
+                    if !keep_going {
+                        break;
+                    }
+                }
+            }
+        }
+        if keep_going {
+            report_failures("tests", total, failures.into_inner().unwrap());
+        }
+
+        if builder.options.coverage && !builder.options.dry_run {
+            let run: Vec<String> = builder
+                .runs
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|run| run.test.clone())
+                .collect();
+            let threshold_met =
+                Self::report_coverage(build_root.as_path(), &run, project.test.coverage_threshold)?;
+            if !threshold_met {
                 builder.all_tests_passed = false;
-                break;
             }
         }
+        builder.record_runs(project)?;
+        builder.check_warnings(project)?;
 
         Ok(builder)
     }
+
+    /// The runner tags a test requires: its //!requires directives' and, for Verilator
+    /// simulations, verilator.
+    fn test_requirements(test: &BuildTarget, test_config: &TestConfig) -> Vec<String> {
+        let mut requires = test.requires.clone();
+        if test_config.simulator == Simulator::Verilator
+            && !requires.iter().any(|tag| tag == "verilator")
+        {
+            requires.push("verilator".to_string());
+        }
+        requires
+    }
+
+    /// Records the durations used to balance shards (in the `[test] shard-durations` file, if
+    /// any) and appends the runs to the history.
+    fn record_runs(&self, project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+        let runs = self.runs.lock().unwrap();
+        if let Some(file) = &project.test.shard_durations {
+            let durations = runs
+                .iter()
+                .map(|run| (run.test.clone(), run.duration))
+                .collect();
+            shard::record_durations(&project.root_path().join(file), &durations)?;
+        }
+        history::record(&history::history_file(project.root_path()), &runs)?;
+        Ok(())
+    }
+
+    /// Merges the coverage databases of the tests run this time (by name), writing the merged
+    /// database and an HTML report to target/coverage.  Returns whether the coverage threshold
+    /// (if any) was met.
+    fn report_coverage(
+        build_root: &path::Path,
+        tests: &[String],
+        threshold: Option<f64>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut coverage = Coverage::new();
+        for test in tests {
+            let coverage_file = build_root.join(test).join("coverage.dat");
+            match fs::File::open(&coverage_file) {
+                Ok(file) => coverage.merge_dat(std::io::BufReader::new(file))?,
+                Err(_) => trace!("No coverage database: {:?}", coverage_file),
+            }
+        }
+
+        let coverage_dir = build_root.join("coverage");
+        fs::create_dir_all(&coverage_dir)?;
+        coverage.write_dat(&coverage_dir.join("coverage.dat"))?;
+        coverage.write_html(&coverage_dir.join("index.html"))?;
+
+        coverage.print_summary();
+        println!(
+            "Coverage report: {}",
+            coverage_dir.join("index.html").to_string_lossy()
+        );
+
+        let percent = coverage.total().percent().unwrap_or(0.0);
+        match threshold {
+            Some(threshold) if percent < threshold => {
+                println!(
+                    "Coverage {:.1}% is below the minimum of {:.1}% -- {}.",
+                    percent,
+                    threshold,
+                    "FAILED".red().bold()
+                );
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A testbench target for a source, without any directives.
+    fn target(path: &path::Path) -> BuildTarget {
+        BuildTarget {
+            path: path.to_path_buf(),
+            top_module: None,
+            extra_libraries: HashSet::new(),
+            snapshot: None,
+            waves: false,
+            compare_signals: Vec::new(),
+            program: None,
+            env: BTreeMap::new(),
+            data: None,
+            requires: Vec::new(),
+            defines: Vec::new(),
+            overrides: Overrides::default(),
+            suite: None,
+        }
+    }
+
+    /// Stands in for bsc, the simulators and the linters: records the command line of each
+    /// command the builder runs on this thread, answering it with the exit status and output
+    /// `answer` gives for the command line.
+    #[cfg(unix)]
+    struct Tools {
+        commands: Mutex<Vec<String>>,
+        answer: Answer,
+    }
+
+    /// The exit status and output of a command, given its command line.
+    #[cfg(unix)]
+    type Answer = Box<dyn Fn(&str) -> (i32, String) + Send + Sync>;
+
+    #[cfg(unix)]
+    impl Tools {
+        /// Runs `step` with the tools, returning its result and the command lines run.
+        fn run<T>(
+            answer: impl Fn(&str) -> (i32, String) + Send + Sync + 'static,
+            step: impl FnOnce() -> T,
+        ) -> (T, Vec<String>) {
+            let tools = std::sync::Arc::new(Tools {
+                commands: Mutex::new(Vec::new()),
+                answer: Box::new(answer),
+            });
+            let result = executor::with(tools.clone(), step);
+            let commands = tools.commands.lock().unwrap().clone();
+            (result, commands)
+        }
+    }
+
+    #[cfg(unix)]
+    impl executor::Executor for Tools {
+        fn run(
+            &self,
+            cmd: &mut process::Command,
+            _name: &str,
+            log_file: &path::Path,
+        ) -> std::io::Result<process::Output> {
+            let output = self.output(cmd)?;
+            fs::write(log_file, &output.stdout)?;
+            Ok(output)
+        }
+
+        fn output(&self, cmd: &mut process::Command) -> std::io::Result<process::Output> {
+            use std::os::unix::process::ExitStatusExt;
+            let line = runner::command_line(cmd);
+            let (status, stdout) = (self.answer)(&line);
+            self.commands.lock().unwrap().push(line);
+            Ok(process::Output {
+                status: process::ExitStatus::from_raw(status << 8),
+                stdout: stdout.into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+
+        fn command_line(&self, cmd: &process::Command) -> String {
+            runner::command_line(cmd)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn waveform_dumps() {
+        let temp = tempfile::tempdir().unwrap();
+        let executable = temp.path().join("Fifo_tb").join("Fifo_tb");
+        fs::create_dir_all(executable.with_file_name("run")).unwrap();
+        let simulate = |builder: &Builder, target: &BuildTarget, config: &TestConfig| {
+            let (passed, commands) = Tools::run(
+                |_| (0, ">>>PASS\n".to_string()),
+                || builder.test_build_target(&executable, target, temp.path(), config),
+            );
+            assert!(passed.unwrap());
+            commands.concat()
+        };
+        let target = target(&temp.path().join("Fifo_tb.bsv"));
+        let bluesim = TestConfig::default();
+
+        // --waves or //!waves dump a VCD next to the executable, with -V for Bluesim, and for
+        // a Verilator harness with +bscvcd.
+        let builder = Builder::new();
+        assert!(!simulate(&builder, &target, &bluesim).contains("-V"));
+        let waves = Builder::with_options(Options {
+            waves: true,
+            ..Options::default()
+        });
+        let vcd = executable.with_file_name("Fifo_tb.vcd");
+        assert!(simulate(&waves, &target, &bluesim).ends_with(&format!(" -V {}", vcd.display())));
+        let directive = BuildTarget {
+            waves: true,
+            ..target.clone()
+        };
+        assert!(simulate(&builder, &directive, &bluesim).contains(" -V "));
+        let verilator = TestConfig {
+            simulator: Simulator::Verilator,
+            ..TestConfig::default()
+        };
+        assert!(simulate(&builder, &directive, &verilator).ends_with(" +bscvcd"));
+        assert!(!simulate(&builder, &target, &verilator).contains("+bscvcd"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fst_waveforms() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let manifest = |test: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[test]\n{}\n",
+                    test
+                ),
+            )
+            .unwrap();
+            Project::load(root.join("dolly.toml"))
+        };
+        assert_eq!(manifest("").unwrap().test.wave_format, WaveFormat::Vcd);
+        assert!(manifest("wave-format = \"fsdb\"").is_err());
+        let project = manifest("wave-format = \"fst\"").unwrap();
+        assert_eq!(project.test.wave_format, WaveFormat::Fst);
+
+        // Bluesim dumps VCD, which is converted afterwards.
+        let executable = root.join("target").join("Fifo_tb").join("Fifo_tb");
+        fs::create_dir_all(executable.with_file_name("run")).unwrap();
+        let target = BuildTarget {
+            waves: true,
+            ..target(&root.join("tests").join("Fifo_tb.bsv"))
+        };
+        let builder = Builder::new();
+        let (passed, commands) = Tools::run(
+            |_| (0, ">>>PASS\n".to_string()),
+            || builder.test_build_target(&executable, &target, root, &project.test),
+        );
+        assert!(passed.unwrap());
+        let vcd = executable.with_file_name("Fifo_tb.vcd");
+        assert!(commands[0].ends_with(&format!(" -V {}", vcd.display())));
+
+        // Baselines are compared as VCD, which isn't kept.
+        let compare = Builder::with_options(Options {
+            compare_waves: Some(root.join("baseline")),
+            ..Options::default()
+        });
+        let error = Builder::run_tests(&project, compare).err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("--compare-waves needs VCD waveforms"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn environment_variables() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let manifest = |env: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[env]\n{}\n",
+                    env
+                ),
+            )
+            .unwrap();
+            Project::load(root.join("dolly.toml")).unwrap()
+        };
+        std::env::set_var("DOLLY_TEST_LICENSE_HOST", "license");
+        let project =
+            manifest("LM_LICENSE_FILE = \"27000@$DOLLY_TEST_LICENSE_HOST\"\nSEED = \"1\"");
+        let env = project.env().unwrap();
+        assert_eq!(env["LM_LICENSE_FILE"], "27000@license");
+        assert!(manifest("HOME_IP = \"$DOLLY_TEST_UNSET_VARIABLE/ip\"")
+            .env()
+            .is_err());
+
+        // The simulation gets the project's variables, over which a testbench's //!env wins.
+        let executable = root.join("target").join("Fifo_tb").join("Fifo_tb");
+        fs::create_dir_all(executable.with_file_name("run")).unwrap();
+        let mut target = target(&root.join("tests").join("Fifo_tb.bsv"));
+        target.env.insert("SEED".to_string(), "7".to_string());
+        let mut builder = Builder::new();
+        builder.env = env;
+        let (passed, commands) = Tools::run(
+            |_| (0, ">>>PASS\n".to_string()),
+            || builder.test_build_target(&executable, &target, root, &project.test),
+        );
+        assert!(passed.unwrap());
+        assert!(commands[0].contains(" LM_LICENSE_FILE=27000@license "));
+        assert!(commands[0].contains(" SEED=7 ") && !commands[0].contains("SEED=1"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_directory() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let fixtures = root.join("tests").join("data");
+        fs::create_dir_all(&fixtures).unwrap();
+        fs::write(fixtures.join("program.hex"), "00000013\n").unwrap();
+        let run_dir = root.join("target").join("Fifo_tb").join("run");
+        fs::create_dir_all(&run_dir).unwrap();
+        fs::write(run_dir.join("stale.log"), "PASS\n").unwrap();
+        let target = BuildTarget {
+            data: Some(fixtures),
+            ..target(&root.join("tests").join("Fifo_tb.bsv"))
+        };
+
+        // The test runs in target/<test>/run, emptied of what the last run left and given
+        // the fixture data.
+        let builder = Builder::new();
+        let (passed, commands) = Tools::run(
+            |_| (0, ">>>PASS\n".to_string()),
+            || {
+                builder.run_test(
+                    &project,
+                    &project.target_dir(),
+                    root,
+                    &target,
+                    &HashMap::new(),
+                )
+            },
+        );
+        assert!(passed.unwrap());
+        let run = format!("cd {} && ", run_dir.display());
+        assert!(commands.last().unwrap().starts_with(&run));
+        assert!(!run_dir.join("stale.log").exists());
+        assert!(run_dir.join("program.hex").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn verilog_lint() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let build_path = root.join("target").join("mkTop");
+        fs::create_dir_all(&build_path).unwrap();
+        let verilog = build_path.join("mkTop.v");
+        fs::write(&verilog, "module mkTop(CLK);\nendmodule\n").unwrap();
+        let mut builder = Builder::new();
+        builder.top_modules.push("mkTop".to_string());
+        let lint = |builder: &Builder, status: i32, output: String| {
+            Tools::run(
+                move |_| (status, output.clone()),
+                || builder.lint_verilog(&project, std::slice::from_ref(&build_path)),
+            )
+        };
+
+        let (result, commands) = lint(&builder, 0, String::new());
+        assert!(result.is_ok());
+        assert_eq!(
+            commands,
+            [format!(
+                "verilator --lint-only -Wall --top-module mkTop {}",
+                verilog.display()
+            )]
+        );
+        assert!(build_path.join("lint.log").exists());
+
+        // Warnings only fail with deny-warnings, errors always do.
+        let warning = format!(
+            "%Warning-UNUSEDSIGNAL: {}:2:3: Signal is not used: 'count'\n",
+            verilog.display()
+        );
+        assert!(lint(&builder, 1, warning.clone()).0.is_ok());
+        let strict = Builder {
+            options: Options {
+                deny_warnings: true,
+                ..Options::default()
+            },
+            ..builder
+        };
+        assert!(lint(&strict, 1, warning).0.is_err());
+        let error = format!("%Error: {}:1:1: syntax error\n", verilog.display());
+        assert!(lint(&strict, 1, error).0.is_err());
+
+        // A linter that fails without a finding didn't lint.
+        let (result, _) = lint(&strict, 1, "%Error: Invalid option: -Wbad\n".to_string());
+        let error = result.unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|code| code.code() == "D0103"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn emitted_products() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("Soc.bsv"), "//!topmodule mkSoc\n").unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let target = project.target_dir();
+        // The mode and -bdir (in target/) of each bsc command, in order.
+        let build = |emit: Emit| -> Vec<String> {
+            let mut builder = Builder::with_options(Options {
+                emit,
+                ..Options::default()
+            });
+            builder.top_modules.push("mkSoc".to_string());
+            let (built, commands) = Tools::run(
+                |_| (0, String::new()),
+                || Builder::build_verilog(&project, builder),
+            );
+            assert!(built.is_ok());
+            commands
+                .iter()
+                .map(|command| {
+                    let words: Vec<&str> = command.split(' ').collect();
+                    let bdir = words.iter().position(|word| *word == "-bdir").unwrap();
+                    let mode = ["-verilog", "-sim", "-u"]
+                        .into_iter()
+                        .find(|mode| words.contains(mode))
+                        .unwrap();
+                    let directory = path::Path::new(words[bdir + 1]);
+                    format!(
+                        "{} {}",
+                        mode,
+                        directory.strip_prefix(&target).unwrap().display()
+                    )
+                })
+                .collect()
+        };
+
+        assert_eq!(build(Emit::Verilog), ["-verilog mkSoc"]);
+        assert_eq!(build(Emit::Bo), ["-u bo"]);
+        assert_eq!(
+            build(Emit::Bluesim),
+            ["-sim bluesim/mkSoc/Soc", "-sim bluesim/mkSoc/Soc"]
+        );
+        assert_eq!(
+            build(Emit::All),
+            [
+                "-u bo",
+                "-verilog mkSoc",
+                "-sim bluesim/mkSoc/Soc",
+                "-sim bluesim/mkSoc/Soc"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_going() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(
+            root.join("src").join("Soc.bsv"),
+            "//!topmodule mkBroken\n//!topmodule mkSoc\n",
+        )
+        .unwrap();
+        for test in ["Broken_tb", "Failing_tb", "Passing_tb"] {
+            fs::write(root.join("tests").join(format!("{}.bsv", test)), "").unwrap();
+        }
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        // mkBroken and Broken_tb don't compile, and Failing_tb doesn't pass.
+        let answer = |line: &str| {
+            if line.contains("mkBroken") || line.contains("Broken_tb.bsv") {
+                (1, String::new())
+            } else if line.contains("Failing_tb/run") {
+                (0, "FAIL\n".to_string())
+            } else {
+                (0, ">>>PASS\n".to_string())
+            }
+        };
+        let options = |keep_going: bool| Options {
+            keep_going,
+            no_cache: true,
+            ..Options::default()
+        };
+
+        let build = |keep_going: bool| {
+            Tools::run(answer, || {
+                let builder = Builder::with_options(options(keep_going));
+                Builder::build_verilog(&project, Builder::find_top_modules(&project, builder)?)
+            })
+        };
+
+        // The first top module that fails stops the build, unless going on.
+        let (built, commands) = build(false);
+        assert!(built.is_err());
+        assert_eq!(commands.len(), 1);
+        let (built, commands) = build(true);
+        let error = built.err().unwrap();
+        assert!(codes::coded(error.as_ref()).is_some_and(|code| code.code() == "D0301"));
+        assert_eq!(commands.len(), 2);
+
+        // A test that fails to build stops the run, unless going on, when it fails like the
+        // tests that don't pass.
+        let test = |keep_going: bool| {
+            Tools::run(answer, || {
+                let builder = Builder::with_options(options(keep_going));
+                let builder = Builder::find_modules(&project, builder)?;
+                Builder::run_tests(&project, Builder::find_tests(&project, builder)?)
+            })
+        };
+        let (tested, commands) = test(false);
+        assert!(tested.is_err());
+        assert_eq!(commands.len(), 1);
+        let (tested, commands) = test(true);
+        assert!(!tested.unwrap().all_tests_passed());
+        assert_eq!(commands.len(), 7);
+    }
+
+    #[test]
+    fn test_support_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        for directory in ["src", "tests/common", "tests/shared"] {
+            fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        fs::write(root.join("src").join("Soc.bsv"), "").unwrap();
+        fs::write(
+            root.join("tests").join("Fifo_tb.bsv"),
+            "import Scoreboard::*;\n",
+        )
+        .unwrap();
+        fs::write(root.join("tests").join("common").join("Scoreboard.bsv"), "").unwrap();
+        fs::write(root.join("tests").join("shared").join("Checker.bsv"), "").unwrap();
+        let load = |test: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n\n[test]\n{}\n",
+                    test
+                ),
+            )
+            .unwrap();
+            let project = Project::load(root.join("dolly.toml")).unwrap();
+            let builder = Builder::find_modules(&project, Builder::new()).unwrap();
+            Builder::find_tests(&project, builder).unwrap()
+        };
+        let tests = |builder: &Builder| -> Vec<String> {
+            builder.tests.iter().map(BuildTarget::name).collect()
+        };
+
+        // The packages in tests/common are on the tests' search path, and aren't tests (as
+        // those in other directories of tests/ are).
+        let builder = load("");
+        let common = root.join("tests").join("common");
+        let search_path = |path: std::ffi::OsString| -> Vec<path::PathBuf> {
+            std::env::split_paths(&path).collect()
+        };
+        assert!(search_path(builder.test_module_path_string()).contains(&common));
+        assert!(!search_path(builder.module_path_string()).contains(&common));
+        assert_eq!(tests(&builder), ["Fifo_tb", "shared.Checker"]);
+
+        // [test] support-dirs replace tests/common, which then holds a suite of tests.
+        let builder = load("support-dirs = [\"tests/shared\"]");
+        let shared = root.join("tests").join("shared");
+        assert!(search_path(builder.test_module_path_string()).contains(&shared));
+        assert!(!search_path(builder.test_module_path_string()).contains(&common));
+        assert_eq!(tests(&builder), ["Fifo_tb", "common.Scoreboard"]);
+    }
+
+    #[test]
+    fn default_test_top_module() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("Fifo_tb.bsv");
+        fs::write(&path, "module mkFifo_tb(Empty);\nendmodule\n").unwrap();
+
+        let target = |top_module: Option<&str>| BuildTarget {
+            top_module: top_module.map(str::to_string),
+            ..target(&path)
+        };
+        let config = TestConfig::default();
+        assert_eq!(
+            Builder::test_top_module(&target(None), &config),
+            "mkFifo_tb"
+        );
+        assert_eq!(
+            Builder::test_top_module(&target(Some("mkOther")), &config),
+            "mkOther"
+        );
+
+        fs::write(&path, "module mkTb(Empty);\nendmodule\n").unwrap();
+        let config = TestConfig {
+            default_top: Some("mkTb".to_string()),
+            ..TestConfig::default()
+        };
+        assert_eq!(Builder::test_top_module(&target(None), &config), "mkTb");
+
+        // Without either module, or a readable file, the default is left for bsc to report.
+        fs::write(&path, "module mkOther(Empty);\nendmodule\n").unwrap();
+        assert_eq!(Builder::test_top_module(&target(None), &config), "mkTb");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            Builder::test_top_module(&target(None), &TestConfig::default()),
+            "mkTopModule"
+        );
+        // A configured default the file doesn't define gives way to mk<FileStem>.
+        fs::write(&path, "module mkFifo_tb(Empty);\nendmodule\n").unwrap();
+        assert_eq!(
+            Builder::test_top_module(&target(None), &config),
+            "mkFifo_tb"
+        );
+    }
+
+    #[test]
+    fn fixture_data_directory() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("tests").join("Rom.data")).unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"Rom\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let patterns = DirectivePatterns::new().unwrap();
+        let data =
+            |test: &path::Path| TestDirectives::parse(&patterns, project.root_path(), test).data;
+
+        let test = root.join("tests").join("Rom.bsv");
+        fs::write(&test, "module mkTopModule(Empty);\nendmodule\n").unwrap();
+        assert_eq!(data(&test), Some(root.join("tests").join("Rom.data")));
+        fs::write(&test, "//!data fixtures/rom\n").unwrap();
+        assert_eq!(data(&test), Some(root.join("fixtures/rom")));
+        let other = root.join("tests").join("Ram.bsv");
+        fs::write(&other, "module mkTopModule(Empty);\nendmodule\n").unwrap();
+        assert_eq!(data(&other), None);
+        assert_eq!(data(&root.join("tests").join("Gone.bsv")), None);
+
+        // The data is copied into the simulation directory, nested directories and all; a
+        // //!data directory that doesn't exist can't be.
+        let data = root.join("tests").join("Rom.data");
+        fs::create_dir_all(data.join("images")).unwrap();
+        fs::write(data.join("images").join("boot.hex"), "00\n").unwrap();
+        let run_dir = root.join("target").join("Rom");
+        mutate::copy_tree(&data, &run_dir, &[]).unwrap();
+        assert!(run_dir.join("images").join("boot.hex").is_file());
+        assert!(mutate::copy_tree(&root.join("fixtures/rom"), &run_dir, &[]).is_err());
+    }
+
+    #[test]
+    fn testbench_directives() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let path = root.join("tests").join("Soc_tb.bsv");
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(
+            &path,
+            "//!topmodule mkSoc_tb\n//!snapshot\n//!waves\n//!compare_signals pc\n\
+             //!compare_signals irq valid\n//!firmware sw/boot\n//!elf sw/other.elf\n\
+             //!env SEED=7\n//!env MODE = fast \n--!requires fpga\n//!requires ddr\n\
+             //!matrix XLEN=32,64\n//!matrix WAYS=2\n",
+        )
+        .unwrap();
+        let patterns = DirectivePatterns::new().unwrap();
+
+        let directives = TestDirectives::parse(&patterns, root, &path);
+        assert_eq!(directives.top_module.as_deref(), Some("mkSoc_tb"));
+        assert!(matches!(directives.snapshot, Some(SnapshotSource::Stdout)));
+        assert!(directives.waves);
+        assert_eq!(directives.compare_signals, ["pc", "irq", "valid"]);
+        assert!(
+            matches!(&directives.program, Some(Program::Firmware(dir)) if *dir == root.join("sw/boot"))
+        );
+        assert_eq!(directives.env["SEED"], "7");
+        assert!(!directives.env.contains_key("MODE"));
+        assert_eq!(directives.requires, ["fpga", "ddr"]);
+        assert_eq!(directives.data, None);
+        assert_eq!(directives.matrix.len(), 2);
+
+        // Several top modules name none; a source that can't be read has no directives.
+        fs::write(&path, "//!topmodule mkA\n//!topmodule mkB\n").unwrap();
+        assert_eq!(
+            TestDirectives::parse(&patterns, root, &path).top_module,
+            None
+        );
+        let missing = TestDirectives::parse(&patterns, root, &root.join("Gone_tb.bsv"));
+        assert!(missing.top_module.is_none() && !missing.waves && missing.env.is_empty());
+        assert_eq!(missing.matrix, [Vec::new()]);
+    }
+
+    #[test]
+    fn matrix_configurations() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("Cache_tb.bsv");
+        fs::write(&path, "//!matrix XLEN=32,64  CACHE=wb,wt\n").unwrap();
+        let patterns = DirectivePatterns::new().unwrap();
+        let matrix = |path: &path::Path| TestDirectives::parse(&patterns, directory, path).matrix;
+
+        let target = target(&path);
+        let targets = Builder::expand_matrix(target.clone(), matrix(&path));
+        let names: Vec<String> = targets.iter().map(BuildTarget::name).collect();
+        assert_eq!(
+            names,
+            [
+                "Cache_tb-XLEN=32-CACHE=wb",
+                "Cache_tb-XLEN=32-CACHE=wt",
+                "Cache_tb-XLEN=64-CACHE=wb",
+                "Cache_tb-XLEN=64-CACHE=wt"
+            ]
+        );
+        assert_eq!(
+            targets[1].define_args(),
+            vec![
+                std::ffi::OsString::from("-D"),
+                "XLEN=32".into(),
+                "-D".into(),
+                "CACHE=wt".into()
+            ]
+        );
+
+        // Axes without a name or values are ignored, as are empty values.
+        fs::write(&path, "//!matrix XLEN WAYS= =4 CACHE=wb,,wt\n").unwrap();
+        let configurations = matrix(&path);
+        assert_eq!(
+            configurations,
+            [
+                vec![("CACHE".to_string(), "wb".to_string())],
+                vec![("CACHE".to_string(), "wt".to_string())]
+            ]
+        );
+
+        fs::write(&path, "module mkTopModule(Empty);\nendmodule\n").unwrap();
+        let targets = Builder::expand_matrix(target.clone(), matrix(&path));
+        assert_eq!(targets.len(), 1);
+        assert!(targets[0].define_args().is_empty());
+        let missing = directory.join("Missing_tb.bsv");
+        assert_eq!(matrix(&missing), [Vec::new()]);
+        assert_eq!(targets[0].name(), "Cache_tb");
+    }
+
+    #[test]
+    fn module_overrides() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(
+            root.join("src").join(".dolly.toml"),
+            "[defines]\nFAST = \"1\"\n\n[env]\nSEED = \"7\"\n",
+        )?;
+        let project = Project::load(root.join("dolly.toml"))?;
+
+        let source = root.join("src").join("Core.bsv");
+        let target = BuildTarget::module(&project, &source, "mkCore", &HashSet::new())?;
+        assert_eq!(target.define_args(), ["-D", "FAST=1"]);
+        assert_eq!(target.env["SEED"], "7");
+
+        fs::write(root.join("src").join(".dolly.toml"), "simulator = 3\n")?;
+        assert!(BuildTarget::module(&project, &source, "mkCore", &HashSet::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_imports() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        fs::create_dir_all(root.join("src"))?;
+        fs::create_dir_all(root.join("dep").join("src").join("util"))?;
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[dependencies]\nuart = { path = \"dep\" }\n",
+        )?;
+        fs::write(
+            root.join("src").join("Core.bsv"),
+            "package Core;\nimport Crc::*;\nendpackage\n",
+        )?;
+        fs::write(
+            root.join("dep").join("dolly.toml"),
+            "[package]\nname = \"uart\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(
+            root.join("dep").join("src").join("Uart.bsv"),
+            "package Uart;\nendpackage\n",
+        )?;
+        let crc = root.join("dep").join("src").join("util");
+        fs::write(crc.join("Crc.bsv"), "package Crc;\nendpackage\n")?;
+
+        // The dependency doesn't import its Crc package, but the project does.
+        let project = Project::load(root.join("dolly.toml"))?;
+        let builder = Builder::find_dependencies(&project, Builder::new())
+            .and_then(|builder| Builder::find_modules(&project, builder))?;
+        assert!(builder.module_paths().any(|path| *path == crc));
+        Ok(())
+    }
+
+    #[test]
+    fn library_top_modules() {
+        // A project without top modules builds, unless one is asked for.
+        let mut builder = Builder::new();
+        builder.retain_top_modules(&[]).unwrap();
+        let error = builder
+            .retain_top_modules(&["mkTop".to_string()])
+            .unwrap_err();
+        assert!(error.to_string().starts_with("D0203: --top mkTop"));
+
+        builder.top_modules = vec!["mkCore".to_string(), "mkSoc".to_string()];
+        let error = builder
+            .retain_top_modules(&["mkSco".to_string()])
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("D0204: 'mkSco' isn't a top module"));
+        builder.retain_top_modules(&["mkSoc".to_string()]).unwrap();
+        assert_eq!(builder.top_modules, ["mkSoc"]);
+    }
+
+    #[test]
+    fn coverage_of_run_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        for (test, count) in [("Run_tb", 1), ("Stale_tb", 0)] {
+            fs::create_dir_all(root.join(test))?;
+            fs::write(
+                root.join(test).join("coverage.dat"),
+                format!(
+                    "C '\x01f\x02{0}.v\x01l\x0210\x01page\x02v_line/mk{0}\x01o\x02block' {1}\n",
+                    test, count
+                ),
+            )?;
+        }
+
+        // The database left by Stale_tb, which didn't run, isn't merged.
+        assert!(Builder::report_coverage(
+            root,
+            &["Run_tb".to_string()],
+            Some(100.0)
+        )?);
+        let merged = fs::read_to_string(root.join("coverage").join("coverage.dat"))?;
+        assert!(merged.contains("Run_tb.v") && !merged.contains("Stale_tb.v"));
+        assert!(!Builder::report_coverage(
+            root,
+            &["Run_tb".to_string(), "Stale_tb".to_string()],
+            Some(100.0)
+        )?);
+        Ok(())
+    }
 }
@@ -0,0 +1,215 @@
+//! Clock domain crossing checks (`dolly lint --cdc`) over the Verilog of the last build.  bsc
+//! inlines registers as `always @(posedge <clock>)` blocks, so each register's domain is the
+//! clock of the block assigning it.  A register whose next value or enable depends, through
+//! wires, on a register of another domain reads it without a synchronizer: the Sync*
+//! primitives (SyncBit, SyncFIFO, SyncHandshake...) are instances, whose outputs end the
+//! search.  Clock ports of one domain (such as a clock and its gated copy) are grouped in
+//! dolly.toml; the other ports are each a domain of their own:
+//!
+//! ```toml
+//! [clocks]
+//! core = ["CLK", "CLK_core_gated"]
+//! uart = ["CLK_uart"]
+//! ```
+//!
+//! Crossings between modules, through their ports, aren't seen.
+use super::diagnostics::{Diagnostic, Severity};
+use super::project::Project;
+use super::verilog;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+/// A register reading one of another clock domain.
+#[derive(Debug, PartialEq)]
+struct Crossing {
+    register: String,
+    domain: String,
+    source: String, // The register read
+    source_domain: String,
+    offset: usize, // Of the assignment of the register's next value, in the module's Verilog
+}
+
+/// The clock domain of a clock port.
+fn domain(clocks: &BTreeMap<String, Vec<String>>, port: &str) -> String {
+    clocks
+        .iter()
+        .find(|(_, ports)| ports.iter().any(|clock| clock == port))
+        .map(|(domain, _)| domain.clone())
+        .unwrap_or_else(|| port.to_string())
+}
+
+/// The crossings in the module a bsc Verilog file defines.
+fn crossings(source: &str, clocks: &BTreeMap<String, Vec<String>>) -> Vec<Crossing> {
+    let block_re = Regex::new(r"always\s*@\s*\(\s*posedge\s+(\w+)").unwrap();
+    let end_re = Regex::new(r"\b(?:always|initial|endmodule)\b").unwrap();
+    let write_re = Regex::new(r"([A-Za-z_]\w*)\s*<=").unwrap();
+    let assign_re = Regex::new(r"(?m)^\s*assign\s+([\w$]+)\s*=([^;]*);").unwrap();
+    // Sized literals (8'd255) are matched so that their digits aren't taken as names.
+    let token_re = Regex::new(r"\d*'[sS]?[bodhBODH]\w+|[A-Za-z_][\w$]*").unwrap();
+
+    let mut registers = BTreeMap::<String, String>::new();
+    for capture in block_re.captures_iter(source) {
+        let start = capture.get(0).unwrap().end();
+        let end = end_re
+            .find(&source[start..])
+            .map_or(source.len(), |found| start + found.start());
+        let clock_domain = domain(clocks, &capture[1]);
+        for write in write_re.captures_iter(&source[start..end]) {
+            registers
+                .entry(write[1].to_string())
+                .or_insert_with(|| clock_domain.clone());
+        }
+    }
+    let assigns: BTreeMap<&str, (&str, usize)> = assign_re
+        .captures_iter(source)
+        .map(|capture| {
+            let expression = capture.get(2).unwrap();
+            (
+                capture.get(1).unwrap().as_str(),
+                (expression.as_str(), expression.start()),
+            )
+        })
+        .collect();
+
+    let mut crossings = Vec::new();
+    for (register, register_domain) in &registers {
+        let inputs = [format!("{}$D_IN", register), format!("{}$EN", register)];
+        let Some(offset) = inputs
+            .iter()
+            .find_map(|input| assigns.get(input.as_str()).map(|(_, offset)| *offset))
+        else {
+            continue;
+        };
+        let mut seen = BTreeSet::<&str>::new();
+        let mut remaining: Vec<&str> = inputs
+            .iter()
+            .filter_map(|input| {
+                assigns
+                    .get(input.as_str())
+                    .map(|(expression, _)| *expression)
+            })
+            .collect();
+        let mut sources = BTreeSet::<&str>::new();
+        while let Some(expression) = remaining.pop() {
+            for token in token_re.find_iter(expression).map(|token| token.as_str()) {
+                if !seen.insert(token) {
+                    continue;
+                }
+                match registers.get(token) {
+                    Some(domain) if domain != register_domain => {
+                        sources.insert(token);
+                    }
+                    Some(_) => {}
+                    None => remaining.extend(assigns.get(token).map(|(expression, _)| *expression)),
+                }
+            }
+        }
+        crossings.extend(sources.into_iter().map(|source| Crossing {
+            register: register.clone(),
+            domain: register_domain.clone(),
+            source: source.to_string(),
+            source_domain: registers[source].clone(),
+            offset,
+        }));
+    }
+    crossings
+}
+
+/// The crossings in the Verilog of the last build, each module checked once.
+pub fn lints(project: &Project) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    let files = verilog::generated_files(&project.target_dir());
+    if files.is_empty() {
+        println!("No Verilog found (run 'dolly build' to check clock domain crossings)");
+    }
+
+    let mut checked = BTreeSet::<String>::new();
+    let mut diagnostics = Vec::new();
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let Some(module) = verilog::defined_modules(&source).into_iter().next() else {
+            continue;
+        };
+        if !checked.insert(module.clone()) {
+            continue;
+        }
+        for crossing in crossings(&source, &project.clocks) {
+            let line = source[..crossing.offset].matches('\n').count() + 1;
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "clock-crossing",
+                    format!(
+                        "{}: {} (clock domain {}) reads {} (clock domain {}) without a \
+                         synchronizer",
+                        module,
+                        crossing.register,
+                        crossing.domain,
+                        crossing.source,
+                        crossing.source_domain
+                    ),
+                )
+                .at(&file, Some(line)),
+            );
+        }
+    }
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BRIDGE: &str = "module mkBridge(CLK, CLK_uart, RST_N, RST_N_uart);
+  reg [7 : 0] count;
+  wire [7 : 0] count$D_IN;
+  wire count$EN;
+  reg [7 : 0] shadow;
+  wire [7 : 0] shadow$D_IN;
+  wire shadow$EN;
+  reg [7 : 0] synced;
+  wire [7 : 0] synced$D_IN;
+  wire synced$EN;
+  wire [7 : 0] next_shadow;
+  SyncRegister #(.width(32'd8)) sync(.sCLK(CLK), .dCLK(CLK_uart), .sD_IN(count), .dD_OUT(sync$dD_OUT));
+  assign count$D_IN = count + 8'd1 ;
+  assign count$EN = 1'd1 ;
+  assign next_shadow = count ^ 8'hFF ;
+  assign shadow$D_IN = next_shadow ;
+  assign shadow$EN = 1'd1 ;
+  assign synced$D_IN = sync$dD_OUT ;
+  assign synced$EN = 1'd1 ;
+  always@(posedge CLK)
+  begin
+    if (count$EN) count <= `BSV_ASSIGNMENT_DELAY count$D_IN;
+  end
+  always@(posedge CLK_uart)
+  begin
+    if (shadow$EN) shadow <= `BSV_ASSIGNMENT_DELAY shadow$D_IN;
+    if (synced$EN) synced <= `BSV_ASSIGNMENT_DELAY synced$D_IN;
+  end
+endmodule
+";
+
+    #[test]
+    fn unsynchronized_crossings() {
+        let found = crossings(BRIDGE, &BTreeMap::new());
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            (found[0].register.as_str(), found[0].domain.as_str()),
+            ("shadow", "CLK_uart")
+        );
+        assert_eq!(
+            (found[0].source.as_str(), found[0].source_domain.as_str()),
+            ("count", "CLK")
+        );
+
+        let one_domain = BTreeMap::from([(
+            "core".to_string(),
+            vec!["CLK".to_string(), "CLK_uart".to_string()],
+        )]);
+        assert!(crossings(BRIDGE, &one_domain).is_empty());
+    }
+}
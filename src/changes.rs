@@ -0,0 +1,167 @@
+use super::bsv;
+use super::codes;
+use super::config;
+use super::net;
+use super::project::Project;
+use std::{
+    collections::{HashMap, HashSet},
+    path,
+};
+
+/// The files changed in a project's git working tree, used by `dolly test --changed` to pick
+/// the tests that could be affected.
+pub struct Changes {
+    files: HashSet<path::PathBuf>,
+    packages: HashMap<String, Vec<path::PathBuf>>, // Project and dependency packages by name
+    everything: bool,                              // The manifest or configuration changed
+}
+
+impl Changes {
+    pub fn find(project: &Project) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = project.root_path();
+        let toplevel = git(root, &["rev-parse", "--show-toplevel"])?;
+        let toplevel = path::PathBuf::from(String::from_utf8_lossy(&toplevel).trim_end());
+        let status = git(
+            root,
+            &["status", "--porcelain", "-z", "--untracked-files=all"],
+        )?;
+        let files = parse_status(&toplevel, &status);
+        let packages = project.packages()?;
+
+        let everything = [root.join("dolly.toml"), config::project_file(root)]
+            .iter()
+            .any(|file| files.contains(file));
+        Ok(Changes {
+            files,
+            packages,
+            everything,
+        })
+    }
+
+    /// Whether a change could affect the testbench `test`, which also reads `inputs` (such as
+    /// its firmware or fixture data directories).
+    pub fn affect(&self, test: &path::Path, inputs: &[&path::Path]) -> bool {
+        if self.everything {
+            return true;
+        }
+        let sources: HashSet<path::PathBuf> = bsv::imported_sources(test, &self.packages)
+            .iter()
+            .map(|source| canonical(source))
+            .collect();
+        let inputs: Vec<path::PathBuf> = inputs.iter().map(|input| canonical(input)).collect();
+        self.files.iter().any(|file| {
+            sources.contains(file) || inputs.iter().any(|input| file.starts_with(input))
+        })
+    }
+}
+
+fn canonical(path: &path::Path) -> path::PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Runs git in a directory, returning its output (and failing with its error output).  It is
+/// configured for fetching (see `net::git`), for the commands that clone.
+pub fn git(root: &path::Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let output = net::git()
+        .args(args)
+        .current_dir(root)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                codes::error("D0102", "Unable to locate 'git' program.")
+            }
+            _ => e,
+        })?;
+    if !output.status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(output.stdout)
+}
+
+/// The paths in `git status --porcelain -z` output, relative to the repository's top level.
+/// Renames and copies are followed by their original path, which counts as changed too.
+fn parse_status(toplevel: &path::Path, status: &[u8]) -> HashSet<path::PathBuf> {
+    let mut files = HashSet::<path::PathBuf>::new();
+    let mut entries = status
+        .split(|byte| *byte == 0)
+        .map(String::from_utf8_lossy)
+        .filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let Some((code, file)) = entry
+            .split_at_checked(3)
+            .filter(|(_, file)| !file.is_empty())
+        else {
+            continue;
+        };
+        files.insert(toplevel.join(file));
+        if code.contains(['R', 'C']) {
+            if let Some(original) = entries.next() {
+                files.insert(toplevel.join(original.as_ref()));
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn affected_tests() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        let write = |file: &str, contents: &str| fs::write(root.join(file), contents).unwrap();
+        write("src/Alu.bsv", "package Alu;\nendpackage\n");
+        write(
+            "src/Core.bsv",
+            "package Core;\nimport Alu::*;\nendpackage\n",
+        );
+        write("src/Uart.bsv", "package Uart;\nendpackage\n");
+        write("src/Core_tb.bsv", "import Core::*;\n");
+        write("src/Uart_tb.bsv", "import Uart::*;\n");
+
+        let status = b" M src/Alu.bsv\0R  src/Serial.bsv\0src/Uart2.bsv\0?? notes.txt\0";
+        let files = parse_status(root, status);
+        assert_eq!(files.len(), 4);
+        assert!(files.contains(&root.join("src/Uart2.bsv")));
+
+        let changes = Changes {
+            files,
+            packages: bsv::find_packages(&root.join("src")),
+            everything: false,
+        };
+        assert!(changes.affect(&root.join("src/Core_tb.bsv"), &[]));
+        assert!(!changes.affect(&root.join("src/Uart_tb.bsv"), &[]));
+        assert!(changes.affect(&root.join("src/Uart_tb.bsv"), &[&root.join("notes.txt")]));
+
+        // Truncated entries are skipped, as is a rename missing its original path.
+        let files = parse_status(root, b"M\0\0 M \0R  src/Serial.bsv");
+        assert_eq!(files, HashSet::from([root.join("src/Serial.bsv")]));
+        assert!(parse_status(root, b"").is_empty());
+
+        // A changed manifest affects every test, even one importing nothing changed.
+        let changes = Changes {
+            everything: true,
+            ..changes
+        };
+        assert!(changes.affect(&root.join("src/Uart_tb.bsv"), &[]));
+
+        // Outside a git working tree, the changes can't be found.
+        write(
+            "dolly.toml",
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        );
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let error = Changes::find(&project).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("git rev-parse --show-toplevel failed"));
+    }
+}
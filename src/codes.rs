@@ -0,0 +1,484 @@
+//! The codes of dolly's own errors, which `dolly explain` describes at length.
+use std::{error::Error, fmt};
+
+/// An error code, with a one-line summary and an explanation of its causes and fixes.
+pub struct Code {
+    pub code: &'static str,
+    pub summary: &'static str,
+    pub category: Category,
+    pub explanation: &'static str,
+}
+
+/// The kinds of failure, which dolly's exit status tells apart for scripts (see
+/// `exit_status`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    /// dolly.toml (or the project or its configuration) is missing or invalid.
+    Manifest,
+    /// Dependencies can't be found, resolved or fetched.
+    Dependency,
+    /// Sources couldn't be found or compiled, or a hook around the build failed.
+    Compile,
+    /// A testbench couldn't be linked.
+    Link,
+    /// A testbench failed.
+    Test,
+    /// A program dolly runs isn't installed or didn't work.
+    Tool,
+    /// A bug in dolly.
+    Internal,
+}
+
+impl Category {
+    /// The exit status of the failure.  1 is for other errors and 2 for invalid command lines.
+    pub fn exit_status(&self) -> u8 {
+        match self {
+            Category::Manifest => 3,
+            Category::Dependency => 4,
+            Category::Compile => 5,
+            Category::Link => 6,
+            Category::Test => 7,
+            Category::Tool => 8,
+            Category::Internal => 101,
+        }
+    }
+}
+
+pub const CODES: &[Code] = &[
+    Code {
+        code: "D0001",
+        summary: "project not found",
+        category: Category::Manifest,
+        explanation: "\
+No dolly.toml was found in the current directory (or the one given on the command line) or any
+of its parents.  Run dolly from inside a project, pass the project directory to commands that
+take one (dolly build path/to/project), or create a project with 'dolly init <name>'.",
+    },
+    Code {
+        code: "D0002",
+        summary: "environment variable not set",
+        category: Category::Manifest,
+        explanation: "\
+A setting in dolly.toml (such as an [env] value or a path) refers to an environment variable
+with $VAR or ${VAR} that isn't set.  Set the variable before running dolly, or change the
+setting so that it no longer needs it.",
+    },
+    Code {
+        code: "D0003",
+        summary: "dependency not found",
+        category: Category::Dependency,
+        explanation: "\
+A [dependencies] entry points at a directory without a dolly.toml.  Check the entry's path,
+which is relative to the project's root, and that the dependency has been checked out (for
+example with 'git submodule update --init').",
+    },
+    Code {
+        code: "D0004",
+        summary: "precompiled dependency has no packages",
+        category: Category::Dependency,
+        explanation: "\
+A [dependencies] entry with precompiled = true points at a directory without any .bo files.
+Build the dependency first, or point the entry at the directory that holds its .bo files.",
+    },
+    Code {
+        code: "D0005",
+        summary: "unknown lint",
+        category: Category::Manifest,
+        explanation: "\
+The [lints] table of dolly.toml names a lint that doesn't exist.  Run 'dolly lint --list' to see
+the names of the lints, and remove or rename the entry.",
+    },
+    Code {
+        code: "D0006",
+        summary: "invalid package metadata",
+        category: Category::Manifest,
+        explanation: "\
+A [package] metadata field of dolly.toml has a value dolly can't pass on to the generated
+Verilog headers and package archives.  Authors are \"Name\" or \"Name <email>\", the license is
+an SPDX expression (\"MIT\", \"Apache-2.0 OR MIT\"), the description is a single line, the
+repository is a URL and there are at most 5 keywords of up to 20 lowercase letters, digits,
+'-' or '_'.",
+    },
+    Code {
+        code: "D0007",
+        summary: "invalid dolly.toml",
+        category: Category::Manifest,
+        explanation: "\
+dolly.toml isn't valid TOML, or has a key or value dolly doesn't know.  The error points at the
+line, suggests the key or value that was probably meant and lists the valid ones.  Keys are
+kebab-case (src-dirs, not src_dirs), and each table only takes the keys described for it in
+the README; unknown keys are errors rather than being ignored, so that misspelled settings
+don't silently have no effect.",
+    },
+    Code {
+        code: "D0008",
+        summary: "dependency version conflict",
+        category: Category::Dependency,
+        explanation: "\
+A [dependencies] version requirement can't be met.  Either it isn't a requirement dolly reads
+(\"^1.2\" or \"1.2\" for compatible versions, \"~1.2.3\" for patch releases, \"=1.2.0\" for one
+version, \"*\" for any), or no package reached by the dependency paths has a version meeting
+every requirement on it.  The error lists each requirement with the chain of packages leading
+to it and the versions found; change a requirement or point a path at a version meeting
+them all.  `dolly bump` fails the same way when the new version wouldn't meet the
+requirement of a workspace member depending on the package: update the requirement first, or
+bump with --force.",
+    },
+    Code {
+        code: "D0009",
+        summary: "fetch while offline",
+        category: Category::Dependency,
+        explanation: "\
+dolly was run offline (with --offline, DOLLY_OFFLINE=1 or [net] offline = true in a
+configuration file) and needed something that isn't on disk yet: a git [patch], a template
+repository or riscv-tests.  The error lists each, with where it would come from.  Run the
+command once online to fetch them into the cache (later offline runs use the copies), or
+point dolly at local copies: a [patch] path, a template directory, or an [isa-test]
+riscv-tests checkout.",
+    },
+    Code {
+        code: "D0010",
+        summary: "invalid script",
+        category: Category::Manifest,
+        explanation: "\
+The Rhai script [hooks] script names couldn't be run: it is missing, doesn't parse, failed
+while registering its stages, or registered one at a point other than pre-build, post-build,
+pre-test and post-test.  The error gives the line and position.  A dolly built without the
+'scripting' feature (cargo install dolly --no-default-features) refuses any script; build it
+with the feature, or run the steps from [hooks] commands instead.",
+    },
+    Code {
+        code: "D0011",
+        summary: "user-only setting in a project configuration",
+        category: Category::Manifest,
+        explanation: "\
+A project's .dolly/config.toml holds a setting that only the user configuration
+(~/.config/dolly/config.toml, or config.toml in DOLLY_CONFIG_HOME) may: [registries] and the
+[net] proxy and mirrors, which say where dolly sends registry tokens, and the programs and
+hosts dolly runs or connects to on the user's behalf ([tools], [remote], [runners], [notify]
+command, [update] and [telemetry] otlp-endpoint).  Anyone can commit a .dolly/config.toml to a
+repository, so a cloned project mustn't be able to choose them.  Move the setting to the user
+configuration with 'dolly config set --user', or remove it.",
+    },
+    Code {
+        code: "D0012",
+        summary: "workspace member not found",
+        category: Category::Manifest,
+        explanation: "\
+A command needed a package of a workspace and found none: -p names a package that isn't one of
+the [workspace] members (the error suggests close names and lists them), -p or --workspace was
+given outside a workspace, the workspace has no members, or the command was run in a
+workspace root without a [package] of its own (choose a member with -p, or use --workspace).",
+    },
+    Code {
+        code: "D0013",
+        summary: "configuration setting not set",
+        category: Category::Manifest,
+        explanation: "\
+'dolly config get' was given a setting that neither the user configuration nor the project's
+.dolly/config.toml sets.  Keys are dotted paths into the files (net.offline, tools.bsc); see
+the README for the settings, and set one with 'dolly config set'.",
+    },
+    Code {
+        code: "D0101",
+        summary: "bsc not found",
+        category: Category::Tool,
+        explanation: "\
+The Bluespec compiler (bsc) isn't on the PATH.  Install it (see
+https://github.com/B-Lang-org/bsc), add its bin directory to the PATH, or point dolly at it with
+'dolly config set --user tools.bsc /path/to/bsc'.",
+    },
+    Code {
+        code: "D0102",
+        summary: "tool not found",
+        category: Category::Tool,
+        explanation: "\
+A program that a command runs (such as verilator, spike, riscof, sby or git) isn't on the PATH.
+Install it or add its directory to the PATH; most tools can also be set with
+'dolly config set --user tools.<name> /path/to/program'.",
+    },
+    Code {
+        code: "D0103",
+        summary: "linter failed",
+        category: Category::Tool,
+        explanation: "\
+A [verilog] linter (such as Verilator or Verible) exited with an error without reporting a
+finding, so it didn't lint: usually a flag the installed version doesn't take, or a crash.
+Its output is in its log in the top module's build directory; run with --show-commands to see
+the command line.",
+    },
+    Code {
+        code: "D0201",
+        summary: "submodule directory not found",
+        category: Category::Compile,
+        explanation: "\
+A //!submodule directive names a directory that doesn't exist next to the source containing
+it.  The directory name is case sensitive; the error suggests directories with close names.
+Fix the directive, or remove it if the directory was deleted (imports are followed without any
+directive).",
+    },
+    Code {
+        code: "D0202",
+        summary: "imported package not found",
+        category: Category::Compile,
+        explanation: "\
+A source imports a package that no source of the project or its dependencies defines and that
+isn't a Bluespec library, but whose name is close to one that is (case differences count).
+Fix the import to name the suggested package, or add the source that defines it.",
+    },
+    Code {
+        code: "D0203",
+        summary: "no top module declared",
+        category: Category::Compile,
+        explanation: "\
+'dolly build --top' generates Verilog for the named top modules, which are those named by
+//!topmodule directives in the top file (the [package] top-file, or src/<Package name>.bsv),
+and found none.  Add a directive naming the module to build:
+
+    //!topmodule mkTop
+
+Projects that only provide packages to others have no top module; 'dolly build' without --top
+only warns about them, and compiles their packages with --emit bo.",
+    },
+    Code {
+        code: "D0204",
+        summary: "unknown top module",
+        category: Category::Compile,
+        explanation: "\
+'dolly build --top' names a module that isn't one of the top modules of the top file (those
+of its //!topmodule directives).  The error suggests close names and lists the top modules;
+module names are case sensitive.",
+    },
+    Code {
+        code: "D0301",
+        summary: "compile failed",
+        category: Category::Compile,
+        explanation: "\
+bsc failed to compile a top module or testbench.  Its messages were printed above and are kept
+in the compile.log of the target's build directory (target/<name>/compile.log).  Run with
+--show-commands to see the command line dolly used.",
+    },
+    Code {
+        code: "D0302",
+        summary: "link failed",
+        category: Category::Link,
+        explanation: "\
+bsc compiled a testbench but failed to link its simulation, usually because of a missing
+Verilog or C file (a BDPI import or an //!extra_library).  See the link.log in the
+testbench's build directory.",
+    },
+    Code {
+        code: "D0303",
+        summary: "tests failed",
+        category: Category::Test,
+        explanation: "\
+At least one testbench didn't report success.  A testbench passes when its output contains
+>>>PASS (or matches its snapshot, for //!snapshot testbenches).  Each test's
+output is in its build directory; 'dolly report tests' lists the recent results.",
+    },
+    Code {
+        code: "D0304",
+        summary: "hook failed",
+        category: Category::Compile,
+        explanation: "\
+A [hooks] command exited with an error, which fails the command it ran for.  Its output is in
+target/hooks/<hook>.log; run with --show-commands to see the command line.",
+    },
+    Code {
+        code: "D0305",
+        summary: "script stage failed",
+        category: Category::Compile,
+        explanation: "\
+A stage of the [hooks] script threw an error (or a command it ran failed and it threw), which
+fails the command it ran for.  The error is the one the stage threw; with --dry-run the stages
+are only listed.",
+    },
+    Code {
+        code: "D0901",
+        summary: "internal error",
+        category: Category::Internal,
+        explanation: "\
+dolly panicked, which is a bug in dolly rather than a problem with the project.  The message
+printed before the error says where; run the command again with RUST_BACKTRACE=1 for a
+backtrace, and report them both with the dolly version ('dolly version').",
+    },
+];
+
+/// One of dolly's own errors, with its code.  It shows as the code and the message
+/// (`D0101: Unable to locate...`).
+#[derive(Clone, Debug)]
+pub struct CodedError {
+    code: &'static str,
+    category: Category,
+    message: String,
+}
+
+impl CodedError {
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn category(&self) -> Category {
+        self.category
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl Error for CodedError {}
+
+/// A failure that decides dolly's exit status itself, such as that of a plugin.
+#[derive(Debug)]
+pub struct ExitStatus {
+    pub status: u8,
+    pub message: String,
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ExitStatus {}
+
+/// An error with a code, as an `io::Error` so that it goes wherever those do.
+pub fn error(code: &str, message: impl fmt::Display) -> std::io::Error {
+    let code = CODES
+        .iter()
+        .find(|known| known.code == code)
+        .unwrap_or_else(|| panic!("Unregistered error code {}", code));
+    std::io::Error::other(CodedError {
+        code: code.code,
+        category: code.category,
+        message: message.to_string(),
+    })
+}
+
+/// The coded error an error is, wraps (in an `io::Error`) or was caused by.
+pub fn coded<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a CodedError> {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        let coded = error.downcast_ref::<CodedError>().or_else(|| {
+            error
+                .downcast_ref::<std::io::Error>()
+                .and_then(std::io::Error::get_ref)
+                .and_then(|inner| inner.downcast_ref::<CodedError>())
+        });
+        if coded.is_some() {
+            return coded;
+        }
+        next = error.source();
+    }
+    None
+}
+
+/// The exit status for an error: the one it decides, else that of the category of its code,
+/// else 1.
+pub fn exit_status(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(exit) = error.downcast_ref::<ExitStatus>() {
+        return exit.status;
+    }
+    coded(error).map_or(1, |coded| coded.category().exit_status())
+}
+
+/// Implements `dolly explain`: the explanation of a code, or a list of every code.
+pub fn explain(code: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let Some(code) = code else {
+        return Ok(CODES
+            .iter()
+            .map(|code| format!("{}  {}\n", code.code, code.summary))
+            .collect());
+    };
+    match CODES
+        .iter()
+        .find(|known| known.code.eq_ignore_ascii_case(code))
+    {
+        Some(code) => Ok(format!(
+            "{}: {}\n\n{}\n\ndolly exits with status {} after this error.\n",
+            code.code,
+            code.summary,
+            code.explanation,
+            code.category.exit_status()
+        )),
+        None => Err(Box::new(std::io::Error::other(format!(
+            "Unknown error code '{}' (run 'dolly explain' to list them)",
+            code
+        )))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn explained_codes() {
+        let mut codes: Vec<&str> = CODES.iter().map(|code| code.code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), CODES.len());
+
+        assert_eq!(
+            error("D0101", "Unable to locate 'bsc' program.").to_string(),
+            "D0101: Unable to locate 'bsc' program."
+        );
+        let explanation = explain(Some("d0203")).unwrap();
+        assert!(explanation.starts_with("D0203: no top module declared\n\n"));
+        assert!(explain(None)
+            .unwrap()
+            .contains("D0001  project not found\n"));
+        assert!(explain(Some("D9999")).is_err());
+
+        assert_eq!(exit_status(&error("D0303", "Not all tests passed")), 7);
+        let boxed: Box<dyn Error> = Box::new(error("D0102", "Unable to run 'verilator'"));
+        assert_eq!(exit_status(boxed.as_ref()), 8);
+        assert_eq!(coded(boxed.as_ref()).unwrap().code(), "D0102");
+        // Only the type counts, not a code quoted in the text.
+        let quoted = std::io::Error::other("Unable to read D0102: notes.md");
+        assert_eq!(exit_status(&quoted), 1);
+        let plugin = ExitStatus {
+            status: 3,
+            message: "dolly-tapeout failed".to_string(),
+        };
+        assert_eq!(exit_status(&plugin), 3);
+    }
+
+    #[test]
+    fn category_exit_statuses() {
+        for (category, status) in [
+            (Category::Manifest, 3),
+            (Category::Dependency, 4),
+            (Category::Compile, 5),
+            (Category::Link, 6),
+            (Category::Test, 7),
+            (Category::Tool, 8),
+            (Category::Internal, 101),
+        ] {
+            assert_eq!(category.exit_status(), status, "{:?}", category);
+            // Each code exits with the status of its category.
+            for code in CODES.iter().filter(|code| code.category == category) {
+                assert_eq!(
+                    exit_status(&error(code.code, "failed")),
+                    status,
+                    "{}",
+                    code.code
+                );
+            }
+        }
+        for (code, status) in [
+            ("D0012", 3),
+            ("D0013", 3),
+            ("D0103", 8),
+            ("D0204", 5),
+            ("D0304", 5),
+            ("D0305", 5),
+        ] {
+            assert_eq!(exit_status(&error(code, "failed")), status, "{}", code);
+        }
+    }
+}
@@ -0,0 +1,589 @@
+//! The configuration files, for settings that belong to the user or the machine rather than
+//! the project: the user's config.toml and, overriding it for one checkout, .dolly/config.toml.
+use super::codes;
+use super::executor;
+use super::jobserver;
+use super::net;
+use super::notify;
+use super::project::Simulator;
+use super::self_update::Channel;
+use super::telemetry;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path, sync::OnceLock};
+
+/// Settings from the user-level (~/.config/dolly/config.toml) and project-level
+/// (.dolly/config.toml) configuration files; the latter take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub tools: BTreeMap<String, String>, // Paths of external tools (bsc, sby, gtkwave, ...)
+    pub jobs: Option<usize>,             // Parallel jobs (defaults to the number of CPUs)
+    pub color: Color,
+    pub simulator: Option<Simulator>, // Used when dolly.toml doesn't set [test] simulator
+    pub cache_dir: Option<String>,    // Defaults to target/cache
+    pub new: NewDefaults,
+    pub net: Net,
+    pub registries: BTreeMap<String, Registry>,
+    pub remote: Option<Remote>, // Build server to run bsc and the simulations on
+    pub runners: BTreeMap<String, Runner>, // Machines `dolly test --distribute` shares tests among
+    pub telemetry: Telemetry,
+    pub notify: Notify,
+    pub update: Update,
+    #[serde(skip)]
+    pub root: Option<path::PathBuf>, // The project the configuration was loaded for
+}
+
+/// How dolly fetches repositories (templates, git patches, riscv-tests): through a proxy,
+/// and from mirrors.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Net {
+    pub proxy: Option<String>, // HTTP(S) proxy (default: git's own settings)
+    pub mirrors: BTreeMap<String, String>, // URL prefix -> prefix of its mirror
+    pub offline: bool,         // Fail rather than fetch (as with --offline)
+}
+
+/// A build server the builder runs its commands on, over ssh (see executor.rs).  It needs
+/// the tools, and the project at the same path: on a shared file system, or copied there
+/// with rsync before the first command (`sync`), build products being copied back.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Remote {
+    pub host: String, // ssh destination, such as ada@build1
+    #[serde(default)]
+    pub sync: bool,
+}
+
+/// A machine of the pool `dolly test --distribute` runs tests on (see distribute.rs).  Like
+/// a [remote] build server, it needs the tools and the project at the same path.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Runner {
+    pub host: String, // ssh destination
+    #[serde(default)]
+    pub sync: bool,
+    pub tags: Option<Vec<String>>, // What it has, for //!requires (default: everything)
+    #[serde(default = "one")]
+    pub jobs: usize, // Tests it runs at once
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Where to send traces of dolly's build phases (see telemetry.rs).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Telemetry {
+    pub otlp_endpoint: Option<String>, // OTLP/HTTP collector, such as https://otel.example.com:4318
+    pub service_name: String,
+    pub attributes: BTreeMap<String, String>, // Resource attributes, such as the CI job
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "dolly".to_string(),
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+/// How to tell the user a long `dolly test`, `build`, `prove`, ... has finished (see
+/// notify.rs).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Notify {
+    pub desktop: bool,           // With notify-send (osascript on macOS)
+    pub command: Option<String>, // Run with the shell, given DOLLY_RESULT, DOLLY_DURATION, ...
+    pub min_duration: u64,       // Seconds a command must take to be notified about
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self {
+            desktop: false,
+            command: None,
+            min_duration: 60,
+        }
+    }
+}
+
+/// Where `dolly self update` looks for releases (see self_update.rs).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Update {
+    pub feed: String, // URL of the directory with a <channel>.toml for each channel
+    pub channel: Channel,
+    pub public_key: Option<String>, // The minisign key binaries are signed with, if not dolly's
+}
+
+impl Default for Update {
+    fn default() -> Self {
+        Self {
+            feed: "https://github.com/Pretty-Good-RISC-V/dolly/releases/download/feed".to_string(),
+            channel: Channel::Stable,
+            public_key: None,
+        }
+    }
+}
+
+/// A host of BSV package repositories, which dolly authenticates to with a token when it
+/// fetches from below its URL.  The token comes from DOLLY_REGISTRY_<NAME>_TOKEN or the
+/// credentials file (see `credentials_file`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registry {
+    pub url: String,
+}
+
+/// The [package] metadata `dolly new` fills in when it isn't given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NewDefaults {
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub edition: Option<String>,
+}
+
+/// When to color the output.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// The settings only the user configuration may hold: where dolly sends registry tokens, and
+/// the programs and hosts it runs or connects to, which a cloned repository's
+/// .dolly/config.toml mustn't choose.
+const USER_ONLY: &[&str] = &[
+    "registries",
+    "net.proxy",
+    "net.mirrors",
+    "tools",
+    "remote",
+    "runners",
+    "notify.command",
+    "update",
+    "telemetry.otlp-endpoint",
+];
+
+/// The environment variable overriding the jobs setting (as --jobs does).
+pub const JOBS_VARIABLE: &str = "DOLLY_JOBS";
+
+/// The environment variable naming the directory of the user configuration, instead of
+/// ~/.config/dolly.
+pub const CONFIG_HOME_VARIABLE: &str = "DOLLY_CONFIG_HOME";
+
+static TOOLS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+impl Config {
+    /// Loads and merges the configuration files, when they exist.
+    pub fn load(project_root: Option<&path::Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load_from(user_file().as_deref(), project_root)
+    }
+
+    /// Loads and merges a user configuration file and the project's.
+    fn load_from(
+        user_file: Option<&path::Path>,
+        project_root: Option<&path::Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let table = merged_table(user_file, project_root)?;
+        let mut config: Self = toml::Value::Table(table).try_into()?;
+        config.root = project_root.map(path::Path::to_path_buf);
+        Ok(config)
+    }
+
+    /// The most jobs to run at once: DOLLY_JOBS (set by --jobs), the jobs setting, or the
+    /// number of CPUs.
+    pub fn jobs(&self) -> usize {
+        std::env::var(JOBS_VARIABLE)
+            .ok()
+            .and_then(|jobs| jobs.parse().ok())
+            .filter(|jobs| *jobs > 0)
+            .or(self.jobs)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |jobs| jobs.get()))
+    }
+
+    /// Applies the process-wide settings: tool paths (see `tool`), fetching (see `net::git`),
+    /// parallel jobs (see `jobserver::acquire`), where commands run (see `executor::current`),
+    /// tracing (see telemetry.rs), notifications (see notify.rs) and color.
+    pub fn install(&self) {
+        let _ = TOOLS.set(self.tools.clone());
+        jobserver::install(self);
+        executor::install(self.remote.as_ref(), self.root.as_deref());
+        telemetry::install(&self.telemetry);
+        notify::install(&self.notify);
+        net::install(&self.net, &self.registries, &read_credentials());
+        match self.color {
+            Color::Auto => {}
+            Color::Always => colored::control::set_override(true),
+            Color::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+/// The program to run for an external tool: its configured path, or else its name.
+pub fn tool(name: &str) -> String {
+    TOOLS
+        .get()
+        .and_then(|tools| tools.get(name).cloned())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// The user configuration: config.toml in DOLLY_CONFIG_HOME, else in the dolly directory of
+/// XDG_CONFIG_HOME or ~/.config.
+pub fn user_file() -> Option<path::PathBuf> {
+    if let Some(dir) = std::env::var_os(CONFIG_HOME_VARIABLE).filter(|dir| !dir.is_empty()) {
+        return Some(path::PathBuf::from(dir).join("config.toml"));
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| path::Path::new(&home).join(".config")))?;
+    Some(config_home.join("dolly").join("config.toml"))
+}
+
+/// The registry tokens, kept apart from the configuration so that it can be shared: `token`
+/// keys of [registries.<name>] tables in credentials.toml next to the user configuration.
+pub fn credentials_file() -> Option<path::PathBuf> {
+    Some(user_file()?.with_file_name("credentials.toml"))
+}
+
+/// The registry tokens of the credentials file, by registry name.
+fn read_credentials() -> BTreeMap<String, String> {
+    let table = credentials_file()
+        .and_then(|file| read_table(&file).ok())
+        .unwrap_or_default();
+    let registries = table.get("registries").and_then(toml::Value::as_table);
+    registries
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, registry)| {
+            let token = registry.get("token")?.as_str()?;
+            Some((name.clone(), token.to_string()))
+        })
+        .collect()
+}
+
+pub fn project_file(project_root: &path::Path) -> path::PathBuf {
+    project_root.join(".dolly").join("config.toml")
+}
+
+fn read_table(file: &path::Path) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    match fs::read_to_string(file) {
+        Ok(contents) => Ok(contents
+            .parse::<toml::Table>()
+            .map_err(|e| std::io::Error::other(format!("{}: {}", file.to_string_lossy(), e)))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Reads a configuration file, failing with its name on a setting dolly doesn't know.
+fn read_config(file: &path::Path) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    let table = read_table(file)?;
+    toml::Value::Table(table.clone())
+        .try_into::<Config>()
+        .map_err(|e| std::io::Error::other(format!("{}: {}", file.to_string_lossy(), e)))?;
+    Ok(table)
+}
+
+/// The value of a dotted key (e.g. `tools.bsc`) in a table.
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (first, rest) = key
+        .split_once('.')
+        .map_or((key, None), |(first, rest)| (first, Some(rest)));
+    match (table.get(first)?, rest) {
+        (value, None) => Some(value),
+        (toml::Value::Table(table), Some(rest)) => lookup(table, rest),
+        _ => None,
+    }
+}
+
+/// Fails if a project configuration file holds a setting only the user's may.
+fn check_project_config(
+    file: &path::Path,
+    table: &toml::Table,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match USER_ONLY.iter().find(|key| lookup(table, key).is_some()) {
+        Some(key) => Err(Box::new(codes::error(
+            "D0011",
+            format!(
+                "{}: {} can only be set in the user configuration (dolly config set --user)",
+                file.to_string_lossy(),
+                key
+            ),
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Merges `overlay` into `base`, table by table.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merged_table(
+    user_file: Option<&path::Path>,
+    project_root: Option<&path::Path>,
+) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    let mut table = match user_file {
+        Some(file) => read_config(file)?,
+        None => toml::Table::new(),
+    };
+    if let Some(project_root) = project_root {
+        let file = project_file(project_root);
+        let project = read_config(&file)?;
+        check_project_config(&file, &project)?;
+        merge(&mut table, project);
+    }
+    Ok(table)
+}
+
+/// The merged value of a dotted key (e.g. `tools.bsc`).
+pub fn get(
+    project_root: Option<&path::Path>,
+    key: &str,
+) -> Result<Option<toml::Value>, Box<dyn std::error::Error>> {
+    let table = merged_table(user_file().as_deref(), project_root)?;
+    Ok(lookup(&table, key).cloned())
+}
+
+/// Sets a dotted key in a configuration file, the user's or (unless `user`) a project's.  The
+/// value is parsed as TOML if possible (so `8` is a number) and is otherwise a string.
+pub fn set(
+    file: &path::Path,
+    key: &str,
+    value: &str,
+    user: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let value = format!("value = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+    let mut table = read_table(file)?;
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap();
+    let mut current = &mut table;
+    for part in parts {
+        current = match current
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(table) => table,
+            _ => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "'{}' is not a table",
+                    part
+                ))))
+            }
+        };
+    }
+    current.insert(last.to_string(), value);
+
+    // Reject unknown keys and invalid values before writing.
+    toml::Value::Table(table.clone())
+        .try_into::<Config>()
+        .map_err(|e| std::io::Error::other(format!("Invalid configuration: {}", e)))?;
+    if !user {
+        check_project_config(file, &table)?;
+    }
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file, toml::to_string(&table)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_and_merge() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let file = project_file(root);
+        set(&file, "jobs", "4", false).unwrap();
+        set(&file, "new.license", "MIT", false).unwrap();
+        set(&file, "simulator", "verilator", false).unwrap();
+        assert!(set(&file, "colour", "never", false).is_err());
+        assert!(set(&file, "jobs", "many", false).is_err());
+
+        let mut base: toml::Table = "jobs = 2\n[tools]\nsby = \"sby\"\n".parse().unwrap();
+        merge(&mut base, read_table(&file).unwrap());
+        let config: Config = toml::Value::Table(base).try_into().unwrap();
+        assert_eq!(config.jobs(), 4);
+        assert_eq!(config.new.license.as_deref(), Some("MIT"));
+        assert_eq!(config.tools["sby"], "sby");
+        assert_eq!(config.simulator, Some(Simulator::Verilator));
+
+        // Errors name the file at fault.
+        let file = project_file(root);
+        fs::write(&file, "jobs = 4\ncolour = \"never\"\n").unwrap();
+        let error = Config::load_from(None, Some(root)).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("{}: ", file.to_string_lossy())));
+        assert!(error.contains("colour"));
+        fs::write(&file, "jobs = \n").unwrap();
+        let error = Config::load_from(None, Some(root)).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("{}: ", file.to_string_lossy())));
+    }
+
+    #[test]
+    fn user_and_project_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let (home, root) = (temp.path().join("home"), temp.path().join("core"));
+        let user_file = home.join("config.toml");
+        fs::create_dir_all(&home).unwrap();
+        fs::write(
+            &user_file,
+            "jobs = 2\ncolor = \"never\"\n\n[new]\nauthors = [\"Ada\"]\nlicense = \"MIT\"\n",
+        )
+        .unwrap();
+        set(&project_file(&root), "jobs", "4", false).unwrap();
+        set(&project_file(&root), "new.license", "Apache-2.0", false).unwrap();
+
+        // The project's settings win, table by table.
+        let config = Config::load_from(Some(&user_file), Some(&root)).unwrap();
+        assert_eq!((config.jobs, config.color), (Some(4), Color::Never));
+        assert_eq!(config.new.authors, ["Ada"]);
+        assert_eq!(config.new.license.as_deref(), Some("Apache-2.0"));
+        assert_eq!(config.root.as_deref(), Some(root.as_path()));
+        let config = Config::load_from(Some(&user_file), None).unwrap();
+        assert_eq!(config.jobs, Some(2));
+        assert_eq!(config.new.license.as_deref(), Some("MIT"));
+
+        // Either file may be missing, but not invalid.
+        let config = Config::load_from(Some(&home.join("none.toml")), Some(&root)).unwrap();
+        assert_eq!((config.jobs, config.color), (Some(4), Color::Auto));
+        fs::write(&user_file, "jobs = \"many\"\n").unwrap();
+        let error = Config::load_from(Some(&user_file), Some(&root)).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("{}: ", user_file.to_string_lossy())));
+    }
+
+    #[test]
+    fn user_only_settings() {
+        let temp = tempfile::tempdir().unwrap();
+        let (user_file, root) = (temp.path().join("config.toml"), temp.path().join("core"));
+        fs::write(
+            &user_file,
+            "[registries.corp]\nurl = \"https://bsv.example.com/\"\n",
+        )
+        .unwrap();
+        let file = project_file(&root);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+
+        // A checkout can't redirect a registry's token, add a registry for one, or send
+        // fetches elsewhere, whether in the file or with dolly config set.
+        for (settings, key) in [
+            (
+                "[registries.corp]\nurl = \"https://evil.example.com/\"\n",
+                "registries",
+            ),
+            (
+                "[registries.evil]\nurl = \"https://evil.example.com/\"\n",
+                "registries",
+            ),
+            (
+                "[net]\nproxy = \"http://evil.example.com:3128\"\n",
+                "net.proxy",
+            ),
+            (
+                "[net.mirrors]\n\"https://bsv.example.com/\" = \"https://evil.example.com/\"\n",
+                "net.mirrors",
+            ),
+        ] {
+            fs::write(&file, settings).unwrap();
+            let error = Config::load_from(Some(&user_file), Some(&root)).unwrap_err();
+            assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0011"));
+            assert!(error.to_string().contains(&format!(
+                "{}: {} can only be set in the user configuration",
+                file.to_string_lossy(),
+                key
+            )));
+        }
+
+        // Nor can it choose the programs and hosts dolly runs or connects to.
+        for (settings, key) in [
+            ("[tools]\nbsc = \"./bsc\"\n", "tools"),
+            ("[remote]\nhost = \"evil.example.com\"\n", "remote"),
+            ("[runners.farm]\nhost = \"evil.example.com\"\n", "runners"),
+            (
+                "[notify]\ncommand = \"curl evil.example.com\"\n",
+                "notify.command",
+            ),
+            ("[update]\nfeed = \"https://evil.example.com/\"\n", "update"),
+            (
+                "[telemetry]\notlp-endpoint = \"https://evil.example.com/\"\n",
+                "telemetry.otlp-endpoint",
+            ),
+        ] {
+            fs::write(&file, settings).unwrap();
+            let error = Config::load_from(Some(&user_file), Some(&root)).unwrap_err();
+            assert!(error.to_string().contains(&format!(
+                "{}: {} can only",
+                file.to_string_lossy(),
+                key
+            )));
+        }
+        fs::write(
+            &file,
+            "[notify]\ndesktop = true\n\n[telemetry]\nservice-name = \"ci\"\n",
+        )
+        .unwrap();
+        let config = Config::load_from(Some(&user_file), Some(&root)).unwrap();
+        assert!(config.notify.desktop);
+        assert_eq!(config.telemetry.service_name, "ci");
+        assert!(set(&file, "tools.bsc", "/opt/bsc/bin/bsc", false).is_err());
+        fs::remove_file(&file).unwrap();
+        assert!(set(
+            &file,
+            "registries.corp.url",
+            "https://evil.example.com/",
+            false
+        )
+        .is_err());
+        assert!(set(
+            &file,
+            "net",
+            "{ proxy = \"http://evil.example.com\" }",
+            false
+        )
+        .is_err());
+        assert!(!file.exists());
+
+        // The token still goes to the registry the user configured.
+        set(&file, "net.offline", "true", false).unwrap();
+        let config = Config::load_from(Some(&user_file), Some(&root)).unwrap();
+        assert_eq!(config.registries["corp"].url, "https://bsv.example.com/");
+        assert!(config.net.offline);
+        set(
+            &user_file,
+            "net.proxy",
+            "http://proxy.example.com:3128",
+            true,
+        )
+        .unwrap();
+        let config = Config::load_from(Some(&user_file), Some(&root)).unwrap();
+        assert_eq!(
+            config.net.proxy.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+    }
+}
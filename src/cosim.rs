@@ -0,0 +1,469 @@
+use super::bsv;
+use super::builder::Builder;
+use super::codes;
+use super::elf::Elf;
+use super::project::Project;
+use colored::Colorize;
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufRead, BufReader, Read},
+    path, process,
+};
+use tracing::{error, trace};
+
+/// Number of matching instructions shown before a divergence.
+const HISTORY: usize = 8;
+
+/// One retired instruction and the integer registers it wrote.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commit {
+    pub pc: u64,
+    pub insn: u32,
+    pub writes: Vec<(usize, u64)>,
+}
+
+impl std::fmt::Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "pc {:#010x} insn {:#010x}", self.pc, self.insn)?;
+        for (register, value) in &self.writes {
+            write!(f, " x{}={:#x}", register, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a core commit log line: `commit <pc> <insn> [x<n>=<value>...]` (hex).
+pub fn parse_dut_commit(line: &str) -> Option<Commit> {
+    let commit_re = Regex::new(r"^commit\s+([0-9a-fA-F]+)\s+([0-9a-fA-F]+)(.*)$").unwrap();
+    let write_re = Regex::new(r"\bx(\d+)=([0-9a-fA-F]+)").unwrap();
+
+    let capture = commit_re.captures(line.trim())?;
+    Some(Commit {
+        pc: u64::from_str_radix(&capture[1], 16).ok()?,
+        insn: u32::from_str_radix(&capture[2], 16).ok()?,
+        writes: write_re
+            .captures_iter(&capture[3])
+            .filter_map(|write| {
+                Some((
+                    write[1].parse().ok()?,
+                    u64::from_str_radix(&write[2], 16).ok()?,
+                ))
+            })
+            .collect(),
+    })
+}
+
+/// Parses a Spike `--log-commits` line, e.g. `core 0: 3 0x80000000 (0x00000297) x5 0x80000000`.
+pub fn parse_spike_commit(line: &str) -> Option<Commit> {
+    let commit_re =
+        Regex::new(r"^core\s+\d+:\s+\d+\s+0x([0-9a-f]+)\s+\(0x([0-9a-f]+)\)(.*)$").unwrap();
+    let write_re = Regex::new(r"\bx(\d+)\s+0x([0-9a-f]+)").unwrap();
+
+    let capture = commit_re.captures(line.trim())?;
+    Some(Commit {
+        pc: u64::from_str_radix(&capture[1], 16).ok()?,
+        insn: u32::from_str_radix(&capture[2], 16).ok()?,
+        writes: write_re
+            .captures_iter(&capture[3])
+            .filter_map(|write| {
+                Some((
+                    write[1].parse().ok()?,
+                    u64::from_str_radix(&write[2], 16).ok()?,
+                ))
+            })
+            .collect(),
+    })
+}
+
+/// The first instruction at which the core and the reference disagree.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub index: usize,
+    pub dut: Commit,
+    pub reference: Commit,
+    pub history: Vec<Commit>,
+    pub registers: [u64; 32], // Reference register state before the diverging instruction
+}
+
+/// Why the commit streams don't match.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    Diverged(Box<Divergence>),
+    Malformed(String), // A commit writes a register that doesn't exist
+}
+
+/// Records the writes of a reference commit in the register state.
+fn apply(registers: &mut [u64; 32], commit: &Commit) -> Result<(), Mismatch> {
+    for (register, value) in &commit.writes {
+        let Some(slot) = registers.get_mut(*register) else {
+            return Err(Mismatch::Malformed(format!(
+                "the reference trace writes x{} at {}",
+                register, commit
+            )));
+        };
+        *slot = *value;
+    }
+    Ok(())
+}
+
+/// Compares two commit streams in lock step, returning the number of matching instructions or
+/// the first divergence.  Reference commits before the core's first PC (such as Spike's boot
+/// ROM) are skipped, and values are compared at `xlen` bits.
+pub fn compare(
+    dut: impl Iterator<Item = Commit>,
+    reference: impl Iterator<Item = Commit>,
+    xlen: u32,
+) -> Result<usize, Mismatch> {
+    let mask = if xlen >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << xlen) - 1
+    };
+    let normalize = |commit: Commit| Commit {
+        pc: commit.pc & mask,
+        insn: commit.insn,
+        writes: commit
+            .writes
+            .into_iter()
+            .filter(|(register, _)| *register != 0)
+            .map(|(register, value)| (register, value & mask))
+            .collect(),
+    };
+
+    let mut reference = reference.map(normalize);
+
+    let mut registers = [0u64; 32];
+    let mut history = VecDeque::<Commit>::new();
+    let mut synchronized = false;
+    let mut index = 0;
+    for dut_commit in dut.map(normalize) {
+        let reference_commit = loop {
+            match reference.next() {
+                Some(commit) if !synchronized && commit.pc != dut_commit.pc => {
+                    apply(&mut registers, &commit)?;
+                }
+                other => break other,
+            }
+        };
+        synchronized = true;
+
+        // The core may keep running briefly after the reference has stopped.
+        let Some(reference_commit) = reference_commit else {
+            break;
+        };
+
+        if dut_commit != reference_commit {
+            return Err(Mismatch::Diverged(Box::new(Divergence {
+                index,
+                dut: dut_commit,
+                reference: reference_commit,
+                history: history.into(),
+                registers,
+            })));
+        }
+
+        apply(&mut registers, &reference_commit)?;
+        history.push_back(reference_commit);
+        if history.len() > HISTORY {
+            history.pop_front();
+        }
+        index += 1;
+    }
+
+    Ok(index)
+}
+
+fn report(divergence: &Divergence) {
+    println!(
+        "{} at instruction {}:",
+        "Divergence".red().bold(),
+        divergence.index
+    );
+    for commit in &divergence.history {
+        println!("          {}", commit);
+    }
+    println!("  core:   {}", divergence.dut);
+    println!("  spike:  {}", divergence.reference);
+
+    println!("Registers (spike, before the diverging instruction):");
+    for (row, values) in divergence.registers.chunks(4).enumerate() {
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("x{:<2} {:#018x}", row * 4 + column, value))
+            .collect();
+        println!("  {}", line.join("  "));
+    }
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let _ = fs::remove_file(path);
+    let status = process::Command::new("mkfifo").arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Box::new(std::io::Error::other(format!(
+            "Unable to create FIFO {:?}",
+            path
+        ))))
+    }
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    Err(Box::new(std::io::Error::other(
+        "commit-fifo is only supported on Unix",
+    )))
+}
+
+/// Opens the commit FIFO once the core opens it to write, failing if the core exits first
+/// instead of waiting for it forever.
+#[cfg(unix)]
+fn open_fifo(
+    path: &path::Path,
+    core: &mut process::Child,
+) -> Result<fs::File, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let fifo = path.to_path_buf();
+    std::thread::spawn(move || {
+        let _ = sender.send(fs::File::open(fifo));
+    });
+    loop {
+        match receiver.recv_timeout(std::time::Duration::from_millis(100)) {
+            Ok(file) => return Ok(file?),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Unable to open FIFO {:?}",
+                    path
+                ))))
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if let Some(status) = core.try_wait()? {
+            // Opening the other end releases the thread blocked opening this one.
+            let _ = fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path);
+            return Err(Box::new(std::io::Error::other(format!(
+                "The core simulation exited ({}) without opening the commit FIFO {:?}",
+                status, path
+            ))));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn open_fifo(
+    _path: &path::Path,
+    _core: &mut process::Child,
+) -> Result<fs::File, Box<dyn std::error::Error>> {
+    Err(Box::new(std::io::Error::other(
+        "commit-fifo is only supported on Unix",
+    )))
+}
+
+/// Runs `elf` on the core and on Spike side by side, comparing every retired instruction.
+pub fn run(project: &Project, elf_path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let config = &project.cosim;
+    let Some(top) = &config.top else {
+        return Err(Box::new(std::io::Error::other(
+            "No testbench configured: set 'top' in the [cosim] section of dolly.toml",
+        )));
+    };
+
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let tests_dir = project.root_path().join("tests");
+    let Some(location) = bsv::find_module(
+        builder.module_paths().chain(std::iter::once(&tests_dir)),
+        top,
+    ) else {
+        error!("Co-simulation testbench {} not found", top);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+
+    let build_root = project.output_dir("cosim");
+    fs::create_dir_all(&build_root)?;
+    let executable = Builder::build_executable(
+        project,
+        &builder,
+        &build_root,
+        &location.path,
+        &location.module.name,
+    )?;
+
+    let run_dir = build_root.join("run");
+    fs::create_dir_all(&run_dir)?;
+    let elf_path = elf_path.canonicalize()?;
+    fs::write(
+        run_dir.join(&config.image),
+        Elf::load(&elf_path)?.to_hex(config.base_address)?,
+    )?;
+
+    let fifo = config.commit_fifo.as_ref().map(|fifo| run_dir.join(fifo));
+    if let Some(fifo) = &fifo {
+        make_fifo(fifo)?;
+    }
+
+    trace!("Starting core simulation {:?}", executable);
+    let mut core = process::Command::new("sh")
+        .arg("-c")
+        .arg(r#""$0" "$@""#)
+        .arg(&executable)
+        .current_dir(&run_dir)
+        .stdout(if fifo.is_some() {
+            process::Stdio::null()
+        } else {
+            process::Stdio::piped()
+        })
+        .spawn()?;
+
+    trace!("Starting spike");
+    let spike = process::Command::new(&config.spike)
+        .arg(format!("--isa={}", config.isa))
+        .arg("--log-commits")
+        .args(&config.spike_args)
+        .arg(&elf_path)
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::piped())
+        .spawn();
+    let mut spike = match spike {
+        Ok(spike) => spike,
+        Err(e) => {
+            let _ = core.kill();
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return Err(Box::new(codes::error(
+                    "D0102",
+                    format!("Unable to locate '{}' program.", config.spike),
+                )));
+            }
+            return Err(Box::new(e));
+        }
+    };
+
+    let core_log: Box<dyn Read> = match &fifo {
+        Some(fifo) => match open_fifo(fifo, &mut core) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                let _ = spike.kill();
+                let _ = spike.wait();
+                return Err(e);
+            }
+        },
+        None => Box::new(core.stdout.take().unwrap()),
+    };
+    let spike_log = spike.stderr.take().unwrap();
+
+    let xlen = if config.isa.to_lowercase().starts_with("rv64") {
+        64
+    } else {
+        32
+    };
+    let result = compare(
+        BufReader::new(core_log)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| parse_dut_commit(&line)),
+        BufReader::new(spike_log)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| parse_spike_commit(&line)),
+        xlen,
+    );
+
+    let _ = core.kill();
+    let _ = spike.kill();
+    let _ = core.wait();
+    let _ = spike.wait();
+
+    match result {
+        Ok(count) => {
+            println!(
+                "Co-simulation: {} instructions matched -- {}.",
+                count,
+                "PASSED".green()
+            );
+            Ok(())
+        }
+        Err(Mismatch::Malformed(message)) => Err(Box::new(std::io::Error::other(format!(
+            "Malformed commit trace: {}",
+            message
+        )))),
+        Err(Mismatch::Diverged(divergence)) => {
+            report(&divergence);
+            println!("Co-simulation -- {}.", "FAILED".red().bold());
+            Err(Box::new(std::io::Error::other(
+                "The core diverged from spike",
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_divergence() {
+        let spike = [
+            "core   0: 3 0x00001000 (0x00000297) x5  0x00001000",
+            "core   0: 3 0x80000000 (0x00000093) x1  0x00000000",
+            "core   0: 3 0x80000004 (0x00108093) x1  0x00000001",
+            "core   0: 3 0x80000008 (0x00108093) x1  0x00000002",
+        ];
+        let dut = [
+            "commit 80000000 00000093 x1=0",
+            "commit 80000004 00108093 x1=1",
+            "commit 80000008 00108093 x1=3",
+        ];
+
+        let Err(Mismatch::Diverged(divergence)) = compare(
+            dut.iter().filter_map(|line| parse_dut_commit(line)),
+            spike.iter().filter_map(|line| parse_spike_commit(line)),
+            32,
+        ) else {
+            panic!("no divergence");
+        };
+
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.dut.writes, vec![(1, 3)]);
+        assert_eq!(divergence.reference.writes, vec![(1, 2)]);
+        assert_eq!(divergence.history.len(), 2);
+        assert_eq!(divergence.registers[1], 1);
+        assert_eq!(divergence.registers[5], 0x1000);
+
+        let malformed = compare(
+            dut.iter().filter_map(|line| parse_dut_commit(line)),
+            ["core   0: 3 0x00001000 (0x00000297) x40 0x00001000"]
+                .iter()
+                .filter_map(|line| parse_spike_commit(line)),
+            32,
+        );
+        assert!(matches!(malformed, Err(Mismatch::Malformed(message)) if message.contains("x40")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn core_exits_before_fifo() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let fifo = temp.path().join("commits");
+        make_fifo(&fifo)?;
+        let mut core = process::Command::new("sh")
+            .arg("-c")
+            .arg("exit 3")
+            .spawn()?;
+        let error = open_fifo(&fifo, &mut core).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("without opening the commit FIFO"));
+        Ok(())
+    }
+}
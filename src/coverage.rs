@@ -0,0 +1,312 @@
+use super::waves;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path,
+};
+
+/// The kind of a coverage point.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum CoverageKind {
+    Line,
+    Toggle,
+    Branch,
+    Rule,
+}
+
+impl CoverageKind {
+    const ALL: [CoverageKind; 4] = [Self::Line, Self::Toggle, Self::Branch, Self::Rule];
+
+    fn from_page(page: &str) -> Self {
+        match page {
+            "v_toggle" => Self::Toggle,
+            "v_branch" => Self::Branch,
+            "v_rule" => Self::Rule,
+            _ => Self::Line,
+        }
+    }
+
+    fn page(&self) -> &'static str {
+        match self {
+            Self::Line => "v_line",
+            Self::Toggle => "v_toggle",
+            Self::Branch => "v_branch",
+            Self::Rule => "v_rule",
+        }
+    }
+
+    fn heading(&self) -> &'static str {
+        match self {
+            Self::Line => "Lines",
+            Self::Toggle => "Toggles",
+            Self::Branch => "Branches",
+            Self::Rule => "Rules",
+        }
+    }
+}
+
+/// Covered and total point counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tally {
+    pub covered: usize,
+    pub total: usize,
+}
+
+impl Tally {
+    pub fn percent(&self) -> Option<f64> {
+        if self.total == 0 {
+            None
+        } else {
+            Some(100.0 * self.covered as f64 / self.total as f64)
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.percent() {
+            Some(percent) => format!("{:5.1}% ({}/{})", percent, self.covered, self.total),
+            None => "-".to_string(),
+        }
+    }
+}
+
+/// A coverage database, using the same point keys as Verilator's coverage.dat.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    points: BTreeMap<String, (String, CoverageKind, u64)>, // key -> (module, kind, count)
+}
+
+// Field and value separators in coverage.dat keys.
+const FIELD_SEPARATOR: char = '\x01';
+const VALUE_SEPARATOR: char = '\x02';
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add_point(&mut self, key: String, module: String, kind: CoverageKind, count: u64) {
+        self.points
+            .entry(key)
+            .and_modify(|(_, _, total)| *total += count)
+            .or_insert((module, kind, count));
+    }
+
+    /// Merges a coverage.dat database into this one, summing counts of matching points.
+    pub fn merge_dat(&mut self, reader: impl BufRead) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            // Point lines are of the form: C '<key>' <count>
+            let Some(rest) = line.strip_prefix("C '") else {
+                continue;
+            };
+            let Some((key, count)) = rest.rsplit_once("' ") else {
+                continue;
+            };
+            let count: u64 = count.trim().parse().unwrap_or(0);
+
+            let page = key
+                .split(FIELD_SEPARATOR)
+                .filter_map(|field| field.split_once(VALUE_SEPARATOR))
+                .find(|(name, _)| *name == "page")
+                .map(|(_, value)| value)
+                .unwrap_or_default();
+            let (kind, module) = page.split_once('/').unwrap_or((page, ""));
+
+            self.add_point(
+                key.to_string(),
+                module.to_string(),
+                CoverageKind::from_page(kind),
+                count,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records which rules fired in a Bluesim VCD dumped with `-keep-fires`.
+    pub fn add_rule_firings(
+        &mut self,
+        vcd_file: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let vars = waves::read_vcd_header(io::BufReader::new(fs::File::open(vcd_file)?))?;
+        let rules: Vec<String> = vars
+            .iter()
+            .filter(|var| {
+                var.name
+                    .rsplit('.')
+                    .next()
+                    .is_some_and(|name| name.starts_with("WILL_FIRE_RL_"))
+            })
+            .map(|var| var.name.clone())
+            .collect();
+
+        let changes =
+            waves::read_vcd_signals(io::BufReader::new(fs::File::open(vcd_file)?), &rules)?;
+        for rule in rules {
+            // Rule names are hierarchical: the module instance is everything before the signal.
+            let (module, signal) = rule.rsplit_once('.').unwrap_or(("", &rule));
+            let firings = changes[&rule]
+                .iter()
+                .filter(|(_, value)| value == "1")
+                .count() as u64;
+
+            let key = format!(
+                "{f}page{v}{page}/{module}{f}o{v}{rule}{f}h{v}{module}",
+                f = FIELD_SEPARATOR,
+                v = VALUE_SEPARATOR,
+                page = CoverageKind::Rule.page(),
+                module = module,
+                rule = signal.trim_start_matches("WILL_FIRE_RL_"),
+            );
+            self.add_point(key, module.to_string(), CoverageKind::Rule, firings);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the database in coverage.dat format.
+    pub fn write_dat(&self, path: &path::Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(file, "# SystemC::Coverage-3")?;
+        for (key, (_, _, count)) in &self.points {
+            writeln!(file, "C '{}' {}", key, count)?;
+        }
+        Ok(())
+    }
+
+    /// Covered/total counts per module and kind.
+    pub fn by_module(&self) -> BTreeMap<String, BTreeMap<CoverageKind, Tally>> {
+        let mut modules = BTreeMap::<String, BTreeMap<CoverageKind, Tally>>::new();
+        for (module, kind, count) in self.points.values() {
+            let tally = modules
+                .entry(module.clone())
+                .or_default()
+                .entry(*kind)
+                .or_default();
+            tally.total += 1;
+            if *count > 0 {
+                tally.covered += 1;
+            }
+        }
+        modules
+    }
+
+    /// Covered/total counts over the whole design.
+    pub fn total(&self) -> Tally {
+        Tally {
+            covered: self
+                .points
+                .values()
+                .filter(|(_, _, count)| *count > 0)
+                .count(),
+            total: self.points.len(),
+        }
+    }
+
+    fn kinds(&self) -> Vec<CoverageKind> {
+        CoverageKind::ALL
+            .into_iter()
+            .filter(|kind| self.points.values().any(|(_, k, _)| k == kind))
+            .collect()
+    }
+
+    pub fn print_summary(&self) {
+        let kinds = self.kinds();
+        let modules = self.by_module();
+        let width = modules.keys().map(String::len).max().unwrap_or(0).max(6);
+
+        print!("{:width$}", "Module", width = width);
+        for kind in &kinds {
+            print!("  {:>22}", kind.heading());
+        }
+        println!();
+
+        for (module, tallies) in &modules {
+            print!("{:width$}", module, width = width);
+            for kind in &kinds {
+                let tally = tallies.get(kind).copied().unwrap_or_default();
+                print!("  {:>22}", tally.describe());
+            }
+            println!();
+        }
+
+        println!("Total coverage: {}", self.total().describe());
+    }
+
+    pub fn write_html(&self, path: &path::Path) -> io::Result<()> {
+        let kinds = self.kinds();
+        let mut html = io::BufWriter::new(fs::File::create(path)?);
+
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(html, "<title>Coverage report</title>")?;
+        writeln!(
+            html,
+            "<style>table {{ border-collapse: collapse; }} td, th {{ border: 1px solid #ccc; padding: 4px 8px; }} td {{ text-align: right; }} td:first-child {{ text-align: left; }}</style>"
+        )?;
+        writeln!(html, "</head><body>")?;
+        writeln!(html, "<h1>Coverage report</h1>")?;
+        writeln!(html, "<p>Total coverage: {}</p>", self.total().describe())?;
+        writeln!(html, "<table><tr><th>Module</th>")?;
+        for kind in &kinds {
+            writeln!(html, "<th>{}</th>", kind.heading())?;
+        }
+        writeln!(html, "</tr>")?;
+
+        for (module, tallies) in self.by_module() {
+            write!(html, "<tr><td>{}</td>", escape_html(&module))?;
+            for kind in &kinds {
+                let tally = tallies.get(kind).copied().unwrap_or_default();
+                write!(html, "<td>{}</td>", tally.describe())?;
+            }
+            writeln!(html, "</tr>")?;
+        }
+
+        writeln!(html, "</table></body></html>")?;
+        Ok(())
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_verilator_coverage() -> io::Result<()> {
+        let dat = "# SystemC::Coverage-3\n\
+            C '\x01f\x02Foo.v\x01l\x0210\x01page\x02v_line/mkFoo\x01o\x02block' 3\n\
+            C '\x01f\x02Foo.v\x01l\x0212\x01page\x02v_line/mkFoo\x01o\x02block' 0\n\
+            C '\x01f\x02Foo.v\x01l\x0214\x01page\x02v_toggle/mkFoo\x01o\x02count' 0\n";
+
+        let mut coverage = Coverage::new();
+        coverage.merge_dat(dat.as_bytes())?;
+        assert_eq!(
+            coverage.by_module()["mkFoo"][&CoverageKind::Line],
+            Tally {
+                covered: 1,
+                total: 2
+            }
+        );
+
+        // Merging a second run covering the toggle point sums the counts.
+        coverage.merge_dat(
+            "C '\x01f\x02Foo.v\x01l\x0214\x01page\x02v_toggle/mkFoo\x01o\x02count' 1\n".as_bytes(),
+        )?;
+        assert_eq!(
+            coverage.total(),
+            Tally {
+                covered: 2,
+                total: 3
+            }
+        );
+
+        Ok(())
+    }
+}
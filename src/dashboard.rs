@@ -0,0 +1,462 @@
+//! `dolly test --ui`: a dashboard of the test run in the terminal, in place of the output of
+//! every compile and simulation one after another.  It shows each test's status and
+//! duration, and the output of the selected test (the running one, unless another is chosen
+//! with the arrow keys), then a summary once the tests have run.
+//
+// The dashboard takes over stdout and stderr, so that the output of the builder and of the
+// commands it runs is kept by test rather than printed, and draws on the terminal instead,
+// with ratatui.
+use super::runner;
+use colored::Colorize;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor,
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, HighlightSpacing, Paragraph, Table, TableState},
+    Frame, TerminalOptions, Viewport,
+};
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
+    thread, time,
+};
+
+const LOG_LINES: usize = 10_000; // The most lines of output kept for each test
+const SUMMARY_LINES: usize = 20; // The lines of a failed test's output the summary shows
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Pending,
+    Running(time::Instant),
+    Passed(time::Duration),
+    Failed(time::Duration),
+}
+
+struct Row {
+    name: String,
+    status: Status,
+    log: Vec<String>,
+}
+
+struct State {
+    rows: Vec<Row>,
+    current: usize,  // The test output goes to: the running (or last) test
+    selected: usize, // The test whose output is shown
+    follow: bool,    // Whether the selection follows the running test
+}
+
+/// The dashboard, shown until it is dropped.
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    done: Arc<AtomicBool>,
+    terminal: Terminal,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+/// Removes escape sequences (colors) and carriage-return overwrites from a line of output.
+fn plain(line: &str) -> String {
+    let line = line
+        .rsplit('\r')
+        .find(|part| !part.is_empty())
+        .unwrap_or("");
+    let mut text = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => text.push_str("    "),
+            c if c.is_control() => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn seconds(duration: time::Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Draws the dashboard: the counts, the tests over the top half (scrolled to keep the
+/// selected one in view), and the selected test's latest output below.
+fn render(frame: &mut Frame, state: &State, now: time::Instant) {
+    let count =
+        |wanted: fn(&Status) -> bool| state.rows.iter().filter(|row| wanted(&row.status)).count();
+    let bold = Style::new().add_modifier(Modifier::BOLD);
+    let header = format!(
+        "dolly test: {} passed, {} failed, {} to run   (up/down or k/j: select, f: follow)",
+        count(|status| matches!(status, Status::Passed(_))),
+        count(|status| matches!(status, Status::Failed(_))),
+        count(|status| matches!(status, Status::Pending | Status::Running(_))),
+    );
+    let list_height = (frame.area().height.saturating_sub(2) / 2)
+        .clamp(1, state.rows.len().max(1).try_into().unwrap_or(u16::MAX));
+    let [header_area, list_area, log_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(list_height),
+        Constraint::Fill(1),
+    ])
+    .areas(frame.area());
+    frame.render_widget(Paragraph::new(header).style(bold), header_area);
+
+    let rows = state.rows.iter().map(|row| {
+        let (status, duration) = match row.status {
+            Status::Pending => (Span::raw(""), String::new()),
+            Status::Running(start) => (Span::styled("RUN", Color::Yellow), seconds(now - start)),
+            Status::Passed(duration) => (Span::styled("PASS", Color::Green), seconds(duration)),
+            Status::Failed(duration) => (Span::styled("FAIL", Color::Red), seconds(duration)),
+        };
+        ratatui::widgets::Row::new([
+            Cell::from(status),
+            Cell::from(row.name.as_str()),
+            Cell::from(Line::from(duration).right_aligned()),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(4),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+        ],
+    )
+    .highlight_symbol("> ")
+    .highlight_spacing(HighlightSpacing::Always)
+    .row_highlight_style(bold);
+    let mut table_state = TableState::new().with_selected(Some(state.selected));
+    frame.render_stateful_widget(table, list_area, &mut table_state);
+
+    let Some(selected) = state.rows.get(state.selected) else {
+        return;
+    };
+    let block = Block::new()
+        .borders(Borders::TOP)
+        .border_style(Style::new().add_modifier(Modifier::DIM))
+        .title(format!(" {} ", selected.name));
+    let log_height = block.inner(log_area).height.into();
+    let skip = selected.log.len().saturating_sub(log_height);
+    let log: Vec<Line> = selected.log[skip..]
+        .iter()
+        .map(|line| Line::raw(line.as_str()))
+        .collect();
+    frame.render_widget(Paragraph::new(log).block(block), log_area);
+}
+
+impl State {
+    /// Moves the selection by `step` tests, no longer following the running test.
+    fn select(&mut self, step: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(step).min(last);
+        self.follow = false;
+    }
+
+    /// Acts on a key: the arrows (or k and j) select a test, f follows the running test again
+    /// and Ctrl-C interrupts the run, as it would without the dashboard's raw mode.
+    fn key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                runner::interrupt()
+            }
+            KeyCode::Char('f') => {
+                self.follow = true;
+                self.selected = self.current;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The terminal, while the dashboard has stdout and stderr.
+struct Terminal {
+    output: fs::File, // Where stdout went before the dashboard
+    saved: [i32; 2],  // Copies of the original stdout and stderr
+}
+
+/// A copy of a file descriptor, which the commands dolly runs don't inherit.
+#[cfg(unix)]
+fn duplicate(fd: i32) -> io::Result<i32> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(copy),
+    }
+}
+
+impl Terminal {
+    /// Takes stdout and stderr over, returning the terminal and the pipe they now write to.
+    #[cfg(unix)]
+    fn take() -> io::Result<(Self, fs::File)> {
+        use std::os::fd::FromRawFd;
+
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::other("stdin and stdout aren't a terminal"));
+        }
+        io::stdout().flush()?;
+        let mut pipe = [0; 2];
+        if unsafe { libc::pipe(pipe.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let terminal = Self {
+            output: unsafe { fs::File::from_raw_fd(duplicate(1)?) },
+            saved: [duplicate(1)?, duplicate(2)?],
+        };
+        let read = duplicate(pipe[0])?;
+        unsafe {
+            libc::close(pipe[0]);
+            libc::dup2(pipe[1], 1);
+            libc::dup2(pipe[1], 2);
+            libc::close(pipe[1]);
+        }
+        Ok((terminal, unsafe { fs::File::from_raw_fd(read) }))
+    }
+
+    #[cfg(not(unix))]
+    fn take() -> io::Result<(Self, fs::File)> {
+        Err(io::Error::other("the dashboard needs a Unix terminal"))
+    }
+
+    /// Gives stdout and stderr back.
+    fn restore(&self) {
+        let _ = io::stdout().flush();
+        #[cfg(unix)]
+        unsafe {
+            libc::dup2(self.saved[0], 1);
+            libc::dup2(self.saved[1], 2);
+            libc::close(self.saved[0]);
+            libc::close(self.saved[1]);
+        }
+    }
+}
+
+impl Dashboard {
+    /// Shows the dashboard for the named tests, failing if stdout isn't a terminal.
+    pub fn start(names: Vec<String>) -> io::Result<Self> {
+        let (terminal, output) = Terminal::take()?;
+        let state = Arc::new(Mutex::new(State {
+            rows: names
+                .into_iter()
+                .map(|name| Row {
+                    name,
+                    status: Status::Pending,
+                    log: Vec::new(),
+                })
+                .collect(),
+            current: 0,
+            selected: 0,
+            follow: true,
+        }));
+        let done = Arc::new(AtomicBool::new(false));
+        let mut dashboard = Self {
+            state: state.clone(),
+            done: done.clone(),
+            terminal,
+            threads: Vec::new(),
+        };
+        // Keys are read as they are pressed, on the alternate screen.
+        terminal::enable_raw_mode()?;
+        execute!(
+            dashboard.terminal.output,
+            EnterAlternateScreen,
+            cursor::Hide
+        )?;
+
+        let output_state = state.clone();
+        dashboard.threads.push(thread::spawn(move || {
+            let mut output = io::BufReader::new(output);
+            let mut line = Vec::new();
+            while output
+                .read_until(b'\n', &mut line)
+                .is_ok_and(|read| read > 0)
+            {
+                let text = plain(String::from_utf8_lossy(&line).trim_end_matches('\n'));
+                let mut state = output_state.lock().unwrap();
+                let current = state.current;
+                if let Some(row) = state.rows.get_mut(current) {
+                    if row.log.len() == LOG_LINES {
+                        row.log.remove(0);
+                    }
+                    row.log.push(text);
+                }
+                line.clear();
+            }
+        }));
+
+        let (keys_state, keys_done) = (state.clone(), done.clone());
+        dashboard.threads.push(thread::spawn(move || {
+            while !keys_done.load(Ordering::SeqCst) {
+                match event::poll(time::Duration::from_millis(100)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => {
+                        thread::sleep(time::Duration::from_millis(100));
+                        continue;
+                    }
+                }
+                if let Ok(Event::Key(key)) = event::read() {
+                    if key.kind == KeyEventKind::Press {
+                        keys_state.lock().unwrap().key(key);
+                    }
+                }
+            }
+        }));
+
+        // A terminal that doesn't know its size (as a pty may not) is taken to be 80 by 24.
+        let viewport = match terminal::size() {
+            Ok((width, height)) if width > 0 && height > 0 => Viewport::Fullscreen,
+            _ => Viewport::Fixed(Rect::new(0, 0, 80, 24)),
+        };
+        let mut screen = ratatui::Terminal::with_options(
+            CrosstermBackend::new(dashboard.terminal.output.try_clone()?),
+            TerminalOptions { viewport },
+        )?;
+        dashboard.threads.push(thread::spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                let _ = screen
+                    .draw(|frame| render(frame, &state.lock().unwrap(), time::Instant::now()));
+                thread::sleep(time::Duration::from_millis(100));
+            }
+        }));
+        Ok(dashboard)
+    }
+
+    /// Marks a test (by index) as running.
+    pub fn started(&self, test: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.rows[test].status = Status::Running(time::Instant::now());
+        state.current = test;
+        if state.follow {
+            state.selected = test;
+        }
+    }
+
+    /// Marks a running test as passed or failed.
+    pub fn finished(&self, test: usize, passed: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Status::Running(start) = state.rows[test].status {
+            state.rows[test].status = if passed {
+                Status::Passed(start.elapsed())
+            } else {
+                Status::Failed(start.elapsed())
+            };
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    /// Gives the terminal back and prints the summary: each test's result and the end of the
+    /// output of those that failed.
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::SeqCst);
+        self.terminal.restore();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(self.terminal.output, LeaveAlternateScreen, cursor::Show);
+
+        let state = self.state.lock().unwrap();
+        for row in &state.rows {
+            match row.status {
+                Status::Passed(duration) => {
+                    println!("{} {} ({})", "PASS".green(), row.name, seconds(duration))
+                }
+                Status::Failed(_) | Status::Running(_) => {
+                    let duration = match row.status {
+                        Status::Failed(duration) => seconds(duration),
+                        _ => "interrupted".to_string(),
+                    };
+                    println!("{} {} ({})", "FAIL".red(), row.name, duration);
+                    let skip = row.log.len().saturating_sub(SUMMARY_LINES);
+                    for line in row.log.iter().skip(skip) {
+                        println!("    {}", line);
+                    }
+                }
+                Status::Pending => println!("{} {}", "SKIP".dimmed(), row.name),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn dashboard_lines() {
+        assert_eq!(plain("\x1b[32mPASS\x1b[0m\tFifo_tb"), "PASS    Fifo_tb");
+        assert_eq!(plain("cycle 10\rcycle 20\r"), "cycle 20");
+
+        let row = |name: &str, status, log: &[&str]| Row {
+            name: name.to_string(),
+            status,
+            log: log.iter().map(|line| line.to_string()).collect(),
+        };
+        let mut state = State {
+            rows: vec![
+                row(
+                    "Fifo_tb",
+                    Status::Passed(time::Duration::from_millis(1500)),
+                    &[],
+                ),
+                row(
+                    "Uart_tb",
+                    Status::Failed(time::Duration::from_secs(3)),
+                    &["one", "two", "three"],
+                ),
+                row("Spi_tb", Status::Pending, &[]),
+            ],
+            current: 1,
+            selected: 1,
+            follow: true,
+        };
+        let mut screen = ratatui::Terminal::new(TestBackend::new(40, 7)).unwrap();
+        screen
+            .draw(|frame| render(frame, &state, time::Instant::now()))
+            .unwrap();
+        let buffer = screen.backend().buffer();
+        let lines: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "dolly test: 1 passed, 1 failed, 1 to run",
+                "  PASS Fifo_tb                      1.5s",
+                "> FAIL Uart_tb                      3.0s",
+                " Uart_tb ───────────────────────────────",
+                "one                                     ",
+                "two                                     ",
+                "three                                   ",
+            ]
+        );
+
+        state.key(KeyEvent::from(KeyCode::Up));
+        assert_eq!((state.selected, state.follow), (0, false));
+        state.key(KeyEvent::from(KeyCode::Char('f')));
+        assert_eq!((state.selected, state.follow), (1, true));
+        state.select(5);
+        assert_eq!((state.selected, state.follow), (2, false));
+    }
+}
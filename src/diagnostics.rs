@@ -0,0 +1,238 @@
+use colored::Colorize;
+use regex::Regex;
+use std::{fmt, path};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in a project, by one of dolly's checks or by a tool it runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String, // The check or tool message tag (e.g. never-fires or G0010)
+    pub file: Option<path::PathBuf>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, message: String) -> Self {
+        Diagnostic {
+            severity,
+            code: code.to_string(),
+            file: None,
+            line: None,
+            message,
+        }
+    }
+
+    pub fn at(mut self, file: &path::Path, line: Option<usize>) -> Self {
+        self.file = Some(file.to_path_buf());
+        self.line = line;
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: ", file.to_string_lossy(), line)?,
+            (Some(file), None) => write!(f, "{}: ", file.to_string_lossy())?,
+            _ => {}
+        }
+        let severity = match self.severity {
+            Severity::Warning => format!("warning[{}]", self.code).yellow().bold(),
+            Severity::Error => format!("error[{}]", self.code).red().bold(),
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// Parses the warnings and errors in a bsc output, such as
+/// `Warning: "src/Top.bsv", line 10, column 5: (G0010)` followed by the indented message.
+pub fn parse_bsc(output: &str) -> Vec<Diagnostic> {
+    let re = Regex::new(
+        r#"(?m)^(Warning|Error): "([^"]*)", line (\d+), column \d+: \(([A-Z]\d{4})\)\n((?:[ \t]+\S[^\n]*\n?)*)"#,
+    )
+    .unwrap();
+    re.captures_iter(output)
+        .map(|capture| {
+            let severity = match &capture[1] {
+                "Error" => Severity::Error,
+                _ => Severity::Warning,
+            };
+            let message: Vec<&str> = capture[5].lines().map(str::trim).collect();
+            Diagnostic::new(severity, &capture[4], message.join(" "))
+                .at(path::Path::new(&capture[2]), capture[3].parse().ok())
+        })
+        .collect()
+}
+
+/// Parses the warnings and errors in a Verilator output, such as
+/// `%Warning-UNUSEDSIGNAL: mkTop.v:45:14: Signal is not used: 'count'`.  Messages without a
+/// location (like `%Error: Exiting due to 3 warning(s)`) are left out.
+pub fn parse_verilator(output: &str) -> Vec<Diagnostic> {
+    let re =
+        Regex::new(r"(?m)^%(Warning|Error)(?:-(\w+))?: ([^:\s]+):(\d+):(?:\d+:)? (.*)$").unwrap();
+    re.captures_iter(output)
+        .map(|capture| {
+            let severity = match &capture[1] {
+                "Error" => Severity::Error,
+                _ => Severity::Warning,
+            };
+            let code = capture.get(2).map_or("verilator", |code| code.as_str());
+            Diagnostic::new(severity, code, capture[5].trim().to_string())
+                .at(path::Path::new(&capture[3]), capture[4].parse().ok())
+        })
+        .collect()
+}
+
+/// Parses the warnings and errors in a slang output, such as
+/// `mkTop.v:45:14: warning: unused net 'count' [-Wunused-net]`.  Its notes are left out.
+pub fn parse_slang(output: &str) -> Vec<Diagnostic> {
+    let re =
+        Regex::new(r"(?m)^([^:\s]+):(\d+):(?:\d+:)? (warning|error): (.*?)(?: \[-W([\w-]+)\])?$")
+            .unwrap();
+    re.captures_iter(output)
+        .map(|capture| {
+            let severity = match &capture[3] {
+                "error" => Severity::Error,
+                _ => Severity::Warning,
+            };
+            let code = capture.get(5).map_or("slang", |code| code.as_str());
+            Diagnostic::new(severity, code, capture[4].trim().to_string())
+                .at(path::Path::new(&capture[1]), capture[2].parse().ok())
+        })
+        .collect()
+}
+
+/// Parses the findings in a Verible lint output, such as
+/// `mkTop.v:45:14-20: Line length exceeds max: 100; is: 120 [Style: line-length] [line-length]`,
+/// all of which are warnings.
+pub fn parse_verible(output: &str) -> Vec<Diagnostic> {
+    let re = Regex::new(r"(?m)^([^:\s]+):(\d+):[\d-]+: (.*?)(?: \[Style: [^\]]*\])? \[([\w-]+)\]$")
+        .unwrap();
+    re.captures_iter(output)
+        .map(|capture| {
+            Diagnostic::new(
+                Severity::Warning,
+                &capture[4],
+                capture[3].trim().to_string(),
+            )
+            .at(path::Path::new(&capture[1]), capture[2].parse().ok())
+        })
+        .collect()
+}
+
+/// Prints diagnostics with a count of each severity, failing if there were errors (or, with
+/// `deny_warnings`, warnings).
+pub fn report(
+    diagnostics: &[Diagnostic],
+    deny_warnings: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for diagnostic in diagnostics {
+        println!("{}", diagnostic);
+    }
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    };
+    let (warnings, errors) = (count(Severity::Warning), count(Severity::Error));
+    if warnings + errors > 0 {
+        println!("{} warnings, {} errors", warnings, errors);
+    }
+    if errors > 0 || (deny_warnings && warnings > 0) {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} warnings, {} errors",
+            warnings, errors
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tool_diagnostics() {
+        let diagnostics = parse_bsc(
+            r#"Warning: "src/Top.bsv", line 10, column 5: (G0010)
+  Rule `tick' was treated as more urgent than `tock'. Conflicts:
+    "tick" cannot fire before "tock": calls to count.write vs. count.read
+Error: "src/Top.bsv", line 22, column 8: (T0080)
+  Type error at the use of the following function:
+compiling src/Top.bsv
+"#,
+        );
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, "G0010");
+        assert_eq!(diagnostics[0].line, Some(10));
+        assert!(diagnostics[0]
+            .message
+            .ends_with("calls to count.write vs. count.read"));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[1].message,
+            "Type error at the use of the following function:"
+        );
+
+        let verilator = parse_verilator(
+            "%Warning-UNUSEDSIGNAL: target/mkTop/mkTop.v:45:14: Signal is not used: 'count'\n                                     : ... note: In instance 'mkTop'\n%Error: target/mkTop/mkTop.v:60:1: syntax error, unexpected endmodule\n%Error: Exiting due to 1 error(s)\n",
+        );
+        assert_eq!(verilator.len(), 2);
+        assert_eq!(
+            (verilator[0].code.as_str(), verilator[0].line),
+            ("UNUSEDSIGNAL", Some(45))
+        );
+        assert_eq!(verilator[1].code, "verilator");
+        assert_eq!(verilator[1].severity, Severity::Error);
+
+        let slang = parse_slang(
+            "target/mkTop/mkTop.v:45:14: warning: unused net 'count' [-Wunused-net]
+   45 |   wire count;
+      |        ^~~~~
+target/mkTop/mkTop.v:60:1: error: expected a declaration
+",
+        );
+        assert_eq!(
+            (slang[0].code.as_str(), slang[0].line),
+            ("unused-net", Some(45))
+        );
+        assert_eq!(
+            (slang[1].code.as_str(), slang[1].severity),
+            ("slang", Severity::Error)
+        );
+        let verible = parse_verible(
+            "target/mkTop/mkTop.v:45:101-120: Line length exceeds max: 100; is: 120 [Style: line-length] [line-length]
+",
+        );
+        assert_eq!(
+            (verible[0].code.as_str(), verible[0].message.as_str()),
+            ("line-length", "Line length exceeds max: 100; is: 120")
+        );
+
+        assert!(report(&diagnostics[..1], false).is_ok());
+        assert!(report(&diagnostics[..1], true).is_err());
+        assert!(report(&diagnostics, false).is_err());
+
+        // Messages missing their location or tag aren't bsc diagnostics, and one with no
+        // message lines has an empty message.
+        let diagnostics = parse_bsc(
+            "Error: \"src/Top.bsv\", line 3: (P0005)\n  No column.\nWarning: src/Top.bsv, line 3, column 1: (G0010)\n  Unquoted.\nError: \"src/Top.bsv\", line 3, column 1:\n  No tag.\nError: \"src/Top.bsv\", line 7, column 1: (S0015)\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].code.as_str(), diagnostics[0].line),
+            ("S0015", Some(7))
+        );
+        assert_eq!(diagnostics[0].message, "");
+        assert!(parse_bsc("").is_empty());
+        assert!(report(&[], true).is_ok());
+    }
+}
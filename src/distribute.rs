@@ -0,0 +1,242 @@
+//! Distributed test runs (`dolly test --distribute`): the tests are shared out among the
+//! machines of the [runners] configuration, each running as many at once as its jobs
+//! setting allows, while the results, logs and history are collected here.
+use super::codes;
+use super::config::Runner;
+use super::executor::{self, Executor, Ssh};
+use super::runner;
+use std::{
+    collections::BTreeMap,
+    path,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Whether a runner has every tag a test requires.
+fn can_run(runner: &Runner, requires: &[String]) -> bool {
+    match &runner.tags {
+        Some(tags) => requires.iter().all(|tag| tags.contains(tag)),
+        None => true,
+    }
+}
+
+/// Runs each test (by index) on a runner with the tags it requires, stopping after the first
+/// failure once the tests already started finish (unless `keep_going`).  Returns whether
+/// every test passed.
+pub fn run_tests(
+    runners: &BTreeMap<String, Runner>,
+    root: &path::Path,
+    tests: &[(String, Vec<String>)], // The name of each test, and the tags it requires
+    keep_going: bool,
+    run: impl Fn(usize) -> Result<bool, Box<dyn std::error::Error>> + Sync,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if runners.is_empty() {
+        return Err(Box::new(std::io::Error::other(
+            "--distribute needs machines to run the tests on, in [runners] of a configuration \
+             file",
+        )));
+    }
+    let unrunnable: Vec<String> = tests
+        .iter()
+        .filter(|(_, requires)| !runners.values().any(|runner| can_run(runner, requires)))
+        .map(|(name, requires)| format!("{} (requires {})", name, requires.join(" ")))
+        .collect();
+    if !unrunnable.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No runner has the tags of {}",
+            unrunnable.join(", ")
+        ))));
+    }
+
+    let queue = Mutex::new((0..tests.len()).collect::<Vec<usize>>());
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None::<codes::ExitStatus>);
+    let counts = Mutex::new(BTreeMap::<&str, usize>::new());
+    thread::scope(|scope| {
+        for (name, runner) in runners {
+            let executor: Arc<dyn Executor> =
+                Arc::new(Ssh::new(&runner.host, runner.sync.then_some(root)));
+            for _ in 0..runner.jobs.max(1) {
+                let executor = executor.clone();
+                let (queue, failed, error, counts, run) = (&queue, &failed, &error, &counts, &run);
+                scope.spawn(move || {
+                    executor::with(executor, || loop {
+                        if failed.load(Ordering::SeqCst) && !keep_going || runner::is_interrupted()
+                        {
+                            break;
+                        }
+                        let next = {
+                            let mut queue = queue.lock().unwrap();
+                            let position = queue
+                                .iter()
+                                .position(|test| can_run(runner, &tests[*test].1));
+                            position.map(|position| queue.remove(position))
+                        };
+                        let Some(test) = next else {
+                            break;
+                        };
+                        println!("Running {} on {}", tests[test].0, name);
+                        *counts.lock().unwrap().entry(name).or_default() += 1;
+                        match run(test) {
+                            Ok(true) => {}
+                            Ok(false) => failed.store(true, Ordering::SeqCst),
+                            Err(e) => {
+                                failed.store(true, Ordering::SeqCst);
+                                // Keeps the exit status the error's code gives.
+                                error.lock().unwrap().get_or_insert(codes::ExitStatus {
+                                    status: codes::exit_status(e.as_ref()),
+                                    message: e.to_string(),
+                                });
+                            }
+                        }
+                    })
+                });
+            }
+        }
+    });
+
+    let counts = counts.into_inner().unwrap();
+    if !counts.is_empty() {
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|(name, count)| format!("{} on {}", count, name))
+            .collect();
+        println!("Distributed tests: {}", counts.join(", "));
+    }
+    runner::check_interrupted()?;
+    match error.into_inner().unwrap() {
+        Some(e) => Err(Box::new(e)),
+        None => Ok(!failed.into_inner()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runner_tags() {
+        let runner = |tags: Option<&[&str]>| Runner {
+            host: "build1".to_string(),
+            sync: false,
+            tags: tags.map(|tags| tags.iter().map(|tag| tag.to_string()).collect()),
+            jobs: 1,
+        };
+        let requires = ["verilator".to_string(), "fpga".to_string()];
+        assert!(can_run(&runner(None), &requires));
+        assert!(can_run(
+            &runner(Some(&["fpga", "verilator", "vcs"])),
+            &requires
+        ));
+        assert!(!can_run(&runner(Some(&["verilator"])), &requires));
+        assert!(can_run(&runner(Some(&[])), &[]));
+
+        let runners = BTreeMap::from([("small".to_string(), runner(Some(&["verilator"])))]);
+        let error = run_tests(
+            &runners,
+            path::Path::new("."),
+            &[("Fpga_tb".to_string(), requires.to_vec())],
+            false,
+            |_| Ok(true),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No runner has the tags of Fpga_tb (requires verilator fpga)"
+        );
+    }
+
+    /// Two runners, big (fpga and verilator, two jobs) and small (verilator only).
+    fn runners() -> BTreeMap<String, Runner> {
+        let runner = |host: &str, tags: &[&str], jobs| Runner {
+            host: host.to_string(),
+            sync: false,
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            jobs,
+        };
+        BTreeMap::from([
+            ("big".to_string(), runner("big1", &["fpga", "verilator"], 2)),
+            ("small".to_string(), runner("small1", &["verilator"], 1)),
+        ])
+    }
+
+    /// Tests named by number, every third needing an FPGA.
+    fn tests(count: usize) -> Vec<(String, Vec<String>)> {
+        (0..count)
+            .map(|test| {
+                let requires = if test % 3 == 0 {
+                    vec!["fpga".to_string()]
+                } else {
+                    Vec::new()
+                };
+                (format!("Test{}_tb", test), requires)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queue_tags() {
+        let tests = tests(12);
+        let hosts = Mutex::new(BTreeMap::new());
+        let passed = run_tests(&runners(), path::Path::new("."), &tests, false, |test| {
+            // Each test runs with its runner's commands running there.
+            let line = executor::current().command_line(&std::process::Command::new("true"));
+            hosts.lock().unwrap().insert(test, line);
+            Ok(true)
+        })
+        .unwrap();
+        assert!(passed);
+        let hosts = hosts.into_inner().unwrap();
+        assert_eq!(hosts.len(), tests.len());
+        for (test, line) in hosts {
+            assert!(
+                line.contains(" big1 ") || line.contains(" small1 "),
+                "{}",
+                line
+            );
+            if test % 3 == 0 {
+                assert!(line.contains(" big1 "), "{} ran {}", tests[test].0, line);
+            }
+        }
+    }
+
+    #[test]
+    fn queue_failures() {
+        let tests = tests(12);
+
+        // Once a test fails, the runners start no more: at most one per job.
+        let started = Mutex::new(0);
+        let passed = run_tests(&runners(), path::Path::new("."), &tests, false, |_| {
+            *started.lock().unwrap() += 1;
+            Ok(false)
+        })
+        .unwrap();
+        assert!(!passed);
+        assert!(*started.lock().unwrap() <= 3);
+
+        // With keep_going every test runs, and an error on any runner keeps its exit status.
+        let started = Mutex::new(0);
+        let error = run_tests(&runners(), path::Path::new("."), &tests, true, |test| {
+            *started.lock().unwrap() += 1;
+            match test {
+                4 => Err(Box::new(codes::error(
+                    "D0101",
+                    "Unable to locate 'bsc' program.",
+                ))),
+                7 => Ok(false),
+                _ => Ok(true),
+            }
+        })
+        .unwrap_err();
+        assert_eq!(*started.lock().unwrap(), tests.len());
+        assert_eq!(error.to_string(), "D0101: Unable to locate 'bsc' program.");
+        assert_eq!(codes::exit_status(error.as_ref()), 8);
+
+        let passed = run_tests(&runners(), path::Path::new("."), &tests, true, |test| {
+            Ok(test != 7)
+        })
+        .unwrap();
+        assert!(!passed);
+    }
+}
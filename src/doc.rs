@@ -0,0 +1,462 @@
+use super::bsv;
+use super::project::Project;
+use regex::Regex;
+use std::{collections::BTreeMap, fs, path};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum DocFormat {
+    #[default]
+    Html,
+    Markdown,
+}
+
+impl DocFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            DocFormat::Html => "html",
+            DocFormat::Markdown => "md",
+        }
+    }
+}
+
+/// A documented declaration of a package: an interface (with its methods) or a module.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Item {
+    pub kind: &'static str, // interface, method or module
+    pub name: String,
+    pub signature: String, // The declaration, on one line
+    pub doc: String,
+    pub members: Vec<Item>, // The methods and subinterfaces of an interface
+}
+
+/// The documentation of one package (source file).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub path: path::PathBuf,
+    pub doc: String,
+    pub imports: Vec<String>,
+    pub items: Vec<Item>,
+}
+
+/// Removes the type parameters (`#(...)`) of a declaration, so that its name is easy to find.
+fn strip_parameters(declaration: &str) -> String {
+    let parameters_re = Regex::new(r"#\s*\([^()]*\)").unwrap();
+    let mut declaration = declaration.to_string();
+    while parameters_re.is_match(&declaration) {
+        declaration = parameters_re.replace_all(&declaration, "").to_string();
+    }
+    declaration
+}
+
+/// Parses the `///` doc comments of a BSV source, which document the package, interface,
+/// method or module declaration that follows them.
+pub fn parse(name: &str, path: &path::Path, source: &str) -> Package {
+    let package_re = Regex::new(r"^package\s+\w+\s*;").unwrap();
+    let interface_re = Regex::new(r"^interface\s+(\w+)").unwrap();
+    let member_re = Regex::new(r"^(method|interface)\s+\w+\s+(\w+)").unwrap();
+    let module_re = Regex::new(r"^module\s+(?:\[\s*\w+\s*\]\s*)?(\w+)").unwrap();
+
+    let mut package = Package {
+        name: name.to_string(),
+        path: path.to_path_buf(),
+        imports: bsv::parse_imports(source),
+        ..Package::default()
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    let mut doc = Vec::<&str>::new();
+    let mut in_interface = false;
+    let mut in_module = false;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index].trim();
+        index += 1;
+        if let Some(text) = line.strip_prefix("///") {
+            doc.push(text.strip_prefix(' ').unwrap_or(text));
+            continue;
+        }
+        if line.starts_with("(*") {
+            continue; // Attributes sit between a doc comment and its declaration
+        }
+
+        // Declarations may span lines up to their semicolon.
+        let mut signature = line.to_string();
+        let declaration = ["interface", "method", "module"]
+            .iter()
+            .any(|keyword| line.starts_with(keyword));
+        if declaration && !in_module {
+            while !signature.contains(';') && index < lines.len() {
+                signature.push(' ');
+                signature.push_str(lines[index].trim());
+                index += 1;
+            }
+        }
+        let signature = signature.trim_end_matches(';').trim().to_string();
+        let item = |kind, name: &str, doc: &[&str]| Item {
+            kind,
+            name: name.to_string(),
+            signature: signature.clone(),
+            doc: doc.join("\n").trim().to_string(),
+            members: Vec::new(),
+        };
+
+        if in_module {
+            in_module = !line.starts_with("endmodule");
+        } else if in_interface {
+            if line.starts_with("endinterface") {
+                in_interface = false;
+            } else if let Some(capture) = member_re.captures(&strip_parameters(line)) {
+                let kind = if &capture[1] == "method" {
+                    "method"
+                } else {
+                    "interface"
+                };
+                let member = item(kind, &capture[2], &doc);
+                if let Some(interface) = package.items.last_mut() {
+                    interface.members.push(member);
+                }
+            }
+        } else if package_re.is_match(line) {
+            package.doc = doc.join("\n").trim().to_string();
+        } else if let Some(capture) = interface_re.captures(line) {
+            package.items.push(item("interface", &capture[1], &doc));
+            in_interface = true;
+        } else if let Some(capture) = module_re.captures(line) {
+            package.items.push(item("module", &capture[1], &doc));
+            in_module = true;
+        }
+        doc.clear();
+    }
+    package
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Builds a page in either format.
+struct Page {
+    format: DocFormat,
+    text: String,
+}
+
+impl Page {
+    fn new(format: DocFormat, title: &str) -> Self {
+        let text = match format {
+            DocFormat::Html => format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+                 <style>\nbody {{ font-family: sans-serif; }}\npre {{ background: #f4f4f4; padding: 4px; }}\n\
+                 </style>\n</head>\n<body>\n",
+                escape(title)
+            ),
+            DocFormat::Markdown => String::new(),
+        };
+        Page { format, text }
+    }
+
+    fn heading(&mut self, level: usize, text: &str, id: Option<&str>) {
+        match self.format {
+            DocFormat::Html => {
+                let id = id.map_or(String::new(), |id| format!(" id=\"{}\"", id));
+                self.text
+                    .push_str(&format!("<h{0}{1}>{2}</h{0}>\n", level, id, escape(text)));
+            }
+            DocFormat::Markdown => {
+                // Markdown renderers derive the anchor from the heading text.
+                self.text
+                    .push_str(&format!("{} {}\n\n", "#".repeat(level), text));
+            }
+        }
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.format {
+            DocFormat::Html => {
+                for paragraph in text.split("\n\n") {
+                    self.text
+                        .push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+                }
+            }
+            DocFormat::Markdown => self.text.push_str(&format!("{}\n\n", text)),
+        }
+    }
+
+    /// A paragraph of already formatted text, such as links.
+    fn formatted(&mut self, text: &str) {
+        match self.format {
+            DocFormat::Html => self.text.push_str(&format!("<p>{}</p>\n", text)),
+            DocFormat::Markdown => self.text.push_str(&format!("{}\n\n", text)),
+        }
+    }
+
+    fn code(&mut self, text: &str) {
+        match self.format {
+            DocFormat::Html => self
+                .text
+                .push_str(&format!("<pre>{}</pre>\n", escape(text))),
+            DocFormat::Markdown => self.text.push_str(&format!("```bsv\n{}\n```\n\n", text)),
+        }
+    }
+
+    /// A link, or plain text if there is nothing to link to.
+    fn link(&self, text: &str, target: Option<String>) -> String {
+        match (self.format, target) {
+            (DocFormat::Html, Some(target)) => {
+                format!("<a href=\"{}\">{}</a>", target, escape(text))
+            }
+            (DocFormat::Html, None) => escape(text),
+            (DocFormat::Markdown, Some(target)) => format!("[{}]({})", text, target),
+            (DocFormat::Markdown, None) => format!("`{}`", text),
+        }
+    }
+
+    fn list(&mut self, entries: &[String]) {
+        if entries.is_empty() {
+            return;
+        }
+        match self.format {
+            DocFormat::Html => {
+                self.text.push_str("<ul>\n");
+                for entry in entries {
+                    self.text.push_str(&format!("<li>{}</li>\n", entry));
+                }
+                self.text.push_str("</ul>\n");
+            }
+            DocFormat::Markdown => {
+                for entry in entries {
+                    self.text.push_str(&format!("- {}\n", entry));
+                }
+                self.text.push('\n');
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == DocFormat::Html {
+            self.text.push_str("</body>\n</html>\n");
+        }
+        self.text
+    }
+}
+
+/// The documentation tree: a page per package and an index, by file name.
+pub fn render(packages: &BTreeMap<String, Package>, format: DocFormat) -> BTreeMap<String, String> {
+    let extension = format.extension();
+    let page_of = |package: &str| {
+        packages
+            .contains_key(package)
+            .then(|| format!("{}.{}", package, extension))
+    };
+    let anchor = |package: &str, item: &str| {
+        let anchor = match format {
+            DocFormat::Html => item.to_string(),
+            DocFormat::Markdown => item.to_lowercase(),
+        };
+        format!("{}.{}#{}", package, extension, anchor)
+    };
+    let mut interfaces = BTreeMap::<&str, &str>::new();
+    let mut importers = BTreeMap::<&str, Vec<&str>>::new();
+    for package in packages.values() {
+        for item in package.items.iter().filter(|item| item.kind == "interface") {
+            interfaces.insert(&item.name, &package.name);
+        }
+        for import in &package.imports {
+            importers.entry(import).or_default().push(&package.name);
+        }
+    }
+
+    let mut pages = BTreeMap::<String, String>::new();
+    let mut index = Page::new(format, "Packages");
+    index.heading(1, "Packages", None);
+    let entries: Vec<String> = packages
+        .values()
+        .map(|package| {
+            let summary = package.doc.lines().next().unwrap_or_default();
+            let link = index.link(&package.name, page_of(&package.name));
+            match (summary.is_empty(), format) {
+                (true, _) => link,
+                (false, DocFormat::Html) => format!("{}: {}", link, escape(summary)),
+                (false, DocFormat::Markdown) => format!("{}: {}", link, summary),
+            }
+        })
+        .collect();
+    index.list(&entries);
+    pages.insert(format!("index.{}", extension), index.finish());
+
+    for package in packages.values() {
+        let mut page = Page::new(format, &package.name);
+        page.heading(1, &format!("Package {}", package.name), None);
+        page.paragraph(&package.doc);
+        let index = page.link("All packages", Some(format!("index.{}", extension)));
+        page.formatted(&index);
+        if !package.imports.is_empty() {
+            page.heading(2, "Imports", None);
+            let imports: Vec<String> = package
+                .imports
+                .iter()
+                .map(|import| page.link(import, page_of(import)))
+                .collect();
+            page.list(&imports);
+        }
+        if let Some(importers) = importers.get(package.name.as_str()) {
+            page.heading(2, "Imported by", None);
+            let importers: Vec<String> = importers
+                .iter()
+                .map(|importer| page.link(importer, page_of(importer)))
+                .collect();
+            page.list(&importers);
+        }
+        for (kind, heading) in [("interface", "Interfaces"), ("module", "Modules")] {
+            let items: Vec<&Item> = package
+                .items
+                .iter()
+                .filter(|item| item.kind == kind)
+                .collect();
+            if items.is_empty() {
+                continue;
+            }
+            page.heading(2, heading, None);
+            for item in items {
+                page.heading(3, &item.name, Some(&item.name));
+                page.code(&item.signature);
+                page.paragraph(&item.doc);
+                if kind == "module" {
+                    let interface = bsv::parse_modules(&format!("{};", item.signature))
+                        .into_iter()
+                        .next()
+                        .map(|module| module.interface);
+                    if let Some(interface) = interface {
+                        let target = interfaces
+                            .get(interface.as_str())
+                            .map(|package| anchor(package, &interface));
+                        let provides = format!("Provides {}", page.link(&interface, target));
+                        page.formatted(&provides);
+                    }
+                }
+                for member in &item.members {
+                    page.code(&member.signature);
+                    page.paragraph(&member.doc);
+                }
+            }
+        }
+        pages.insert(format!("{}.{}", package.name, extension), page.finish());
+    }
+    pages
+}
+
+/// Implements `dolly doc`: documents the packages of the given module directories (leaving
+/// out the testbenches) under target/doc.
+pub fn generate<'a>(
+    project: &Project,
+    module_paths: impl Iterator<Item = &'a path::PathBuf>,
+    format: DocFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut packages = BTreeMap::<String, Package>::new();
+    for directory in module_paths {
+        let mut sources: Vec<path::PathBuf> = directory
+            .read_dir()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "bsv"))
+            .filter(|path| !project.is_unit_test(path))
+            .collect();
+        sources.sort();
+        for source in sources {
+            let name = source.file_stem().unwrap().to_string_lossy().to_string();
+            if packages.contains_key(&name) {
+                continue;
+            }
+            let package = parse(&name, &source, &fs::read_to_string(&source)?);
+            packages.insert(name, package);
+        }
+    }
+
+    let doc_dir = project.output_dir("doc");
+    fs::create_dir_all(&doc_dir)?;
+    for (file, text) in render(&packages, format) {
+        fs::write(doc_dir.join(file), text)?;
+    }
+    println!(
+        "Documented {} packages in {}",
+        packages.len(),
+        doc_dir
+            .join(format!("index.{}", format.extension()))
+            .to_string_lossy()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = r#"/// A saturating counter.
+///
+/// Counts up to its limit.
+package Counter;
+
+import FIFO::*;
+
+/// The counter interface.
+interface Counter#(numeric type n);
+    /// Adds to the count.
+    method Action increment(Bit#(n) amount,
+                            Bool wrap);
+    method Bit#(n) value;
+endinterface
+
+/// A counter that starts at zero.
+(* synthesize *)
+module mkCounter(Counter#(8));
+    /// Not documented: methods of modules are definitions.
+    method Bit#(8) value = 0;
+endmodule
+
+endpackage
+"#;
+
+    #[test]
+    fn documented_package() {
+        let package = parse("Counter", path::Path::new("src/Counter.bsv"), SOURCE);
+        assert_eq!(
+            package.doc,
+            "A saturating counter.\n\nCounts up to its limit."
+        );
+        assert_eq!(package.imports, ["FIFO"]);
+        assert_eq!(package.items.len(), 2);
+        let interface = &package.items[0];
+        assert_eq!(interface.signature, "interface Counter#(numeric type n)");
+        assert_eq!(interface.members.len(), 2);
+        assert_eq!(interface.members[0].name, "increment");
+        assert_eq!(
+            interface.members[0].signature,
+            "method Action increment(Bit#(n) amount, Bool wrap)"
+        );
+        assert_eq!(interface.members[0].doc, "Adds to the count.");
+        assert_eq!(interface.members[1].doc, "");
+        assert_eq!(package.items[1].name, "mkCounter");
+        assert_eq!(package.items[1].doc, "A counter that starts at zero.");
+
+        let top = parse(
+            "Top",
+            path::Path::new("src/Top.bsv"),
+            "import Counter::*;\nmodule mkTop(Empty);\nendmodule\n",
+        );
+        let packages = BTreeMap::from([("Counter".to_string(), package), ("Top".to_string(), top)]);
+        let pages = render(&packages, DocFormat::Markdown);
+        assert!(pages["index.md"].contains("- [Counter](Counter.md): A saturating counter.\n"));
+        let counter = &pages["Counter.md"];
+        assert!(counter.contains("## Imports\n\n- `FIFO`\n"));
+        assert!(counter.contains("## Imported by\n\n- [Top](Top.md)\n"));
+        assert!(counter.contains("Provides [Counter](Counter.md#counter)"));
+        assert!(pages["Top.md"].contains("- [Counter](Counter.md)\n"));
+
+        let pages = render(&packages, DocFormat::Html);
+        assert!(pages["Counter.html"].contains("<h3 id=\"mkCounter\">mkCounter</h3>"));
+        assert!(pages["Counter.html"].contains("<a href=\"Counter.html#Counter\">Counter</a>"));
+    }
+}
@@ -0,0 +1,288 @@
+//! A minimal little-endian ELF loader, enough to turn RISC-V executables into memory images.
+use std::{collections::BTreeMap, fmt::Write, fs, path};
+
+/// A loadable segment, with its uninitialized (.bss) portion zero filled.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub address: u64, // Physical load address
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Elf {
+    pub segments: Vec<Segment>, // Sorted by address
+}
+
+fn read(bytes: &[u8], offset: usize, size: usize) -> Result<u64, Box<dyn std::error::Error>> {
+    let field = offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| std::io::Error::other("Truncated ELF file"))?;
+    Ok(field
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | *byte as u64))
+}
+
+impl Elf {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if bytes.len() < 16 || &bytes[..4] != b"\x7fELF" {
+            return Err(Box::new(std::io::Error::other("Not an ELF file")));
+        }
+        if bytes[5] != 1 {
+            return Err(Box::new(std::io::Error::other(
+                "Only little-endian ELF files are supported",
+            )));
+        }
+
+        // Field offsets and sizes differ between ELF32 and ELF64.
+        let is_64 = match bytes[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(Box::new(std::io::Error::other("Unknown ELF class"))),
+        };
+        let word = if is_64 { 8 } else { 4 };
+        let phoff = read(bytes, 24 + word, word)? as usize;
+        let header_end = 24 + 3 * word + 4;
+        let phentsize = read(bytes, header_end + 2, 2)? as usize;
+        let phnum = read(bytes, header_end + 4, 2)? as usize;
+
+        const PT_LOAD: u64 = 1;
+        let mut segments = Vec::<Segment>::new();
+        for index in 0..phnum {
+            let header = phoff
+                .checked_add(index * phentsize)
+                .ok_or_else(|| std::io::Error::other("Malformed ELF program header offset"))?;
+            if read(bytes, header, 4)? != PT_LOAD {
+                continue;
+            }
+
+            let (offset, paddr, filesz, memsz) = if is_64 {
+                (
+                    read(bytes, header + 8, 8)?,
+                    read(bytes, header + 24, 8)?,
+                    read(bytes, header + 32, 8)?,
+                    read(bytes, header + 40, 8)?,
+                )
+            } else {
+                (
+                    read(bytes, header + 4, 4)?,
+                    read(bytes, header + 12, 4)?,
+                    read(bytes, header + 16, 4)?,
+                    read(bytes, header + 20, 4)?,
+                )
+            };
+            if memsz == 0 {
+                continue;
+            }
+
+            let end = offset
+                .checked_add(filesz)
+                .ok_or_else(|| std::io::Error::other("Malformed ELF segment size"))?;
+            let mut data = bytes
+                .get(offset as usize..end as usize)
+                .ok_or_else(|| std::io::Error::other("Truncated ELF segment"))?
+                .to_vec();
+            data.resize(memsz as usize, 0);
+            segments.push(Segment {
+                address: paddr,
+                data,
+            });
+        }
+        segments.sort_by_key(|segment| segment.address);
+
+        Ok(Self { segments })
+    }
+
+    pub fn load(path: &path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Writes a `$readmemh` image of 32-bit little-endian words whose addresses are word
+    /// indices relative to `base`.
+    pub fn to_hex(&self, base: u64) -> Result<String, Box<dyn std::error::Error>> {
+        let layout = ImageLayout {
+            base: Some(base),
+            ..ImageLayout::default()
+        };
+        Ok(self.to_images(&layout)?.remove(0))
+    }
+
+    /// Writes one `$readmemh` image per bank of little-endian words; word `n` of the memory
+    /// goes to bank `n % banks` at address `n / banks`.
+    pub fn to_images(
+        &self,
+        layout: &ImageLayout,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        if layout.word_bytes == 0 || layout.banks == 0 {
+            return Err(Box::new(std::io::Error::other(
+                "Image word width and bank count must be non-zero",
+            )));
+        }
+        let base = layout
+            .base
+            .unwrap_or_else(|| self.segments.first().map_or(0, |segment| segment.address));
+        let word_bytes = layout.word_bytes as u64;
+
+        let mut words = BTreeMap::<u64, Vec<u8>>::new();
+        for segment in &self.segments {
+            if segment.address < base {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Segment at {:#x} is below the image base {:#x}",
+                    segment.address, base
+                ))));
+            }
+            for (offset, byte) in segment.data.iter().enumerate() {
+                let address = segment.address - base + offset as u64;
+                let word = words
+                    .entry(address / word_bytes)
+                    .or_insert_with(|| vec![0; layout.word_bytes]);
+                word[(address % word_bytes) as usize] = *byte;
+            }
+        }
+
+        let banks = layout.banks as u64;
+        let mut images = vec![String::new(); layout.banks];
+        let mut next_address = vec![None::<u64>; layout.banks];
+        for (word, bytes) in words {
+            let bank = (word % banks) as usize;
+            let address = word / banks;
+            if next_address[bank] != Some(address) {
+                writeln!(images[bank], "@{:x}", address).unwrap();
+            }
+            next_address[bank] = Some(address + 1);
+
+            let value: String = bytes
+                .iter()
+                .rev()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            writeln!(images[bank], "{}", value).unwrap();
+        }
+        Ok(images)
+    }
+
+    /// Writes the images for `layout` to `output`, or to `<stem>_<bank>.<ext>` files when
+    /// banked, returning the files written.
+    pub fn write_images(
+        &self,
+        layout: &ImageLayout,
+        output: &path::Path,
+    ) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+        let images = self.to_images(layout)?;
+        let files: Vec<path::PathBuf> = if images.len() == 1 {
+            vec![output.to_path_buf()]
+        } else {
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = output.extension().unwrap_or_default().to_string_lossy();
+            (0..images.len())
+                .map(|bank| output.with_file_name(format!("{}_{}.{}", stem, bank, extension)))
+                .collect()
+        };
+
+        for (file, image) in files.iter().zip(images) {
+            fs::write(file, image)?;
+        }
+        Ok(files)
+    }
+}
+
+/// How an ELF is laid out in memory images.
+#[derive(Clone, Debug)]
+pub struct ImageLayout {
+    pub base: Option<u64>, // Address of the first word (the lowest segment address if None)
+    pub word_bytes: usize,
+    pub banks: usize,
+}
+
+impl Default for ImageLayout {
+    fn default() -> Self {
+        Self {
+            base: None,
+            word_bytes: 4,
+            banks: 1,
+        }
+    }
+}
+
+/// Parses a decimal or 0x-prefixed hexadecimal address.
+pub fn parse_address(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.replace('_', "").parse(),
+    };
+    parsed.map_err(|e| format!("invalid address '{}': {}", text, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds an ELF32 file with a single loadable segment.
+    fn elf32(address: u32, data: &[u8], memsz: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; 52 + 32];
+        bytes[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        bytes[28..32].copy_from_slice(&52u32.to_le_bytes()); // e_phoff
+        bytes[42..44].copy_from_slice(&32u16.to_le_bytes()); // e_phentsize
+        bytes[44..46].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+
+        let header = 52;
+        bytes[header..header + 4].copy_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        bytes[header + 4..header + 8].copy_from_slice(&84u32.to_le_bytes()); // p_offset
+        bytes[header + 12..header + 16].copy_from_slice(&address.to_le_bytes()); // p_paddr
+        bytes[header + 16..header + 20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes[header + 20..header + 24].copy_from_slice(&memsz.to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn banked_images() {
+        let elf = Elf::parse(&elf32(0x1000, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06], 6)).unwrap();
+        let layout = ImageLayout {
+            base: None,
+            word_bytes: 2,
+            banks: 2,
+        };
+        assert_eq!(
+            elf.to_images(&layout).unwrap(),
+            vec!["@0\n0201\n0605\n", "@0\n0403\n"]
+        );
+        assert_eq!(parse_address("0x8000_0000"), Ok(0x8000_0000));
+    }
+
+    #[test]
+    fn elf32_to_hex() {
+        let elf = Elf::parse(&elf32(
+            0x8000_0004,
+            &[0x13, 0x00, 0x00, 0x00, 0x6f, 0x00],
+            12,
+        ))
+        .unwrap();
+        assert_eq!(elf.segments[0].data.len(), 12);
+
+        assert_eq!(
+            elf.to_hex(0x8000_0000).unwrap(),
+            "@1\n00000013\n0000006f\n00000000\n"
+        );
+    }
+
+    #[test]
+    fn malformed_elf64_segment() {
+        let mut bytes = vec![0u8; 64 + 56];
+        bytes[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        bytes[32..40].copy_from_slice(&64u64.to_le_bytes()); // e_phoff
+        bytes[54..56].copy_from_slice(&56u16.to_le_bytes()); // e_phentsize
+        bytes[56..58].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+        bytes[64..68].copy_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        bytes[72..80].copy_from_slice(&(u64::MAX - 1).to_le_bytes()); // p_offset
+        bytes[96..104].copy_from_slice(&16u64.to_le_bytes()); // p_filesz
+        bytes[104..112].copy_from_slice(&16u64.to_le_bytes()); // p_memsz
+        let error = Elf::parse(&bytes).unwrap_err();
+        assert_eq!(error.to_string(), "Malformed ELF segment size");
+
+        bytes[72..80].copy_from_slice(&120u64.to_le_bytes());
+        let error = Elf::parse(&bytes).unwrap_err();
+        assert_eq!(error.to_string(), "Truncated ELF segment");
+    }
+}
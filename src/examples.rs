@@ -0,0 +1,102 @@
+//! Examples: standalone BSV programs in examples/ showing how a package is used.  They
+//! aren't part of `dolly build` or `dolly test`, and are built (and run) one at a time with
+//! `dolly build --example <name>`, against the project's modules and dependencies.
+use super::bsv;
+use super::builder::Builder;
+use super::project::Project;
+use super::suggest;
+use regex::Regex;
+use std::{fs, path, process};
+
+/// The examples of a project, by name (the file stem), with their sources.
+pub fn examples(project: &Project) -> Vec<(String, path::PathBuf)> {
+    let mut examples: Vec<(String, path::PathBuf)> = project
+        .root_path()
+        .join("examples")
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|source| source.is_file() && source.extension().is_some_and(|ext| ext == "bsv"))
+        .filter_map(|source| Some((source.file_stem()?.to_str()?.to_string(), source)))
+        .collect();
+    examples.sort();
+    examples
+}
+
+/// The module an example elaborates: that of its //!topmodule directive, or mk<name>.
+fn top_module(name: &str, source: &str) -> String {
+    let re = Regex::new(&format!(r"{}topmodule\s+(\w+)", bsv::DIRECTIVE)).unwrap();
+    re.captures(source)
+        .map(|capture| capture[1].to_string())
+        .unwrap_or_else(|| format!("mk{}", name))
+}
+
+/// Builds the named example into target/examples/<name>/, then with `run` runs it from the
+/// project's root.
+pub fn build(
+    project: &Project,
+    builder: &Builder,
+    name: &str,
+    run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let examples = examples(project);
+    let Some((_, source)) = examples.iter().find(|(example, _)| example == name) else {
+        let names: Vec<&str> = examples
+            .iter()
+            .map(|(example, _)| example.as_str())
+            .collect();
+        return Err(Box::new(std::io::Error::other(if names.is_empty() {
+            format!("No example named '{}': there's no examples/*.bsv", name)
+        } else {
+            format!(
+                "No example named '{}'{}; the examples are {}",
+                name,
+                suggest::did_you_mean(&suggest::similar(name, names.iter().copied())),
+                names.join(", ")
+            )
+        })));
+    };
+
+    let top_module = top_module(name, &fs::read_to_string(source)?);
+    let build_root = project.output_dir("examples");
+    let executable = Builder::build_executable(project, builder, &build_root, source, &top_module)?;
+    if !run || builder.options().dry_run {
+        return Ok(());
+    }
+
+    let status = process::Command::new(&executable)
+        .current_dir(project.root_path())
+        .status()
+        .map_err(|e| {
+            std::io::Error::other(format!(
+                "Unable to run {}: {}",
+                executable.to_string_lossy(),
+                e
+            ))
+        })?;
+    if !status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "The example {} failed ({})",
+            name, status
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn example_top_module() {
+        assert_eq!(
+            top_module("FifoDemo", "module mkFifoDemo(Empty);\nendmodule\n"),
+            "mkFifoDemo"
+        );
+        assert_eq!(
+            top_module("FifoDemo", "//!topmodule mkDemo\nmodule mkDemo(Empty);\n"),
+            "mkDemo"
+        );
+    }
+}
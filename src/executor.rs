@@ -0,0 +1,364 @@
+//! Where the builder runs bsc, Verilator and the simulations: on this machine, or on a build
+//! server over SSH ([remote] in the configuration files).
+use super::config::{self, Remote};
+use super::runner;
+use std::{cell::RefCell, io, path, process, sync::Arc, sync::Mutex, sync::OnceLock};
+use tracing::trace;
+
+/// Runs the builder's commands.
+pub trait Executor: Send + Sync {
+    /// Runs a command for the named target as `runner::run` does, streaming its output and
+    /// logging it to `log_file`.
+    fn run(
+        &self,
+        cmd: &mut process::Command,
+        name: &str,
+        log_file: &path::Path,
+    ) -> io::Result<process::Output>;
+
+    /// Runs a command, capturing its output as `Command::output` does.
+    fn output(&self, cmd: &mut process::Command) -> io::Result<process::Output>;
+
+    /// The command line that runs a command, as --show-commands prints it.
+    fn command_line(&self, cmd: &process::Command) -> String;
+}
+
+/// Runs commands on this machine.
+pub struct Local;
+
+impl Executor for Local {
+    fn run(
+        &self,
+        cmd: &mut process::Command,
+        name: &str,
+        log_file: &path::Path,
+    ) -> io::Result<process::Output> {
+        runner::run(cmd, name, log_file)
+    }
+
+    fn output(&self, cmd: &mut process::Command) -> io::Result<process::Output> {
+        cmd.output()
+    }
+
+    fn command_line(&self, cmd: &process::Command) -> String {
+        runner::command_line(cmd)
+    }
+}
+
+/// Runs commands on another machine with ssh, in the same directories: either a shared file
+/// system, or a copy of the project kept up to date with rsync (`sync`).
+pub struct Ssh {
+    host: String,
+    sync: Option<path::PathBuf>, // The project root to copy to the host, if any
+    synced: Mutex<bool>,         // Whether the project's sources have been copied yet
+    ssh: String,                 // The ssh and rsync programs (see `config::tool`)
+    rsync: String,
+}
+
+/// The remote shell command running a command, in the directory dolly runs in.
+fn remote_command_line(cwd: &path::Path, cmd: &process::Command) -> String {
+    format!(
+        "cd {} && {}",
+        runner::quote(cwd.as_os_str()),
+        runner::command_line(cmd)
+    )
+}
+
+impl Ssh {
+    /// Runs commands on a host, first copying the project at `sync` there when given.
+    pub fn new(host: &str, sync: Option<&path::Path>) -> Self {
+        Self {
+            host: host.to_string(),
+            sync: sync.map(|root| root.canonicalize().unwrap_or_else(|_| root.to_path_buf())),
+            synced: Mutex::new(false),
+            ssh: config::tool("ssh"),
+            rsync: config::tool("rsync"),
+        }
+    }
+
+    /// ssh running a command on the host.
+    fn ssh(&self, cmd: &process::Command) -> io::Result<process::Command> {
+        let mut ssh = process::Command::new(&self.ssh);
+        ssh.arg("-o")
+            .arg("BatchMode=yes")
+            .arg(&self.host)
+            .arg(remote_command_line(&std::env::current_dir()?, cmd));
+        Ok(ssh)
+    }
+
+    /// Runs rsync, failing if it does.
+    fn rsync(&self, args: &[std::ffi::OsString]) -> io::Result<()> {
+        runner::check_interrupted()?;
+        let mut rsync = process::Command::new(&self.rsync);
+        rsync.arg("-az").args(args);
+        trace!("{}", runner::command_line(&rsync));
+        let output = rsync.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "rsync to {} failed: {}",
+                self.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// A directory on the host, for rsync.
+    fn remote(&self, dir: &path::Path) -> std::ffi::OsString {
+        let mut remote = std::ffi::OsString::from(format!("{}:", self.host));
+        remote.push(dir);
+        remote.push("/");
+        remote
+    }
+
+    /// The build products a command reads: the directories of target/ it runs in, logs to
+    /// (the target's build directory, holding its run directory) and names in its arguments
+    /// or their path lists (such as bsc's -p), a file standing for the directory holding it.
+    fn inputs(
+        root: &path::Path,
+        cmd: &process::Command,
+        log_file: Option<&path::Path>,
+    ) -> Vec<path::PathBuf> {
+        let target_dir = root.join("target");
+        let mut paths: Vec<path::PathBuf> = cmd
+            .get_current_dir()
+            .into_iter()
+            .chain(log_file.and_then(path::Path::parent))
+            .map(path::Path::to_path_buf)
+            .collect();
+        for arg in cmd.get_args() {
+            paths.extend(arg.to_string_lossy().split(':').map(path::PathBuf::from));
+        }
+        let mut dirs: Vec<path::PathBuf> = paths
+            .into_iter()
+            .filter_map(|path| {
+                let path = std::path::absolute(path).ok()?;
+                let dir = if path.is_file() {
+                    path.parent()?.to_path_buf()
+                } else {
+                    path
+                };
+                (dir.is_dir() && dir.starts_with(&target_dir)).then_some(dir)
+            })
+            .collect();
+        // Sorted, a directory comes just before those inside it, which it already copies.
+        dirs.sort();
+        let mut inputs = Vec::<path::PathBuf>::new();
+        for dir in dirs {
+            if !inputs.last().is_some_and(|input| dir.starts_with(input)) {
+                inputs.push(dir);
+            }
+        }
+        inputs
+    }
+
+    /// Copies what a command needs to the host: the project's sources (and generated
+    /// sources), once per run of dolly, then the build products it reads (see `inputs`),
+    /// leaving any the host has newer copies of.
+    fn push(&self, cmd: &process::Command, log_file: Option<&path::Path>) -> io::Result<()> {
+        let Some(root) = &self.sync else {
+            return Ok(());
+        };
+        {
+            let mut synced = self.synced.lock().unwrap();
+            if !*synced {
+                let mut mkdir = std::ffi::OsString::from("--rsync-path=mkdir -p ");
+                mkdir.push(runner::quote(root.as_os_str()));
+                mkdir.push(" && rsync");
+                let mut source = root.clone().into_os_string();
+                source.push("/");
+                self.rsync(&[
+                    mkdir,
+                    "--filter=+ /target/generated/***".into(),
+                    "--filter=- /target/*".into(),
+                    source,
+                    self.remote(root),
+                ])?;
+                *synced = true;
+            }
+        }
+        let inputs = Self::inputs(root, cmd, log_file);
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let mut args: Vec<std::ffi::OsString> = vec!["--relative".into(), "--update".into()];
+        for input in inputs {
+            // With --relative, the path after /./ is made below the project root on the host.
+            let mut source = root.join(".").into_os_string();
+            source.push("/");
+            source.push(input.strip_prefix(root).unwrap_or(&input));
+            args.push(source);
+        }
+        args.push(self.remote(root));
+        self.rsync(&args)
+    }
+
+    /// Copies a directory a command wrote to (its log's) back from the host.
+    fn pull(&self, dir: &path::Path) -> io::Result<()> {
+        if self.sync.is_none() {
+            return Ok(());
+        }
+        let dir = std::path::absolute(dir)?;
+        let mut local = dir.clone().into_os_string();
+        local.push("/");
+        self.rsync(&[self.remote(&dir), local])
+    }
+}
+
+impl Executor for Ssh {
+    fn run(
+        &self,
+        cmd: &mut process::Command,
+        name: &str,
+        log_file: &path::Path,
+    ) -> io::Result<process::Output> {
+        self.push(cmd, Some(log_file))?;
+        let output = runner::run(&mut self.ssh(cmd)?, name, log_file)?;
+        if let Some(dir) = log_file.parent() {
+            self.pull(dir)?;
+        }
+        Ok(output)
+    }
+
+    fn output(&self, cmd: &mut process::Command) -> io::Result<process::Output> {
+        self.push(cmd, None)?;
+        runner::check_interrupted()?;
+        self.ssh(cmd)?.output()
+    }
+
+    fn command_line(&self, cmd: &process::Command) -> String {
+        match self.ssh(cmd) {
+            Ok(ssh) => runner::command_line(&ssh),
+            Err(_) => runner::command_line(cmd),
+        }
+    }
+}
+
+static EXECUTOR: OnceLock<Arc<dyn Executor>> = OnceLock::new();
+
+thread_local! {
+    /// Where the commands of this thread run instead, if somewhere else (see `with`).
+    static THREAD_EXECUTOR: RefCell<Option<Arc<dyn Executor>>> = const { RefCell::new(None) };
+}
+
+/// Chooses where commands run, once per process.
+pub fn install(remote: Option<&Remote>, root: Option<&path::Path>) {
+    let executor: Arc<dyn Executor> = match remote {
+        Some(remote) => Arc::new(Ssh::new(&remote.host, root.filter(|_| remote.sync))),
+        None => Arc::new(Local),
+    };
+    let _ = EXECUTOR.set(executor);
+}
+
+/// Runs `step` with the builder's commands on this thread running on `executor`.
+pub fn with<T>(executor: Arc<dyn Executor>, step: impl FnOnce() -> T) -> T {
+    let previous = THREAD_EXECUTOR.replace(Some(executor));
+    let result = step();
+    THREAD_EXECUTOR.set(previous);
+    result
+}
+
+/// Where the builder's commands run.
+pub fn current() -> Arc<dyn Executor> {
+    THREAD_EXECUTOR
+        .with_borrow(Option::clone)
+        .or_else(|| EXECUTOR.get().cloned())
+        .unwrap_or_else(|| Arc::new(Local))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remote_commands() {
+        let mut cmd = process::Command::new("bsc");
+        cmd.arg("-p")
+            .arg("%/Libraries:src")
+            .current_dir("target/My Test")
+            .env("BLUESPECDIR", "/opt/bsc/lib");
+        assert_eq!(
+            remote_command_line(path::Path::new("/home/ada/uart"), &cmd),
+            "cd /home/ada/uart && cd 'target/My Test' && BLUESPECDIR=/opt/bsc/lib bsc -p \
+             %/Libraries:src"
+        );
+
+        let ssh = Ssh::new("ada@build1", None);
+        assert_eq!(
+            ssh.remote(path::Path::new("/home/ada/uart/target")),
+            "ada@build1:/home/ada/uart/target/"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn synced_inputs() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+        let temp = tempfile::tempdir().unwrap();
+        let temp = temp.path().canonicalize().unwrap();
+        let root = temp.join("uart");
+        let transfers = temp.join("transfers");
+        let script = |name: &str, body: String| {
+            let file = temp.join(name);
+            fs::write(&file, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+            file.to_string_lossy().into_owned()
+        };
+        // ssh runs the command here, and rsync only records what it was asked to copy.
+        let ssh = Ssh {
+            ssh: script("ssh", r#"shift 3; exec sh -c "$1""#.to_string()),
+            rsync: script("rsync", format!(r#"echo "$@" >> {}"#, transfers.display())),
+            ..Ssh::new("build1", Some(&root))
+        };
+
+        let build_path = root.join("target").join("Adder_tb");
+        let run_dir = build_path.join("run");
+        let bdir = root.join("target").join("lib");
+        for dir in [
+            &root.join("src"),
+            &run_dir,
+            &bdir,
+            &root.join("target").join("Other_tb"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(build_path.join("Adder_tb"), "").unwrap();
+        fs::write(run_dir.join("firmware.hex"), "").unwrap();
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(r#"test -f "$0" && test -f firmware.hex"#)
+            .arg(build_path.join("Adder_tb"))
+            .arg("-p")
+            .arg(format!(
+                "{}:{}:%/Libraries",
+                root.join("src").display(),
+                bdir.display()
+            ))
+            .current_dir(&run_dir);
+        let log_file = build_path.join("run.log");
+        for _ in 0..2 {
+            assert!(ssh
+                .run(&mut cmd, "Adder_tb", &log_file)
+                .unwrap()
+                .status
+                .success());
+        }
+
+        let root = root.display();
+        let sources = format!(
+            "-az --rsync-path=mkdir -p {root} && rsync --filter=+ /target/generated/*** \
+             --filter=- /target/* {root}/ build1:{root}/"
+        );
+        let inputs = format!(
+            "-az --relative --update {root}/./target/Adder_tb {root}/./target/lib build1:{root}/"
+        );
+        let outputs = format!("-az build1:{root}/target/Adder_tb/ {root}/target/Adder_tb/");
+        assert_eq!(
+            fs::read_to_string(&transfers)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            [&sources, &inputs, &outputs, &inputs, &outputs]
+        );
+    }
+}
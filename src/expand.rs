@@ -0,0 +1,205 @@
+//! `dolly expand`: a module's design as bsc elaborated and scheduled it, from the dump of its
+//! ATS (the rules and methods after elaboration, with their predicates and actions in terms
+//! of bsc's own signals) that `bsc -dATS` writes, along with the logical execution order of
+//! its schedule.
+use super::bsv;
+use super::builder::Builder;
+use super::project::Project;
+use super::schedule;
+use regex::Regex;
+use std::fs;
+
+/// A rule or method of the dump.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub kind: &'static str, // "rule" or "method"
+    pub name: String,       // The rule's name in the source (without RL_), or the method's
+    pub lines: Vec<String>,
+}
+
+/// Parses the rules (in the "rules" section) and methods (in the "interface" section, each
+/// from its --AI<kind> line) of an ATS dump.
+pub fn parse(dump: &str) -> Vec<Entry> {
+    let rule_re = Regex::new(r#"^rule\s+(\S+?)(?:\s+"([^"]*)")?\s*:"#).unwrap();
+    let method_re = Regex::new(r"^--AI\w*\s+(\w+)").unwrap();
+    let mut entries = Vec::<Entry>::new();
+    let mut section = "";
+    let mut open = false;
+    for line in dump.lines() {
+        if let Some(title) = line.strip_prefix("-- AP") {
+            section = title.trim_start_matches("ackage").trim();
+            open = false;
+        } else if let (true, Some(rule)) = (section == "rules", rule_re.captures(line)) {
+            let name = match rule.get(2) {
+                Some(name) => name.as_str(),
+                None => rule[1].strip_prefix("RL_").unwrap_or(&rule[1]),
+            };
+            entries.push(Entry {
+                kind: "rule",
+                name: name.to_string(),
+                lines: Vec::new(),
+            });
+            open = true;
+        } else if let (true, Some(method)) =
+            (section.contains("interface"), method_re.captures(line))
+        {
+            entries.push(Entry {
+                kind: "method",
+                name: method[1].to_string(),
+                lines: Vec::new(),
+            });
+            open = true;
+        } else if open && !matches!(line.trim(), "" | "[]") {
+            entries
+                .last_mut()
+                .unwrap()
+                .lines
+                .push(line.trim().to_string());
+        }
+    }
+    entries
+}
+
+/// An entry, its actions (`==> { a; b; }`) one per line.
+fn pretty(entry: &Entry) -> String {
+    let mut text = format!("{} {}\n", entry.kind, entry.name);
+    for line in &entry.lines {
+        let Some((before, actions)) = line.split_once("==>") else {
+            text.push_str(&format!("  {}\n", line));
+            continue;
+        };
+        if !before.trim().is_empty() {
+            text.push_str(&format!("  {}\n", before.trim()));
+        }
+        text.push_str("  ==>\n");
+        let actions = actions.trim().trim_start_matches('{').trim_end_matches('}');
+        for action in actions.split(';').map(str::trim).filter(|a| !a.is_empty()) {
+            text.push_str(&format!("    {}\n", action));
+        }
+    }
+    text
+}
+
+/// Implements `dolly expand`: elaborates a module (`mkName` or `Name`) and prints its rules and
+/// methods (only those named by `names`, if any), or with `raw` the whole dump.
+pub fn run(
+    project: &Project,
+    module: &str,
+    names: &[String],
+    raw: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+    let Some(location) = bsv::find_module(builder.module_paths(), module) else {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Module '{}' not found",
+            module
+        ))));
+    };
+    let module = &location.module.name;
+
+    // A new directory, so that bsc (with -u) elaborates the module again.
+    let build_path = project.output_dir("expand").join(module);
+    if build_path.exists() {
+        fs::remove_dir_all(&build_path)?;
+    }
+    let dump_file = build_path.join(format!("{}.ats", module));
+    let dump_flag = format!("-dATS={}", dump_file.to_string_lossy());
+    builder.generate_verilog(
+        project,
+        &[dump_flag.into()],
+        &location.path,
+        module,
+        &build_path,
+    )?;
+    let dump = fs::read_to_string(&dump_file).map_err(|e| {
+        std::io::Error::other(format!(
+            "bsc wrote no dump of {} ({}): {}",
+            module,
+            dump_file.to_string_lossy(),
+            e
+        ))
+    })?;
+    if raw {
+        print!("{}", dump);
+        return Ok(());
+    }
+
+    let entries: Vec<Entry> = parse(&dump)
+        .into_iter()
+        .filter(|entry| names.is_empty() || names.contains(&entry.name))
+        .collect();
+    if entries.is_empty() && !names.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} has no rule or method named {}",
+            module,
+            names.join(", ")
+        ))));
+    }
+    let relative = dump_file
+        .strip_prefix(project.root_path())
+        .unwrap_or(&dump_file);
+    println!("{} ({})", module, relative.to_string_lossy());
+    if let Some(schedule) = fs::read_to_string(build_path.join(format!("{}.sched", module)))
+        .ok()
+        .and_then(|text| schedule::parse(&text))
+    {
+        println!("order: {}", schedule.order.join(", "));
+    }
+    for entry in entries {
+        println!();
+        print!("{}", pretty(&entry));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DUMP: &str = r#"APackage mkGCD
+-- APackage parameters
+[]
+-- AP state elements
+x :: ABSTRACT:  Prelude.VReg = RegN
+-- AP rules
+rule RL_swap "swap":
+ when x_read__h138 ULT y_read__h139 && ! (y_read__h139 == 0)
+  ==> { x.write y_read__h139; y.write x_read__h138; }
+[]
+rule RL_subtract "subtract":
+ when ! (x_read__h138 ULT y_read__h139) && ! (y_read__h139 == 0)
+  ==> { y.write y_read__h139 - x_read__h138; }
+[]
+-- AP scheduling pragmas
+[]
+-- AP interface
+-- AP  apkg_interface def mkGCD
+--AIDef result
+result :: Bit 32;
+result  = x_read__h138;
+-- AP instance comments
+"#;
+
+    #[test]
+    fn expanded_design() {
+        let entries = parse(DUMP);
+        let names: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.kind, entry.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("rule", "swap"), ("rule", "subtract"), ("method", "result")]
+        );
+        assert_eq!(
+            pretty(&entries[0]),
+            "rule swap\n  when x_read__h138 ULT y_read__h139 && ! (y_read__h139 == 0)\n  ==>\n    \
+             x.write y_read__h139\n    y.write x_read__h138\n"
+        );
+        assert_eq!(
+            entries[2].lines,
+            ["result :: Bit 32;", "result  = x_read__h138;"]
+        );
+    }
+}
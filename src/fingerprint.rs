@@ -0,0 +1,129 @@
+use std::{fs, io, path};
+
+/// A hash (64-bit FNV-1a, which is stable across builds of dolly) of the inputs of a test
+/// run, recorded when the test passes so that an unchanged test can be skipped.
+pub struct Fingerprint {
+    hash: u64,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    /// Adds a length-prefixed byte string, so that adjacent inputs can't run together.
+    pub fn add(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn add_str(&mut self, text: &str) {
+        self.add(text.as_bytes());
+    }
+
+    /// Adds a file's path and contents (or, for a directory, those of every file below it).  A
+    /// missing file is tagged differently from any contents.
+    pub fn add_path(&mut self, path: &path::Path) {
+        self.add_str(&path.to_string_lossy());
+        if path.is_dir() {
+            let mut entries: Vec<path::PathBuf> = path
+                .read_dir()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                self.add_path(&entry);
+            }
+        } else {
+            match fs::read(path) {
+                Ok(contents) => {
+                    self.add(b"file");
+                    self.add(&contents);
+                }
+                Err(_) => self.add(b"missing"),
+            }
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        format!("{:016x}", self.hash)
+    }
+
+    /// Whether `file` records this fingerprint.
+    pub fn matches(&self, file: &path::Path) -> bool {
+        fs::read_to_string(file).is_ok_and(|recorded| recorded.trim() == self.hex())
+    }
+
+    pub fn record(&self, file: &path::Path) -> io::Result<()> {
+        fs::write(file, format!("{}\n", self.hex()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fingerprint_inputs() {
+        assert_eq!(Fingerprint::new().hex(), "cbf29ce484222325");
+        let fingerprint = |parts: &[&str]| {
+            let mut fingerprint = Fingerprint::new();
+            for part in parts {
+                fingerprint.add_str(part);
+            }
+            fingerprint.hex()
+        };
+        assert_eq!(
+            fingerprint(&["-D", "XLEN=32"]),
+            fingerprint(&["-D", "XLEN=32"])
+        );
+        assert_ne!(
+            fingerprint(&["-D", "XLEN=32"]),
+            fingerprint(&["-DXLEN", "=32"])
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        fs::create_dir_all(directory.join("data")).unwrap();
+        fs::write(directory.join("data").join("rom.hex"), "00000013\n").unwrap();
+        let mut before = Fingerprint::new();
+        before.add_path(&directory.join("data"));
+        let record = directory.join("passed.fingerprint");
+        before.record(&record).unwrap();
+
+        fs::write(directory.join("data").join("rom.hex"), "00100093\n").unwrap();
+        let mut after = Fingerprint::new();
+        after.add_path(&directory.join("data"));
+        assert!(before.matches(&record));
+        assert!(!after.matches(&record));
+
+        // A missing file isn't taken for an empty one, or one whose contents are a marker.
+        let path_fingerprint = |file: &str| {
+            let mut fingerprint = Fingerprint::new();
+            fingerprint.add_path(&directory.join(file));
+            fingerprint.hex()
+        };
+        let missing = path_fingerprint("rom.hex");
+        fs::write(directory.join("rom.hex"), "").unwrap();
+        assert_ne!(path_fingerprint("rom.hex"), missing);
+        for marker in ["<missing>", "missing"] {
+            fs::write(directory.join("rom.hex"), marker).unwrap();
+            assert_ne!(path_fingerprint("rom.hex"), missing);
+        }
+
+        // A missing or garbled record matches nothing, and one can't be written to a missing
+        // directory.
+        assert!(!before.matches(&directory.join("none.fingerprint")));
+        fs::write(&record, "not a fingerprint\n").unwrap();
+        assert!(!before.matches(&record));
+        assert!(before
+            .record(&directory.join("none").join("passed.fingerprint"))
+            .is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use super::codes;
+use super::elf::{Elf, ImageLayout};
+use super::project::RiscvToolchain;
+use std::{ffi::OsString, fs, path, process};
+use tracing::{error, trace};
+
+/// The program a testbench runs from its memory image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Program {
+    Firmware(path::PathBuf), // Directory of sources built with the RISC-V toolchain
+    Elf(path::PathBuf),      // Prebuilt executable
+}
+
+/// Finds the C and assembly sources of a firmware directory.
+fn find_sources(source_dir: &path::Path) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    let mut sources: Vec<path::PathBuf> = source_dir
+        .read_dir()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "c" || ext == "S" || ext == "s")
+        })
+        .collect();
+    sources.sort();
+    Ok(sources)
+}
+
+/// Builds the gcc arguments that compile and link `sources` into `elf`.  A link.ld in the
+/// firmware directory takes precedence over the toolchain's linker script, which is relative
+/// to the project root.
+pub fn gcc_args(
+    toolchain: &RiscvToolchain,
+    root_path: &path::Path,
+    source_dir: &path::Path,
+    sources: &[path::PathBuf],
+    elf: &path::Path,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![
+        format!("-march={}", toolchain.march).into(),
+        format!("-mabi={}", toolchain.mabi).into(),
+    ];
+    args.extend(toolchain.cflags.iter().map(OsString::from));
+    args.extend(sources.iter().map(OsString::from));
+    args.push("-o".into());
+    args.push(elf.into());
+
+    let linker_script = source_dir.join("link.ld");
+    let linker_script = if linker_script.exists() {
+        Some(linker_script)
+    } else {
+        toolchain
+            .linker_script
+            .as_ref()
+            .map(|script| root_path.join(script))
+    };
+    if let Some(linker_script) = linker_script {
+        args.push("-T".into());
+        args.push(linker_script.into());
+    }
+    args.extend(toolchain.ldflags.iter().map(OsString::from));
+    args
+}
+
+/// Builds the firmware in `source_dir` into an ELF in `run_dir`.
+fn build(
+    toolchain: &RiscvToolchain,
+    root_path: &path::Path,
+    source_dir: &path::Path,
+    run_dir: &path::Path,
+) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    let sources = find_sources(source_dir)?;
+    if sources.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No firmware sources found in {:?}",
+            source_dir
+        ))));
+    }
+
+    fs::create_dir_all(run_dir)?;
+    let name = source_dir.file_name().unwrap().to_string_lossy();
+    let elf = run_dir.join(format!("{}.elf", name));
+
+    let gcc = format!("{}gcc", toolchain.prefix);
+    trace!("Building firmware {:?} with {}", source_dir, gcc);
+    let output = process::Command::new(&gcc)
+        .args(gcc_args(toolchain, root_path, source_dir, &sources, &elf))
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Box::new(codes::error(
+                "D0102",
+                format!("Unable to locate '{}' program.", gcc),
+            )))
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+    if !output.status.success() {
+        error!(
+            "Firmware build failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to build firmware {:?}",
+            source_dir
+        ))));
+    }
+
+    Ok(elf)
+}
+
+/// Builds the program if needed and writes its `$readmemh` image(s) to `run_dir`, where the
+/// testbench loads them.
+pub fn prepare(
+    toolchain: &RiscvToolchain,
+    root_path: &path::Path,
+    program: &Program,
+    run_dir: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = match program {
+        Program::Firmware(source_dir) => build(toolchain, root_path, source_dir, run_dir)?,
+        Program::Elf(elf) => elf.clone(),
+    };
+
+    if !toolchain.word_width.is_multiple_of(8) {
+        return Err(Box::new(std::io::Error::other(
+            "[toolchain.riscv] word-width must be a multiple of 8 bits",
+        )));
+    }
+    let layout = ImageLayout {
+        base: Some(toolchain.base_address),
+        word_bytes: toolchain.word_width as usize / 8,
+        banks: toolchain.banks,
+    };
+    fs::create_dir_all(run_dir)?;
+    Elf::load(&elf)?.write_images(&layout, &run_dir.join(&toolchain.image))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gcc_args_order() {
+        let toolchain = RiscvToolchain {
+            cflags: vec!["-O2".to_string()],
+            ldflags: vec!["-nostdlib".to_string()],
+            linker_script: Some(path::PathBuf::from("sw/common.ld")),
+            ..RiscvToolchain::default()
+        };
+        let args = gcc_args(
+            &toolchain,
+            path::Path::new("/p"),
+            path::Path::new("/p/sw/hello"),
+            &[path::PathBuf::from("/p/sw/hello/start.S")],
+            path::Path::new("/p/target/hello_tb/hello.elf"),
+        );
+
+        assert_eq!(
+            args,
+            [
+                "-march=rv32i",
+                "-mabi=ilp32",
+                "-O2",
+                "/p/sw/hello/start.S",
+                "-o",
+                "/p/target/hello_tb/hello.elf",
+                "-T",
+                "/p/sw/common.ld",
+                "-nostdlib"
+            ]
+            .map(OsString::from)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use super::bsv;
+use super::builder::Builder;
+use super::codes;
+use super::config;
+use super::project::{FormalConfig, Project};
+use super::rvfi;
+use super::verilog;
+use colored::Colorize;
+use regex::Regex;
+use std::{fmt::Write, fs, path, process};
+use tracing::{error, trace, warn};
+
+/// A module carrying a `//!formal` directive.
+#[derive(Debug, PartialEq)]
+pub struct FormalTarget {
+    pub source: path::PathBuf,
+    pub module: String,
+    pub property_files: Vec<path::PathBuf>, // (System)Verilog files holding the properties
+}
+
+/// Finds the `//!formal <module> [property-file...]` directives in the given directories.
+pub fn find_targets<'a>(
+    directories: impl Iterator<Item = &'a path::PathBuf>,
+) -> Result<Vec<FormalTarget>, Box<dyn std::error::Error>> {
+    let re = Regex::new(&format!(r"{}formal\s+(\w+)(.*)$", bsv::DIRECTIVE))?;
+
+    let mut sources: Vec<path::PathBuf> = directories
+        .filter_map(|directory| directory.read_dir().ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| bsv::is_source(path))
+        .collect();
+    sources.sort();
+
+    let mut targets = Vec::<FormalTarget>::new();
+    for source in sources {
+        let directory = source.parent().unwrap().to_path_buf();
+        for capture in fs::read_to_string(&source)?
+            .lines()
+            .flat_map(|line| re.captures(line))
+        {
+            targets.push(FormalTarget {
+                source: source.clone(),
+                module: capture[1].to_string(),
+                property_files: capture[2]
+                    .split_whitespace()
+                    .map(|file| directory.join(file))
+                    .collect(),
+            });
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Writes the SymbiYosys configuration for proving `top` over the given Verilog files.
+pub fn sby_config(config: &FormalConfig, top: &str, files: &[path::PathBuf]) -> String {
+    let mut sby = String::new();
+    writeln!(sby, "[options]").unwrap();
+    writeln!(sby, "mode {}", config.mode).unwrap();
+    writeln!(sby, "depth {}", config.depth).unwrap();
+
+    writeln!(sby, "\n[engines]").unwrap();
+    for engine in &config.engines {
+        writeln!(sby, "{}", engine).unwrap();
+    }
+
+    // SymbiYosys copies [files] into its work directory, so the script refers to base names.
+    writeln!(sby, "\n[script]").unwrap();
+    for file in files {
+        writeln!(
+            sby,
+            "read -formal {}",
+            file.file_name().unwrap().to_string_lossy()
+        )
+        .unwrap();
+    }
+    writeln!(sby, "prep -top {}", top).unwrap();
+
+    writeln!(sby, "\n[files]").unwrap();
+    for file in files {
+        writeln!(sby, "{}", file.to_string_lossy()).unwrap();
+    }
+
+    sby
+}
+
+/// Finds the counterexample traces SymbiYosys wrote in its work directory.
+fn find_traces(work_dir: &path::Path) -> Vec<path::PathBuf> {
+    let mut traces: Vec<path::PathBuf> = work_dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("engine_"))
+        })
+        .filter_map(|engine| engine.read_dir().ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "vcd")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("trace"))
+        })
+        .collect();
+    traces.sort();
+    traces
+}
+
+fn prove_target(
+    project: &Project,
+    builder: &Builder,
+    target: &FormalTarget,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let build_path = project.output_dir("formal").join(&target.module);
+    builder.generate_verilog(project, &[], &target.source, &target.module, &build_path)?;
+
+    let mut files = vec![build_path.join(format!("{}.v", target.module))];
+    files.extend(verilog::primitives(&files)?);
+    for property_file in &target.property_files {
+        files.push(property_file.canonicalize()?);
+    }
+
+    // A property file may wrap the module in a <module>_formal top holding the assertions.
+    let wrapper = format!("{}_formal", target.module);
+    let wrapper_re = Regex::new(&format!(r"\bmodule\s+{}\b", wrapper))?;
+    let top = if target
+        .property_files
+        .iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .any(|contents| wrapper_re.is_match(&contents))
+    {
+        wrapper
+    } else {
+        target.module.clone()
+    };
+
+    let sby_file = build_path.join(format!("{}.sby", target.module));
+    fs::write(&sby_file, sby_config(&project.formal, &top, &files))?;
+
+    let work_dir = build_path.join("work");
+    trace!("Running SymbiYosys: {:?}", sby_file);
+    let output = process::Command::new(config::tool("sby"))
+        .arg("-f")
+        .arg("-d")
+        .arg(&work_dir)
+        .arg(&sby_file)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Box::new(codes::error(
+                "D0102",
+                "Unable to locate 'sby' program.",
+            )))
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    if output.status.success() {
+        println!("Prove: {} -- {}.", target.module, "PASSED".green());
+        return Ok(true);
+    }
+
+    println!("{}", String::from_utf8_lossy(&output.stdout));
+    println!("Prove: {} -- {}.", target.module, "FAILED".red().bold());
+
+    // Copy counterexamples out of the SymbiYosys work directory where they're easy to find.
+    for (index, trace) in find_traces(&work_dir).iter().enumerate() {
+        let counterexample = build_path.join(format!("counterexample_{}.vcd", index));
+        match fs::copy(trace, &counterexample) {
+            Ok(_) => println!("Counterexample: {}", counterexample.to_string_lossy()),
+            Err(e) => warn!("Unable to copy {:?}: {}", trace, e),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Generates Verilog for every module carrying a `//!formal` directive and proves it with
+/// SymbiYosys.
+pub fn prove(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let targets = find_targets(builder.module_paths())?;
+    if targets.is_empty() && project.formal.rvfi.is_none() {
+        warn!("Warning - no //!formal directives found");
+    }
+
+    let mut all_passed = true;
+    for target in &targets {
+        if !prove_target(project, &builder, target)? {
+            all_passed = false;
+        }
+    }
+
+    if let Some(rvfi) = &project.formal.rvfi {
+        if !rvfi::prove(project, &builder, rvfi)? {
+            all_passed = false;
+        }
+    }
+
+    if all_passed {
+        Ok(())
+    } else {
+        error!("Not all properties were proven");
+        Err(Box::new(std::io::Error::other("Not all proofs passed")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sby_config_lists_files() {
+        let config = FormalConfig {
+            mode: "prove".to_string(),
+            depth: 8,
+            engines: vec!["smtbmc yices".to_string(), "abc pdr".to_string()],
+            rvfi: None,
+        };
+        let sby = sby_config(
+            &config,
+            "mkCounter_formal",
+            &[
+                path::PathBuf::from("/p/target/formal/mkCounter/mkCounter.v"),
+                path::PathBuf::from("/p/src/counter_props.sv"),
+            ],
+        );
+
+        assert_eq!(
+            sby,
+            "[options]\nmode prove\ndepth 8\n\n[engines]\nsmtbmc yices\nabc pdr\n\n[script]\n\
+             read -formal mkCounter.v\nread -formal counter_props.sv\nprep -top mkCounter_formal\n\n\
+             [files]\n/p/target/formal/mkCounter/mkCounter.v\n/p/src/counter_props.sv\n"
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! The `dolly fmt` BSV formatter.  It only changes layout: lines are re-indented by block
+//! nesting (continuation lines keep their indentation relative to the start of their
+//! statement), runs of imports are sorted, the method prototypes of interface declarations
+//! are aligned and blank lines and trailing whitespace are tidied.
+use super::bsv;
+use super::project::Project;
+use std::{collections::BTreeSet, fs, path};
+
+const INDENT: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Symbol(char),
+    Literal, // A string
+    Comment,
+}
+
+/// Splits a line into tokens.  `in_comment` carries a `/* */` comment across lines.
+fn tokenize(line: &str, in_comment: &mut bool) -> Vec<Token> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::<Token>::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let next = chars.get(index + 1).copied();
+        if *in_comment {
+            if c == '*' && next == Some('/') {
+                *in_comment = false;
+                index += 1;
+            }
+            index += 1;
+        } else if c == '/' && next == Some('/') {
+            tokens.push(Token::Comment);
+            break;
+        } else if c == '/' && next == Some('*') {
+            tokens.push(Token::Comment);
+            *in_comment = true;
+            index += 2;
+        } else if c == '"' {
+            index += 1;
+            while index < chars.len() && chars[index] != '"' {
+                index += if chars[index] == '\\' { 2 } else { 1 };
+            }
+            tokens.push(Token::Literal);
+            index += 1;
+        } else if c.is_alphanumeric() || c == '_' || c == '$' {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '$')
+            {
+                index += 1;
+            }
+            tokens.push(Token::Word(chars[start..index].iter().collect()));
+        } else {
+            if !c.is_whitespace() {
+                tokens.push(Token::Symbol(c));
+            }
+            index += 1;
+        }
+    }
+    tokens
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Block {
+    Declaration, // An interface declaration or typeclass, whose members are prototypes
+    Definition,
+}
+
+/// Keywords that always open a block, and the keywords that close blocks.
+const OPENERS: &[&str] = &[
+    "action",
+    "actionvalue",
+    "begin",
+    "case",
+    "instance",
+    "module",
+    "par",
+    "rule",
+    "rules",
+    "seq",
+];
+const CLOSERS: &[&str] = &[
+    "end",
+    "endaction",
+    "endactionvalue",
+    "endcase",
+    "endfunction",
+    "endinstance",
+    "endinterface",
+    "endmethod",
+    "endmodule",
+    "endpar",
+    "endrule",
+    "endrules",
+    "endseq",
+    "endtypeclass",
+];
+
+/// Whether the statement starting at `tokens[start]` (and running to the next `;` outside
+/// parentheses) is an assignment, like `method Bool empty = !fifo.notEmpty;`.
+fn is_assignment(tokens: &[&Token], start: usize) -> bool {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Symbol('(' | '[' | '{') => depth += 1,
+            Token::Symbol(')' | ']' | '}') => depth -= 1,
+            Token::Symbol(';') if depth <= 0 => return false,
+            Token::Symbol('=') if depth <= 0 => {
+                let before = index.checked_sub(1).map(|before| tokens[before]);
+                let after = tokens.get(index + 1).copied();
+                let compound = matches!(before, Some(Token::Symbol('=' | '<' | '>' | '!')));
+                if !compound && after != Some(&Token::Symbol('=')) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Re-indents the lines of a source by block nesting.
+fn indent(lines: &[&str]) -> Vec<String> {
+    let mut in_comment = false;
+    let mut starts_in_comment = Vec::<bool>::new(); // Whether each line continues a comment
+    let line_tokens: Vec<Vec<Token>> = lines
+        .iter()
+        .map(|line| {
+            starts_in_comment.push(in_comment);
+            tokenize(line, &mut in_comment)
+        })
+        .collect();
+    // The code tokens of the whole source, with the line each is on.
+    let code: Vec<(usize, &Token)> = line_tokens
+        .iter()
+        .enumerate()
+        .flat_map(|(line, tokens)| tokens.iter().map(move |token| (line, token)))
+        .filter(|(_, token)| !matches!(token, Token::Comment | Token::Literal))
+        .collect();
+    let code_tokens: Vec<&Token> = code.iter().map(|(_, token)| *token).collect();
+
+    let mut stack = Vec::<Block>::new();
+    let mut next = 0; // Index in `code` of the first token of the current line
+    let mut continuation: Option<(usize, usize)> = None; // (original, new) statement indent
+    let mut formatted = Vec::<String>::new();
+    for (number, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let original: usize = line[..line.len() - line.trim_start().len()]
+            .chars()
+            .map(|c| if c == '\t' { INDENT } else { 1 })
+            .sum();
+        let first = next;
+        let mut depth = None;
+        let mut last = None;
+        while next < code.len() && code[next].0 == number {
+            let token = code[next].1;
+            let word = match token {
+                Token::Word(word) => Some(word.as_str()),
+                _ => None,
+            };
+            let closes =
+                word.is_some_and(|word| CLOSERS.contains(&word)) || *token == Token::Symbol('}');
+            if closes {
+                stack.pop();
+            }
+            if depth.is_none() && !closes {
+                depth = Some(stack.len());
+            }
+            let declaration = stack.last() == Some(&Block::Declaration);
+            let opens = match word {
+                Some("interface") => !declaration && !is_assignment(&code_tokens, next),
+                Some("method" | "function") => {
+                    // `import "BDPI" function ...;` declares a foreign function.
+                    let imported = next.checked_sub(1).is_some_and(|before| {
+                        *code_tokens[before] == Token::Word("import".to_string())
+                    });
+                    !declaration && !imported && !is_assignment(&code_tokens, next)
+                }
+                Some("typeclass") => true,
+                Some(word) => OPENERS.contains(&word),
+                None => *token == Token::Symbol('{'),
+            };
+            if opens {
+                let package_level = stack.is_empty();
+                stack.push(match word {
+                    Some("typeclass") => Block::Declaration,
+                    Some("interface") if package_level => Block::Declaration,
+                    _ => Block::Definition,
+                });
+            }
+            last = Some(token);
+            next += 1;
+        }
+        let depth = depth.unwrap_or(stack.len());
+        if starts_in_comment[number] || trimmed.is_empty() {
+            // Lines in comments are left as they are.
+            formatted.push(line.trim_end().to_string());
+            continue;
+        }
+
+        let new = match continuation {
+            Some((statement_original, statement_new)) => {
+                statement_new + original.saturating_sub(statement_original)
+            }
+            None => depth * INDENT,
+        };
+        formatted.push(format!("{}{}", " ".repeat(new), trimmed));
+
+        // A line ends its statement with a `;`, a block keyword (`endrule: drain` included),
+        // an attribute or a case header (`case (x)` or `case (x) matches`).
+        let labelled_end = matches!(
+            code_tokens[first..next],
+            [Token::Word(word), Token::Symbol(':'), Token::Word(_)] if CLOSERS.contains(&word.as_str())
+        );
+        let case_header = code_tokens.get(first) == Some(&&Token::Word("case".to_string()));
+        let complete = match last {
+            None => continuation.is_none(),
+            Some(Token::Symbol(';' | '{' | '}')) => true,
+            Some(Token::Word(word)) => {
+                OPENERS.contains(&word.as_str())
+                    || CLOSERS.contains(&word.as_str())
+                    || word == "endpackage"
+                    || labelled_end
+                    || (case_header && word == "matches")
+            }
+            Some(Token::Symbol(')')) => trimmed.starts_with("(*") || case_header,
+            Some(_) => false,
+        };
+        if complete {
+            continuation = None;
+        } else if continuation.is_none() {
+            continuation = Some((original, new));
+        }
+    }
+    formatted
+}
+
+/// Sorts (and deduplicates) each run of consecutive `import Package::*;` lines.
+fn sort_imports(lines: Vec<String>) -> Vec<String> {
+    let is_import = |line: &str| {
+        let line = line.trim();
+        line.starts_with("import ") && line.ends_with("::*;") && !line.contains('"')
+    };
+    let mut sorted = Vec::<String>::new();
+    let mut run = BTreeSet::<String>::new();
+    for line in lines {
+        if is_import(&line) {
+            run.insert(line);
+            continue;
+        }
+        sorted.append(&mut std::mem::take(&mut run).into_iter().collect());
+        sorted.push(line);
+    }
+    sorted.extend(run);
+    sorted
+}
+
+/// Splits an interface method prototype into its result type and the rest (the name,
+/// arguments and `;`), e.g. `method ActionValue#(Bit#(8)) take;`.
+fn split_prototype(line: &str) -> Option<(String, String)> {
+    let rest = line.trim().strip_prefix("method ")?.trim_start();
+    if !rest.ends_with(';') || rest.contains("//") || rest.contains("/*") {
+        return None;
+    }
+    let mut depth = 0;
+    let mut words = Vec::<(usize, usize)>::new(); // Spans of the words outside parentheses
+    let mut start: Option<usize> = None;
+    for (index, c) in rest.char_indices() {
+        match c {
+            '(' if depth == 0 && !rest[..index].trim_end().ends_with('#') => {
+                if let Some(word_start) = start.take() {
+                    words.push((word_start, index));
+                }
+                break;
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                if let Some(word_start) = start.take() {
+                    words.push((word_start, index));
+                }
+                break;
+            }
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(word_start) = start.take() {
+                    words.push((word_start, index));
+                }
+            }
+            _ => {
+                if start.is_none() {
+                    start = Some(index);
+                }
+            }
+        }
+    }
+    if words.len() < 2 {
+        return None;
+    }
+    let name = words.last().unwrap().0;
+    Some((
+        rest[..name].trim_end().to_string(),
+        rest[name..].to_string(),
+    ))
+}
+
+/// Aligns the names of consecutive method prototypes in interface declarations.
+fn align_methods(lines: Vec<String>) -> Vec<String> {
+    let mut aligned = Vec::<String>::with_capacity(lines.len());
+    let mut group = Vec::<(String, String, String)>::new(); // Indentation, type and rest
+    let flush = |group: &mut Vec<(String, String, String)>, aligned: &mut Vec<String>| {
+        let width = group.iter().map(|(_, ty, _)| ty.len()).max().unwrap_or(0);
+        for (indentation, ty, rest) in group.drain(..) {
+            aligned.push(format!("{}method {:width$} {}", indentation, ty, rest));
+        }
+    };
+    let mut in_interface = false;
+    for line in lines {
+        let trimmed = line.trim_start();
+        let prototype = in_interface.then(|| split_prototype(&line)).flatten();
+        match prototype {
+            Some((ty, rest)) => {
+                group.push((line[..line.len() - trimmed.len()].to_string(), ty, rest));
+                continue;
+            }
+            None => flush(&mut group, &mut aligned),
+        }
+        if line.starts_with("interface ") {
+            in_interface = true;
+        } else if trimmed.starts_with("endinterface") {
+            in_interface = false;
+        }
+        aligned.push(line);
+    }
+    flush(&mut group, &mut aligned);
+    aligned
+}
+
+/// Formats a BSV source.
+pub fn format_source(source: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let lines = align_methods(sort_imports(indent(&lines)));
+
+    let mut formatted = String::new();
+    let mut blank = true; // Drops blank lines at the start too
+    for line in lines {
+        if line.is_empty() {
+            if !blank {
+                formatted.push('\n');
+            }
+            blank = true;
+        } else {
+            formatted.push_str(&line);
+            formatted.push('\n');
+            blank = false;
+        }
+    }
+    while formatted.ends_with("\n\n") {
+        formatted.pop();
+    }
+    formatted
+}
+
+/// Implements `dolly fmt`: formats the BSV sources in the project's source roots and tests/,
+/// or with `check`, fails if any of them isn't formatted.
+pub fn run(project: &Project, check: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut directories: Vec<path::PathBuf> = project
+        .package
+        .src_dirs
+        .iter()
+        .map(|src_dir| project.root_path().join(src_dir))
+        .collect();
+    directories.push(project.root_path().join("tests"));
+    let sources: BTreeSet<path::PathBuf> = directories
+        .iter()
+        .flat_map(|directory| bsv::find_packages(directory).into_values().flatten())
+        .filter(|source| source.extension().is_some_and(|ext| ext == "bsv"))
+        .collect();
+
+    let mut unformatted = 0;
+    for source in sources {
+        let contents = fs::read_to_string(&source)?;
+        let formatted = format_source(&contents);
+        if formatted == contents {
+            continue;
+        }
+        let name = source
+            .strip_prefix(project.root_path())
+            .unwrap_or(&source)
+            .to_string_lossy()
+            .to_string();
+        if check {
+            let line = contents
+                .lines()
+                .zip(formatted.lines())
+                .position(|(old, new)| old != new)
+                .unwrap_or(contents.lines().count().min(formatted.lines().count()));
+            println!("{}:{}: not formatted", name, line + 1);
+        } else {
+            fs::write(&source, formatted)?;
+            println!("Formatted {}", name);
+        }
+        unformatted += 1;
+    }
+    if check && unformatted > 0 {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} files need formatting (run 'dolly fmt')",
+            unformatted
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formatted_source() {
+        let source = r#"package Fifo;
+import Vector::*;
+import FIFOF::*;
+	import FIFOF::*;
+
+
+interface Fifo#(type t);
+method Action enq(t x);
+  method ActionValue#(t) deq;
+    method Bool notEmpty;
+interface Put#(t) sink;
+endinterface
+
+import "BDPI" function Bit#(32) checksum(Bit#(8) value);
+
+(* synthesize *)
+module mkFifo(Fifo#(Bit#(8)));
+FIFOF#(Bit#(8)) fifo <- mkFIFOF;
+Reg#(Bool) busy <- mkReg(False);   // comment
+
+rule drain (busy &&
+            fifo.notEmpty);
+if (fifo.first == 0)
+    busy <= False;
+else begin
+$display("begin %d", fifo.first);
+end
+endrule
+
+method Action enq(Bit#(8) x) if (!busy);
+/* a comment
+   kept as it is */
+case (x)
+0: noAction;
+default: fifo.enq(x);
+endcase
+endmethod
+method Bool notEmpty = fifo.notEmpty;
+function Bit#(8) low(Maybe#(Bit#(8)) m);
+case (m) matches
+tagged Valid .v: return v;
+tagged Invalid: return 0;
+endcase
+endfunction
+interface Put sink;
+method Action put(Bit#(8) x);
+fifo.enq(x);
+endmethod
+endinterface
+endmodule
+endpackage
+"#;
+        let expected = r#"package Fifo;
+import FIFOF::*;
+import Vector::*;
+
+interface Fifo#(type t);
+    method Action          enq(t x);
+    method ActionValue#(t) deq;
+    method Bool            notEmpty;
+    interface Put#(t) sink;
+endinterface
+
+import "BDPI" function Bit#(32) checksum(Bit#(8) value);
+
+(* synthesize *)
+module mkFifo(Fifo#(Bit#(8)));
+    FIFOF#(Bit#(8)) fifo <- mkFIFOF;
+    Reg#(Bool) busy <- mkReg(False);   // comment
+
+    rule drain (busy &&
+                fifo.notEmpty);
+        if (fifo.first == 0)
+            busy <= False;
+        else begin
+            $display("begin %d", fifo.first);
+        end
+    endrule
+
+    method Action enq(Bit#(8) x) if (!busy);
+        /* a comment
+   kept as it is */
+        case (x)
+            0: noAction;
+            default: fifo.enq(x);
+        endcase
+    endmethod
+    method Bool notEmpty = fifo.notEmpty;
+    function Bit#(8) low(Maybe#(Bit#(8)) m);
+        case (m) matches
+            tagged Valid .v: return v;
+            tagged Invalid: return 0;
+        endcase
+    endfunction
+    interface Put sink;
+        method Action put(Bit#(8) x);
+            fifo.enq(x);
+        endmethod
+    endinterface
+endmodule
+endpackage
+"#;
+        let formatted = format_source(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
@@ -0,0 +1,321 @@
+use super::bsv::{self, Interface, MethodKind};
+use super::builder::Builder;
+use super::project::Project;
+use colored::Colorize;
+use std::{fmt::Write, fs, path, process};
+use tracing::{error, trace};
+
+/// Salts mixed into the random value for each method argument, so arguments differ.
+const ARGUMENT_SALTS: [u32; 8] = [
+    0x9e3779b9, 0x7f4a7c15, 0x85ebca6b, 0xc2b2ae35, 0x27d4eb2f, 0x165667b1, 0xd3a2646c, 0xfd7046c5,
+];
+
+/// Generates a BSV harness that makes `steps` randomized calls (seeded with `seed`) to the
+/// methods of `module`, printing ">>>PASS" if no assertion fires.
+pub fn generate_harness(
+    harness_module: &str,
+    package: &str,
+    module: &str,
+    interface: &Interface,
+    seed: u32,
+    steps: u32,
+) -> String {
+    let methods = &interface.methods;
+    let mut harness = String::new();
+
+    writeln!(harness, "// Generated by dolly fuzz -- do not edit.").unwrap();
+    writeln!(harness, "import {}::*;", package).unwrap();
+    writeln!(harness, "import LFSR::*;").unwrap();
+    writeln!(
+        harness,
+        r#"
+function t fuzzValue(Bit#(32) r) provisos (Bits#(t, n));
+    Bit#(n) bits = 0;
+    for (Integer i = 0; i < valueOf(n); i = i + 1)
+        bits[i] = r[(i * 7 + 3) % 32];
+    return unpack(bits);
+endfunction
+"#
+    )
+    .unwrap();
+
+    // Method calls are more urgent than skipping a step whose method can't be called.
+    let mut rules: Vec<String> = methods
+        .iter()
+        .map(|method| format!("call_{}", method.name))
+        .collect();
+    rules.push("skip".to_string());
+    writeln!(harness, "module {}(Empty);", harness_module).unwrap();
+    writeln!(harness, "    {} dut <- {};", interface.name, module).unwrap();
+    writeln!(harness, "    LFSR#(Bit#(32)) lfsr <- mkLFSR_32;").unwrap();
+    writeln!(harness, "    Reg#(Bool) seeded <- mkReg(False);").unwrap();
+    writeln!(harness, "    Reg#(Bool) pending <- mkReg(False);").unwrap();
+    writeln!(harness, "    Reg#(Bit#(32)) choice <- mkReg(0);").unwrap();
+    writeln!(harness, "    Reg#(UInt#(32)) step <- mkReg(0);").unwrap();
+    writeln!(
+        harness,
+        r#"
+    rule seed_lfsr (!seeded);
+        lfsr.seed('h{:08x});
+        seeded <= True;
+    endrule
+
+    rule choose (seeded && !pending && step < {});
+        choice <= lfsr.value;
+        lfsr.next;
+        pending <= True;
+    endrule
+"#,
+        seed, steps
+    )
+    .unwrap();
+
+    for (index, method) in methods.iter().enumerate() {
+        let arguments: Vec<String> = method
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(argument, _)| {
+                format!(
+                    "fuzzValue(choice ^ 'h{:08x})",
+                    ARGUMENT_SALTS[argument % ARGUMENT_SALTS.len()]
+                )
+            })
+            .collect();
+        let call = if arguments.is_empty() {
+            format!("dut.{}", method.name)
+        } else {
+            format!("dut.{}({})", method.name, arguments.join(", "))
+        };
+
+        let attribute = if index == 0 {
+            format!("    (* descending_urgency = \"{}\" *)\n", rules.join(", "))
+        } else {
+            String::new()
+        };
+        writeln!(
+            harness,
+            "{}    rule call_{} (pending && choice % {} == {});",
+            attribute,
+            method.name,
+            methods.len(),
+            index
+        )
+        .unwrap();
+        match method.kind {
+            MethodKind::Action => writeln!(harness, "        {};", call).unwrap(),
+            MethodKind::ActionValue => {
+                writeln!(harness, "        let result <- {};", call).unwrap()
+            }
+            MethodKind::Value => writeln!(harness, "        let result = {};", call).unwrap(),
+        }
+        writeln!(
+            harness,
+            "        $display(\"fuzz: step %0d {}\", step);",
+            method.name
+        )
+        .unwrap();
+        writeln!(harness, "        pending <= False;").unwrap();
+        writeln!(harness, "        step <= step + 1;").unwrap();
+        writeln!(harness, "    endrule\n").unwrap();
+    }
+
+    writeln!(
+        harness,
+        r#"    rule skip (pending);
+        $display("fuzz: step %0d skipped", step);
+        pending <= False;
+        step <= step + 1;
+    endrule
+
+    rule finish (seeded && step == {});
+        $display(">>>PASS");
+        $finish();
+    endrule
+endmodule"#,
+        steps
+    )
+    .unwrap();
+
+    harness
+}
+
+struct Fuzzer<'a> {
+    project: &'a Project,
+    builder: &'a Builder,
+    build_root: path::PathBuf,
+    harness_module: String,
+    package: String,
+    module: String,
+    interface: Interface,
+}
+
+impl Fuzzer<'_> {
+    /// Builds and runs the harness, returning whether it passed along with its output.
+    fn run(&self, seed: u32, steps: u32) -> Result<(bool, String), Box<dyn std::error::Error>> {
+        // The harness package is named after the module without its "mk" prefix.
+        let harness_file = self.build_root.join(format!(
+            "{}.bsv",
+            self.harness_module.trim_start_matches("mk")
+        ));
+        fs::write(
+            &harness_file,
+            generate_harness(
+                &self.harness_module,
+                &self.package,
+                &self.module,
+                &self.interface,
+                seed,
+                steps,
+            ),
+        )?;
+
+        let executable = Builder::build_executable(
+            self.project,
+            self.builder,
+            &self.build_root,
+            &harness_file,
+            &self.harness_module,
+        )?;
+
+        trace!("Fuzzing with seed {:#x}, {} steps", seed, steps);
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(r#""$0" "$@""#)
+            .arg(&executable)
+            .output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let passed = output.status.success() && stdout.contains(">>>PASS");
+
+        Ok((passed, stdout))
+    }
+
+    /// Finds the shortest prefix of a failing run that still fails.
+    fn shrink(
+        &self,
+        seed: u32,
+        failing_steps: u32,
+        failing_output: String,
+    ) -> Result<(u32, String), Box<dyn std::error::Error>> {
+        let mut passing = 0;
+        let mut failing = (failing_steps, failing_output);
+        while failing.0 - passing > 1 {
+            let steps = passing + (failing.0 - passing) / 2;
+            println!("Shrinking: replaying {} steps", steps);
+            match self.run(seed, steps)? {
+                (true, _) => passing = steps,
+                (false, output) => failing = (steps, output),
+            }
+        }
+        Ok(failing)
+    }
+}
+
+/// Drives randomized method calls into `module_name` until an assertion fires or `runs`
+/// seeds have passed.
+pub fn run(
+    project: &Project,
+    module_name: &str,
+    seed: Option<u32>,
+    steps: u32,
+    runs: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let Some(location) = bsv::find_module(builder.module_paths(), module_name) else {
+        error!("Module {} not found", module_name);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+    if location.module.parameters.is_some() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to fuzz {}: modules with parameters are not supported",
+            location.module.name
+        ))));
+    }
+
+    let Some(interface) = bsv::find_interface(builder.module_paths(), &location.module.interface)
+    else {
+        error!("Interface {} not found", location.module.interface);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+    if interface.parameters.is_some() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to fuzz {}: parameterized interfaces are not supported",
+            location.module.name
+        ))));
+    }
+
+    let harness_module = format!("mkFuzz{}", location.module.name.trim_start_matches("mk"));
+    let build_root = project.output_dir("fuzz");
+    fs::create_dir_all(&build_root)?;
+
+    let fuzzer = Fuzzer {
+        project,
+        builder: &builder,
+        build_root,
+        harness_module,
+        package: location.package(),
+        module: location.module.name.clone(),
+        interface,
+    };
+
+    let first_seed = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(1)
+    });
+
+    for run in 0..runs {
+        // An LFSR seeded with zero never advances.
+        let seed = first_seed.wrapping_add(run).max(1);
+        let (passed, output) = fuzzer.run(seed, steps)?;
+        if passed {
+            println!(
+                "Fuzz run {} (seed {:#x}) -- {}.",
+                run + 1,
+                seed,
+                "PASSED".green()
+            );
+            continue;
+        }
+
+        let (steps, output) = fuzzer.shrink(seed, steps, output)?;
+        println!("{}", output);
+        println!(
+            "Fuzz run {} (seed {:#x}) -- {} after {} steps.",
+            run + 1,
+            seed,
+            "FAILED".red().bold(),
+            steps
+        );
+        println!(
+            "Reproduce with: dolly fuzz {} --seed {} --steps {} --runs 1",
+            module_name, seed, steps
+        );
+        return Err(Box::new(std::io::Error::other("Fuzzing found a failure")));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn harness_calls_every_method() {
+        let interface = bsv::parse_interfaces(
+            "interface Counter;\n    method Action add(Bit#(8) amount);\n    method Bit#(8) value;\nendinterface\n",
+        )
+        .remove(0);
+        let harness = generate_harness("mkFuzzCounter", "Counter", "mkCounter", &interface, 5, 100);
+
+        assert!(harness.contains("(* descending_urgency = \"call_add, call_value, skip\" *)"));
+        assert!(harness.contains("rule call_add (pending && choice % 2 == 0);"));
+        assert!(harness.contains("dut.add(fuzzValue(choice ^ 'h9e3779b9));"));
+        assert!(harness.contains("let result = dut.value;"));
+        assert!(harness.contains("lfsr.seed('h00000005);"));
+    }
+}
@@ -0,0 +1,47 @@
+use super::build_info;
+use super::builder::Options;
+use super::project::Project;
+use super::runner;
+use std::{fs, process};
+
+/// Runs each of the project's `[generators]` (in name order) with a shell in the project root.
+/// They write BSV packages into `DOLLY_OUT_DIR` (target/generated/src), which is searched like
+/// the project's own source directories.  The BuildInfo package is written there first.
+pub fn run(project: &Project, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if !options.dry_run {
+        build_info::write(project)?;
+    }
+    if project.generators.is_empty() {
+        return Ok(());
+    }
+    let out_dir = project.generated_dir();
+    fs::create_dir_all(&out_dir)?;
+
+    for (name, command) in &project.generators {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(command)
+            .current_dir(project.root_path())
+            .envs(project.env()?)
+            .env("DOLLY_OUT_DIR", &out_dir)
+            .env("DOLLY_PACKAGE", &project.package.name)
+            .env("DOLLY_PROJECT_ROOT", project.root_path());
+        if options.show_commands || options.dry_run {
+            println!("{}", runner::command_line(&cmd));
+        }
+        if options.dry_run {
+            continue;
+        }
+
+        let log_file = out_dir.with_file_name(format!("{}.log", name));
+        let output = runner::run(&mut cmd, name, &log_file)?;
+        if !output.status.success() {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Generator '{}' failed (see {})",
+                name,
+                log_file.to_string_lossy()
+            ))));
+        }
+    }
+    Ok(())
+}
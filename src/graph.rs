@@ -0,0 +1,389 @@
+//! The graph of the sources of a project: which source includes which, through imports and
+//! `//!submodule` directives, starting from the top modules and tests.
+use super::bsv;
+use super::project::Project;
+use convert_case::{Case, Casing};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt, fs, path,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Import,
+    Submodule,
+}
+
+/// One source including another.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub name: String, // The imported package or the submodule directory
+    pub to: path::PathBuf,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            EdgeKind::Import => write!(f, "import {}", self.name),
+            EdgeKind::Submodule => write!(f, "//!submodule {}", self.name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RootKind {
+    Top,
+    Test,
+}
+
+/// A top module or test, where the build starts.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Root {
+    pub kind: RootKind,
+    pub name: String, // The top module or the testbench
+    pub path: path::PathBuf,
+}
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            RootKind::Top => write!(f, "top {}", self.name),
+            RootKind::Test => write!(f, "test {}", self.name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    pub edges: BTreeMap<path::PathBuf, Vec<Edge>>, // Every source, with the sources it includes
+    pub roots: Vec<Root>,
+}
+
+impl Graph {
+    /// The graph of the given sources (by path, with their contents).  Imports of packages
+    /// that aren't among the sources (library packages) are left out.
+    pub fn new(sources: &BTreeMap<path::PathBuf, String>, roots: Vec<Root>) -> Self {
+        let submodule_re = Regex::new(&format!(r"{}submodule\s+(\w+)", bsv::DIRECTIVE)).unwrap();
+        let mut packages = BTreeMap::<String, Vec<&path::PathBuf>>::new();
+        for path in sources.keys() {
+            let package = path.file_stem().unwrap().to_string_lossy().to_string();
+            packages.entry(package).or_default().push(path);
+        }
+
+        let mut edges = BTreeMap::<path::PathBuf, Vec<Edge>>::new();
+        for (path, contents) in sources {
+            let mut source_edges = Vec::<Edge>::new();
+            for import in bsv::parse_source_imports(path, contents) {
+                for to in packages.get(&import).into_iter().flatten() {
+                    source_edges.push(Edge {
+                        kind: EdgeKind::Import,
+                        name: import.clone(),
+                        to: to.to_path_buf(),
+                    });
+                }
+            }
+            for capture in contents
+                .lines()
+                .filter_map(|line| submodule_re.captures(line))
+            {
+                let directory = path.parent().unwrap().join(&capture[1]);
+                let name = capture[1].to_string().to_case(Case::Pascal);
+                let mod_file = ["bs", "bsv"]
+                    .iter()
+                    .map(|ext| directory.join(format!("{}.{}", name, ext)))
+                    .find(|mod_file| sources.contains_key(mod_file));
+                if let Some(to) = mod_file {
+                    source_edges.push(Edge {
+                        kind: EdgeKind::Submodule,
+                        name: capture[1].to_string(),
+                        to,
+                    });
+                }
+            }
+            source_edges.sort();
+            source_edges.dedup();
+            edges.insert(path.clone(), source_edges);
+        }
+        Graph { edges, roots }
+    }
+
+    /// The graph of a project: the sources of its source roots, tests/ and dependencies, with
+    /// the top modules of its top file and its testbenches (`*_tb` and tests/) as roots.
+    pub fn load(project: &Project) -> Result<Self, Box<dyn std::error::Error>> {
+        let tests_dir = project.root_path().join("tests");
+        let mut directories = project.src_dirs();
+        directories.push(tests_dir.clone());
+        for dependency in project.load_dependencies()? {
+            directories.extend(dependency.src_dirs());
+        }
+        let mut sources = BTreeMap::<path::PathBuf, String>::new();
+        for directory in directories {
+            for path in bsv::find_packages(&directory).into_values().flatten() {
+                let contents = fs::read_to_string(&path)?;
+                sources.insert(path, contents);
+            }
+        }
+
+        let topmodule_re = Regex::new(&format!(r"{}topmodule\s+(\w+)", bsv::DIRECTIVE))?;
+        let top_file = project.top_file();
+        let mut roots: Vec<Root> = sources
+            .get(&top_file)
+            .into_iter()
+            .flat_map(|contents| topmodule_re.captures_iter(contents))
+            .map(|capture| Root {
+                kind: RootKind::Top,
+                name: capture[1].to_string(),
+                path: top_file.clone(),
+            })
+            .collect();
+        let project_sources = sources
+            .keys()
+            .filter(|path| path.starts_with(project.root_path()));
+        for path in project_sources {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            if project.is_unit_test(path) || path.starts_with(&tests_dir) {
+                roots.push(Root {
+                    kind: RootKind::Test,
+                    name,
+                    path: path.clone(),
+                });
+            }
+        }
+        Ok(Graph::new(&sources, roots))
+    }
+
+    /// The sources a source includes, transitively (including itself).
+    pub fn reachable(&self, from: &path::Path) -> BTreeSet<path::PathBuf> {
+        let mut reachable = BTreeSet::<path::PathBuf>::new();
+        let mut remaining = vec![from.to_path_buf()];
+        while let Some(source) = remaining.pop() {
+            if reachable.insert(source.clone()) {
+                remaining.extend(
+                    self.edges
+                        .get(&source)
+                        .into_iter()
+                        .flatten()
+                        .map(|e| e.to.clone()),
+                );
+            }
+        }
+        reachable
+    }
+
+    /// The shortest chain of edges from one source to another, if it includes it.
+    pub fn chain(&self, from: &path::Path, to: &path::Path) -> Option<Vec<(&path::Path, &Edge)>> {
+        let (from, _) = self.edges.get_key_value(from)?;
+        let mut previous = BTreeMap::<&path::Path, (&path::Path, &Edge)>::new();
+        let mut remaining = VecDeque::from([from.as_path()]);
+        while let Some(source) = remaining.pop_front() {
+            if source == to {
+                let mut chain = Vec::new();
+                let mut current = to;
+                while let Some((parent, edge)) = previous.get(current).copied() {
+                    chain.push((parent, edge));
+                    current = parent;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for edge in self.edges.get(source).into_iter().flatten() {
+                if edge.to != *from && !previous.contains_key(edge.to.as_path()) {
+                    previous.insert(&edge.to, (source, edge));
+                    remaining.push_back(&edge.to);
+                }
+            }
+        }
+        None
+    }
+
+    /// The roots that include a source.
+    pub fn including_roots(&self, source: &path::Path) -> Vec<&Root> {
+        self.roots
+            .iter()
+            .filter(|root| self.reachable(&root.path).contains(source))
+            .collect()
+    }
+}
+
+/// Finds the source of a module (`mkName` or `Name`), a package or a file.
+fn find_source(graph: &Graph, query: &str) -> Option<path::PathBuf> {
+    if let Ok(file) = path::Path::new(query).canonicalize() {
+        let source = graph
+            .edges
+            .keys()
+            .find(|source| source.canonicalize().is_ok_and(|source| source == file));
+        if let Some(source) = source {
+            return Some(source.clone());
+        }
+    }
+    let package = graph
+        .edges
+        .keys()
+        .find(|source| source.file_stem().is_some_and(|stem| *stem == *query));
+    if let Some(package) = package {
+        return Some(package.clone());
+    }
+    let directories: BTreeSet<path::PathBuf> = graph
+        .edges
+        .keys()
+        .filter_map(|source| source.parent().map(path::Path::to_path_buf))
+        .collect();
+    bsv::find_module(directories.iter(), query).map(|location| location.path)
+}
+
+/// Implements `dolly why`: the chain of imports and //!submodule directives by which each top
+/// module and test includes a module, package or file.
+pub fn why(project: &Project, query: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let graph = Graph::load(project)?;
+    let Some(source) = find_source(&graph, query) else {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No module, package or source named '{}'",
+            query
+        ))));
+    };
+    let relative = |path: &path::Path| {
+        path.strip_prefix(project.root_path())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let mut report = String::new();
+    for root in &graph.roots {
+        let Some(chain) = graph.chain(&root.path, &source) else {
+            continue;
+        };
+        report.push_str(&format!("{}:\n", root));
+        if chain.is_empty() {
+            report.push_str(&format!("  {} is its source\n", relative(&source)));
+        }
+        for (from, edge) in chain {
+            report.push_str(&format!(
+                "  {} -> {} ({})\n",
+                relative(from),
+                relative(&edge.to),
+                edge
+            ));
+        }
+    }
+    if report.is_empty() {
+        report = format!(
+            "{} is included by no top module or test\n",
+            relative(&source)
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn source_graph() {
+        let sources = BTreeMap::from([
+            (
+                path::PathBuf::from("src/Top.bsv"),
+                "//!topmodule mkTop\n//!submodule core\nimport Core::*;\nimport FIFO::*;\n"
+                    .to_string(),
+            ),
+            (
+                path::PathBuf::from("src/core/Core.bsv"),
+                "import Alu::*;\n".to_string(),
+            ),
+            (path::PathBuf::from("src/core/Alu.bsv"), String::new()),
+            (
+                path::PathBuf::from("src/core/Alu_tb.bsv"),
+                "import Alu::*;\n".to_string(),
+            ),
+            (path::PathBuf::from("src/Old.bsv"), String::new()),
+        ]);
+        let roots = vec![
+            Root {
+                kind: RootKind::Top,
+                name: "mkTop".to_string(),
+                path: "src/Top.bsv".into(),
+            },
+            Root {
+                kind: RootKind::Test,
+                name: "Alu_tb".to_string(),
+                path: "src/core/Alu_tb.bsv".into(),
+            },
+        ];
+        let graph = Graph::new(&sources, roots);
+        assert_eq!(
+            graph.edges[path::Path::new("src/Top.bsv")]
+                .iter()
+                .map(Edge::to_string)
+                .collect::<Vec<_>>(),
+            ["import Core", "//!submodule core"]
+        );
+        assert_eq!(graph.reachable(path::Path::new("src/Top.bsv")).len(), 3);
+
+        let chain = graph
+            .chain(
+                path::Path::new("src/Top.bsv"),
+                path::Path::new("src/core/Alu.bsv"),
+            )
+            .unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1].0, path::Path::new("src/core/Core.bsv"));
+        assert_eq!(chain[1].1.to_string(), "import Alu");
+
+        let roots = graph.including_roots(path::Path::new("src/core/Alu.bsv"));
+        assert_eq!(
+            roots
+                .iter()
+                .map(|root| root.to_string())
+                .collect::<Vec<_>>(),
+            ["top mkTop", "test Alu_tb"]
+        );
+        assert!(graph
+            .including_roots(path::Path::new("src/Old.bsv"))
+            .is_empty());
+    }
+
+    #[test]
+    fn why_included() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        for (file, contents) in [
+            ("src/Soc.bsv", "//!topmodule mkTop\nimport Core::*;\n"),
+            ("src/Core.bsv", "import Alu::*;\n"),
+            ("src/Alu.bsv", "module mkAlu(Empty);\nendmodule\n"),
+            ("src/Old.bsv", ""),
+            ("tests/Alu_tb.bsv", "import Alu::*;\n"),
+        ] {
+            fs::write(root.join(file), contents).unwrap();
+        }
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+
+        // A module, its package and its file all name the same source.
+        let report = why(&project, "mkAlu").unwrap();
+        assert_eq!(
+            report,
+            "top mkTop:\n  src/Soc.bsv -> src/Core.bsv (import Core)\n  src/Core.bsv -> src/Alu.bsv (import Alu)\n\
+             test Alu_tb:\n  tests/Alu_tb.bsv -> src/Alu.bsv (import Alu)\n"
+        );
+        assert_eq!(why(&project, "Alu").unwrap(), report);
+        let file = root.join("src").join("Alu.bsv");
+        assert_eq!(why(&project, &file.to_string_lossy()).unwrap(), report);
+        assert!(why(&project, "Soc")
+            .unwrap()
+            .starts_with("top mkTop:\n  src/Soc.bsv is its source\n"));
+        assert_eq!(
+            why(&project, "Old").unwrap(),
+            "src/Old.bsv is included by no top module or test\n"
+        );
+        assert!(why(&project, "mkMissing").is_err());
+    }
+}
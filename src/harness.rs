@@ -0,0 +1,268 @@
+//! The Verilog toplevel of Verilator testbenches: it instantiates the testbench module bsc
+//! generated, drives its CLK and RST_N, and implements the plusargs dolly runs simulations
+//! with (+bscvcd to dump waves to dump.vcd, +timeout=<cycles> to fail a hung test).  Its timing
+//! is set in dolly.toml, along with any other clocks of the testbench (the Clock arguments of
+//! its module, which bsc makes CLK_<name> ports) and the resets of their domains:
+//!
+//! ```toml
+//! [test.harness]
+//! clock-period = 10 # ns
+//! reset-cycles = 4
+//! timeout = 100000  # cycles
+//!
+//! [[test.harness.clocks]]
+//! port = "CLK_fast"
+//! period = 2.5
+//! phase = 0.5
+//! reset = "RST_N_fast"
+//! ```
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// The name of the harness module, the toplevel Verilator builds.
+pub const MODULE: &str = "dolly_harness";
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HarnessConfig {
+    pub clock_period: f64,        // In ns
+    pub reset_cycles: u32,        // Clock cycles RST_N is held low for
+    pub timeout: Option<u64>,     // Cycles after which a test fails, unless +timeout says otherwise
+    pub clocks: Vec<ClockConfig>, // The clocks besides CLK
+}
+
+impl Default for HarnessConfig {
+    fn default() -> Self {
+        Self {
+            clock_period: 10.0,
+            reset_cycles: 1,
+            timeout: None,
+            clocks: Vec::new(),
+        }
+    }
+}
+
+/// A clock of the testbench besides its default one.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ClockConfig {
+    pub port: String, // The testbench's input, e.g. CLK_fast
+    pub period: f64,  // In ns
+    #[serde(default)]
+    pub phase: f64, // The delay (in ns) of its first rising edge after that of CLK (at half its period)
+    pub reset: Option<String>, // The input of the reset in its domain, e.g. RST_N_fast
+    #[serde(default = "default_reset_cycles")]
+    pub reset_cycles: u32, // Cycles of this clock the reset is held low for
+}
+
+fn default_reset_cycles() -> u32 {
+    1
+}
+
+/// A clock the harness drives, with its reset.
+struct Clock<'a> {
+    port: &'a str,
+    period: f64,
+    phase: f64,
+    delay: f64, // Before it starts toggling, so that its first rising edge is at its phase
+    reset: Option<&'a str>,
+    reset_cycles: u32,
+}
+
+impl HarnessConfig {
+    /// The clocks to drive, the default one first, checking that they are well formed.
+    fn clocks(&self) -> Result<Vec<Clock<'_>>, std::io::Error> {
+        let mut clocks = vec![Clock {
+            port: "CLK",
+            period: self.clock_period,
+            phase: 0.0,
+            delay: 0.0,
+            reset: Some("RST_N"),
+            reset_cycles: self.reset_cycles,
+        }];
+        clocks.extend(self.clocks.iter().map(|clock| Clock {
+            port: &clock.port,
+            period: clock.period,
+            phase: clock.phase,
+            // CLK rises at half its period, and a clock toggling from its delay at half its own.
+            delay: self.clock_period / 2.0 + clock.phase - clock.period / 2.0,
+            reset: clock.reset.as_deref(),
+            reset_cycles: clock.reset_cycles,
+        }));
+
+        let mut ports: Vec<&str> = Vec::new();
+        for clock in &clocks {
+            if clock.period <= 0.0 || clock.phase < 0.0 {
+                return Err(std::io::Error::other(format!(
+                    "[test.harness]: the clock {} needs a positive period and a phase of 0 or more",
+                    clock.port
+                )));
+            }
+            if clock.delay < 0.0 {
+                return Err(std::io::Error::other(format!(
+                    "[test.harness]: the clock {} first rises half its period after the start, \
+                     so it needs a phase of at least {} after CLK",
+                    clock.port,
+                    clock.phase - clock.delay
+                )));
+            }
+            for port in std::iter::once(clock.port).chain(clock.reset) {
+                if ports.contains(&port) {
+                    return Err(std::io::Error::other(format!(
+                        "[test.harness]: the port {} is driven twice",
+                        port
+                    )));
+                }
+                ports.push(port);
+            }
+        }
+        Ok(clocks)
+    }
+}
+
+/// Generates the harness for a testbench's top module.
+pub fn generate(top_module: &str, config: &HarnessConfig) -> Result<String, std::io::Error> {
+    let clocks = config.clocks()?;
+    let mut harness = String::new();
+    writeln!(harness, "// Generated by dolly -- do not edit.").unwrap();
+    writeln!(harness, "`timescale 1ns / 1ps").unwrap();
+    writeln!(harness, "module {};", MODULE).unwrap();
+    for clock in &clocks {
+        writeln!(harness, "    reg {} = 1'b0;", clock.port).unwrap();
+        if let Some(reset) = clock.reset {
+            writeln!(harness, "    reg {} = 1'b0;", reset).unwrap();
+        }
+    }
+    writeln!(harness, "    reg [63:0] cycle = 0;").unwrap();
+    writeln!(harness, "    reg [63:0] timeout = 0;").unwrap();
+    writeln!(harness).unwrap();
+    let connections: Vec<String> = clocks
+        .iter()
+        .flat_map(|clock| std::iter::once(clock.port).chain(clock.reset))
+        .map(|port| format!(".{}({})", port, port))
+        .collect();
+    writeln!(
+        harness,
+        "    {} top({});",
+        top_module,
+        connections.join(", ")
+    )
+    .unwrap();
+
+    for clock in &clocks {
+        writeln!(harness).unwrap();
+        writeln!(harness, "    initial begin").unwrap();
+        if clock.delay > 0.0 {
+            writeln!(harness, "        #{};", clock.delay).unwrap();
+        }
+        writeln!(
+            harness,
+            "        forever #{} {} = ~{};",
+            clock.period / 2.0,
+            clock.port,
+            clock.port
+        )
+        .unwrap();
+        writeln!(harness, "    end").unwrap();
+        if let Some(reset) = clock.reset {
+            writeln!(harness, "    initial begin").unwrap();
+            writeln!(
+                harness,
+                "        repeat ({}) @(posedge {});",
+                clock.reset_cycles, clock.port
+            )
+            .unwrap();
+            writeln!(
+                harness,
+                "        @(negedge {}) {} = 1'b1;",
+                clock.port, reset
+            )
+            .unwrap();
+            writeln!(harness, "    end").unwrap();
+        }
+    }
+
+    writeln!(harness).unwrap();
+    writeln!(harness, "    initial begin").unwrap();
+    writeln!(harness, "        if ($test$plusargs(\"bscvcd\")) begin").unwrap();
+    writeln!(harness, "            $dumpfile(\"dump.vcd\");").unwrap();
+    writeln!(harness, "            $dumpvars;").unwrap();
+    writeln!(harness, "        end").unwrap();
+    writeln!(
+        harness,
+        "        if (!$value$plusargs(\"timeout=%d\", timeout)) timeout = {};",
+        config.timeout.unwrap_or(0)
+    )
+    .unwrap();
+    writeln!(harness, "    end").unwrap();
+    writeln!(harness).unwrap();
+    writeln!(harness, "    always @(posedge CLK) begin").unwrap();
+    writeln!(harness, "        cycle <= cycle + 1;").unwrap();
+    writeln!(
+        harness,
+        "        if (timeout != 0 && cycle >= timeout) begin"
+    )
+    .unwrap();
+    writeln!(
+        harness,
+        "            $display(\">>>FAIL: timeout after %0d cycles\", cycle);"
+    )
+    .unwrap();
+    writeln!(harness, "            $fatal(1);").unwrap();
+    writeln!(harness, "        end").unwrap();
+    writeln!(harness, "    end").unwrap();
+    writeln!(harness, "endmodule").unwrap();
+    Ok(harness)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn harness_timing() {
+        let harness = generate(
+            "mkFifo_tb",
+            &HarnessConfig {
+                clock_period: 5.0,
+                reset_cycles: 4,
+                timeout: Some(1000),
+                clocks: Vec::new(),
+            },
+        )
+        .unwrap();
+        assert!(harness.contains("    mkFifo_tb top(.CLK(CLK), .RST_N(RST_N));\n"));
+        assert!(harness.contains("        forever #2.5 CLK = ~CLK;\n"));
+        assert!(harness.contains("        repeat (4) @(posedge CLK);\n"));
+        assert!(harness.contains("timeout)) timeout = 1000;\n"));
+    }
+
+    #[test]
+    fn harness_clocks() {
+        let config: HarnessConfig = toml::from_str(
+            "[[clocks]]\nport = \"CLK_fast\"\nperiod = 4\nphase = 1\nreset = \"RST_N_fast\"\n\
+             reset-cycles = 3\n\n[[clocks]]\nport = \"CLK_slow\"\nperiod = 30\nphase = 10\n",
+        )
+        .unwrap();
+        let harness = generate("mkCdc_tb", &config).unwrap();
+        assert!(harness.contains(
+            "    mkCdc_tb top(.CLK(CLK), .RST_N(RST_N), .CLK_fast(CLK_fast), \
+             .RST_N_fast(RST_N_fast), .CLK_slow(CLK_slow));\n"
+        ));
+        // CLK rises at 5, CLK_fast at 4 + 2 and CLK_slow at 0 + 15.
+        assert!(harness.contains("        #4;\n        forever #2 CLK_fast = ~CLK_fast;\n"));
+        assert!(harness.contains("    initial begin\n        forever #15 CLK_slow = ~CLK_slow;\n"));
+        assert!(harness.contains("        repeat (3) @(posedge CLK_fast);\n"));
+        assert!(!harness.contains("RST_N_slow"));
+
+        let twice: HarnessConfig =
+            toml::from_str("[[clocks]]\nport = \"CLK\"\nperiod = 4\n").unwrap();
+        assert!(generate("mkCdc_tb", &twice).is_err());
+        let early: HarnessConfig =
+            toml::from_str("[[clocks]]\nport = \"CLK_slow\"\nperiod = 30\nphase = 5\n").unwrap();
+        assert!(generate("mkCdc_tb", &early)
+            .unwrap_err()
+            .to_string()
+            .ends_with("needs a phase of at least 10 after CLK"));
+    }
+}
@@ -0,0 +1,233 @@
+//! `dolly export --hierarchy`: the instance tree of each top module, read from the Verilog bsc
+//! generated, as JSON for floorplanning, documentation and power estimation scripts.  Each
+//! instance has its module, its name and its parameters (as written in the Verilog, such as
+//! 32'd8); the instances of generated modules have theirs, while the primitives are leaves:
+//!
+//! ```json
+//! [{"top": "mkTop", "module": "mkTop", "instances": [
+//!   {"name": "queue", "module": "FIFO2", "parameters": {"width": "32'd8"}, "instances": []}
+//! ]}]
+//! ```
+use super::history::quote;
+use super::project::Project;
+use super::size::KEYWORDS;
+use super::verilog;
+use regex::Regex;
+use std::{collections::BTreeMap, fs, path};
+
+/// An instance in a module's Verilog.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub name: String,
+    pub module: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+/// The text from `open` (just after an opening parenthesis) to its closing parenthesis, and
+/// the offset just after that.
+fn balanced(source: &str, open: usize) -> Option<(&str, usize)> {
+    let mut depth = 1;
+    for (offset, c) in source[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&source[open..open + offset], open + offset + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The parameters of a `#(.name(value), ...)` list.
+fn parameters(list: &str) -> Vec<(String, String)> {
+    let parameter_re = Regex::new(r"\.(\w+)\s*\(").unwrap();
+    let mut parameters = Vec::new();
+    let mut offset = 0;
+    while let Some(capture) = parameter_re.captures(&list[offset..]) {
+        let open = offset + capture.get(0).unwrap().end();
+        let Some((value, end)) = balanced(list, open) else {
+            break;
+        };
+        parameters.push((capture[1].to_string(), value.trim().to_string()));
+        offset = end;
+    }
+    parameters
+}
+
+/// The instances in a bsc Verilog module.
+pub fn instances(source: &str) -> Vec<Instance> {
+    let start_re = Regex::new(r"(?m)^[ \t]*(\w+)[ \t]+(#\s*\(|\w+[ \t]*\()").unwrap();
+    let name_re = Regex::new(r"^\s*(\w+)\s*\(").unwrap();
+    let mut instances = Vec::new();
+    let mut offset = 0;
+    for capture in start_re.captures_iter(source) {
+        let whole = capture.get(0).unwrap();
+        // Skips the lines of the connections of the last instance.
+        if whole.start() < offset {
+            continue;
+        }
+        let module = &capture[1];
+        if KEYWORDS.contains(&module) {
+            continue;
+        }
+        let opening = &capture[2];
+        let (parameters, rest) = if opening.starts_with('#') {
+            let Some((list, end)) = balanced(source, whole.end()) else {
+                continue;
+            };
+            (parameters(list), end)
+        } else {
+            (Vec::new(), capture.get(2).unwrap().start())
+        };
+        let Some(name) = name_re.captures(&source[rest..]) else {
+            continue;
+        };
+        instances.push(Instance {
+            name: name[1].to_string(),
+            module: module.to_string(),
+            parameters,
+        });
+        offset = rest + name.get(0).unwrap().end();
+    }
+    instances
+}
+
+/// Writes the JSON of a module's instances, recursively, at an indentation.
+fn write_instances(
+    json: &mut String,
+    modules: &BTreeMap<String, Vec<Instance>>,
+    module: &str,
+    depth: usize,
+) {
+    let instances = modules.get(module).map(Vec::as_slice).unwrap_or_default();
+    if instances.is_empty() {
+        json.push_str("[]");
+        return;
+    }
+    let indent = "  ".repeat(depth);
+    json.push_str("[\n");
+    for (index, instance) in instances.iter().enumerate() {
+        let parameters: Vec<String> = instance
+            .parameters
+            .iter()
+            .map(|(name, value)| format!("{}: {}", quote(name), quote(value)))
+            .collect();
+        json.push_str(&format!(
+            "{}  {{\"name\": {}, \"module\": {}, \"parameters\": {{{}}}, \"instances\": ",
+            indent,
+            quote(&instance.name),
+            quote(&instance.module),
+            parameters.join(", ")
+        ));
+        // Modules can't instantiate themselves, so a deeper hierarchy is a loop.
+        if depth <= modules.len() && instance.module != module {
+            write_instances(json, modules, &instance.module, depth + 1);
+        } else {
+            json.push_str("[]");
+        }
+        json.push('}');
+        json.push_str(if index + 1 < instances.len() {
+            ",\n"
+        } else {
+            "\n"
+        });
+    }
+    json.push_str(&format!("{}]", indent));
+}
+
+/// The generated modules in a build directory, by (Verilog) name, with their instances.
+fn modules(build_path: &path::Path) -> std::io::Result<BTreeMap<String, Vec<Instance>>> {
+    let mut modules = BTreeMap::new();
+    for entry in build_path.read_dir()? {
+        let file = entry?.path();
+        if !file
+            .extension()
+            .is_some_and(|ext| ext == "v" || ext == "sv")
+        {
+            continue;
+        }
+        let source = fs::read_to_string(&file)?;
+        if let Some(module) = verilog::defined_modules(&source).into_iter().next() {
+            modules.insert(module, instances(&source));
+        }
+    }
+    Ok(modules)
+}
+
+/// Implements `dolly export --hierarchy`: the JSON hierarchy of the built top modules.
+pub fn export(
+    project: &Project,
+    top_modules: &[String],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let prefix = project.verilog.module_prefix.as_deref().unwrap_or_default();
+    let mut tops = Vec::new();
+    for top_module in top_modules {
+        let modules = modules(&project.target_dir().join(top_module))?;
+        let verilog_top = format!("{}{}", prefix, top_module);
+        let mut json = format!(
+            "  {{\"top\": {}, \"module\": {}, \"instances\": ",
+            quote(top_module),
+            quote(&verilog_top)
+        );
+        write_instances(&mut json, &modules, &verilog_top, 1);
+        json.push('}');
+        tops.push(json);
+    }
+    Ok(format!("[\n{}\n]\n", tops.join(",\n")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verilog_instances() {
+        let top = "module mkTop(CLK, RST_N);
+  input CLK;
+  FIFO2 #(.width(32'd8),
+	  .guarded(1'd1)) queue(.RST(RST_N), .CLK(CLK));
+  mkCore core(.CLK(CLK), .RST_N(RST_N));
+  always@(posedge CLK)
+  begin
+    if (RST_N == `BSV_RESET_VALUE) count <= 8'd0;
+  end
+endmodule
+";
+        let found = instances(top);
+        assert_eq!(
+            found,
+            vec![
+                Instance {
+                    name: "queue".to_string(),
+                    module: "FIFO2".to_string(),
+                    parameters: vec![
+                        ("width".to_string(), "32'd8".to_string()),
+                        ("guarded".to_string(), "1'd1".to_string())
+                    ],
+                },
+                Instance {
+                    name: "core".to_string(),
+                    module: "mkCore".to_string(),
+                    parameters: Vec::new(),
+                },
+            ]
+        );
+
+        let modules = BTreeMap::from([
+            ("mkTop".to_string(), found),
+            ("mkCore".to_string(), Vec::new()),
+        ]);
+        let mut json = String::new();
+        write_instances(&mut json, &modules, "mkTop", 0);
+        assert_eq!(
+            json,
+            "[\n  {\"name\": \"queue\", \"module\": \"FIFO2\", \"parameters\": {\"width\": \"32'd8\", \
+             \"guarded\": \"1'd1\"}, \"instances\": []},\n  {\"name\": \"core\", \"module\": \
+             \"mkCore\", \"parameters\": {}, \"instances\": []}\n]"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path,
+};
+
+/// Runs kept per test when computing the recent failure rate and average duration.
+const RECENT_RUNS: usize = 20;
+/// Runs averaged on each side of a duration trend.
+const TREND_RUNS: usize = 5;
+
+/// One run of a test, as recorded (one JSON object per line) in .dolly/history/tests.jsonl.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestRun {
+    pub timestamp: u64, // Seconds since the Unix epoch
+    pub test: String,
+    pub passed: bool,
+    pub duration: f64, // Seconds taken to build and run the test
+}
+
+pub fn history_file(project_root: &path::Path) -> path::PathBuf {
+    project_root
+        .join(".dolly")
+        .join("history")
+        .join("tests.jsonl")
+}
+
+impl TestRun {
+    pub fn new(test: String, passed: bool, duration: f64) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        TestRun {
+            timestamp,
+            test,
+            passed,
+            duration,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"timestamp":{},"test":{},"result":"{}","duration":{:.3}}}"#,
+            self.timestamp,
+            quote(&self.test),
+            if self.passed { "pass" } else { "fail" },
+            self.duration
+        )
+    }
+
+    fn from_json(line: &str) -> Option<Self> {
+        let fields = parse_object(line)?;
+        Some(TestRun {
+            timestamp: fields.get("timestamp")?.parse().ok()?,
+            test: fields.get("test")?.clone(),
+            passed: fields.get("result")? == "pass",
+            duration: fields.get("duration")?.parse().ok()?,
+        })
+    }
+}
+
+/// Appends runs to the history file.
+pub fn record(file: &path::Path, runs: &[TestRun]) -> io::Result<()> {
+    if runs.is_empty() {
+        return Ok(());
+    }
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut history = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?;
+    for run in runs {
+        writeln!(history, "{}", run.to_json())?;
+    }
+    Ok(())
+}
+
+/// The recorded runs, oldest first.  Lines that can't be parsed are skipped.
+pub fn load(file: &path::Path) -> Vec<TestRun> {
+    fs::read_to_string(file)
+        .map(|contents| contents.lines().filter_map(TestRun::from_json).collect())
+        .unwrap_or_default()
+}
+
+/// A string as a JSON string literal.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses a flat JSON object whose values are strings or numbers, such as the lines written
+/// by `TestRun::to_json`.
+fn parse_object(line: &str) -> Option<BTreeMap<String, String>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = BTreeMap::<String, String>::new();
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    if chars.next()? != '{' {
+        return None;
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next_if_eq(&'}').is_some() {
+            break;
+        }
+        let key = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        let value = if chars.peek() == Some(&'"') {
+            parse_string(&mut chars)?
+        } else {
+            let mut value = String::new();
+            while let Some(c) = chars.next_if(|c| *c != ',' && *c != '}') {
+                value.push(c);
+            }
+            value.trim().to_string()
+        };
+        fields.insert(key, value);
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => break,
+            _ => return None,
+        }
+    }
+    Some(fields)
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    text.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+fn average(durations: &[f64]) -> f64 {
+    durations.iter().sum::<f64>() / durations.len().max(1) as f64
+}
+
+/// The `dolly report tests` report: the slowest tests, the tests that both passed and failed
+/// recently, and the largest changes in duration, `limit` of each.
+pub fn report(runs: &[TestRun], limit: usize) -> String {
+    let mut by_test = BTreeMap::<&str, Vec<&TestRun>>::new();
+    for run in runs {
+        by_test.entry(&run.test).or_default().push(run);
+    }
+    let recent = |runs: &[&TestRun], count: usize| -> Vec<f64> {
+        runs.iter()
+            .rev()
+            .take(count)
+            .map(|run| run.duration)
+            .collect()
+    };
+    let width = by_test.keys().map(|test| test.len()).max().unwrap_or(0);
+    let mut report = format!("{} runs of {} tests recorded\n", runs.len(), by_test.len());
+
+    report.push_str("\nSlowest tests (average of recent runs):\n");
+    let mut slowest: Vec<(&str, f64)> = by_test
+        .iter()
+        .map(|(test, runs)| (*test, average(&recent(runs, RECENT_RUNS))))
+        .collect();
+    slowest.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (test, duration) in slowest.iter().take(limit) {
+        report.push_str(&format!("  {:width$}  {:.1}s\n", test, duration));
+    }
+
+    report.push_str("\nFlaky tests (recent failure rate):\n");
+    let mut flaky: Vec<(&str, usize, usize)> = by_test
+        .iter()
+        .map(|(test, runs)| {
+            let recent: Vec<&&TestRun> = runs.iter().rev().take(RECENT_RUNS).collect();
+            let failures = recent.iter().filter(|run| !run.passed).count();
+            (*test, failures, recent.len())
+        })
+        .filter(|(_, failures, total)| *failures > 0 && failures < total)
+        .collect();
+    flaky.sort_by(|a, b| (b.1 * a.2).cmp(&(a.1 * b.2)).then(a.0.cmp(b.0)));
+    if flaky.is_empty() {
+        report.push_str("  none\n");
+    }
+    for (test, failures, total) in flaky.iter().take(limit) {
+        report.push_str(&format!(
+            "  {:width$}  {}/{} failed ({:.0}%)\n",
+            test,
+            failures,
+            total,
+            100.0 * *failures as f64 / *total as f64
+        ));
+    }
+
+    report.push_str("\nDuration trends (recent runs against the runs before them):\n");
+    let mut trends: Vec<(&str, f64, f64)> = by_test
+        .iter()
+        .filter(|(_, runs)| runs.len() > TREND_RUNS)
+        .map(|(test, runs)| {
+            let before = &runs[..runs.len() - TREND_RUNS];
+            (
+                *test,
+                average(&recent(before, TREND_RUNS)),
+                average(&recent(runs, TREND_RUNS)),
+            )
+        })
+        .filter(|(_, before, _)| *before > 0.0)
+        .collect();
+    let change = |before: f64, after: f64| (after - before) / before;
+    trends.sort_by(|a, b| change(b.1, b.2).abs().total_cmp(&change(a.1, a.2).abs()));
+    if trends.is_empty() {
+        report.push_str(&format!(
+            "  none (needs more than {} runs of a test)\n",
+            TREND_RUNS
+        ));
+    }
+    for (test, before, after) in trends.iter().take(limit) {
+        report.push_str(&format!(
+            "  {:width$}  {:.1}s -> {:.1}s ({:+.0}%)\n",
+            test,
+            before,
+            after,
+            100.0 * change(*before, *after)
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recorded_history() {
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("tests.jsonl");
+        let run = |test: &str, passed: bool, duration: f64| TestRun {
+            timestamp: 1_700_000_000,
+            test: test.to_string(),
+            passed,
+            duration,
+        };
+        let mut runs = vec![run("Quote\"d_tb", true, 0.5)];
+        for index in 0..10 {
+            runs.push(run(
+                "Core_tb",
+                index != 3,
+                if index < 5 { 10.0 } else { 20.0 },
+            ));
+            runs.push(run("Alu_tb", true, 1.0));
+        }
+        record(&file, &runs[..1]).unwrap();
+        record(&file, &runs[1..]).unwrap();
+        assert_eq!(load(&file), runs);
+
+        let report = report(&load(&file), 10);
+        assert!(report.contains("21 runs of 3 tests"));
+        assert!(report.contains("Core_tb     15.0s"));
+        assert!(report.contains("Core_tb     1/10 failed (10%)"));
+        assert!(report.contains("Core_tb     10.0s -> 20.0s (+100%)"));
+
+        // Lines cut short or garbled are skipped, and the rest loaded.
+        let good = run("Uart_tb", false, 2.0).to_json();
+        fs::write(
+            &file,
+            [
+                &good[..good.len() / 2],
+                r#"{"timestamp":"soon","test":"Uart_tb","result":"pass","duration":1}"#,
+                r#"{"test":"Uart_tb","result":"pass","duration":1}"#,
+                r#"{"timestamp":1,"test":"Uart\u00"}"#,
+                "[1, 2]",
+                "",
+                &good,
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        assert_eq!(load(&file), [run("Uart_tb", false, 2.0)]);
+
+        // No history is an empty report, and nothing recorded writes no file.
+        assert!(load(&temp.path().join("none.jsonl")).is_empty());
+        let empty = super::report(&[], 10);
+        assert!(empty.contains("0 runs of 0 tests"));
+        assert!(empty.contains("Flaky tests (recent failure rate):\n  none"));
+        record(&temp.path().join("none").join("tests.jsonl"), &[]).unwrap();
+        assert!(!temp.path().join("none").exists());
+
+        // A history that can't be written is an error.
+        assert!(record(&file.join("tests.jsonl"), &runs).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use super::builder::Options;
+use super::codes;
+use super::plugin;
+use super::project::Project;
+use super::runner;
+use super::script;
+use std::{fs, process};
+
+/// The points in the build and test pipelines at which `[hooks]` commands run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    PreBuild, // Before module discovery in 'build' and 'test'
+    PostBuild,
+    PreTest,
+    PostTest, // After the tests have run, whether or not they passed
+}
+
+impl Hook {
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub const ALL: [Hook; 4] = [
+        Hook::PreBuild,
+        Hook::PostBuild,
+        Hook::PreTest,
+        Hook::PostTest,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreBuild => "pre-build",
+            Hook::PostBuild => "post-build",
+            Hook::PreTest => "pre-test",
+            Hook::PostTest => "post-test",
+        }
+    }
+}
+
+/// Runs the project's command for `hook` (if any) with a shell in the project root, then the
+/// stages its script registers for `hook`, failing if either fails.
+pub fn run(
+    project: &Project,
+    hook: Hook,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command = match hook {
+        Hook::PreBuild => &project.hooks.pre_build,
+        Hook::PostBuild => &project.hooks.post_build,
+        Hook::PreTest => &project.hooks.pre_test,
+        Hook::PostTest => &project.hooks.post_test,
+    };
+    let Some(command) = command else {
+        return script::run(project, hook, options);
+    };
+
+    let target_dir = project.target_dir();
+    let mut cmd = process::Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .current_dir(project.root_path())
+        .envs(project.env()?)
+        .env("DOLLY_HOOK", hook.name())
+        .env("DOLLY_PACKAGE", &project.package.name)
+        .env("DOLLY_PROJECT_ROOT", project.root_path())
+        .env("DOLLY_TARGET_DIR", &target_dir);
+    if options.show_commands || options.dry_run {
+        println!("{}", runner::command_line(&cmd));
+    }
+    // The script's stages are listed after the command, as they would run.
+    if options.dry_run {
+        return script::run(project, hook, options);
+    }
+    cmd.env("DOLLY_METADATA", plugin::write_metadata(project)?);
+
+    let log_dir = project.output_dir("hooks");
+    fs::create_dir_all(&log_dir)?;
+    let output = runner::run(
+        &mut cmd,
+        hook.name(),
+        &log_dir.join(format!("{}.log", hook.name())),
+    )?;
+    if !output.status.success() {
+        return Err(Box::new(codes::error(
+            "D0304",
+            format!("The {} hook failed", hook.name()),
+        )));
+    }
+    script::run(project, hook, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hook_environment() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            r#"
+            [package]
+            name = "core"
+            version = "0.1.0"
+
+            [hooks]
+            pre-build = "echo $DOLLY_HOOK $DOLLY_PACKAGE > generated.txt"
+            pre-test = "exit 1"
+            "#,
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let options = Options::default();
+
+        run(&project, Hook::PreBuild, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("generated.txt")).unwrap(),
+            "pre-build core\n"
+        );
+        let error = run(&project, Hook::PreTest, &options).unwrap_err();
+        assert_eq!(error.to_string(), "D0304: The pre-test hook failed");
+        assert!(project.output_dir("hooks").join("pre-test.log").exists());
+        assert!(run(&project, Hook::PostTest, &options).is_ok());
+
+        // A dry run only prints the command.
+        let dry_run = Options {
+            dry_run: true,
+            ..Options::default()
+        };
+        assert!(run(&project, Hook::PreTest, &dry_run).is_ok());
+
+        // Hooks don't run when the [env] they get refers to an undefined variable.
+        fs::remove_file(root.join("generated.txt")).unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[env]\n\
+             TOOLS = \"$DOLLY_HOOK_TEST_UNDEFINED/bin\"\n\n[hooks]\npre-build = \"touch generated.txt\"\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        assert!(run(&project, Hook::PreBuild, &options).is_err());
+        assert!(!root.join("generated.txt").exists());
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn dry_run_with_script() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            r#"
+            [package]
+            name = "core"
+            version = "0.1.0"
+
+            [hooks]
+            pre-build = "touch generated.txt"
+            script = "dolly.rhai"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            root.join("dolly.rhai"),
+            r#"stage("pre-build", "staged", |project| { run("touch staged.txt"); });"#,
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let dry_run = Options {
+            dry_run: true,
+            ..Options::default()
+        };
+
+        // Neither the command nor the stages run, but the script is, as without a command.
+        run(&project, Hook::PreBuild, &dry_run).unwrap();
+        assert!(!root.join("generated.txt").exists());
+        assert!(!root.join("staged.txt").exists());
+        fs::write(
+            root.join("dolly.rhai"),
+            r#"stage("pre-bulid", "typo", |project| {});"#,
+        )
+        .unwrap();
+        let error = run(&project, Hook::PreBuild, &dry_run).unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0010"));
+    }
+}
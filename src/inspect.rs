@@ -0,0 +1,194 @@
+use super::bsv;
+use super::graph::Graph;
+use super::project::Project;
+use regex::Regex;
+use std::{collections::BTreeSet, fs, path};
+
+/// A submodule instantiated by a module (`Reg#(Bit#(8)) count <- mkReg(0);`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub name: String,
+    pub module: String,
+}
+
+/// The instances in the body of a module.  Only constructors following the `mk` naming
+/// convention are taken as modules, since `x <- f` also calls ActionValue methods.
+pub fn parse_instances(source: &str, module: &str) -> Vec<Instance> {
+    let source = bsv::strip_comments(source);
+    let header_re = Regex::new(&format!(
+        r"(?:^|\n)\s*module\s+(?:\[\s*\w+\s*\]\s*)?{}\b",
+        regex::escape(module)
+    ))
+    .unwrap();
+    let instance_re = Regex::new(r"(\w+)\s*<-\s*(mk\w*)\s*(?:#\s*)?[(;]").unwrap();
+    let Some(header) = header_re.find(&source) else {
+        return Vec::new();
+    };
+    let body = &source[header.end()..];
+    let body = &body[..body.find("endmodule").unwrap_or(body.len())];
+    instance_re
+        .captures_iter(body)
+        .map(|capture| Instance {
+            name: capture[1].to_string(),
+            module: capture[2].to_string(),
+        })
+        .collect()
+}
+
+fn method_signature(method: &bsv::Method) -> String {
+    let arguments: Vec<String> = method
+        .arguments
+        .iter()
+        .map(|argument| format!("{} {}", argument.ty, argument.name))
+        .collect();
+    if arguments.is_empty() {
+        format!("method {} {}", method.result, method.name)
+    } else {
+        format!(
+            "method {} {}({})",
+            method.result,
+            method.name,
+            arguments.join(", ")
+        )
+    }
+}
+
+/// The Verilog modules bsc reported a module instantiating (its `<module>.use` file, written
+/// with -show-module-use), from the first build directory that has one.
+fn module_use(target_dir: &path::Path, module: &str) -> Option<(path::PathBuf, Vec<String>)> {
+    let file_name = format!("{}.use", module);
+    let mut remaining = vec![target_dir.to_path_buf()];
+    let mut found = BTreeSet::<path::PathBuf>::new();
+    while let Some(directory) = remaining.pop() {
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.is_dir() {
+                remaining.push(path);
+            } else if path.file_name().is_some_and(|name| *name == *file_name) {
+                found.insert(path);
+            }
+        }
+    }
+    let path = found.into_iter().next()?;
+    let modules = fs::read_to_string(&path)
+        .ok()?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    Some((path, modules))
+}
+
+/// Implements `dolly inspect`: describes a module (`mkName` or `Name`), its interface and
+/// instances, and the top modules and tests that include it.
+pub fn report(project: &Project, module: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let graph = Graph::load(project)?;
+    let directories: BTreeSet<path::PathBuf> = graph
+        .edges
+        .keys()
+        .filter_map(|source| source.parent().map(path::Path::to_path_buf))
+        .collect();
+    let Some(location) = bsv::find_module(directories.iter(), module) else {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Module '{}' not found",
+            module
+        ))));
+    };
+    let relative = |path: &path::Path| {
+        path.strip_prefix(project.root_path())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let mut report = format!("{} ({})\n", location.module.name, relative(&location.path));
+    if let Some(parameters) = &location.module.parameters {
+        report.push_str(&format!("  parameters: {}\n", parameters));
+    }
+    report.push_str(&format!("  interface: {}\n", location.module.interface));
+    if let Some(interface) = bsv::find_interface(directories.iter(), &location.module.interface) {
+        for method in &interface.methods {
+            report.push_str(&format!("    {}\n", method_signature(method)));
+        }
+        for subinterface in &interface.subinterfaces {
+            report.push_str(&format!(
+                "    interface {} {}\n",
+                subinterface.ty, subinterface.name
+            ));
+        }
+    }
+
+    let source = fs::read_to_string(&location.path)?;
+    let instances = parse_instances(&source, &location.module.name);
+    if !instances.is_empty() {
+        report.push_str("  instances:\n");
+        for instance in instances {
+            report.push_str(&format!("    {} <- {}\n", instance.name, instance.module));
+        }
+    }
+    if let Some((path, modules)) = module_use(&project.target_dir(), &location.module.name) {
+        report.push_str(&format!("  Verilog modules used ({}):\n", relative(&path)));
+        for module in modules {
+            report.push_str(&format!("    {}\n", module));
+        }
+    }
+
+    let roots = graph.including_roots(&location.path);
+    if roots.is_empty() {
+        report.push_str("  included by no top module or test\n");
+    } else {
+        report.push_str("  included by:\n");
+        for root in roots {
+            report.push_str(&format!("    {}\n", root));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn module_instances() {
+        let source = r#"
+module mkCore(Core);
+    Reg#(Bit#(32)) pc <- mkReg(0);
+    FIFO#(Word) queue <- mkSizedFIFO(4); // Fetched instructions
+    Alu alu <- mkAlu;
+    rule step;
+        let result <- alu.response.get();
+        let value <- takeValue;
+    endrule
+endmodule
+
+module mkOther(Empty);
+    Reg#(Bool) done <- mkRegU;
+endmodule
+"#;
+        let instances = parse_instances(source, "mkCore");
+        let instances: Vec<(&str, &str)> = instances
+            .iter()
+            .map(|instance| (instance.name.as_str(), instance.module.as_str()))
+            .collect();
+        assert_eq!(
+            instances,
+            [("pc", "mkReg"), ("queue", "mkSizedFIFO"), ("alu", "mkAlu")]
+        );
+        assert!(parse_instances(source, "mkMissing").is_empty());
+
+        let method = bsv::Method {
+            kind: bsv::MethodKind::Action,
+            name: "start".to_string(),
+            result: "Action".to_string(),
+            arguments: vec![bsv::Argument {
+                ty: "Bit#(8)".to_string(),
+                name: "a".to_string(),
+            }],
+        };
+        assert_eq!(method_signature(&method), "method Action start(Bit#(8) a)");
+    }
+}
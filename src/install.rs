@@ -0,0 +1,223 @@
+//! `dolly install`: copies the generated Verilog and its collateral to
+//! `<prefix>/share/dolly/<package name>/`, so that projects built with CMake or Make can use it
+//! without knowing dolly's target directory.
+//!
+//! The installed directory contains
+//!
+//! * `rtl/`: the generated Verilog (or SystemVerilog) and the Bluespec primitives it uses,
+//! * `include/`: the headers bsc generated for BDPI imports and the [memory-map] C header,
+//! * `bdpi/`: the C wrappers bsc generated for BDPI imports,
+//! * `ld/`: the [memory-map] linker script,
+//! * `<name>.f`: a filelist of `rtl/` with absolute paths,
+//! * `<name>Config.cmake` and `<name>.mk`: the installed files as CMake and Make variables,
+//! * `package.toml` and the project's license files, as `dolly package` writes them.
+use super::builder::Builder;
+use super::package;
+use super::project::Project;
+use std::{fs, path};
+
+/// The installed files the CMake and Make variables list, relative to the installed directory.
+#[derive(Debug, Default)]
+struct Installed {
+    verilog: Vec<String>,
+    bdpi: Vec<String>,
+    include_dirs: Vec<String>,
+}
+
+/// `<name>Config.cmake`, for `find_package(<name> CONFIG)` with `<name>_DIR` set to the
+/// installed directory.
+fn cmake_config(name: &str, version: &str, installed: &Installed) -> String {
+    let list = |files: &[String]| {
+        files
+            .iter()
+            .map(|file| format!("\n    \"${{CMAKE_CURRENT_LIST_DIR}}/{}\"", file))
+            .collect::<String>()
+    };
+    format!(
+        "# Written by dolly install.\n\
+         set({name}_VERSION \"{version}\")\n\
+         set({name}_VERILOG_SOURCES{})\n\
+         set({name}_BDPI_SOURCES{})\n\
+         set({name}_INCLUDE_DIRS{})\n\
+         set({name}_FILELIST \"${{CMAKE_CURRENT_LIST_DIR}}/{name}.f\")\n",
+        list(&installed.verilog),
+        list(&installed.bdpi),
+        list(&installed.include_dirs),
+    )
+}
+
+/// `<name>.mk`, for Makefiles to include.
+fn makefile(name: &str, version: &str, installed: &Installed) -> String {
+    let list = |files: &[String]| {
+        files
+            .iter()
+            .map(|file| format!(" \\\n    $({name}_DIR){}", file))
+            .collect::<String>()
+    };
+    format!(
+        "# Written by dolly install.\n\
+         {name}_DIR := $(dir $(lastword $(MAKEFILE_LIST)))\n\
+         {name}_VERSION := {version}\n\
+         {name}_VERILOG_SOURCES :={}\n\
+         {name}_BDPI_SOURCES :={}\n\
+         {name}_INCLUDE_DIRS :={}\n\
+         {name}_FILELIST := $({name}_DIR){name}.f\n",
+        list(&installed.verilog),
+        list(&installed.bdpi),
+        list(&installed.include_dirs),
+    )
+}
+
+/// Copies a file into a subdirectory of the installed directory, returning its installed path
+/// relative to it.
+fn copy(
+    file: &path::Path,
+    directory: &path::Path,
+    subdirectory: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let name = format!(
+        "{}/{}",
+        subdirectory,
+        file.file_name().unwrap().to_string_lossy()
+    );
+    fs::create_dir_all(directory.join(subdirectory))?;
+    fs::copy(file, directory.join(&name))?;
+    Ok(name)
+}
+
+/// Implements `dolly install` for the top modules `builder` built, replacing any earlier
+/// installation of the package, and returns the installed directory.
+pub fn install(
+    project: &Project,
+    builder: &Builder,
+    prefix: &path::Path,
+) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    let name = &project.package.name;
+    let directory = prefix.join("share").join("dolly").join(name);
+    if directory.exists() {
+        fs::remove_dir_all(&directory)?;
+    }
+    fs::create_dir_all(&directory)?;
+    let directory = directory.canonicalize()?;
+
+    let mut installed = Installed::default();
+    for source in package::generated_sources(project, builder)? {
+        let file = copy(&source, &directory, "rtl")?;
+        if !installed.verilog.contains(&file) {
+            installed.verilog.push(file);
+        }
+    }
+
+    // bsc writes the wrappers of BDPI imports next to the Verilog of the modules using them.
+    let mut wrappers: Vec<path::PathBuf> = builder
+        .top_modules()
+        .iter()
+        .flat_map(|top_module| {
+            project
+                .target_dir()
+                .join(top_module)
+                .read_dir()
+                .into_iter()
+                .flatten()
+        })
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("vpi_wrapper_"))
+        })
+        .collect();
+    wrappers.sort();
+    let mut headers = false;
+    for wrapper in wrappers {
+        match wrapper.extension().and_then(|ext| ext.to_str()) {
+            Some("h") => {
+                copy(&wrapper, &directory, "include")?;
+                headers = true;
+            }
+            Some("c") => {
+                let file = copy(&wrapper, &directory, "bdpi")?;
+                if !installed.bdpi.contains(&file) {
+                    installed.bdpi.push(file);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(memory_map) = &project.memory_map {
+        let header = project.root_path().join(&memory_map.c_header);
+        if header.is_file() {
+            copy(&header, &directory, "include")?;
+            headers = true;
+        }
+        let linker_script = project.root_path().join(&memory_map.linker_script);
+        if linker_script.is_file() {
+            copy(&linker_script, &directory, "ld")?;
+        }
+    }
+    if headers {
+        installed.include_dirs.push("include".to_string());
+    }
+
+    let filelist: String = installed
+        .verilog
+        .iter()
+        .map(|file| format!("{}\n", directory.join(file).to_string_lossy()))
+        .collect();
+    fs::write(directory.join(format!("{}.f", name)), filelist)?;
+    let version = &project.package.version;
+    fs::write(
+        directory.join(format!("{}Config.cmake", name)),
+        cmake_config(name, version, &installed),
+    )?;
+    fs::write(
+        directory.join(format!("{}.mk", name)),
+        makefile(name, version, &installed),
+    )?;
+    fs::write(
+        directory.join("package.toml"),
+        package::metadata(
+            &project.package,
+            builder.top_modules(),
+            builder.options().emit,
+        ),
+    )?;
+    for license in package::license_files(project) {
+        fs::copy(&license, directory.join(license.file_name().unwrap()))?;
+    }
+    Ok(directory)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_system_variables() {
+        let installed = Installed {
+            verilog: vec!["rtl/mkUart.v".to_string(), "rtl/FIFO2.v".to_string()],
+            bdpi: Vec::new(),
+            include_dirs: vec!["include".to_string()],
+        };
+        assert_eq!(
+            cmake_config("Uart", "1.2.0", &installed),
+            "# Written by dolly install.\n\
+             set(Uart_VERSION \"1.2.0\")\n\
+             set(Uart_VERILOG_SOURCES\n    \"${CMAKE_CURRENT_LIST_DIR}/rtl/mkUart.v\"\n    \
+             \"${CMAKE_CURRENT_LIST_DIR}/rtl/FIFO2.v\")\n\
+             set(Uart_BDPI_SOURCES)\n\
+             set(Uart_INCLUDE_DIRS\n    \"${CMAKE_CURRENT_LIST_DIR}/include\")\n\
+             set(Uart_FILELIST \"${CMAKE_CURRENT_LIST_DIR}/Uart.f\")\n"
+        );
+        assert_eq!(
+            makefile("Uart", "1.2.0", &installed),
+            "# Written by dolly install.\n\
+             Uart_DIR := $(dir $(lastword $(MAKEFILE_LIST)))\n\
+             Uart_VERSION := 1.2.0\n\
+             Uart_VERILOG_SOURCES := \\\n    $(Uart_DIR)rtl/mkUart.v \\\n    \
+             $(Uart_DIR)rtl/FIFO2.v\n\
+             Uart_BDPI_SOURCES :=\n\
+             Uart_INCLUDE_DIRS := \\\n    $(Uart_DIR)include\n\
+             Uart_FILELIST := $(Uart_DIR)Uart.f\n"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use super::bsv;
+use super::builder::Builder;
+use super::elf::Elf;
+use super::jobserver;
+use super::net;
+use super::project::{self, IsaTestConfig, Project};
+use colored::Colorize;
+use std::{fs, path, process};
+use tracing::{error, trace};
+
+const RISCV_TESTS_URL: &str = "https://github.com/riscv-software-src/riscv-tests";
+
+/// Finds the built ISA test ELFs (e.g. isa/rv32ui-p-add) belonging to the configured suites.
+pub fn find_tests(isa_dir: &path::Path, suites: &[String]) -> Vec<path::PathBuf> {
+    let mut tests: Vec<path::PathBuf> = isa_dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_none())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            suites
+                .iter()
+                .any(|suite| name.starts_with(&format!("{}-", suite)))
+        })
+        .collect();
+    tests.sort();
+    tests
+}
+
+/// Clones and builds riscv-tests if the configured suites have no ELFs yet.
+fn prepare_tests(
+    project: &Project,
+    config: &IsaTestConfig,
+) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    let riscv_tests = project.root_path().join(&config.riscv_tests);
+    let isa_dir = riscv_tests.join("isa");
+
+    let tests = find_tests(&isa_dir, &config.suites);
+    if !tests.is_empty() {
+        return Ok(tests);
+    }
+
+    if !riscv_tests.exists() {
+        net::forbid_fetching(&[format!(
+            "riscv-tests from {} (into {})",
+            RISCV_TESTS_URL,
+            riscv_tests.to_string_lossy()
+        )])?;
+        println!(
+            "Downloading riscv-tests to {}",
+            riscv_tests.to_string_lossy()
+        );
+        let status = net::git()
+            .arg("clone")
+            .arg("--recursive")
+            .arg(RISCV_TESTS_URL)
+            .arg(&riscv_tests)
+            .status()?;
+        if !status.success() {
+            return Err(Box::new(std::io::Error::other(
+                "Unable to clone riscv-tests",
+            )));
+        }
+        // Let `dolly clean --all` know that this checkout can be removed.
+        fs::write(riscv_tests.join(".git").join(project::CLONED_MARKER), "")?;
+    }
+
+    println!("Building riscv-tests");
+    let output = jobserver::make()
+        .arg("-C")
+        .arg(&isa_dir)
+        .arg(format!("XLEN={}", config.xlen))
+        .arg(format!("RISCV_PREFIX={}", project.toolchain.riscv.prefix))
+        .output()?;
+    if !output.status.success() {
+        error!(
+            "Building riscv-tests failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(std::io::Error::other(
+            "Unable to build riscv-tests",
+        )));
+    }
+
+    Ok(find_tests(&isa_dir, &config.suites))
+}
+
+/// Builds the configured testbench and runs each riscv-tests ELF on it as a memory image.
+pub fn run(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let config = &project.isa_test;
+    let Some(top) = &config.top else {
+        return Err(Box::new(std::io::Error::other(
+            "No testbench configured: set 'top' in the [isa-test] section of dolly.toml",
+        )));
+    };
+
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let tests_dir = project.root_path().join("tests");
+    let Some(location) = bsv::find_module(
+        builder.module_paths().chain(std::iter::once(&tests_dir)),
+        top,
+    ) else {
+        error!("ISA testbench {} not found", top);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+
+    let tests = prepare_tests(project, config)?;
+    if tests.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No riscv-tests found for suites {:?}",
+            config.suites
+        ))));
+    }
+
+    let build_root = project.output_dir("isa-test");
+    fs::create_dir_all(&build_root)?;
+    let executable = Builder::build_executable(
+        project,
+        &builder,
+        &build_root,
+        &location.path,
+        &location.module.name,
+    )?;
+
+    let mut failed = Vec::<String>::new();
+    for test in &tests {
+        let name = test.file_name().unwrap().to_string_lossy().to_string();
+
+        // Each test runs in its own directory holding the memory image.
+        let run_dir = build_root.join(&name);
+        fs::create_dir_all(&run_dir)?;
+        let image = Elf::load(test)?.to_hex(config.base_address)?;
+        fs::write(run_dir.join(&config.image), image)?;
+
+        trace!("Running ISA test {}", name);
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(r#""$0" "$@""#)
+            .arg(&executable)
+            .current_dir(&run_dir)
+            .output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if output.status.success() && stdout.contains(">>>PASS") {
+            println!("Test: {} -- {}.", name, "PASSED".green());
+        } else {
+            println!("{}", stdout);
+            println!("Test: {} -- {}.", name, "FAILED".red().bold());
+            failed.push(name);
+        }
+    }
+
+    println!(
+        "ISA tests: {} passed, {} failed",
+        tests.len() - failed.len(),
+        failed.len()
+    );
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(std::io::Error::other("Not all ISA tests passed")))
+    }
+}
@@ -0,0 +1,223 @@
+//! GNU make's jobserver: when make -j runs dolly from a rule marked with '+' (or through
+//! $(MAKE)), the jobs dolly runs in parallel take tokens from make's pool instead of adding
+//! to it, so that a nested build doesn't oversubscribe the machine.  Otherwise dolly runs up
+//! to --jobs (or the jobs setting) at once.
+use super::config::Config;
+use std::{
+    fs,
+    io::{Read, Write},
+    path, process,
+    sync::OnceLock,
+};
+use tracing::{trace, warn};
+
+/// How to reach make's jobserver.
+#[derive(Debug, PartialEq)]
+enum Client {
+    Pipe(i32, i32), // The read and write ends of a pipe inherited from make
+    Fifo(path::PathBuf),
+}
+
+struct Jobs {
+    limit: usize, // The most jobs to run at once
+    client: Option<Client>,
+}
+
+static JOBS: OnceLock<Jobs> = OnceLock::new();
+
+/// The jobserver MAKEFLAGS names: the last --jobserver-auth (or, before make 4.2,
+/// --jobserver-fds) option.
+fn parse_makeflags(makeflags: &str) -> Option<Client> {
+    let auth = makeflags
+        .split_whitespace()
+        .filter_map(|flag| {
+            flag.strip_prefix("--jobserver-auth=")
+                .or_else(|| flag.strip_prefix("--jobserver-fds="))
+        })
+        .next_back()?;
+    if let Some(fifo) = auth.strip_prefix("fifo:") {
+        return Some(Client::Fifo(path::PathBuf::from(fifo)));
+    }
+    let (read, write) = auth.split_once(',')?;
+    let (read, write) = (read.parse().ok()?, write.parse().ok()?);
+    (read >= 0 && write >= 0).then_some(Client::Pipe(read, write))
+}
+
+/// Whether make passed the jobserver on to dolly: it only keeps the pipe open for rules it
+/// knows run a sub-make.
+fn available(client: &Client) -> bool {
+    match client {
+        #[cfg(unix)]
+        Client::Pipe(read, write) => unsafe {
+            libc::fcntl(*read, libc::F_GETFD) != -1 && libc::fcntl(*write, libc::F_GETFD) != -1
+        },
+        #[cfg(not(unix))]
+        Client::Pipe(..) => false,
+        Client::Fifo(fifo) => fifo.exists(),
+    }
+}
+
+/// Finds make's jobserver, once per process.
+pub fn install(config: &Config) {
+    let client = std::env::var("MAKEFLAGS")
+        .ok()
+        .and_then(|makeflags| parse_makeflags(&makeflags))
+        .filter(|client| {
+            let available = available(client);
+            if !available {
+                warn!(
+                    "make's jobserver isn't available to dolly (mark the rule running it with \
+                     '+'); running up to {} jobs",
+                    config.jobs()
+                );
+            }
+            available
+        });
+    trace!("Jobserver: {:?}", client);
+    let _ = JOBS.set(Jobs {
+        limit: config.jobs(),
+        client,
+    });
+}
+
+/// Tokens taken from make's jobserver, which are given back when dropped.
+pub struct Tokens {
+    jobs: usize,
+    taken: Vec<u8>,
+}
+
+impl Tokens {
+    /// The number of jobs that may run at once.
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+}
+
+impl Drop for Tokens {
+    fn drop(&mut self) {
+        let Some(client) = JOBS.get().and_then(|jobs| jobs.client.as_ref()) else {
+            return;
+        };
+        if self.taken.is_empty() {
+            return;
+        }
+        let returned = match client {
+            #[cfg(unix)]
+            Client::Pipe(_, write) => {
+                let written =
+                    unsafe { libc::write(*write, self.taken.as_ptr().cast(), self.taken.len()) };
+                usize::try_from(written).is_ok_and(|written| written == self.taken.len())
+            }
+            #[cfg(not(unix))]
+            Client::Pipe(..) => false,
+            Client::Fifo(fifo) => fs::OpenOptions::new()
+                .write(true)
+                .open(fifo)
+                .and_then(|mut fifo| fifo.write_all(&self.taken))
+                .is_ok(),
+        };
+        if !returned {
+            warn!(
+                "Unable to return {} tokens to make's jobserver",
+                self.taken.len()
+            );
+        }
+    }
+}
+
+/// Takes one token from the jobserver, if there is one free right now.
+fn try_take(client: &Client) -> Option<u8> {
+    let mut token = [0u8];
+    match client {
+        #[cfg(unix)]
+        Client::Pipe(read, _) => {
+            // Another of make's jobs may take the token between the poll and the read, in
+            // which case dolly waits for the next one.
+            let mut poll = libc::pollfd {
+                fd: *read,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut poll, 1, 0) } == 1;
+            (ready && unsafe { libc::read(*read, token.as_mut_ptr().cast(), 1) } == 1)
+                .then_some(token[0])
+        }
+        #[cfg(not(unix))]
+        Client::Pipe(..) => None,
+        Client::Fifo(fifo) => {
+            let mut options = fs::OpenOptions::new();
+            options.read(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NONBLOCK);
+            let mut fifo = options.open(fifo).ok()?;
+            (fifo.read(&mut token).ok()? == 1).then_some(token[0])
+        }
+    }
+}
+
+/// The jobs dolly may run at once for one step.  Under make, dolly already holds one token
+/// (the job make ran it as) and takes as many more as are free, up to the jobs limit.
+pub fn acquire() -> Tokens {
+    let Some(jobs) = JOBS.get() else {
+        return Tokens {
+            jobs: 1,
+            taken: Vec::new(),
+        };
+    };
+    let Some(client) = &jobs.client else {
+        return Tokens {
+            jobs: jobs.limit,
+            taken: Vec::new(),
+        };
+    };
+    let mut taken = Vec::new();
+    while 1 + taken.len() < jobs.limit {
+        match try_take(client) {
+            Some(token) => taken.push(token),
+            None => break,
+        }
+    }
+    Tokens {
+        jobs: 1 + taken.len(),
+        taken,
+    }
+}
+
+/// make, run in parallel: with -j<jobs>, or, under make's jobserver, sharing it (through
+/// the MAKEFLAGS it inherits).
+pub fn make() -> process::Command {
+    let mut command = process::Command::new("make");
+    if let Some(Jobs {
+        limit,
+        client: None,
+    }) = JOBS.get()
+    {
+        command.arg(format!("-j{}", limit));
+    }
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jobserver_makeflags() {
+        assert_eq!(
+            parse_makeflags(" -j --jobserver-auth=3,4"),
+            Some(Client::Pipe(3, 4))
+        );
+        assert_eq!(
+            parse_makeflags("w --jobserver-fds=5,6 -j"),
+            Some(Client::Pipe(5, 6))
+        );
+        assert_eq!(
+            parse_makeflags("-j4 --jobserver-auth=fifo:/tmp/GMfifo42"),
+            Some(Client::Fifo(path::PathBuf::from("/tmp/GMfifo42")))
+        );
+        // make -j1 and Windows (a semaphore) have no jobserver dolly can use.
+        assert_eq!(parse_makeflags("--jobserver-auth=-2,-2"), None);
+        assert_eq!(parse_makeflags("--jobserver-auth=gmake_semaphore_1"), None);
+        assert_eq!(parse_makeflags("ks"), None);
+    }
+}
@@ -0,0 +1,947 @@
+use super::bsv;
+use super::cdc;
+use super::codes;
+use super::diagnostics::{self, Diagnostic, Severity};
+use super::graph::Graph;
+use super::paths;
+use super::project::{LintLevel, Project};
+use super::schedule::{self, Schedule};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, path,
+};
+
+/// A lint, with the level it has unless the project's `[lints]` table sets another.
+pub struct Lint {
+    pub name: &'static str,
+    pub level: LintLevel,
+    pub description: &'static str,
+}
+
+pub const LINTS: &[Lint] = &[
+    Lint {
+        name: "module-naming",
+        level: LintLevel::Warn,
+        description: "module names are mk followed by an upper-case letter (mkFifo)",
+    },
+    Lint {
+        name: "interface-naming",
+        level: LintLevel::Warn,
+        description: "interface names are UpperCamelCase (FifoIfc)",
+    },
+    Lint {
+        name: "rule-naming",
+        level: LintLevel::Warn,
+        description: "rule names are lowerCamelCase or snake_case (drainFifo, drain_fifo)",
+    },
+    Lint {
+        name: "missing-topmodule",
+        level: LintLevel::Warn,
+        description: "unit testbenches name their top module with //!topmodule",
+    },
+    Lint {
+        name: "unused-imports",
+        level: LintLevel::Warn,
+        description: "imported project packages are used",
+    },
+    Lint {
+        name: "magic-numbers",
+        level: LintLevel::Allow,
+        description: "numbers other than 0 and 1 are named (Integer depth = 16;)",
+    },
+    Lint {
+        name: "unused-sources",
+        level: LintLevel::Allow,
+        description: "sources are included by a top module or test (dolly lint --unused)",
+    },
+    Lint {
+        name: "never-fires",
+        level: LintLevel::Warn,
+        description: "rules can fire (needs a build)",
+    },
+    Lint {
+        name: "implicit-urgency",
+        level: LintLevel::Warn,
+        description: "conflicting rules are ordered by descending_urgency (needs a build)",
+    },
+    Lint {
+        name: "always-ready",
+        level: LintLevel::Warn,
+        description: "always_ready methods have no ready condition (needs a build)",
+    },
+    Lint {
+        name: "clock-crossing",
+        level: LintLevel::Allow,
+        description: "registers read other clock domains through synchronizers (dolly lint --cdc)",
+    },
+    Lint {
+        name: "long-paths",
+        level: LintLevel::Warn,
+        description:
+            "method-to-method paths assign at most [verilog] max-path-depth wires (needs a build)",
+    },
+    Lint {
+        name: "ehr-chains",
+        level: LintLevel::Warn,
+        description: "rules write an EHR through one port and don't read their own writes",
+    },
+];
+
+/// The level of every lint, checking that `[lints]` only names known lints.
+fn levels(project: &Project) -> Result<HashMap<&str, LintLevel>, Box<dyn std::error::Error>> {
+    if let Some(unknown) = project
+        .lints
+        .keys()
+        .find(|name| !LINTS.iter().any(|lint| lint.name == name.as_str()))
+    {
+        return Err(Box::new(codes::error(
+            "D0005",
+            format!("Unknown lint '{}' in [lints]", unknown),
+        )));
+    }
+    Ok(LINTS
+        .iter()
+        .map(|lint| {
+            let level = project.lints.get(lint.name).copied().unwrap_or(lint.level);
+            (lint.name, level)
+        })
+        .collect())
+}
+
+/// The `dolly lint --list` table: each lint with its level and what it checks.
+pub fn list(project: &Project) -> Result<String, Box<dyn std::error::Error>> {
+    let levels = levels(project)?;
+    let mut list = String::new();
+    for lint in LINTS {
+        let level = format!("{:?}", levels[lint.name]).to_lowercase();
+        list.push_str(&format!(
+            "{:18} {:5}  {}\n",
+            lint.name, level, lint.description
+        ));
+    }
+    Ok(list)
+}
+
+/// Runs the source lints and, for `dolly lint`, the scheduling lints over the last build.
+/// Findings of allowed lints are dropped and those of denied lints become errors.
+pub fn lints(
+    project: &Project,
+    schedules: bool,
+) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    let levels = levels(project)?;
+    let mut diagnostics = source_lints(project)?;
+    if levels["unused-sources"] != LintLevel::Allow {
+        diagnostics.extend(unused_sources(&project.src_dirs(), &Graph::load(project)?));
+    }
+    if schedules {
+        diagnostics.extend(schedule_lints(project));
+        if levels["clock-crossing"] != LintLevel::Allow {
+            diagnostics.extend(cdc::lints(project)?);
+        }
+        if levels["long-paths"] != LintLevel::Allow {
+            diagnostics.extend(paths::lints(project)?);
+        }
+    }
+    Ok(diagnostics
+        .into_iter()
+        .filter_map(|mut diagnostic| {
+            diagnostic.severity = match levels.get(diagnostic.code.as_str())? {
+                LintLevel::Allow => return None,
+                LintLevel::Warn => Severity::Warning,
+                LintLevel::Deny => Severity::Error,
+            };
+            Some(relativize(project, diagnostic))
+        })
+        .collect())
+}
+
+/// Implements `dolly lint --unused`: the sources of the source roots that no top module or
+/// test includes, whatever the level of unused-sources.
+pub fn unused(project: &Project) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    Ok(unused_sources(&project.src_dirs(), &Graph::load(project)?)
+        .into_iter()
+        .map(|diagnostic| relativize(project, diagnostic))
+        .collect())
+}
+
+/// Implements `dolly lint --cdc`: the unsynchronized clock domain crossings in the Verilog of
+/// the last build, whatever the level of clock-crossing.
+pub fn cdc(project: &Project) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    Ok(cdc::lints(project)?
+        .into_iter()
+        .map(|diagnostic| relativize(project, diagnostic))
+        .collect())
+}
+
+fn relativize(project: &Project, mut diagnostic: Diagnostic) -> Diagnostic {
+    if let Some(file) = &diagnostic.file {
+        if let Ok(relative) = file.strip_prefix(project.root_path()) {
+            diagnostic.file = Some(relative.to_path_buf());
+        }
+    }
+    diagnostic
+}
+
+/// The sources under the source roots that are reachable from none of the graph's roots.
+fn unused_sources(src_dirs: &[path::PathBuf], graph: &Graph) -> Vec<Diagnostic> {
+    let used: BTreeSet<path::PathBuf> = graph
+        .roots
+        .iter()
+        .flat_map(|root| graph.reachable(&root.path))
+        .collect();
+    graph
+        .edges
+        .keys()
+        .filter(|source| src_dirs.iter().any(|src_dir| source.starts_with(src_dir)))
+        .filter(|source| !used.contains(*source))
+        .map(|source| {
+            Diagnostic::new(
+                Severity::Warning,
+                "unused-sources",
+                "included by no top module or test".to_string(),
+            )
+            .at(source, None)
+        })
+        .collect()
+}
+
+/// The line (from 1) of an offset in a source.
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// Replaces string literals and `#(...)` type parameters with spaces, keeping offsets, so
+/// that the numbers left are values.
+fn blank_types_and_strings(source: &str) -> String {
+    let re = Regex::new(r#""(?:[^"\\\n]|\\.)*"|#\s*\((?:[^()]|\([^()]*\))*\)"#).unwrap();
+    re.replace_all(source, |capture: &regex::Captures| {
+        capture[0]
+            .chars()
+            .map(|c| if c == '\n' { c } else { ' ' })
+            .collect::<String>()
+    })
+    .into_owned()
+}
+
+/// Checks the names of the modules, interfaces and rules a BSV source declares.
+fn check_naming(path: &path::Path, source: &str) -> Vec<Diagnostic> {
+    let module_re = Regex::new(r"\bmodule\s+(?:\[[^\]]*\]\s*)?(\w+)").unwrap();
+    let interface_re = Regex::new(r"(?m)^\s*interface\s+(\w+)\s*(?:#\s*\([^;]*\))?\s*;").unwrap();
+    let rule_re = Regex::new(r"\brule\s+(\w+)").unwrap();
+    let module_name = Regex::new(r"^mk[A-Z0-9]\w*$").unwrap();
+    let interface_name = Regex::new(r"^[A-Z][A-Za-z0-9]*$").unwrap();
+    let rule_name = Regex::new(r"^(?:[a-z][A-Za-z0-9]*|[a-z][a-z0-9_]*)$").unwrap();
+
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    let mut check = |re: &Regex, name_re: &Regex, code: &str, kind: &str, convention: &str| {
+        for capture in re.captures_iter(source) {
+            let name = capture.get(1).unwrap();
+            if !name_re.is_match(name.as_str()) {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        code,
+                        format!("{} {} should be {}", kind, name.as_str(), convention),
+                    )
+                    .at(path, Some(line_at(source, name.start()))),
+                );
+            }
+        }
+    };
+    check(
+        &module_re,
+        &module_name,
+        "module-naming",
+        "module",
+        "mk followed by an upper-case letter",
+    );
+    check(
+        &interface_re,
+        &interface_name,
+        "interface-naming",
+        "interface",
+        "UpperCamelCase",
+    );
+    check(
+        &rule_re,
+        &rule_name,
+        "rule-naming",
+        "rule",
+        "lowerCamelCase or snake_case",
+    );
+    diagnostics
+}
+
+/// The names a BSV package declares, or `None` if it may be imported for something that
+/// can't be seen by name (typeclass instances or re-exports).
+fn declared_names(source: &str) -> Option<HashSet<String>> {
+    let source = bsv::strip_comments(source);
+    if Regex::new(r"\b(?:instance|export)\b")
+        .unwrap()
+        .is_match(&source)
+    {
+        return None;
+    }
+    let patterns = [
+        r"\binterface\s+(\w+)",
+        r"\bmodule\s+(?:\[[^\]]*\]\s*)?(\w+)",
+        r"\bfunction\s+(?:[\w#() ,]*?\s)?(\w+)\s*\(",
+        r"\btypedef\s+[^;{}]*\s(\w+)\s*(?:#\s*\([^;{}]*\))?\s*(?:deriving\s*\([^)]*\)\s*)?;",
+        r"\}\s*(\w+)\s*(?:#\s*\([^;{}]*\))?\s*(?:deriving\s*\([^)]*\)\s*)?;",
+        r"(?m)^\w[\w#() ,]*\s(\w+)\s*=",
+    ];
+    let mut names = HashSet::<String>::new();
+    for pattern in patterns {
+        let re = Regex::new(pattern).unwrap();
+        names.extend(
+            re.captures_iter(&source)
+                .map(|capture| capture[1].to_string()),
+        );
+    }
+    let enum_re = Regex::new(r"\benum\s*\{([^}]*)\}").unwrap();
+    for capture in enum_re.captures_iter(&source) {
+        names.extend(
+            capture[1]
+                .split(',')
+                .filter_map(|member| member.split('=').next())
+                .map(|member| member.trim().to_string())
+                .filter(|member| !member.is_empty()),
+        );
+    }
+    Some(names)
+}
+
+/// Reports the imports of project (or dependency) packages of which nothing is used.
+fn check_imports(
+    path: &path::Path,
+    source: &str,
+    packages: &HashMap<String, Vec<path::PathBuf>>,
+) -> Vec<Diagnostic> {
+    let word_re = Regex::new(r"\w+").unwrap();
+    let import_re = Regex::new(r"\bimport\s+(\w+)\s*::\s*\*\s*;").unwrap();
+    let body = import_re.replace_all(source, "");
+    let words: HashSet<&str> = word_re.find_iter(&body).map(|word| word.as_str()).collect();
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for capture in import_re.captures_iter(source) {
+        let package = &capture[1];
+        let Some(package_paths) = packages.get(package) else {
+            continue;
+        };
+        let declared: Option<Vec<HashSet<String>>> = package_paths
+            .iter()
+            .map(|package_path| {
+                fs::read_to_string(package_path)
+                    .ok()
+                    .and_then(|package_source| declared_names(&package_source))
+            })
+            .collect();
+        let declared: HashSet<String> = declared.into_iter().flatten().flatten().collect();
+        if !declared.is_empty() && !declared.iter().any(|name| words.contains(name.as_str())) {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "unused-imports",
+                    format!("nothing imported from {} is used", package),
+                )
+                .at(path, Some(line_at(source, capture.get(0).unwrap().start()))),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Reports numbers other than 0 and 1 outside of types and named constants
+/// (`Integer depth = 16;`).
+fn check_numbers(path: &path::Path, source: &str) -> Vec<Diagnostic> {
+    let source = blank_types_and_strings(source);
+    let constant_re = Regex::new(r"^\s*(?:typedef\b|[A-Z]\w*\s+\w+\s*=)").unwrap();
+    let number_re = Regex::new(r"\b\d+\b").unwrap();
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for (index, line) in source.lines().enumerate() {
+        if constant_re.is_match(line) {
+            continue;
+        }
+        for number in number_re.find_iter(line) {
+            let sized =
+                line[number.end()..].starts_with('\'') || line[..number.start()].ends_with('\'');
+            let value: u64 = number.as_str().parse().unwrap_or(u64::MAX);
+            if !sized && value > 1 {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        "magic-numbers",
+                        format!("{} could be a named constant", number.as_str()),
+                    )
+                    .at(path, Some(index + 1)),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Whether the text before something ends a statement (or a guard, label or block opening), so
+/// that it starts the next one.
+fn starts_statement(before: &str) -> bool {
+    before.is_empty()
+        || before.ends_with([';', ')', ':', '}'])
+        || ["begin", "else", "action"].iter().any(|word| {
+            before
+                .strip_suffix(word)
+                .is_some_and(|rest| !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+        })
+}
+
+/// Reports the rules using an EHR (or CReg) suspiciously: writing it through several ports,
+/// of which only the last write is kept, or reading a port after the one they write, which
+/// sees their own write through a combinational path.  A write is an `ehr[i] <=` that starts a
+/// statement, so that a comparison such as `if (count[1] <= 5)` is a read.
+fn check_ehrs(path: &path::Path, source: &str) -> Vec<Diagnostic> {
+    let ehr_re = Regex::new(
+        r"\bEhr\s*#\s*\([^;]*?\)\s+(\w+)\s*<-|\bReg\s*#\s*\([^;]*?\)\s+(\w+)\s*\[[^\]]*\]\s*<-\s*mkCReg",
+    )
+    .unwrap();
+    let rule_re = Regex::new(r"\brule\s+(\w+)").unwrap();
+    let end_re = Regex::new(r"\bendrule\b").unwrap();
+    let port_re = Regex::new(r"\b(\w+)\s*\[\s*(\d+)\s*\](\s*<=)?").unwrap();
+    let ehrs: HashSet<&str> = ehr_re
+        .captures_iter(source)
+        .filter_map(|capture| capture.get(1).or(capture.get(2)))
+        .map(|name| name.as_str())
+        .collect();
+    if ehrs.is_empty() {
+        return Vec::new();
+    }
+
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for rule in rule_re.captures_iter(source) {
+        let start = rule.get(0).unwrap().end();
+        let end = end_re
+            .find(&source[start..])
+            .map_or(source.len(), |found| start + found.start());
+        // The ports each EHR is written and read through.
+        let mut uses = BTreeMap::<&str, (BTreeSet<u32>, BTreeSet<u32>)>::new();
+        for port in port_re.captures_iter(&source[start..end]) {
+            let name = port.get(1).unwrap().as_str();
+            if !ehrs.contains(name) {
+                continue;
+            }
+            let (writes, reads) = uses.entry(name).or_default();
+            let index = port[2].parse().unwrap_or(0);
+            let before = source[start..start + port.get(0).unwrap().start()].trim_end();
+            if port.get(3).is_some() && starts_statement(before) {
+                writes.insert(index);
+            } else {
+                reads.insert(index);
+            }
+        }
+        let mut report = |message: String| {
+            diagnostics.push(
+                Diagnostic::new(Severity::Warning, "ehr-chains", message)
+                    .at(path, Some(line_at(source, rule.get(1).unwrap().start()))),
+            )
+        };
+        for (ehr, (writes, reads)) in &uses {
+            if writes.len() > 1 {
+                let ports: Vec<String> = writes.iter().map(u32::to_string).collect();
+                report(format!(
+                    "rule {} writes {} through ports {}; only the last write is kept",
+                    &rule[1],
+                    ehr,
+                    ports.join(", ")
+                ));
+            }
+            if let (Some(write), Some(read)) = (writes.first(), reads.last()) {
+                if read > write {
+                    report(format!(
+                        "rule {} reads {}[{}], which sees its own write to {}[{}] through a \
+                         combinational path",
+                        &rule[1], ehr, read, ehr, write
+                    ));
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+/// The source lints over the project's own sources and integration tests.
+fn source_lints(project: &Project) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    let topmodule_re = Regex::new(&format!(r"{}topmodule\s+\w+", bsv::DIRECTIVE))?;
+    let packages = project.packages()?;
+    let mut directories: Vec<path::PathBuf> = project
+        .package
+        .src_dirs
+        .iter()
+        .map(|src_dir| project.root_path().join(src_dir))
+        .collect();
+    directories.push(project.root_path().join("tests"));
+    let sources: BTreeSet<path::PathBuf> = directories
+        .iter()
+        .flat_map(|directory| bsv::find_packages(directory).into_values().flatten())
+        .collect();
+
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for path in sources {
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if project.is_unit_test(&path) && !topmodule_re.is_match(&contents) {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "missing-topmodule",
+                    "testbench has no //!topmodule directive, so its top module is guessed"
+                        .to_string(),
+                )
+                .at(&path, None),
+            );
+        }
+        if path.extension().is_some_and(|ext| ext == "bsv") {
+            let source = bsv::strip_comments(&contents);
+            diagnostics.extend(check_naming(&path, &source));
+            diagnostics.extend(check_imports(&path, &source, &packages));
+            diagnostics.extend(check_numbers(&path, &source));
+            diagnostics.extend(check_ehrs(&path, &source));
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// The line (from 1) of the first match of `pattern` in `source`.
+fn find_line(source: &str, pattern: &str) -> Option<usize> {
+    let re = Regex::new(pattern).ok()?;
+    let found = re.find(source)?;
+    Some(source[..found.start()].matches('\n').count() + 1)
+}
+
+/// The methods named by the always_ready and always_enabled attributes of a module definition
+/// (such as `(* synthesize, always_ready = "first, deq" *)`), with `None` standing for all of
+/// its methods.  always_enabled implies always_ready.
+fn always_ready_methods(source: &str, module: &str) -> Option<Option<Vec<String>>> {
+    let source = bsv::strip_comments(source);
+    let module_re = Regex::new(&format!(
+        r"((?:\(\*[^*]*\*\)\s*)+)module\s+(?:\[\s*\w+\s*\]\s*)?{}\b",
+        module
+    ))
+    .unwrap();
+    let attribute_re =
+        Regex::new(r#"\b(?:always_ready|always_enabled)\b(?:\s*=\s*"([^"]*)")?"#).unwrap();
+    let capture = module_re.captures(&source)?;
+    let mut methods = Vec::<String>::new();
+    for attribute in attribute_re.captures_iter(&capture[1]) {
+        match attribute.get(1) {
+            None => return Some(None),
+            Some(names) => methods.extend(
+                names
+                    .as_str()
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+            ),
+        }
+    }
+    (!methods.is_empty()).then_some(Some(methods))
+}
+
+/// Checks a module's schedule against its source: rules whose predicate is always false, and
+/// methods that should be always ready but have a ready signal.
+fn check_schedule(schedule: &Schedule, location: Option<&bsv::ModuleLocation>) -> Vec<Diagnostic> {
+    let source = location
+        .and_then(|location| fs::read_to_string(&location.path).ok())
+        .unwrap_or_default();
+    let at = |diagnostic: Diagnostic, pattern: &str| match location {
+        Some(location) => diagnostic.at(&location.path, find_line(&source, pattern)),
+        None => diagnostic,
+    };
+    let mut diagnostics = Vec::<Diagnostic>::new();
+
+    for rule in schedule
+        .rules
+        .iter()
+        .filter(|rule| rule.predicate == "False")
+    {
+        diagnostics.push(at(
+            Diagnostic::new(
+                Severity::Warning,
+                "never-fires",
+                format!(
+                    "rule `{}' in {} can never fire (its predicate is False)",
+                    rule.name, schedule.module
+                ),
+            ),
+            &format!(r"\brule\s+{}\b", rule.name),
+        ));
+    }
+
+    if let Some(always_ready) = always_ready_methods(&source, &schedule.module) {
+        for method in schedule.methods.iter().filter(|method| {
+            method.ready != "True"
+                && always_ready
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&method.name))
+        }) {
+            diagnostics.push(at(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "always-ready",
+                    format!(
+                        "method {} of {} is declared always_ready but is ready only when {}",
+                        method.name, schedule.module, method.ready
+                    ),
+                ),
+                &format!(r"\bmodule\s+(?:\[\s*\w+\s*\]\s*)?{}\b", schedule.module),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Maps the scheduling warnings in a bsc log to lints: G0010 (conflicting rules whose order
+/// no descending_urgency attribute decides) and G0021 (rules that can never fire).  Relative
+/// source paths are resolved against the directory bsc ran in.
+fn check_log(log: &str, directory: &path::Path) -> Vec<Diagnostic> {
+    diagnostics::parse_bsc(log)
+        .into_iter()
+        .filter_map(|diagnostic| {
+            let code = match diagnostic.code.as_str() {
+                "G0010" => "implicit-urgency",
+                "G0021" => "never-fires",
+                _ => return None,
+            };
+            let file = diagnostic
+                .file
+                .map(|file| directory.join(file))
+                .filter(|file| file.exists());
+            let lint = Diagnostic::new(
+                Severity::Warning,
+                code,
+                format!("{} ({})", diagnostic.message, diagnostic.code),
+            );
+            Some(match file {
+                Some(file) => lint.at(&file, diagnostic.line),
+                None => lint,
+            })
+        })
+        .collect()
+}
+
+/// The scheduling lints for the schedules and bsc logs of the project's last build or test
+/// run.  A module built for several targets is reported once.
+fn schedule_lints(project: &Project) -> Vec<Diagnostic> {
+    let target_dir = project.target_dir();
+    let schedules = schedule::find(&target_dir);
+    if schedules.is_empty() {
+        println!("No schedules found (run 'dolly build' or 'dolly test' to lint scheduling)");
+    }
+    let src_dirs = project.src_dirs();
+
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    let mut modules = BTreeMap::<&str, &Schedule>::new();
+    for schedule in schedules.values() {
+        modules.entry(schedule.module.as_str()).or_insert(schedule);
+    }
+    for (module, schedule) in modules {
+        let location = bsv::find_module(src_dirs.iter(), module);
+        diagnostics.extend(check_schedule(schedule, location.as_ref()));
+    }
+
+    let mut directories: Vec<path::PathBuf> = schedules
+        .keys()
+        .filter_map(|relative| relative.parent())
+        .map(|directory| target_dir.join(directory))
+        .collect();
+    directories.dedup();
+    for directory in directories {
+        if let Ok(log) = fs::read_to_string(directory.join("compile.log")) {
+            diagnostics.extend(check_log(&log, &directory));
+        }
+    }
+
+    let mut unique = Vec::<Diagnostic>::new();
+    for diagnostic in diagnostics {
+        let file = diagnostic
+            .file
+            .as_ref()
+            .and_then(|file| file.canonicalize().ok());
+        let duplicate = unique.iter().any(|other| {
+            other.code == diagnostic.code
+                && other.line == diagnostic.line
+                && other.message == diagnostic.message
+                && other
+                    .file
+                    .as_ref()
+                    .and_then(|file| file.canonicalize().ok())
+                    == file
+        });
+        if !duplicate {
+            unique.push(diagnostic);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::graph::{Root, RootKind};
+    use super::schedule::{Method, Rule};
+    use super::*;
+
+    #[test]
+    fn style_lints() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("Alu.bsv"),
+            "package Alu;\ntypedef enum { Add, Sub } AluOp deriving (Bits, Eq);\nfunction Bit#(8) alu(AluOp op, Bit#(8) a, Bit#(8) b);\n    return a + b;\nendfunction\nendpackage\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Uart.bsv"),
+            "package Uart;\ninterface Uart;\nendinterface\nendpackage\n",
+        )
+        .unwrap();
+        let packages = bsv::find_packages(root);
+
+        let path = path::Path::new("src/Core.bsv");
+        let source = bsv::strip_comments(
+            "import Alu::*;\nimport Uart::*;\ninterface core_ifc;\nendinterface\nmodule Core(core_ifc);\n    Integer depth = 16;\n    Reg#(Bit#(8)) count <- mkReg(0);\n    rule Tick_Rule (count < 200); // not 100\n        count <= alu(Add, count, 8'd3);\n        $display(\"%3d\", count);\n    endrule\nendmodule\n",
+        );
+        let codes = |diagnostics: Vec<Diagnostic>| -> Vec<(String, Option<usize>)> {
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.line))
+                .collect()
+        };
+        assert_eq!(
+            codes(check_naming(path, &source)),
+            [
+                ("module-naming".to_string(), Some(5)),
+                ("interface-naming".to_string(), Some(3)),
+                ("rule-naming".to_string(), Some(8))
+            ]
+        );
+        assert_eq!(
+            codes(check_imports(path, &source, &packages)),
+            [("unused-imports".to_string(), Some(2))]
+        );
+        assert_eq!(
+            codes(check_numbers(path, &source)),
+            [("magic-numbers".to_string(), Some(8))]
+        );
+        let ehrs = "module mkCounter(Empty);\n    Ehr#(3, Bit#(8)) count <- mkEhr(0);\n    rule tick;\n        count[0] <= count[0] + 1;\n    endrule\n    rule bump;\n        count[0] <= count[1];\n        count[2] <= 3;\n    endrule\n    rule check;\n        if (count[0] <= 5) count[1] <= 1;\n        else begin count[1] <= 0; end\n    endrule\nendmodule\n";
+        let diagnostics = check_ehrs(path, ehrs);
+        assert!(diagnostics[0].message.contains("through ports 0, 2"));
+        assert!(diagnostics[1].message.contains("reads count[1]"));
+        assert_eq!(
+            codes(diagnostics),
+            [
+                ("ehr-chains".to_string(), Some(6)),
+                ("ehr-chains".to_string(), Some(6))
+            ]
+        );
+
+        let sources = BTreeMap::from([
+            (
+                path::PathBuf::from("src/Top.bsv"),
+                "import Alu::*;\n".to_string(),
+            ),
+            (path::PathBuf::from("src/Alu.bsv"), String::new()),
+            (path::PathBuf::from("src/OldAlu.bsv"), String::new()),
+        ]);
+        let top = Root {
+            kind: RootKind::Top,
+            name: "mkTop".to_string(),
+            path: "src/Top.bsv".into(),
+        };
+        let unused = unused_sources(&["src".into()], &Graph::new(&sources, vec![top]));
+        assert_eq!(unused.len(), 1);
+        assert_eq!(
+            unused[0].file.as_deref(),
+            Some(path::Path::new("src/OldAlu.bsv"))
+        );
+
+        // Imports of library packages, of packages declaring instances, and of packages that
+        // can't be read aren't reported.
+        fs::write(
+            root.join("Show.bsv"),
+            "package Show;
+instance FShow#(Bit#(8));
+endinstance
+endpackage
+",
+        )
+        .unwrap();
+        let mut packages = bsv::find_packages(root);
+        packages.insert("Gone".to_string(), vec![root.join("Gone.bsv")]);
+        let source = "import FIFO::*;
+import Show::*;
+import Gone::*;
+";
+        assert!(check_imports(path, source, &packages).is_empty());
+
+        // [lints] sets the level of each lint, and names only known lints.
+        let manifest = |lints: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[lints]\n{}\n",
+                    lints
+                ),
+            )
+            .unwrap();
+            Project::load(root.join("dolly.toml"))
+        };
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src").join("Core.bsv"),
+            "package Core;\ninterface core_ifc;\nendinterface\nmodule Core(core_ifc);\n    rule Tick;\n    endrule\nendmodule\nendpackage\n",
+        )
+        .unwrap();
+        let project = manifest("module-naming = \"deny\"\nrule-naming = \"allow\"").unwrap();
+        let found: Vec<(String, Severity)> = lints(&project, false)
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.severity))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("module-naming".to_string(), Severity::Error),
+                ("interface-naming".to_string(), Severity::Warning)
+            ]
+        );
+        let project = manifest("module-nameing = \"deny\"").unwrap();
+        let error = lints(&project, false).unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0005"));
+        assert!(error.to_string().contains("'module-nameing'"));
+        assert!(list(&project).is_err());
+        assert!(manifest("module-naming = \"loud\"").is_err());
+    }
+
+    #[test]
+    fn unused_source_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        for (file, contents) in [
+            ("src/Soc.bsv", "//!topmodule mkSoc\nimport Alu::*;\n"),
+            ("src/Alu.bsv", ""),
+            ("src/Uart.bsv", ""),
+            ("src/Spare.bsv", ""),
+            ("tests/Uart_tb.bsv", "import Uart::*;\n"),
+        ] {
+            fs::write(root.join(file), contents).unwrap();
+        }
+        let manifest = |lints: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n\n[lints]\n{}\n",
+                    lints
+                ),
+            )
+            .unwrap();
+            Project::load(root.join("dolly.toml")).unwrap()
+        };
+        let unused_files = |diagnostics: Vec<Diagnostic>| -> Vec<path::PathBuf> {
+            diagnostics
+                .into_iter()
+                .filter(|diagnostic| diagnostic.code == "unused-sources")
+                .filter_map(|diagnostic| diagnostic.file)
+                .collect()
+        };
+
+        // Sources only the tests include are used; the lint is off unless enabled, but
+        // `dolly lint --unused` reports regardless.
+        let project = manifest("");
+        assert!(unused_files(lints(&project, false).unwrap()).is_empty());
+        let spare = [path::PathBuf::from("src/Spare.bsv")];
+        assert_eq!(unused_files(unused(&project).unwrap()), spare);
+        let project = manifest("unused-sources = \"deny\"");
+        let diagnostics = lints(&project, false).unwrap();
+        assert!(diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.code == "unused-sources"
+                && diagnostic.severity == Severity::Error));
+        assert_eq!(unused_files(diagnostics), spare);
+    }
+
+    #[test]
+    fn scheduling_lints() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let path = root.join("Fifo.bsv");
+        fs::write(
+            &path,
+            "package Fifo;\n(* synthesize, always_ready = \"first\" *)\nmodule mkFifo(Fifo);\n    rule drain (False);\n    endrule\nendmodule\nendpackage\n",
+        )
+        .unwrap();
+        let location = bsv::ModuleLocation {
+            module: bsv::parse_modules(&fs::read_to_string(&path).unwrap())[0].clone(),
+            path: path.clone(),
+        };
+        let method = |name: &str, ready: &str| Method {
+            name: name.to_string(),
+            ready: ready.to_string(),
+            ..Method::default()
+        };
+        let schedule = Schedule {
+            module: "mkFifo".to_string(),
+            methods: vec![method("first", "count != 0"), method("deq", "count != 0")],
+            rules: vec![Rule {
+                name: "drain".to_string(),
+                predicate: "False".to_string(),
+                blocking: Vec::new(),
+            }],
+            order: Vec::new(),
+        };
+        let diagnostics = check_schedule(&schedule, Some(&location));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            (diagnostics[0].code.as_str(), diagnostics[0].line),
+            ("never-fires", Some(4))
+        );
+        assert_eq!(
+            (diagnostics[1].code.as_str(), diagnostics[1].line),
+            ("always-ready", Some(3))
+        );
+        assert!(diagnostics[1].message.starts_with("method first of mkFifo"));
+
+        let log = "Warning: \"Fifo.bsv\", line 4, column 10: (G0010)\n  Rule `drain' was treated as more urgent than `fill'.\nWarning: \"Fifo.bsv\", line 9, column 1: (S0080)\n  Unused.\n";
+        let diagnostics = check_log(log, root);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "implicit-urgency");
+        assert_eq!(diagnostics[0].file, Some(path.clone()));
+
+        // Without a source (unfound, or gone since), the lints still report, unplaced, and
+        // always_ready can't be checked.
+        let diagnostics = check_schedule(&schedule, None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].code.as_str(), &diagnostics[0].file),
+            ("never-fires", &None)
+        );
+        let moved = bsv::ModuleLocation {
+            path: root.join("Moved.bsv"),
+            ..location
+        };
+        let diagnostics = check_schedule(&schedule, Some(&moved));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].file.as_ref(), diagnostics[0].line),
+            (Some(&moved.path), None)
+        );
+
+        // Logs naming missing sources are reported unplaced, and other output is ignored.
+        let log = "Warning: \"Gone.bsv\", line 2, column 3: (G0021)\n  Rule `idle' can never fire.\nWarning: \"Fifo.bsv\", line 4: (G0010)\n  No column.\nError: bsc crashed\n";
+        let diagnostics = check_log(log, root);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "never-fires");
+        assert_eq!(diagnostics[0].file, None);
+        assert!(diagnostics[0].message.ends_with("can never fire. (G0021)"));
+        assert!(check_log("", root).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! The RTL linters `dolly build --lint-verilog` runs over the generated Verilog, and the
+//! waivers of the findings a project accepts.  A waiver file (`[verilog] lint-waivers`) lists
+//! them, each by the linter's code and optionally the linter and the file:
+//!
+//! ```toml
+//! [[waiver]]
+//! linter = "verible"
+//! code = "line-length"
+//! reason = "bsc's output"
+//!
+//! [[waiver]]
+//! code = "UNUSEDSIGNAL"
+//! file = "mkTop.v"
+//! ```
+use super::config;
+use super::diagnostics::{self, Diagnostic};
+use super::manifest;
+use super::project::VerilogConfig;
+use serde::Deserialize;
+use std::{fs, path, process};
+
+/// A linter of the generated Verilog.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Linter {
+    Verilator,
+    Slang,
+    Verible,
+}
+
+impl Linter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Linter::Verilator => "verilator",
+            Linter::Slang => "slang",
+            Linter::Verible => "verible",
+        }
+    }
+
+    /// The log its output is kept in, in the top module's build directory.
+    pub fn log_file(&self) -> String {
+        match self {
+            Linter::Verilator => "lint.log".to_string(),
+            _ => format!("lint-{}.log", self.name()),
+        }
+    }
+
+    /// The command linting a top module's Verilog files.  The primitives are given to the
+    /// linters that elaborate the design.
+    pub fn command(
+        &self,
+        config: &VerilogConfig,
+        top_module: &str,
+        files: &[path::PathBuf],
+        primitives: &[path::PathBuf],
+    ) -> process::Command {
+        let mut cmd;
+        match self {
+            Linter::Verilator => {
+                cmd = process::Command::new(config::tool("verilator"));
+                cmd.arg("--lint-only")
+                    .arg("-Wall")
+                    .args(&config.lint_flags)
+                    .arg("--top-module")
+                    .arg(top_module)
+                    .args(files)
+                    .args(primitives);
+            }
+            Linter::Slang => {
+                cmd = process::Command::new(config::tool("slang"));
+                cmd.arg("--lint-only")
+                    .args(&config.slang_flags)
+                    .arg("--top")
+                    .arg(top_module)
+                    .args(files)
+                    .args(primitives);
+            }
+            Linter::Verible => {
+                cmd = process::Command::new(config::tool("verible-verilog-lint"));
+                cmd.args(&config.verible_flags).args(files);
+            }
+        }
+        cmd
+    }
+
+    /// The findings in its output (stdout and stderr together).
+    pub fn parse(&self, output: &str) -> Vec<Diagnostic> {
+        match self {
+            Linter::Verilator => diagnostics::parse_verilator(output),
+            Linter::Slang => diagnostics::parse_slang(output),
+            Linter::Verible => diagnostics::parse_verible(output),
+        }
+    }
+}
+
+/// A finding the project accepts.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Waiver {
+    pub linter: Option<Linter>, // Any linter's, by default
+    pub code: String,
+    pub file: Option<path::PathBuf>, // A file name, or the end of a path
+    #[allow(dead_code)]
+    pub reason: Option<String>, // For the reader of the waiver file
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Waivers {
+    #[serde(default)]
+    pub waiver: Vec<Waiver>,
+}
+
+impl Waivers {
+    /// Loads a waiver file.
+    pub fn load(file: &path::Path) -> Result<Waivers, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(file).map_err(|e| {
+            std::io::Error::other(format!(
+                "Unable to read the lint waivers {}: {}",
+                file.to_string_lossy(),
+                e
+            ))
+        })?;
+        Ok(manifest::parse(file, &text)?)
+    }
+
+    /// Whether a finding of a linter is waived.
+    pub fn waives(&self, linter: Linter, diagnostic: &Diagnostic) -> bool {
+        self.waiver.iter().any(|waiver| {
+            waiver.linter.is_none_or(|waived| waived == linter)
+                && waiver.code == diagnostic.code
+                && waiver.file.as_ref().is_none_or(|waived| {
+                    diagnostic
+                        .file
+                        .as_ref()
+                        .is_some_and(|file| file.ends_with(waived))
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn waived_findings() {
+        let waivers: Waivers = toml::from_str(
+            "[[waiver]]\nlinter = \"verible\"\ncode = \"line-length\"\nreason = \"bsc output\"\n\n\
+             [[waiver]]\ncode = \"UNUSEDSIGNAL\"\nfile = \"mkTop.v\"\n",
+        )
+        .unwrap();
+        let finding = |code: &str, file: &str| {
+            Diagnostic::new(diagnostics::Severity::Warning, code, String::new())
+                .at(path::Path::new(file), Some(1))
+        };
+        let long_line = finding("line-length", "target/mkTop/mkTop.v");
+        assert!(waivers.waives(Linter::Verible, &long_line));
+        assert!(!waivers.waives(Linter::Slang, &long_line));
+        let unused = finding("UNUSEDSIGNAL", "target/mkTop/mkTop.v");
+        assert!(waivers.waives(Linter::Verilator, &unused));
+        let elsewhere = finding("UNUSEDSIGNAL", "target/mkTop/mkCore.v");
+        assert!(!waivers.waives(Linter::Verilator, &elsewhere));
+    }
+}
@@ -1,16 +1,150 @@
 //! dolly is a tool for building Bluespec SystemVerilog (BSV) projects.
 #![warn(missing_docs)]
 
-use clap::{Parser, Subcommand};
-use log::{error, trace};
-use std::path;
+use clap::{CommandFactory, Parser, Subcommand};
+use colored::Colorize;
+use std::{ffi::OsString, path};
+use tracing::{error, trace, warn};
+
+mod adopt;
+
+mod build_info;
 
 mod builder;
 use builder::Builder;
 
+mod bsv;
+
+mod cdc;
+
+mod changes;
+
+mod checksum;
+
+mod codes;
+
+mod config;
+
+mod cosim;
+
+mod coverage;
+
+mod dashboard;
+
+mod diagnostics;
+
+mod distribute;
+
+mod doc;
+
+mod elf;
+
+mod examples;
+
+mod executor;
+
+mod expand;
+
+mod fingerprint;
+
+mod firmware;
+
+mod formal;
+
+mod formatter;
+
+mod fuzz;
+
+mod generators;
+
+mod graph;
+
+mod harness;
+
+mod hierarchy;
+
+mod history;
+
+mod hooks;
+use hooks::Hook;
+
+mod inspect;
+
+mod install;
+
+mod isa_test;
+
+mod jobserver;
+
+mod lint;
+
+mod linters;
+
+mod manifest;
+
+mod memory_map;
+
+mod mutate;
+
+mod net;
+
+mod notify;
+
+mod overrides;
+
+mod package;
+
+mod paths;
+
+mod plugin;
+
 mod project;
 use project::Project;
 
+mod regs;
+
+mod riscof;
+
+mod runner;
+
+mod rvfi;
+
+mod scaffold;
+
+mod schedule;
+
+mod script;
+
+mod self_update;
+
+mod serve;
+
+mod shard;
+
+mod size;
+
+mod snapshot;
+
+mod soc;
+
+mod suggest;
+
+mod telemetry;
+
+mod templates;
+
+mod timings;
+
+mod verilog;
+
+mod version;
+
+mod warnings;
+
+mod waves;
+
+mod workspace;
+
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -18,17 +152,580 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
+    /// Directory for build products, instead of target/ in the project (or $DOLLY_TARGET_DIR).
+    #[arg(long, global = true, value_name = "PATH")]
+    target_dir: Option<path::PathBuf>,
+
+    /// The most jobs to run at once (instead of the jobs setting, or the number of CPUs);
+    /// under make -j, dolly shares make's jobserver.
+    #[arg(long, short, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Fail instead of fetching anything over the network (as with [net] offline).
+    #[arg(long, global = true)]
+    offline: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Build { name: Option<path::PathBuf> },
-    Clean { name: Option<path::PathBuf> },
-    Init { name: path::PathBuf },
-    Test { name: Option<path::PathBuf> },
+    Build {
+        name: Option<path::PathBuf>,
+
+        /// The workspace member to build (by its [package] name), instead of the package
+        /// dolly is run in.
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Build only this top module (may be repeated; defaults to all of them).
+        #[arg(long = "top", value_name = "MODULE")]
+        tops: Vec<String>,
+
+        /// Build the example examples/<NAME>.bsv instead of the top modules.
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["tops", "emit", "lint_verilog"])]
+        example: Option<String>,
+
+        /// Run the example once it is built.
+        #[arg(long, requires = "example")]
+        run: bool,
+
+        /// What to emit: Verilog, SystemVerilog, the compiled packages (bo), a Bluesim binary
+        /// of each top module, or all of Verilog, bo and bluesim.
+        #[arg(long, value_enum, default_value_t = builder::Emit::Verilog)]
+        emit: builder::Emit,
+
+        /// Fail if bsc reports any warnings (as with [build] deny-warnings).
+        #[arg(long)]
+        deny_warnings: bool,
+
+        /// Print each bsc and simulator command line before running it.
+        #[arg(long)]
+        show_commands: bool,
+
+        /// Print the command lines that would be run, without running them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Report how long each compile, link and test run took, in the terminal and in
+        /// target/dolly-timings.html.
+        #[arg(long)]
+        timings: bool,
+
+        /// Lint the generated Verilog (and the primitives it uses) with the [verilog] linters
+        /// (Verilator by default), failing on errors, or on warnings with --deny-warnings.
+        #[arg(long)]
+        lint_verilog: bool,
+
+        /// Carry on building the other top modules after one fails, listing the failures at
+        /// the end.
+        #[arg(long)]
+        keep_going: bool,
+    },
+    Bump {
+        /// The part of [package] version to increment.
+        #[arg(value_enum)]
+        part: version::Part,
+
+        name: Option<path::PathBuf>,
+
+        /// Commit dolly.toml and tag the commit v<version>.
+        #[arg(long)]
+        tag: bool,
+
+        /// Bump even if workspace members depending on the package require another version.
+        #[arg(long)]
+        force: bool,
+    },
+    Check {
+        name: Option<path::PathBuf>,
+    },
+    Clean {
+        name: Option<path::PathBuf>,
+
+        /// The workspace member to clean (by its [package] name), instead of the package
+        /// dolly is run in.
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Remove only the build directory of this test or top module (may be repeated).
+        #[arg(long = "target", value_name = "NAME")]
+        targets: Vec<String>,
+
+        /// Remove only the build directories of the tests and the coverage report.
+        #[arg(long)]
+        tests: bool,
+
+        /// Remove only the compiled packages of the dependencies.
+        #[arg(long)]
+        deps: bool,
+
+        /// Also remove the cache and the checkouts dolly cloned (such as riscv-tests).
+        #[arg(long)]
+        all: bool,
+
+        /// Remove the build directories of tests and top modules that no longer exist, and
+        /// outputs of the other commands older than [build] gc-max-age-days.
+        #[arg(long, conflicts_with_all = ["targets", "tests", "deps", "all"])]
+        gc: bool,
+    },
+    Config {
+        #[command(subcommand)]
+        action: ConfigActions,
+    },
+    Cosim {
+        /// The RISC-V executable to run on both the core and Spike.
+        elf: path::PathBuf,
+    },
+    Doc {
+        name: Option<path::PathBuf>,
+
+        /// The format of the documentation written under target/doc.
+        #[arg(long, value_enum, default_value_t = doc::DocFormat::Html)]
+        format: doc::DocFormat,
+    },
+    /// Shows a module's rules and methods as bsc elaborated and scheduled them.
+    Expand {
+        /// The module to expand (mkName or Name).
+        module: String,
+
+        /// Only show this rule or method (may be repeated).
+        #[arg(long = "rule", value_name = "NAME")]
+        rules: Vec<String>,
+
+        /// Print bsc's dump as it is.
+        #[arg(long, conflicts_with = "rules")]
+        raw: bool,
+    },
+    Explain {
+        /// The error code (such as D0203) to explain; all codes are listed if none is given.
+        code: Option<String>,
+    },
+    /// Writes the instance hierarchy of the top modules, from their generated Verilog.
+    Export {
+        name: Option<path::PathBuf>,
+
+        /// Export the instance tree (module, instance name and parameters), as JSON.
+        #[arg(long, required = true)]
+        hierarchy: bool,
+
+        /// Only export this top module (may be repeated; defaults to all of them).
+        #[arg(long = "top", value_name = "MODULE")]
+        tops: Vec<String>,
+
+        /// The file to write (defaults to the standard output).
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+    },
+    /// Runs dolly-<name> from the PATH, for commands dolly doesn't have.
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
+    Fmt {
+        name: Option<path::PathBuf>,
+
+        /// Report the sources that aren't formatted, and fail if there are any, instead of
+        /// formatting them.
+        #[arg(long)]
+        check: bool,
+    },
+    Fuzz {
+        module: String,
+
+        /// Seed of the first run (random if not given).
+        #[arg(long)]
+        seed: Option<u32>,
+
+        /// Number of randomized method calls per run.
+        #[arg(long, default_value_t = 1000)]
+        steps: u32,
+
+        /// Number of runs (each with the next seed) before giving up.
+        #[arg(long, default_value_t = 10)]
+        runs: u32,
+    },
+    Gen {
+        #[command(subcommand)]
+        generator: Generators,
+    },
+    Init {
+        name: path::PathBuf,
+
+        #[command(flatten)]
+        template: TemplateArgs,
+
+        /// Adopt an existing tree of sources: write a dolly.toml for it, and report what
+        /// couldn't be inferred.
+        #[arg(long, conflicts_with_all = ["lib", "tb_only", "soc", "riscv_core", "template"])]
+        existing: bool,
+    },
+    Inspect {
+        /// The module to describe (mkName or Name).
+        module: String,
+    },
+    Install {
+        name: Option<path::PathBuf>,
+
+        /// The directory to install into, as share/dolly/<package name>.
+        #[arg(long)]
+        prefix: path::PathBuf,
+
+        /// Install only this top module (may be repeated; defaults to all of them).
+        #[arg(long = "top", value_name = "MODULE")]
+        tops: Vec<String>,
+
+        /// The kind of HDL to generate.
+        #[arg(long, value_enum, default_value_t = builder::Emit::Verilog)]
+        emit: builder::Emit,
+    },
+    IsaTest {
+        name: Option<path::PathBuf>,
+    },
+    /// Manage the dolly installation itself.
+    #[command(name = "self")]
+    Itself {
+        #[command(subcommand)]
+        action: SelfActions,
+    },
+    Lint {
+        name: Option<path::PathBuf>,
+
+        /// List the lints with their levels instead of running them.
+        #[arg(long)]
+        list: bool,
+
+        /// Only report the sources that no top module or test includes, failing if there are
+        /// any.
+        #[arg(long, conflicts_with = "list")]
+        unused: bool,
+
+        /// Only report the registers of the last build's Verilog that read registers of other
+        /// clock domains without a synchronizer, failing if there are any.
+        #[arg(long, conflicts_with_all = ["list", "unused"])]
+        cdc: bool,
+    },
+    Mutate {
+        name: Option<path::PathBuf>,
+
+        /// Stop after testing this many mutants.
+        #[arg(long)]
+        max_mutants: Option<usize>,
+    },
+    New {
+        name: path::PathBuf,
+
+        #[command(flatten)]
+        template: TemplateArgs,
+
+        /// An author for [package] authors (repeatable; defaults to new.authors in the
+        /// configuration, or git's user.name and user.email).
+        #[arg(long = "author", value_name = "AUTHOR")]
+        authors: Vec<String>,
+
+        /// The [package] license, as an SPDX expression (defaults to new.license).
+        #[arg(long)]
+        license: Option<String>,
+
+        /// The [package] description.
+        #[arg(long)]
+        description: Option<String>,
+
+        /// The [package] edition (defaults to new.edition).
+        #[arg(long)]
+        edition: Option<String>,
+    },
+    Objcopy {
+        elf: path::PathBuf,
+
+        /// Image file to write (defaults to the ELF's name with a .hex extension).
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+
+        /// Bits per image word.
+        #[arg(long, default_value_t = 32)]
+        word_width: u32,
+
+        /// Interleave words across this many images (<output>_<bank>.hex).
+        #[arg(long, default_value_t = 1)]
+        banks: usize,
+
+        /// Address of the image's first word (defaults to the lowest loaded address).
+        #[arg(long, value_parser = elf::parse_address)]
+        base_address: Option<u64>,
+    },
+    Package {
+        name: Option<path::PathBuf>,
+
+        /// Package only this top module (may be repeated; defaults to all of them).
+        #[arg(long = "top", value_name = "MODULE")]
+        tops: Vec<String>,
+
+        /// The kind of HDL to generate.
+        #[arg(long, value_enum, default_value_t = builder::Emit::Verilog)]
+        emit: builder::Emit,
+    },
+    Prove {
+        name: Option<path::PathBuf>,
+    },
+    Report {
+        #[command(subcommand)]
+        report: Reports,
+    },
+    Riscof {
+        name: Option<path::PathBuf>,
+    },
+    Schedule {
+        name: Option<path::PathBuf>,
+
+        /// Only show the schedules of this target (a testbench or top module).
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Report how the schedules differ from those in a baseline target directory, and fail
+        /// if they do.
+        #[arg(long, value_name = "BASELINE_DIR")]
+        diff: Option<path::PathBuf>,
+    },
+    Serve {
+        name: Option<path::PathBuf>,
+
+        /// The address and port to listen on; 0.0.0.0:<port> serves other machines too.
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        address: String,
+    },
+    Size {
+        name: Option<path::PathBuf>,
+
+        /// Only report on this top module (may be repeated; defaults to all of them).
+        #[arg(long = "top", value_name = "MODULE")]
+        tops: Vec<String>,
+
+        /// Also list the size of each generated module, without its submodules.
+        #[arg(long)]
+        by_module: bool,
+
+        /// Also synthesize each top module with Yosys and report its cell counts.
+        #[arg(long)]
+        yosys: bool,
+    },
+    Test {
+        name: Option<path::PathBuf>,
+
+        /// The workspace member to test (by its [package] name), instead of the package
+        /// dolly is run in.
+        #[arg(short, long, value_name = "NAME", conflicts_with = "workspace")]
+        package: Option<String>,
+
+        /// Rewrite golden snapshot files with the current test output.
+        #[arg(long)]
+        update_snapshots: bool,
+
+        /// Dump a waveform for every test to target/<test>/<test>.vcd (or .fst).
+        #[arg(long)]
+        waves: bool,
+
+        /// Compare each test's //!compare_signals against the waveforms in a baseline target
+        /// directory.
+        #[arg(long, value_name = "BASELINE_DIR")]
+        compare_waves: Option<path::PathBuf>,
+
+        /// Collect coverage and write a merged report to target/coverage.
+        #[arg(long)]
+        coverage: bool,
+
+        /// Fail if bsc reports any warnings (as with [build] deny-warnings).
+        #[arg(long)]
+        deny_warnings: bool,
+
+        /// Print each bsc and simulator command line before running it.
+        #[arg(long)]
+        show_commands: bool,
+
+        /// Print the command lines that would be run, without running them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Only run the tests affected by the files changed in git (modified, staged or
+        /// untracked).
+        #[arg(long)]
+        changed: bool,
+
+        /// Run every test, including those that passed before with the same inputs.
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Only run one shard of the tests, e.g. 2/8 for the second of eight.  Shards are
+        /// balanced using the durations recorded in target/test-durations.toml, if any.
+        #[arg(long, value_parser = shard::parse)]
+        shard: Option<shard::Shard>,
+
+        /// Share the tests out among the machines listed in [runners] of the configuration
+        /// files, running them there with ssh.
+        #[arg(long)]
+        distribute: bool,
+
+        /// Report how long each compile, link and test run took, in the terminal and in
+        /// target/dolly-timings.html.
+        #[arg(long)]
+        timings: bool,
+
+        /// Show the run as a dashboard of the tests and the output of the selected one,
+        /// instead of printing the output of each.
+        #[arg(long, conflicts_with = "distribute")]
+        ui: bool,
+
+        /// Carry on with the other tests after one fails to build or pass, listing the
+        /// failures at the end.
+        #[arg(long)]
+        keep_going: bool,
+
+        /// Test every member of the workspace, with a summary of each package.
+        #[arg(long)]
+        workspace: bool,
+
+        /// Test the path dependencies of the packages as well.
+        #[arg(long)]
+        include_deps: bool,
+
+        /// Only run the tests in this subdirectory of tests/ (integration.cache or
+        /// integration/cache), or the one test named (may be repeated).
+        #[arg(long = "suite", value_name = "NAME")]
+        suites: Vec<String>,
+    },
     Version,
+    Waves {
+        test: String,
+
+        /// The waveform viewer to launch.
+        #[arg(long, value_enum, default_value = "gtkwave")]
+        viewer: waves::Viewer,
+    },
+    Why {
+        /// The module (mkName or Name), package or source file to explain.
+        query: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigActions {
+    /// Print a setting (such as jobs or tools.bsc) from the merged configuration files.
+    Get { key: String },
+    /// Change a setting in .dolly/config.toml (or ~/.config/dolly/config.toml).
+    Set {
+        key: String,
+        value: String,
+
+        /// Edit the user-level configuration file instead of the project's.
+        #[arg(long)]
+        user: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Generators {
+    /// Generate the BSV package, C header and linker fragment for [memory-map].
+    MemoryMap { name: Option<path::PathBuf> },
+    /// Generate a new module, with a unit testbench, in a directory below its parent's.
+    Module {
+        /// The module's package name (FetchUnit or fetch_unit).
+        name: String,
+
+        /// The directory of the parent module (defaults to the top file's).
+        #[arg(long)]
+        parent: Option<path::PathBuf>,
+
+        /// Import the new package in the parent instead of adding a //!submodule directive.
+        #[arg(long)]
+        import: bool,
+    },
+    /// Generate a BSV register package from a register description file.
+    Regs {
+        description: path::PathBuf,
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+        #[arg(long)]
+        c_header: Option<path::PathBuf>,
+        #[arg(long)]
+        markdown: Option<path::PathBuf>,
+    },
+    /// Generate the [soc] top-level wrapper.
+    Soc { name: Option<path::PathBuf> },
+    /// Generate a testbench skeleton for a module, calling each method of its interface.
+    Test {
+        /// The module to test (mkName or Name).
+        module: String,
+
+        /// The testbench to write (defaults to <Name>_tb.bsv next to the module's source).
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Reports {
+    /// Show the slowest tests, flaky tests and duration trends recorded in .dolly/history.
+    Tests {
+        /// The number of tests listed in each section.
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum SelfActions {
+    /// Replace dolly with the latest release of its channel.
+    Update {
+        /// Follow this release channel from now on, instead of the configured one.
+        #[arg(long, value_enum)]
+        channel: Option<self_update::Channel>,
+
+        /// Only report whether there is a newer release.
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+/// The template flags of `dolly init` and `dolly new`.
+#[derive(clap::Args)]
+struct TemplateArgs {
+    /// Create a library: packages with a unit testbench, and no top module.
+    #[arg(long, group = "skeleton")]
+    lib: bool,
+
+    /// Create testbenches only, for a design taken as a dependency.
+    #[arg(long, group = "skeleton")]
+    tb_only: bool,
+
+    /// Create an SoC wrapper generated from [soc] and [memory-map] sections.
+    #[arg(long, group = "skeleton")]
+    soc: bool,
+
+    /// Create a RISC-V core skeleton with an [isa-test] testbench.
+    #[arg(long, group = "skeleton")]
+    riscv_core: bool,
+
+    /// Instantiate the project from a git repository (URL or path) instead, replacing
+    /// {{name}}, {{project}} and {{author}} in its paths and files.
+    #[arg(long, group = "skeleton", value_name = "GIT_URL")]
+    template: Option<String>,
+}
+
+impl TemplateArgs {
+    fn template(&self) -> templates::Template {
+        if let Some(url) = &self.template {
+            templates::Template::Git(url.clone())
+        } else if self.lib {
+            templates::Template::Lib
+        } else if self.tb_only {
+            templates::Template::TbOnly
+        } else if self.soc {
+            templates::Template::Soc
+        } else if self.riscv_core {
+            templates::Template::RiscvCore
+        } else {
+            templates::Template::Default
+        }
+    }
 }
 
 fn find_project_file(starting_path: path::PathBuf) -> std::io::Result<path::PathBuf> {
@@ -66,70 +763,756 @@ fn load_project(
     // use the current directory.
     let search_root = explicit_search_root.unwrap_or(path::PathBuf::from("."));
 
-    if let Ok(project_file_name) = find_project_file(search_root) {
+    if let Ok(project_file_name) = find_project_file(search_root.clone()) {
         trace!("Loading project file...");
-        Project::load(project_file_name)
+        let project = Project::load(project_file_name.clone()).map_err(|e| {
+            // A workspace root with no package of its own is only a way to its members.
+            match workspace::Workspace::find(&search_root) {
+                Ok(Some(workspace))
+                    if workspace.root.join("dolly.toml") == project_file_name
+                        && !workspace.members.contains(&workspace.root) =>
+                {
+                    Box::new(codes::error(
+                        "D0012",
+                        format!(
+                            "{} is a workspace without a package of its own: choose a member \
+                             with -p <package>, or test them all with --workspace",
+                            project_file_name.to_string_lossy()
+                        ),
+                    ))
+                }
+                _ => e,
+            }
+        })?;
+        project.config.install();
+        Ok(project)
     } else {
-        error!("Project file not found");
-        Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)))
+        Err(Box::new(codes::error(
+            "D0001",
+            format!(
+                "No dolly.toml found in {} or its parents",
+                search_root
+                    .canonicalize()
+                    .unwrap_or(search_root)
+                    .to_string_lossy()
+            ),
+        )))
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init();
+/// Finds the workspace the search root (the current directory by default) is in.
+fn find_workspace(
+    explicit_search_root: Option<path::PathBuf>,
+) -> Result<workspace::Workspace, Box<dyn std::error::Error>> {
+    let search_root = explicit_search_root.unwrap_or(path::PathBuf::from("."));
+    workspace::Workspace::find(&search_root)?.ok_or_else(|| {
+        Box::new(codes::error(
+            "D0012",
+            format!(
+                "No dolly.toml with a [workspace] in {} or its parents",
+                search_root
+                    .canonicalize()
+                    .unwrap_or(search_root.clone())
+                    .to_string_lossy()
+            ),
+        )) as Box<dyn std::error::Error>
+    })
+}
+
+/// Loads the workspace member named by -p, or without it, the project as `load_project` does.
+fn load_package(
+    explicit_search_root: Option<path::PathBuf>,
+    package: Option<&str>,
+) -> Result<Project, Box<dyn std::error::Error>> {
+    let Some(package) = package else {
+        return load_project(explicit_search_root);
+    };
+    let project = find_workspace(explicit_search_root)?.member(package)?;
+    project.config.install();
+    Ok(project)
+}
+
+/// Generates the Verilog of the named top modules (all of them if none are named), as
+/// `dolly build` does, for the commands that hand it on.
+fn build_top_modules(
+    project: &Project,
+    tops: &[String],
+    emit: builder::Emit,
+) -> Result<Builder, Box<dyn std::error::Error>> {
+    if !matches!(emit, builder::Emit::Verilog | builder::Emit::Sv) {
+        return Err(Box::new(std::io::Error::other(
+            "Only Verilog (--emit verilog) or SystemVerilog (--emit sv) can be packaged or installed",
+        )));
+    }
+    let options = builder::Options {
+        emit,
+        ..builder::Options::default()
+    };
+
+    hooks::run(project, Hook::PreBuild, &options)?;
+    let builder = Builder::find_dependencies(project, Builder::with_options(options))
+        .and_then(|builder| Builder::find_modules(project, builder))
+        .and_then(|builder| Builder::find_top_modules(project, builder))
+        .and_then(|mut builder| {
+            builder.retain_top_modules(tops)?;
+            Ok(builder)
+        })
+        .and_then(|builder| Builder::build_verilog(project, builder))?;
+    hooks::run(project, Hook::PostBuild, builder.options())?;
+    Ok(builder)
+}
+
+/// Runs a project's tests, as `dolly test` does.
+fn test_project(
+    project: &Project,
+    options: builder::Options,
+) -> Result<Builder, Box<dyn std::error::Error>> {
+    hooks::run(project, Hook::PreBuild, &options)?;
+    hooks::run(project, Hook::PreTest, &options)?;
+    let builder = Builder::find_dependencies(project, Builder::with_options(options))
+        .and_then(|builder| Builder::find_modules(project, builder))
+        .and_then(|builder: Builder| Builder::find_tests(project, builder))
+        .and_then(|builder| Builder::run_tests(project, builder))?;
+    builder.report_timings(project)?;
+    hooks::run(project, Hook::PostBuild, builder.options())?;
+    hooks::run(project, Hook::PostTest, builder.options())?;
+    Ok(builder)
+}
+
+/// Runs the tests of several packages (with `include_deps`, of their path dependencies as
+/// well), one package after another, then summarizes the result of each.
+fn test_packages(
+    projects: Vec<Project>,
+    include_deps: bool,
+    options: builder::Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut packages = Vec::<Project>::new();
+    for project in projects {
+        let dependencies = if include_deps {
+            project.load_dependencies()?
+        } else {
+            Vec::new()
+        };
+        for package in std::iter::once(project).chain(dependencies) {
+            if !packages
+                .iter()
+                .any(|known| known.root_path() == package.root_path())
+            {
+                packages.push(package);
+            }
+        }
+    }
+
+    let mut results = Vec::<(String, Result<usize, String>)>::new();
+    for project in &packages {
+        println!(
+            "{}",
+            format!(
+                "Testing {} {}",
+                project.package.name, project.package.version
+            )
+            .bold()
+        );
+        let result = match test_project(project, options.clone()) {
+            Ok(builder) if builder.all_tests_passed() => Ok(builder.total_test_count()),
+            Ok(_) => Err(codes::error("D0303", "Not all tests passed").to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        results.push((project.package.name.clone(), result));
+    }
+
+    println!("{}", "Packages:".bold());
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, result) in &results {
+        match result {
+            Ok(count) => println!(
+                "    {:width$}  {} ({} tests)",
+                name,
+                "passed".green(),
+                count
+            ),
+            Err(e) => println!("    {:width$}  {}: {}", name, "FAILED".red(), e),
+        }
+    }
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(codes::error(
+            "D0303",
+            format!("Not all tests passed in {}", failed.join(", ")),
+        )))
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    let start = std::time::SystemTime::now();
+    // A panic is a bug in dolly, reported (after the panic's own message) as an error.
+    let result = std::panic::catch_unwind(run).unwrap_or_else(|_| {
+        Err(Box::new(codes::error(
+            "D0901",
+            "dolly panicked (see the message above)",
+        )))
+    });
+    let message = result.as_ref().err().map(|e| e.to_string());
+    telemetry::finish(message.as_deref());
+    notify::finish(start, message.as_deref());
+    match result {
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            if let Some(coded) = codes::coded(error.as_ref()) {
+                eprintln!("Run 'dolly explain {}' for more about it.", coded.code());
+            }
+            // The status says what kind of failure it was (see codes::Category).
+            codes::exit_status(error.as_ref()).into()
+        }
+        Ok(()) => std::process::ExitCode::SUCCESS,
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    telemetry::init();
+    runner::handle_interrupts();
     let cli = Cli::parse();
+    if let Some(target_dir) = &cli.target_dir {
+        // Project::load picks the override up from the environment.
+        std::env::set_var(
+            project::TARGET_DIR_VARIABLE,
+            std::env::current_dir()?.join(target_dir),
+        );
+    }
+    if let Some(jobs) = cli.jobs {
+        std::env::set_var(config::JOBS_VARIABLE, jobs.to_string());
+    }
+    if cli.offline {
+        // net::install picks it up, with the configuration.
+        std::env::set_var(net::OFFLINE_VARIABLE, "1");
+    }
+    // The long-running commands are worth a [notify] notification when they finish.
+    if let Some(command) = match &cli.command {
+        Commands::Build { .. } => Some("build"),
+        Commands::Fuzz { .. } => Some("fuzz"),
+        Commands::Mutate { .. } => Some("mutate"),
+        Commands::Prove { .. } => Some("prove"),
+        Commands::Riscof { .. } => Some("riscof"),
+        Commands::Test { .. } => Some("test"),
+        _ => None,
+    } {
+        notify::watch(command);
+    }
 
     match &cli.command {
-        Commands::Build { name } => {
-            let project = load_project(name.clone())?;
+        Commands::Build {
+            name,
+            package,
+            tops,
+            example,
+            run,
+            emit,
+            deny_warnings,
+            show_commands,
+            dry_run,
+            timings,
+            lint_verilog,
+            keep_going,
+        } => {
+            let project = load_package(name.clone(), package.as_deref())?;
 
             trace!("Project loaded: {:?}", project);
 
-            Builder::find_dependencies(&project, Builder::new())
+            let options = builder::Options {
+                emit: *emit,
+                deny_warnings: *deny_warnings,
+                show_commands: *show_commands,
+                dry_run: *dry_run,
+                timings: *timings,
+                lint_verilog: *lint_verilog,
+                keep_going: *keep_going,
+                ..builder::Options::default()
+            };
+
+            hooks::run(&project, Hook::PreBuild, &options)?;
+            if let Some(example) = example {
+                let builder = Builder::find_dependencies(&project, Builder::with_options(options))
+                    .and_then(|builder| Builder::find_modules(&project, builder))?;
+                examples::build(&project, &builder, example, *run)?;
+                builder.report_timings(&project)?;
+                return hooks::run(&project, Hook::PostBuild, builder.options());
+            }
+            let builder = Builder::find_dependencies(&project, Builder::with_options(options))
                 .and_then(|builder| Builder::find_modules(&project, builder))
                 .and_then(|builder: Builder| Builder::find_top_modules(&project, builder))
+                .and_then(|mut builder| {
+                    builder.retain_top_modules(tops)?;
+                    Ok(builder)
+                })
                 .and_then(|builder| Builder::build_verilog(&project, builder))?;
+            builder.report_timings(&project)?;
 
-            Ok(())
+            hooks::run(&project, Hook::PostBuild, builder.options())
         }
-        Commands::Clean { name } => {
+        Commands::Bump {
+            part,
+            name,
+            tag,
+            force,
+        } => {
             let project = load_project(name.clone())?;
 
-            project.clean()
+            version::bump(&project, *part, *tag, *force).map(|_| ())
         }
-        Commands::Init { name } => Project::init(name),
-        Commands::Test { name } => {
+        Commands::Check { name } => {
             let project = load_project(name.clone())?;
 
-            trace!("Project loaded: {:?}", project);
+            // Loading the dependencies and finding the tests checks the manifests and the
+            // testbench directives.
+            Builder::find_dependencies(&project, Builder::new())
+                .and_then(|builder| Builder::find_modules(&project, builder))
+                .and_then(|builder| Builder::find_tests(&project, builder))?;
+            diagnostics::report(&lint::lints(&project, false)?, false)
+        }
+        Commands::Clean {
+            name,
+            package,
+            targets,
+            tests,
+            deps,
+            all,
+            gc,
+        } => {
+            let project = load_package(name.clone(), package.as_deref())?;
+
+            if *gc {
+                let builder = Builder::find_dependencies(&project, Builder::new())
+                    .and_then(|builder| Builder::find_modules(&project, builder))
+                    .and_then(|builder| Builder::find_top_modules(&project, builder))
+                    .and_then(|builder| Builder::find_tests(&project, builder))?;
+                return project.collect_garbage(&builder.target_names());
+            }
+
+            project.clean(&project::CleanOptions {
+                targets: targets.clone(),
+                tests: *tests,
+                deps: *deps,
+                all: *all,
+            })
+        }
+        Commands::Config { action } => {
+            let project_root = find_project_file(path::PathBuf::from("."))
+                .ok()
+                .and_then(|project_file| project_file.parent().map(path::Path::to_path_buf));
+            match action {
+                ConfigActions::Get { key } => match config::get(project_root.as_deref(), key)? {
+                    Some(toml::Value::String(value)) => {
+                        println!("{}", value);
+                        Ok(())
+                    }
+                    Some(value) => {
+                        println!("{}", value);
+                        Ok(())
+                    }
+                    None => Err(Box::new(codes::error(
+                        "D0013",
+                        format!("'{}' is not set", key),
+                    ))),
+                },
+                ConfigActions::Set { key, value, user } => {
+                    let file = if *user {
+                        config::user_file().ok_or_else(|| {
+                            std::io::Error::other("Unable to locate the user configuration")
+                        })?
+                    } else {
+                        config::project_file(project_root.as_deref().ok_or_else(|| {
+                            std::io::Error::other("Not in a dolly project (use --user)")
+                        })?)
+                    };
+                    config::set(&file, key, value, *user)
+                }
+            }
+        }
+        Commands::Cosim { elf } => {
+            let project = load_project(None)?;
+
+            cosim::run(&project, elf)
+        }
+        Commands::Doc { name, format } => {
+            let project = load_project(name.clone())?;
 
             let builder = Builder::find_dependencies(&project, Builder::new())
-                .and_then(|builder| Builder::find_modules(&project, builder))
-                .and_then(|builder: Builder| Builder::find_tests(&project, builder))
-                .and_then(|builder| Builder::run_tests(&project, builder))?;
+                .and_then(|builder| Builder::find_modules(&project, builder))?;
+            doc::generate(&project, builder.module_paths(), *format)
+        }
+        Commands::Expand { module, rules, raw } => {
+            let project = load_project(None)?;
 
-            if builder.all_tests_passed() {
+            expand::run(&project, module, rules, *raw)
+        }
+        Commands::Explain { code } => {
+            print!("{}", codes::explain(code.as_deref())?);
+            Ok(())
+        }
+        Commands::Export {
+            name,
+            hierarchy: _,
+            tops,
+            output,
+        } => {
+            let project = load_project(name.clone())?;
+            let builder = build_top_modules(&project, tops, builder::Emit::Verilog)?;
+
+            let json = hierarchy::export(&project, builder.top_modules())?;
+            match output {
+                Some(output) => Ok(std::fs::write(output, json)?),
+                None => {
+                    print!("{}", json);
+                    Ok(())
+                }
+            }
+        }
+        Commands::External(args) => {
+            // Plugins can be run outside a project, without its metadata.
+            let project = load_project(None)
+                .map_err(|e| trace!("No project for the plugin: {}", e))
+                .ok();
+            let cli = Cli::command();
+            let commands: Vec<&str> = cli
+                .get_subcommands()
+                .map(|command| command.get_name())
+                .collect();
+
+            plugin::run(project.as_ref(), args, &commands)
+        }
+        Commands::Fmt { name, check } => {
+            let project = load_project(name.clone())?;
+
+            formatter::run(&project, *check)
+        }
+        Commands::Fuzz {
+            module,
+            seed,
+            steps,
+            runs,
+        } => {
+            let project = load_project(None)?;
+
+            fuzz::run(&project, module, *seed, *steps, *runs)
+        }
+        Commands::Gen { generator } => match generator {
+            Generators::MemoryMap { name } => {
+                let project = load_project(name.clone())?;
+
+                memory_map::generate(&project)
+            }
+            Generators::Module {
+                name,
+                parent,
+                import,
+            } => {
+                let project = load_project(None)?;
+
+                scaffold::module(&project, name, parent.as_deref(), *import)
+            }
+            Generators::Regs {
+                description,
+                output,
+                c_header,
+                markdown,
+            } => regs::generate(
+                description,
+                output.as_deref(),
+                c_header.as_deref(),
+                markdown.as_deref(),
+            ),
+            Generators::Soc { name } => {
+                let project = load_project(name.clone())?;
+
+                soc::generate(&project)
+            }
+            Generators::Test { module, output } => {
+                let project = load_project(None)?;
+
+                scaffold::test(&project, module, output.as_deref())
+            }
+        },
+        Commands::Init {
+            name,
+            template,
+            existing,
+        } => {
+            config::Config::load(None)?.install();
+            if *existing {
+                print!("{}", adopt::adopt(name)?);
+                Ok(())
+            } else {
+                Project::init(name, &template.template())
+            }
+        }
+        Commands::Inspect { module } => {
+            let project = load_project(None)?;
+
+            print!("{}", inspect::report(&project, module)?);
+            Ok(())
+        }
+        Commands::Install {
+            name,
+            prefix,
+            tops,
+            emit,
+        } => {
+            let project = load_project(name.clone())?;
+            let builder = build_top_modules(&project, tops, *emit)?;
+
+            let directory = install::install(&project, &builder, prefix)?;
+            println!("Installed {}", directory.to_string_lossy());
+            Ok(())
+        }
+        Commands::IsaTest { name } => {
+            let project = load_project(name.clone())?;
+
+            isa_test::run(&project)
+        }
+        Commands::Itself { action } => match action {
+            SelfActions::Update { channel, check } => {
+                let config = config::Config::load(None)?;
+                config.install();
+
+                self_update::update(&config, *channel, *check)
+            }
+        },
+        Commands::Lint {
+            name,
+            list,
+            unused,
+            cdc,
+        } => {
+            let project = load_project(name.clone())?;
+
+            if *list {
+                print!("{}", lint::list(&project)?);
                 Ok(())
+            } else if *unused {
+                diagnostics::report(&lint::unused(&project)?, true)
+            } else if *cdc {
+                diagnostics::report(&lint::cdc(&project)?, true)
             } else {
-                Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Not all tests passed",
+                diagnostics::report(&lint::lints(&project, true)?, false)
+            }
+        }
+        Commands::Mutate { name, max_mutants } => {
+            let project = load_project(name.clone())?;
+
+            mutate::run(&project, *max_mutants)
+        }
+        Commands::New {
+            name,
+            template,
+            authors,
+            license,
+            description,
+            edition,
+        } => {
+            let config = config::Config::load(None)?;
+            config.install();
+
+            let mut authors = authors.clone();
+            if authors.is_empty() {
+                authors = config.new.authors.clone();
+            }
+            if authors.is_empty() {
+                authors.extend(templates::git_identity());
+            }
+            let metadata = templates::Metadata {
+                authors,
+                license: license.clone().or(config.new.license),
+                description: description.clone(),
+                edition: edition.clone().or(config.new.edition),
+            };
+            Project::create(name, &template.template(), &metadata)
+        }
+        Commands::Objcopy {
+            elf,
+            output,
+            word_width,
+            banks,
+            base_address,
+        } => {
+            if *word_width == 0 || !word_width.is_multiple_of(8) {
+                return Err(Box::new(std::io::Error::other(
+                    "The word width must be a non-zero multiple of 8 bits",
+                )));
+            }
+
+            let layout = elf::ImageLayout {
+                base: *base_address,
+                word_bytes: *word_width as usize / 8,
+                banks: *banks,
+            };
+            let output = output.clone().unwrap_or_else(|| elf.with_extension("hex"));
+            for file in elf::Elf::load(elf)?.write_images(&layout, &output)? {
+                println!("Wrote {}", file.to_string_lossy());
+            }
+            Ok(())
+        }
+        Commands::Package { name, tops, emit } => {
+            let project = load_project(name.clone())?;
+            let builder = build_top_modules(&project, tops, *emit)?;
+
+            let archive = package::bundle(&project, &builder)?;
+            println!("Packaged {}", archive.to_string_lossy());
+            Ok(())
+        }
+        Commands::Prove { name } => {
+            let project = load_project(name.clone())?;
+
+            formal::prove(&project)
+        }
+        Commands::Report { report } => match report {
+            Reports::Tests { limit } => {
+                let project = load_project(None)?;
+                let runs = history::load(&history::history_file(project.root_path()));
+                print!("{}", history::report(&runs, *limit));
+                Ok(())
+            }
+        },
+        Commands::Riscof { name } => {
+            let project = load_project(name.clone())?;
+
+            riscof::run(&project)
+        }
+        Commands::Schedule { name, target, diff } => {
+            let project = load_project(name.clone())?;
+
+            if schedule::report(&project, target.as_deref(), diff.as_deref())? {
+                Ok(())
+            } else {
+                Err(Box::new(std::io::Error::other(
+                    "Schedules differ from the baseline",
                 )))
             }
         }
+        Commands::Serve { name, address } => {
+            let project = load_project(name.clone())?;
+
+            serve::run(&project, address)
+        }
+        Commands::Size {
+            name,
+            tops,
+            by_module,
+            yosys,
+        } => {
+            let project = load_project(name.clone())?;
+            let builder = build_top_modules(&project, tops, builder::Emit::Verilog)?;
+
+            size::report(&project, builder.top_modules(), *by_module, *yosys)
+        }
+        Commands::Test {
+            name,
+            package,
+            update_snapshots,
+            waves,
+            compare_waves,
+            coverage,
+            deny_warnings,
+            show_commands,
+            dry_run,
+            changed,
+            no_cache,
+            shard,
+            distribute,
+            timings,
+            ui,
+            keep_going,
+            workspace,
+            include_deps,
+            suites,
+        } => {
+            let options = builder::Options {
+                emit: builder::Emit::Verilog,
+                update_snapshots: *update_snapshots,
+                waves: *waves,
+                compare_waves: compare_waves.clone(),
+                coverage: *coverage,
+                deny_warnings: *deny_warnings,
+                show_commands: *show_commands,
+                dry_run: *dry_run,
+                changed: *changed,
+                no_cache: *no_cache,
+                shard: *shard,
+                timings: *timings,
+                lint_verilog: false,
+                distribute: *distribute,
+                ui: *ui,
+                keep_going: *keep_going,
+                suites: suites.clone(),
+            };
+
+            if *workspace || *include_deps {
+                let projects = if *workspace {
+                    let workspace = find_workspace(name.clone())?;
+                    let members = workspace.load_members()?;
+                    // The settings are process-wide, so those of the root apply to every member.
+                    config::Config::load(Some(&workspace.root))?.install();
+                    for member in &members {
+                        let file = config::project_file(member.root_path());
+                        if *member.root_path() != workspace.root && file.is_file() {
+                            warn!(
+                                "{} isn't used by --workspace, which takes its settings from {}",
+                                file.to_string_lossy(),
+                                config::project_file(&workspace.root).to_string_lossy()
+                            );
+                        }
+                    }
+                    members
+                } else {
+                    vec![load_package(name.clone(), package.as_deref())?]
+                };
+                return test_packages(projects, *include_deps, options);
+            }
+
+            let project = load_package(name.clone(), package.as_deref())?;
+            trace!("Project loaded: {:?}", project);
+            let builder = test_project(&project, options)?;
+            if builder.all_tests_passed() {
+                Ok(())
+            } else {
+                Err(Box::new(codes::error("D0303", "Not all tests passed")))
+            }
+        }
         Commands::Version => {
             print!("{} v{}", NAME, VERSION);
             Ok(())
         }
+        Commands::Waves { test, viewer } => {
+            let project = load_project(None)?;
+
+            waves::open(&project.target_dir(), test, *viewer)
+        }
+        Commands::Why { query } => {
+            let project = load_project(None)?;
+
+            print!("{}", graph::why(&project, query)?);
+            Ok(())
+        }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
     use std::sync::Once;
 
     static INIT: Once = Once::new();
+    static TRACING: Once = Once::new();
 
     /// Setup function that is only run once, even if called multiple times.
     fn setup() {
@@ -138,6 +1521,13 @@ mod test {
         });
     }
 
+    /// Like `setup`, with dolly's own tracing output (see telemetry.rs).  The logger is set up
+    /// first, as it can't be once tracing has taken over the log.
+    fn setup_tracing() {
+        setup();
+        TRACING.call_once(telemetry::init);
+    }
+
     #[test]
     fn simple_dot_bsv_test() -> Result<(), Box<dyn std::error::Error>> {
         setup();
@@ -178,6 +1568,67 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn import_discovery() -> Result<(), Box<dyn std::error::Error>> {
+        setup_tracing();
+        let working_dir = std::env::current_dir().unwrap().join("examples/imports");
+
+        let project = load_project(Some(working_dir))?;
+
+        let builder = Builder::find_dependencies(&project, Builder::new())
+            .and_then(|builder| Builder::find_modules(&project, builder))?;
+
+        let lib = project.root_path().join("src").join("lib");
+        assert!(builder
+            .module_paths()
+            .any(|module_path| *module_path == lib));
+
+        Ok(())
+    }
+
+    #[test]
+    fn generated_sources() -> Result<(), Box<dyn std::error::Error>> {
+        setup_tracing();
+        let working_dir = std::env::current_dir().unwrap().join("examples/codegen");
+
+        let project = load_project(Some(working_dir))?;
+        let _ = std::fs::remove_dir_all(project.generated_dir());
+
+        let builder = Builder::find_dependencies(&project, Builder::new())
+            .and_then(|builder| Builder::find_modules(&project, builder))?;
+
+        assert!(project.generated_dir().join("Opcodes.bsv").exists());
+        assert!(builder
+            .module_paths()
+            .any(|module_path| *module_path == project.generated_dir()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn configured_source_layout() -> Result<(), Box<dyn std::error::Error>> {
+        setup_tracing();
+        let working_dir = std::env::current_dir().unwrap().join("examples/layout");
+
+        let project = load_project(Some(working_dir))?;
+        assert_eq!(
+            project.top_file(),
+            project.root_path().join("rtl").join("Top.bsv")
+        );
+
+        let builder = Builder::find_dependencies(&project, Builder::new())
+            .and_then(|builder| Builder::find_modules(&project, builder))
+            .and_then(|builder| Builder::find_top_modules(&project, builder))?;
+
+        let generated = project.root_path().join("generated");
+        assert!(builder
+            .module_paths()
+            .any(|module_path| *module_path == generated));
+        assert_eq!(builder.top_module_count(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn bdpi_test() -> Result<(), Box<dyn std::error::Error>> {
         setup();
@@ -198,4 +1649,80 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_hooks() -> Result<(), Box<dyn std::error::Error>> {
+        setup_tracing();
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        let hooks = ["pre-build", "pre-test", "post-build", "post-test"]
+            .map(|hook| format!("{} = \"echo $DOLLY_HOOK >> hooks.txt\"\n", hook));
+        std::fs::write(
+            root.join("dolly.toml"),
+            format!(
+                "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[hooks]\n{}",
+                hooks.concat()
+            ),
+        )?;
+        std::fs::create_dir(root.join("src"))?;
+
+        let project = load_project(Some(root.to_path_buf()))?;
+        test_project(&project, builder::Options::default())?;
+        assert_eq!(
+            std::fs::read_to_string(root.join("hooks.txt"))?,
+            "pre-build\npre-test\npost-build\npost-test\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn package_selection() -> Result<(), Box<dyn std::error::Error>> {
+        setup_tracing();
+        let temp = tempfile::tempdir()?;
+        let root = temp.path().canonicalize()?;
+        std::fs::write(
+            root.join("dolly.toml"),
+            "[workspace]\nmembers = [\"fifo\", \"uart\"]\n",
+        )?;
+        for member in ["fifo", "uart"] {
+            std::fs::create_dir(root.join(member))?;
+            std::fs::write(
+                root.join(member).join("dolly.toml"),
+                format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", member),
+            )?;
+        }
+        let code = |error: Box<dyn std::error::Error>| {
+            codes::coded(error.as_ref()).map(|coded| coded.code().to_string())
+        };
+
+        // -p picks a member wherever dolly runs in the workspace; without it, the package
+        // dolly runs in is used, and the workspace root has none.
+        let fifo = Some(root.join("fifo"));
+        assert_eq!(
+            load_package(fifo.clone(), Some("uart"))?.package.name,
+            "uart"
+        );
+        assert_eq!(
+            load_package(Some(root.clone()), Some("fifo"))?.package.name,
+            "fifo"
+        );
+        assert_eq!(load_package(fifo, None)?.package.name, "fifo");
+        let error = load_package(Some(root.clone()), None).err().unwrap();
+        assert!(error.to_string().contains("-p <package>"));
+        assert_eq!(code(error).as_deref(), Some("D0012"));
+        let error = load_package(Some(root.clone()), Some("urat"))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("'urat'") && error.to_string().contains("uart"));
+        assert_eq!(code(error).as_deref(), Some("D0012"));
+        let outside = tempfile::tempdir()?;
+        let error = load_package(Some(outside.path().to_path_buf()), Some("fifo"))
+            .err()
+            .unwrap();
+        assert_eq!(code(error).as_deref(), Some("D0012"));
+        assert!(Cli::try_parse_from(["dolly", "test", "-p", "uart", "--workspace"]).is_err());
+
+        Ok(())
+    }
 }
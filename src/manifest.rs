@@ -0,0 +1,202 @@
+//! Errors in dolly.toml described for people: where the problem is, the key or value that was
+//! probably meant, and the valid ones.
+use super::codes;
+use super::suggest;
+use regex::Regex;
+use std::path;
+
+/// The names in a serde "expected" list (`` `a`, `b` or `c` ``).
+fn expected_names(list: &str) -> Vec<&str> {
+    let name_re = Regex::new(r"`([^`]*)`").unwrap();
+    name_re
+        .captures_iter(list)
+        .map(|capture| capture.get(1).unwrap().as_str())
+        .collect()
+}
+
+/// The table containing a line, from the closest header before it.
+fn section(lines: &[&str], line: usize) -> String {
+    let header_re = Regex::new(r"^\s*\[+\s*([^\]]+?)\s*\]+").unwrap();
+    lines[..line]
+        .iter()
+        .rev()
+        .find_map(|text| header_re.captures(text))
+        .map_or("the top level".to_string(), |capture| {
+            format!("[{}]", &capture[1])
+        })
+}
+
+/// Describes an error from parsing or deserializing a manifest.
+pub fn describe(file: &path::Path, contents: &str, error: &toml::de::Error) -> String {
+    let message = error.message().trim();
+    let lines: Vec<&str> = contents.lines().collect();
+    let location = error.span().map(|span| {
+        let before = &contents[..span.start.min(contents.len())];
+        let line = before.matches('\n').count();
+        let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1);
+        (line, column, span.len().max(1))
+    });
+
+    let field_re = Regex::new(r"^unknown field `([^`]*)`, (?:expected (.*)|there are no fields)$");
+    let variant_re = Regex::new(r"^unknown variant `([^`]*)`, expected (.*)$");
+    let within = match location {
+        // Unknown tables are reported at their own header.
+        Some((line, _, _))
+            if !lines
+                .get(line)
+                .is_some_and(|l| l.trim_start().starts_with('[')) =>
+        {
+            format!(" in {}", section(&lines, line))
+        }
+        _ => String::new(),
+    };
+    let (headline, note) = if let Some(capture) = field_re.unwrap().captures(message) {
+        let expected = capture
+            .get(2)
+            .map_or(Vec::new(), |list| expected_names(list.as_str()));
+        let suggestions = suggest::similar(&capture[1], expected.iter().copied());
+        (
+            format!(
+                "Unknown key `{}`{}{}",
+                &capture[1],
+                within,
+                suggest::did_you_mean(&suggestions)
+            ),
+            if expected.is_empty() {
+                "this table has no keys".to_string()
+            } else {
+                format!("valid keys: {}", expected.join(", "))
+            },
+        )
+    } else if let Some(capture) = variant_re.unwrap().captures(message) {
+        let expected = expected_names(&capture[2]);
+        let suggestions = suggest::similar(&capture[1], expected.iter().copied());
+        (
+            format!(
+                "Invalid value `{}`{}{}",
+                &capture[1],
+                within,
+                suggest::did_you_mean(&suggestions)
+            ),
+            format!("valid values: {}", expected.join(", ")),
+        )
+    } else {
+        let mut message = message.to_string();
+        if let Some(first) = message.get(..1) {
+            message = first.to_uppercase() + &message[1..];
+        }
+        (format!("{}{}", message, within), String::new())
+    };
+
+    let mut description = format!("{}\n", headline);
+    if let Some((line, column, length)) = location {
+        let number = (line + 1).to_string();
+        let padding = " ".repeat(number.len());
+        let text = lines.get(line).copied().unwrap_or_default();
+        let length = length.min(text.len().saturating_sub(column)).max(1);
+        description.push_str(&format!(
+            "{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            padding,
+            file.to_string_lossy(),
+            line + 1,
+            column + 1,
+            padding,
+            number,
+            text,
+            padding,
+            " ".repeat(column),
+            "^".repeat(length)
+        ));
+        if !note.is_empty() {
+            description.push_str(&format!("{} = {}\n", padding, note));
+        }
+    } else if !note.is_empty() {
+        description.push_str(&format!("  {}\n", note));
+    }
+    description
+}
+
+/// Sets fields of a manifest's [package] table, replacing those it already has and keeping the
+/// rest of the file (and its comments) as it is.
+pub fn set_package_fields(manifest: &str, fields: &[(&str, toml::Value)]) -> String {
+    let mut lines: Vec<String> = manifest.lines().map(str::to_string).collect();
+    let Some(start) = lines.iter().position(|line| line.trim() == "[package]") else {
+        return manifest.to_string();
+    };
+    let mut end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    while end > start + 1 && lines[end - 1].trim().is_empty() {
+        end -= 1;
+    }
+    for (key, value) in fields {
+        let line = format!("{} = {}", key, value);
+        let existing = lines[start + 1..end].iter().position(|line| {
+            line.split_once('=')
+                .is_some_and(|(name, _)| name.trim() == *key)
+        });
+        match existing {
+            Some(offset) => lines[start + 1 + offset] = line,
+            None => {
+                lines.insert(end, line);
+                end += 1;
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Deserializes a manifest, describing any error.
+pub fn parse<T: serde::de::DeserializeOwned>(
+    file: &path::Path,
+    contents: &str,
+) -> Result<T, std::io::Error> {
+    toml::from_str(contents).map_err(|e| {
+        codes::error(
+            "D0007",
+            format!(
+                "Invalid {}: {}",
+                file.to_string_lossy(),
+                describe(file, contents, &e)
+            ),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::Project;
+
+    #[test]
+    fn described_errors() {
+        let file = path::Path::new("dolly.toml");
+        let describe = |contents: &str| {
+            let error = toml::from_str::<Project>(contents).unwrap_err();
+            describe(file, contents, &error)
+        };
+        let description =
+            describe("[package]\nname = \"Core\"\nversion = \"0.1.0\"\nsrcdirs = [\"rtl\"]\n");
+        assert!(description.starts_with(
+            "Unknown key `srcdirs` in [package] (did you mean src-dirs?)\n \
+             --> dolly.toml:4:1\n  |\n4 | srcdirs = [\"rtl\"]\n  | ^^^^^^^\n  \
+             = valid keys: name, version, src-dirs,"
+        ));
+
+        let description = describe(
+            "[package]\nname = \"Core\"\nversion = \"0.1.0\"\n\n[test]\nsimulator = \"verilatr\"\n",
+        );
+        assert_eq!(
+            description.lines().next().unwrap(),
+            "Invalid value `verilatr` in [test] (did you mean verilator?)"
+        );
+        assert!(description.ends_with("  = valid values: bluesim, verilator\n"));
+
+        let description = describe("[package]\nname = \"Core\"\nversion = \"0.1.0\"\n[verilg]\n");
+        assert_eq!(
+            description.lines().next().unwrap(),
+            "Unknown key `verilg` (did you mean verilog?)"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use super::project::{MemoryMapConfig, Project, Region};
+use convert_case::{Case, Casing};
+use std::{fmt::Write, fs, path};
+
+/// Checks that no two regions overlap.
+pub fn validate(regions: &[Region]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sorted: Vec<&Region> = regions.iter().collect();
+    sorted.sort_by_key(|region| region.base);
+    for pair in sorted.windows(2) {
+        if pair[0].base.saturating_add(pair[0].size) > pair[1].base {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Memory regions '{}' and '{}' overlap",
+                pair[0].name, pair[1].name
+            ))));
+        }
+    }
+    Ok(())
+}
+
+fn hex(config: &MemoryMapConfig, value: u64) -> String {
+    format!(
+        "{:0width$x}",
+        value,
+        width = config.address_width.div_ceil(4) as usize
+    )
+}
+
+/// Generates the BSV package of address constants and region membership functions.
+pub fn generate_bsv(config: &MemoryMapConfig, package: &str) -> String {
+    let mut bsv = String::new();
+    writeln!(bsv, "// Generated by dolly gen memory-map -- do not edit.").unwrap();
+    writeln!(bsv, "package {};\n", package).unwrap();
+    writeln!(bsv, "typedef Bit#({}) Address;", config.address_width).unwrap();
+    for region in &config.regions {
+        let name = region.name.to_case(Case::Camel);
+        let pascal = region.name.to_case(Case::Pascal);
+        writeln!(bsv).unwrap();
+        writeln!(
+            bsv,
+            "Address {}Base = 'h{};",
+            name,
+            hex(config, region.base)
+        )
+        .unwrap();
+        writeln!(
+            bsv,
+            "Address {}Size = 'h{};",
+            name,
+            hex(config, region.size)
+        )
+        .unwrap();
+        writeln!(
+            bsv,
+            "function Bool is{}(Address address) = address >= {}Base && address - {}Base < {}Size;",
+            pascal, name, name, name
+        )
+        .unwrap();
+    }
+    writeln!(bsv, "\nendpackage").unwrap();
+    bsv
+}
+
+/// Generates the matching C header.
+pub fn generate_c_header(config: &MemoryMapConfig, guard: &str) -> String {
+    let mut header = String::new();
+    writeln!(
+        header,
+        "// Generated by dolly gen memory-map -- do not edit."
+    )
+    .unwrap();
+    writeln!(header, "#ifndef {}", guard).unwrap();
+    writeln!(header, "#define {}\n", guard).unwrap();
+    for region in &config.regions {
+        let name = region.name.to_case(Case::UpperSnake);
+        writeln!(
+            header,
+            "#define {}_BASE 0x{}UL",
+            name,
+            hex(config, region.base)
+        )
+        .unwrap();
+        writeln!(
+            header,
+            "#define {}_SIZE 0x{}UL",
+            name,
+            hex(config, region.size)
+        )
+        .unwrap();
+    }
+    writeln!(header, "\n#endif").unwrap();
+    header
+}
+
+/// Generates a linker script MEMORY fragment (for use with INCLUDE).
+pub fn generate_linker_fragment(config: &MemoryMapConfig) -> String {
+    let mut fragment = String::new();
+    writeln!(
+        fragment,
+        "/* Generated by dolly gen memory-map -- do not edit. */"
+    )
+    .unwrap();
+    writeln!(fragment, "MEMORY\n{{").unwrap();
+    for region in &config.regions {
+        writeln!(
+            fragment,
+            "  {} ({}) : ORIGIN = 0x{}, LENGTH = 0x{}",
+            region.name.to_case(Case::Snake),
+            region.attributes,
+            hex(config, region.base),
+            hex(config, region.size)
+        )
+        .unwrap();
+    }
+    writeln!(fragment, "}}").unwrap();
+    fragment
+}
+
+fn write(path: &path::Path, contents: String) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    println!("Wrote {}", path.to_string_lossy());
+    Ok(())
+}
+
+/// Writes the BSV package, C header and linker fragment for the `[memory-map]` table.
+pub fn generate(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(config) = &project.memory_map else {
+        return Err(Box::new(std::io::Error::other(
+            "No [memory-map] section in dolly.toml",
+        )));
+    };
+    validate(&config.regions)?;
+
+    let bsv = project.root_path().join(&config.bsv);
+    // The package is named after the file, as bsc requires.
+    let package = bsv.file_stem().unwrap().to_string_lossy().to_string();
+    write(&bsv, generate_bsv(config, &package))?;
+
+    let header = project.root_path().join(&config.c_header);
+    let guard = format!(
+        "{}_H",
+        header
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .to_case(Case::UpperSnake)
+    );
+    write(&header, generate_c_header(config, &guard))?;
+
+    write(
+        &project.root_path().join(&config.linker_script),
+        generate_linker_fragment(config),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_map_outputs() {
+        let config: MemoryMapConfig = toml::from_str(
+            r#"
+            regions = [
+                { name = "boot-rom", base = 0x0, size = 0x1000, attributes = "rx" },
+                { name = "ram", base = 0x80000000, size = 0x10000 },
+            ]
+            "#,
+        )
+        .unwrap();
+        validate(&config.regions).unwrap();
+
+        let bsv = generate_bsv(&config, "MemoryMap");
+        assert!(bsv.contains("Address bootRomBase = 'h00000000;\n"));
+        assert!(bsv.contains(
+            "function Bool isRam(Address address) = address >= ramBase && address - ramBase < ramSize;\n"
+        ));
+        assert!(
+            generate_c_header(&config, "MEMORY_MAP_H").contains("#define RAM_BASE 0x80000000UL\n")
+        );
+        assert!(generate_linker_fragment(&config)
+            .contains("  boot_rom (rx) : ORIGIN = 0x00000000, LENGTH = 0x00001000\n"));
+
+        let overlapping = vec![
+            config.regions[1].clone(),
+            Region {
+                name: "mmio".to_string(),
+                base: 0x8000_8000,
+                size: 0x100,
+                attributes: "rw".to_string(),
+            },
+        ];
+        assert!(validate(&overlapping).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use super::builder::Builder;
+use super::project::Project;
+use colored::Colorize;
+use regex::Regex;
+use std::{fs, path};
+use tracing::{error, trace};
+
+/// A single source mutation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mutant {
+    pub file: path::PathBuf, // Relative to the project root
+    pub line: usize,         // 1-based
+    start: usize,            // Byte range within the line that is replaced
+    end: usize,
+    replacement: String,
+    pub description: String,
+}
+
+impl Mutant {
+    fn apply(&self, source: &str) -> String {
+        source
+            .split_inclusive('\n')
+            .enumerate()
+            .map(|(index, line)| {
+                if index + 1 == self.line {
+                    format!(
+                        "{}{}{}",
+                        &line[..self.start],
+                        self.replacement,
+                        &line[self.end..]
+                    )
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Killed,
+    Survived,
+    Invalid, // The mutant didn't compile
+}
+
+const OPERATOR_FLIPS: [(&str, &str); 8] = [
+    ("==", "!="),
+    ("!=", "=="),
+    ("&&", "||"),
+    ("||", "&&"),
+    (" + ", " - "),
+    (" - ", " + "),
+    (" < ", " >= "),
+    (" > ", " <= "),
+];
+
+/// Returns whether byte `offset` of `line` is inside a string literal.
+fn in_string(line: &str, offset: usize) -> bool {
+    line[..offset].matches('"').count() % 2 == 1
+}
+
+/// Finds every mutation of a BSV source file.
+pub fn find_mutants(file: &path::Path, source: &str) -> Vec<Mutant> {
+    let constant_re = Regex::new(r"\b\d+\b").unwrap();
+    let boolean_re = Regex::new(r"\b(True|False)\b").unwrap();
+    let rule_re = Regex::new(r"^\s*rule\s+\w+\s*\((.*)\)\s*;").unwrap();
+
+    let mut mutants = Vec::<Mutant>::new();
+    for (index, line) in source.lines().enumerate() {
+        // Ignore comments (including dolly directives).
+        let code = match line.find("//") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+
+        let mut mutate = |start: usize, end: usize, replacement: String, description: String| {
+            if !in_string(code, start) {
+                mutants.push(Mutant {
+                    file: file.to_path_buf(),
+                    line: index + 1,
+                    start,
+                    end,
+                    replacement,
+                    description,
+                });
+            }
+        };
+
+        for (from, to) in OPERATOR_FLIPS {
+            for (start, _) in code.match_indices(from) {
+                mutate(
+                    start,
+                    start + from.len(),
+                    to.to_string(),
+                    format!("replaced '{}' with '{}'", from.trim(), to.trim()),
+                );
+            }
+        }
+
+        for constant in constant_re.find_iter(code) {
+            // Skip numeric type parameters such as Bit#(32), which would only fail to compile.
+            if code[..constant.start()].ends_with("#(") {
+                continue;
+            }
+            let value: u64 = constant.as_str().parse().unwrap_or(0);
+            mutate(
+                constant.start(),
+                constant.end(),
+                (value + 1).to_string(),
+                format!("replaced {} with {}", value, value + 1),
+            );
+        }
+
+        for boolean in boolean_re.find_iter(code) {
+            let flipped = if boolean.as_str() == "True" {
+                "False"
+            } else {
+                "True"
+            };
+            mutate(
+                boolean.start(),
+                boolean.end(),
+                flipped.to_string(),
+                format!("replaced {} with {}", boolean.as_str(), flipped),
+            );
+        }
+
+        if let Some(condition) = rule_re.captures(code).and_then(|capture| capture.get(1)) {
+            mutate(
+                condition.start(),
+                condition.end(),
+                format!("!({})", condition.as_str()),
+                "inverted rule condition".to_string(),
+            );
+        }
+    }
+
+    mutants
+}
+
+/// Copies a directory tree, skipping `skip` (relative to `from`).
+pub fn copy_tree(from: &path::Path, to: &path::Path, skip: &[&str]) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if skip.iter().any(|skipped| name == *skipped) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &to.join(&name), &[])?;
+        } else {
+            fs::copy(entry.path(), to.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the test suite of the project at `project_file`.
+fn run_suite(project_file: &path::Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut project = Project::load(project_file.to_path_buf())?;
+    // Keep the mutants' builds inside the sandbox, even with a target directory override.
+    project.set_target_dir(project.root_path().join("target"));
+
+    let builder = Builder::find_dependencies(&project, Builder::new())
+        .and_then(|builder| Builder::find_modules(&project, builder))
+        .and_then(|builder: Builder| Builder::find_tests(&project, builder))
+        .and_then(|builder| Builder::run_tests(&project, builder))?;
+
+    Ok(builder.all_tests_passed())
+}
+
+/// Applies each mutation to non-test sources in a sandbox copy of the project, runs the test
+/// suite per mutant and reports the mutants that survive.
+pub fn run(
+    project: &Project,
+    max_mutants: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    // Mutate every non-testbench source in the project's modules.
+    let mut mutants = Vec::<Mutant>::new();
+    let mut sources: Vec<path::PathBuf> = builder
+        .module_paths()
+        .filter_map(|module| module.read_dir().ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "bsv") && !project.is_unit_test(path)
+        })
+        .collect();
+    sources.sort();
+
+    for source in &sources {
+        let relative = source.strip_prefix(project.root_path())?;
+        mutants.extend(find_mutants(relative, &fs::read_to_string(source)?));
+    }
+    if let Some(max_mutants) = max_mutants {
+        mutants.truncate(max_mutants);
+    }
+
+    // Make sure the unmodified project passes before blaming the mutants.
+    let sandbox = project.output_dir("mutate");
+    let _ = fs::remove_dir_all(&sandbox);
+    // The target directory may have been moved into the project under another name.
+    let target_name = project
+        .target_dir()
+        .strip_prefix(project.root_path())
+        .ok()
+        .and_then(|relative| relative.iter().next())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    copy_tree(
+        project.root_path(),
+        &sandbox,
+        &["target", &target_name, ".git"],
+    )?;
+    let sandbox_project = sandbox.join("dolly.toml");
+    if !run_suite(&sandbox_project)? {
+        error!("The test suite must pass before mutation testing");
+        return Err(Box::new(std::io::Error::other("Test suite failed")));
+    }
+
+    let mut survivors = Vec::<&Mutant>::new();
+    let mut killed = 0;
+    let mut invalid = 0;
+    for (index, mutant) in mutants.iter().enumerate() {
+        println!(
+            "Mutant {}/{}: {}:{}: {}",
+            index + 1,
+            mutants.len(),
+            mutant.file.to_string_lossy(),
+            mutant.line,
+            mutant.description
+        );
+
+        let mutated_file = sandbox.join(&mutant.file);
+        let original = fs::read_to_string(&mutated_file)?;
+        fs::write(&mutated_file, mutant.apply(&original))?;
+        let outcome = match run_suite(&sandbox_project) {
+            Ok(true) => Outcome::Survived,
+            Ok(false) => Outcome::Killed,
+            Err(e) => {
+                trace!("Mutant failed to build: {}", e);
+                Outcome::Invalid
+            }
+        };
+        fs::write(&mutated_file, original)?;
+
+        match outcome {
+            Outcome::Killed => killed += 1,
+            Outcome::Invalid => invalid += 1,
+            Outcome::Survived => survivors.push(mutant),
+        }
+    }
+
+    println!();
+    for mutant in &survivors {
+        println!(
+            "{}: {}:{}: {}",
+            "SURVIVED".red().bold(),
+            mutant.file.to_string_lossy(),
+            mutant.line,
+            mutant.description
+        );
+    }
+
+    let viable = killed + survivors.len();
+    println!(
+        "Mutants: {} killed, {} survived, {} failed to compile (mutation score {:.1}%)",
+        killed,
+        survivors.len(),
+        invalid,
+        if viable == 0 {
+            100.0
+        } else {
+            100.0 * killed as f64 / viable as f64
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mutants_of_rule() {
+        let source = "    rule count (counter < 10 && enabled); // counter + 1\n        $display(\"a == b\");\n        counter <= counter + 1;\n";
+        let mutants = find_mutants(path::Path::new("src/Counter.bsv"), source);
+        let descriptions: Vec<(usize, &str)> = mutants
+            .iter()
+            .map(|mutant| (mutant.line, mutant.description.as_str()))
+            .collect();
+
+        assert_eq!(
+            descriptions,
+            vec![
+                (1, "replaced '&&' with '||'"),
+                (1, "replaced '<' with '>='"),
+                (1, "replaced 10 with 11"),
+                (1, "inverted rule condition"),
+                (3, "replaced '+' with '-'"),
+                (3, "replaced 1 with 2"),
+            ]
+        );
+
+        assert_eq!(
+            mutants[3].apply(source).lines().next(),
+            Some("    rule count (!(counter < 10 && enabled)); // counter + 1")
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Fetching repositories: every clone dolly makes goes through git configured with the
+//! user's [net] settings and registry credentials (see config.rs).
+use super::codes;
+use super::config::{self, Net, Registry};
+use std::{
+    collections::BTreeMap,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::OnceLock,
+};
+
+/// The git configuration for fetches, as (key, value) pairs.
+static GIT_CONFIG: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Whether fetching is forbidden (see `forbid_fetching`).
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// The environment variable that, when set to anything but 0, forbids fetching (as the
+/// --offline option does).
+pub const OFFLINE_VARIABLE: &str = "DOLLY_OFFLINE";
+
+/// The environment variable holding a registry's token, which takes precedence over the
+/// credentials file.
+pub fn token_variable(registry: &str) -> String {
+    format!(
+        "DOLLY_REGISTRY_{}_TOKEN",
+        registry.to_uppercase().replace('-', "_")
+    )
+}
+
+/// The git configuration applying the settings: mirrors as `url.<mirror>.insteadOf`, the proxy
+/// as `http.proxy`, and each registry's token as an authorization header for its URL.
+fn git_config(
+    net: &Net,
+    registries: &BTreeMap<String, Registry>,
+    token: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let mut settings = Vec::<(String, String)>::new();
+    for (url, mirror) in &net.mirrors {
+        settings.push((format!("url.{}.insteadOf", mirror), url.clone()));
+    }
+    if let Some(proxy) = &net.proxy {
+        settings.push(("http.proxy".to_string(), proxy.clone()));
+    }
+    for (name, registry) in registries {
+        if let Some(token) = token(name) {
+            settings.push((
+                format!("http.{}.extraHeader", registry.url),
+                format!("Authorization: Bearer {}", token),
+            ));
+        }
+    }
+    settings
+}
+
+/// Sets the configuration of fetches, once per process.  Going offline ([net] offline or
+/// DOLLY_OFFLINE) can't be undone.
+pub fn install(
+    net: &Net,
+    registries: &BTreeMap<String, Registry>,
+    credentials: &BTreeMap<String, String>,
+) {
+    let token = |name: &str| {
+        std::env::var(token_variable(name))
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or_else(|| credentials.get(name).cloned())
+    };
+    let _ = GIT_CONFIG.set(git_config(net, registries, token));
+    let offline =
+        std::env::var(OFFLINE_VARIABLE).is_ok_and(|value| !value.is_empty() && value != "0");
+    if net.offline || offline {
+        OFFLINE.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether dolly is offline, so that anything not already on disk can't be fetched.
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// The error for fetches made offline, listing what would have been fetched.
+fn offline_error(fetches: &[String]) -> std::io::Error {
+    codes::error(
+        "D0009",
+        format!(
+            "dolly is offline (--offline, DOLLY_OFFLINE or [net] offline), but would need to \
+             fetch:\n{}\nRun once online to fetch them, or point dolly at local copies.",
+            fetches
+                .iter()
+                .map(|fetch| format!("  {}\n", fetch))
+                .collect::<String>()
+                .trim_end()
+        ),
+    )
+}
+
+/// Fails when dolly is offline and there is something to fetch (each fetch described as
+/// "<what> from <URL>").
+pub fn forbid_fetching(fetches: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if offline() && !fetches.is_empty() {
+        return Err(Box::new(offline_error(fetches)));
+    }
+    Ok(())
+}
+
+/// curl, configured for downloading a file: through the [net] proxy, and failing on HTTP
+/// errors.
+pub fn curl() -> process::Command {
+    let mut command = process::Command::new(config::tool("curl"));
+    command.arg("-fsSL");
+    let settings = GIT_CONFIG.get().map_or(&[][..], Vec::as_slice);
+    if let Some((_, proxy)) = settings.iter().find(|(key, _)| key == "http.proxy") {
+        command.arg("--proxy").arg(proxy);
+    }
+    command
+}
+
+/// git, configured for fetching.  The settings are passed in the environment (as
+/// GIT_CONFIG_COUNT, GIT_CONFIG_KEY_<n> and GIT_CONFIG_VALUE_<n>) so that tokens don't appear
+/// on command lines.  Offline, git may only reach local repositories.
+pub fn git() -> process::Command {
+    let mut command = process::Command::new(config::tool("git"));
+    if offline() {
+        command.env("GIT_ALLOW_PROTOCOL", "file");
+    }
+    let settings = GIT_CONFIG.get().map_or(&[][..], Vec::as_slice);
+    if !settings.is_empty() {
+        command.env("GIT_CONFIG_COUNT", settings.len().to_string());
+        for (index, (key, value)) in settings.iter().enumerate() {
+            command.env(format!("GIT_CONFIG_KEY_{}", index), key);
+            command.env(format!("GIT_CONFIG_VALUE_{}", index), value);
+        }
+    }
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fetch_configuration() {
+        let net = Net {
+            proxy: Some("http://proxy.example.com:3128".to_string()),
+            mirrors: BTreeMap::from([(
+                "https://github.com/".to_string(),
+                "https://git.example.com/github/".to_string(),
+            )]),
+            offline: false,
+        };
+        let registries = BTreeMap::from([
+            (
+                "corp".to_string(),
+                Registry {
+                    url: "https://bsv.example.com/".to_string(),
+                },
+            ),
+            (
+                "public".to_string(),
+                Registry {
+                    url: "https://bsv.example.org/".to_string(),
+                },
+            ),
+        ]);
+        let settings = git_config(&net, &registries, |name| {
+            (name == "corp").then(|| "secret".to_string())
+        });
+        assert_eq!(
+            settings,
+            [
+                (
+                    "url.https://git.example.com/github/.insteadOf".to_string(),
+                    "https://github.com/".to_string()
+                ),
+                (
+                    "http.proxy".to_string(),
+                    "http://proxy.example.com:3128".to_string()
+                ),
+                (
+                    "http.https://bsv.example.com/.extraHeader".to_string(),
+                    "Authorization: Bearer secret".to_string()
+                ),
+            ]
+        );
+        assert_eq!(token_variable("corp-ip"), "DOLLY_REGISTRY_CORP_IP_TOKEN");
+    }
+
+    #[test]
+    fn offline_fetches() {
+        let error = offline_error(&[
+            "[patch] Uart from https://example.com/uart.git".to_string(),
+            "riscv-tests from https://github.com/riscv/riscv-tests".to_string(),
+        ]);
+        assert_eq!(
+            error.to_string(),
+            "D0009: dolly is offline (--offline, DOLLY_OFFLINE or [net] offline), but would need \
+             to fetch:\n  [patch] Uart from https://example.com/uart.git\n  riscv-tests from \
+             https://github.com/riscv/riscv-tests\nRun once online to fetch them, or point dolly \
+             at local copies."
+        );
+    }
+}
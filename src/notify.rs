@@ -0,0 +1,145 @@
+//! Notifications when a long-running command (`dolly test`, `build`, `prove`, ...) finishes:
+//! on the desktop, and through a user command, as the [notify] configuration asks.
+use super::config::{self, Notify};
+use std::{process, sync::Mutex, sync::OnceLock, time};
+use tracing::{trace, warn};
+
+static NOTIFY: OnceLock<Notify> = OnceLock::new();
+static COMMAND: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Keeps the [notify] settings, once per process.
+pub fn install(config: &Notify) {
+    let _ = NOTIFY.set(config.clone());
+}
+
+/// Notes that the running command is one to notify about when it finishes.
+pub fn watch(command: &'static str) {
+    *COMMAND.lock().unwrap() = Some(command);
+}
+
+/// A duration as people read it: 42s, 3m 05s or 1h 02m.
+fn human(duration: time::Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// The variables the [notify] command gets.
+fn environment(
+    command: &str,
+    duration: time::Duration,
+    error: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let mut variables = vec![
+        ("DOLLY_COMMAND", command.to_string()),
+        (
+            "DOLLY_RESULT",
+            if error.is_some() { "failed" } else { "passed" }.to_string(),
+        ),
+        ("DOLLY_DURATION", duration.as_secs().to_string()),
+    ];
+    if let Some(error) = error {
+        variables.push(("DOLLY_ERROR", error.to_string()));
+    }
+    variables
+}
+
+/// notify-send, or osascript on macOS, showing a notification.
+fn desktop(title: &str, body: &str) -> process::Command {
+    if cfg!(target_os = "macos") {
+        let quoted =
+            |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut osascript = process::Command::new(config::tool("osascript"));
+        osascript.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            quoted(body),
+            quoted(title)
+        ));
+        osascript
+    } else {
+        let mut notify_send = process::Command::new(config::tool("notify-send"));
+        notify_send.arg("--app-name=dolly").arg(title).arg(body);
+        notify_send
+    }
+}
+
+/// Runs a notification command, warning rather than failing if it does.
+fn run(cmd: &mut process::Command, what: &str) {
+    trace!("Notifying: {:?}", cmd);
+    match cmd.output() {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!(
+            "The {} notification failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => warn!("Unable to run the {} notification: {}", what, e),
+    }
+}
+
+/// Sends the notifications for the watched command, if it ran for long enough.
+pub fn finish(start: time::SystemTime, error: Option<&str>) {
+    let (Some(notify), Some(command)) = (NOTIFY.get(), *COMMAND.lock().unwrap()) else {
+        return;
+    };
+    let duration = start.elapsed().unwrap_or_default();
+    if duration.as_secs() < notify.min_duration {
+        return;
+    }
+    if notify.desktop {
+        let title = format!(
+            "dolly {} {}",
+            command,
+            if error.is_some() {
+                "failed"
+            } else {
+                "finished"
+            }
+        );
+        let body = match error {
+            Some(error) => format!("after {}: {}", human(duration), error),
+            None => format!("in {}", human(duration)),
+        };
+        run(&mut desktop(&title, &body), "desktop");
+    }
+    if let Some(user_command) = &notify.command {
+        let mut sh = process::Command::new("sh");
+        sh.arg("-c")
+            .arg(user_command)
+            .envs(environment(command, duration, error));
+        run(&mut sh, "[notify] command");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn notification() {
+        assert_eq!(human(time::Duration::from_secs(42)), "42s");
+        assert_eq!(human(time::Duration::from_secs(185)), "3m 05s");
+        assert_eq!(human(time::Duration::from_secs(3725)), "1h 02m");
+
+        assert_eq!(
+            environment(
+                "test",
+                time::Duration::from_secs(3725),
+                Some("2 tests failed")
+            ),
+            vec![
+                ("DOLLY_COMMAND", "test".to_string()),
+                ("DOLLY_RESULT", "failed".to_string()),
+                ("DOLLY_DURATION", "3725".to_string()),
+                ("DOLLY_ERROR", "2 tests failed".to_string()),
+            ]
+        );
+        assert_eq!(
+            environment("build", time::Duration::ZERO, None)[1].1,
+            "passed"
+        );
+    }
+}
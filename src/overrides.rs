@@ -0,0 +1,113 @@
+//! Directory overrides: a .dolly.toml in a directory of the project adjusts the settings of
+//! the testbenches below it (and the macros and environment of the modules built from its
+//! sources), over those of dolly.toml, so that a team can keep its part of a large repository
+//! on another simulator or with its own macros.  Nearer files override farther ones:
+//!
+//! ```toml
+//! simulator = "verilator"
+//!
+//! [defines]
+//! FAST_MODEL = "1"
+//!
+//! [env]
+//! SEED = "7"
+//! ```
+use super::manifest;
+use super::project::{Simulator, TestConfig};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path};
+
+/// The name of a directory's override file.
+pub const FILE: &str = ".dolly.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Overrides {
+    pub simulator: Option<Simulator>, // Instead of [test] simulator
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>, // Macros defined for bsc (-D NAME=value)
+    #[serde(default)]
+    pub env: BTreeMap<String, String>, // Over the project's [env], under //!env directives
+}
+
+impl Overrides {
+    /// Applies nearer overrides over these.
+    fn merge(&mut self, nearer: Overrides) {
+        if nearer.simulator.is_some() {
+            self.simulator = nearer.simulator;
+        }
+        self.defines.extend(nearer.defines);
+        self.env.extend(nearer.env);
+    }
+
+    /// The overrides of a file of the project: those of the .dolly.toml files in the
+    /// directories from the project root down to the file's.
+    pub fn for_file(
+        root: &path::Path,
+        file: &path::Path,
+    ) -> Result<Overrides, Box<dyn std::error::Error>> {
+        let mut overrides = Overrides::default();
+        let Some(dir) = file.parent() else {
+            return Ok(overrides);
+        };
+        let mut dirs: Vec<&path::Path> = dir
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(root))
+            .collect();
+        dirs.reverse();
+        for dir in dirs {
+            let file = dir.join(FILE);
+            if file.is_file() {
+                overrides.merge(manifest::parse(&file, &fs::read_to_string(&file)?)?);
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// The project's test settings, with these applied.
+    pub fn test_config(&self, test_config: &TestConfig) -> TestConfig {
+        TestConfig {
+            simulator: self.simulator.unwrap_or(test_config.simulator),
+            ..test_config.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_overrides() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        let team = root.join("tests").join("cache");
+        fs::create_dir_all(&team)?;
+        fs::write(
+            root.join("tests").join(FILE),
+            "simulator = \"verilator\"\n[defines]\nWAYS = \"2\"\nFAST = \"1\"\n",
+        )?;
+        fs::write(
+            team.join(FILE),
+            "[defines]\nWAYS = \"4\"\n[env]\nSEED = \"7\"\n",
+        )?;
+
+        let overrides = Overrides::for_file(root, &team.join("Evict_tb.bsv"))?;
+        let outside = Overrides::for_file(root, &root.join("src").join("Cache.bsv"))?;
+        assert_eq!(overrides.simulator, Some(Simulator::Verilator));
+        assert_eq!(
+            overrides.defines,
+            BTreeMap::from([
+                ("FAST".to_string(), "1".to_string()),
+                ("WAYS".to_string(), "4".to_string())
+            ])
+        );
+        assert_eq!(overrides.env["SEED"], "7");
+        assert_eq!(outside, Overrides::default());
+        assert_eq!(
+            overrides.test_config(&TestConfig::default()).simulator,
+            Simulator::Verilator
+        );
+        Ok(())
+    }
+}
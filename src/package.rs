@@ -0,0 +1,216 @@
+//! `dolly package`: an archive of the generated Verilog of the top modules, for handing RTL
+//! drops to teams that don't build with dolly.
+//!
+//! The archive `target/package/<name>-<version>.tar.gz` contains the directory
+//! `<name>-<version>/` with
+//!
+//! * `rtl/`: the generated Verilog (or SystemVerilog) and the Bluespec primitives it uses,
+//! * `<name>.f`: a filelist of the sources in `rtl/`,
+//! * `package.toml`: the [package] metadata, the top modules and the HDL,
+//! * `artifacts/`: the files the script's stages added (see `script`), if any,
+//! * the project's license files, and
+//! * `SHA256SUMS`: checksums of all of the above, in the format of `sha256sum`.
+use super::builder::{Builder, Emit};
+use super::checksum;
+use super::codes;
+use super::config;
+use super::project::{Package, Project};
+use super::script;
+use super::verilog;
+use std::{fs, path, process};
+
+/// License files copied from the project root.
+const LICENSE_FILES: [&str; 5] = ["LICENSE", "LICENSE.txt", "LICENSE.md", "COPYING", "NOTICE"];
+
+/// The project's license files.
+pub fn license_files(project: &Project) -> Vec<path::PathBuf> {
+    LICENSE_FILES
+        .iter()
+        .map(|license| project.root_path().join(license))
+        .filter(|file| file.is_file())
+        .collect()
+}
+
+/// The package.toml describing an archive's contents.
+pub fn metadata(package: &Package, top_modules: &[String], emit: Emit) -> String {
+    let strings = |values: &[String]| {
+        toml::Value::Array(values.iter().cloned().map(toml::Value::String).collect())
+    };
+    let mut fields: Vec<(&str, toml::Value)> = vec![
+        ("name", package.name.clone().into()),
+        ("version", package.version.clone().into()),
+    ];
+    if !package.authors.is_empty() {
+        fields.push(("authors", strings(&package.authors)));
+    }
+    for (key, value) in [
+        ("description", &package.description),
+        ("license", &package.license),
+        ("repository", &package.repository),
+    ] {
+        if let Some(value) = value {
+            fields.push((key, value.clone().into()));
+        }
+    }
+    if !package.keywords.is_empty() {
+        fields.push(("keywords", strings(&package.keywords)));
+    }
+    let hdl = match emit {
+        Emit::Sv => "systemverilog",
+        _ => "verilog",
+    };
+    let contents: [(&str, toml::Value); 3] = [
+        ("top-modules", strings(top_modules)),
+        ("hdl", hdl.into()),
+        ("dolly", env!("CARGO_PKG_VERSION").into()),
+    ];
+
+    let mut metadata = "[package]\n".to_string();
+    for (key, value) in fields {
+        metadata.push_str(&format!("{} = {}\n", key, value));
+    }
+    metadata.push_str("\n[contents]\n");
+    for (key, value) in contents {
+        metadata.push_str(&format!("{} = {}\n", key, value));
+    }
+    metadata
+}
+
+/// The generated sources of the top modules built, in the order of the tops, including the
+/// primitives they use unless they were concatenated into a single file.
+pub fn generated_sources(
+    project: &Project,
+    builder: &Builder,
+) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    let extension = match builder.options().emit {
+        Emit::Sv => "sv",
+        _ => "v",
+    };
+    let target_dir = project.target_dir();
+    if project.verilog.single_file {
+        return Ok(vec![
+            target_dir.join(format!("{}.{}", project.package.name, extension))
+        ]);
+    }
+
+    // The same submodule may be generated for more than one top.
+    let mut files = Vec::<path::PathBuf>::new();
+    for top_module in builder.top_modules() {
+        let mut generated: Vec<path::PathBuf> = target_dir
+            .join(top_module)
+            .read_dir()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+            .filter(|path| {
+                !files
+                    .iter()
+                    .any(|file| file.file_name() == path.file_name())
+            })
+            .collect();
+        generated.sort();
+        files.append(&mut generated);
+    }
+    files.extend(verilog::primitives(&files)?);
+    Ok(files)
+}
+
+/// Implements `dolly package` for the top modules `builder` built: lays out the archive's
+/// directory under target/package and archives it with tar.
+pub fn bundle(
+    project: &Project,
+    builder: &Builder,
+) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    let package = &project.package;
+    let stem = format!("{}-{}", package.name, package.version);
+    let package_dir = project.output_dir("package");
+    let root = package_dir.join(&stem);
+    if root.exists() {
+        fs::remove_dir_all(&root)?;
+    }
+    let rtl = root.join("rtl");
+    fs::create_dir_all(&rtl)?;
+    if !script::artifacts().is_empty() {
+        fs::create_dir_all(root.join("artifacts"))?;
+    }
+
+    // Paths relative to the root of the archive's directory, with their contents.
+    let mut files = Vec::<(String, Vec<u8>)>::new();
+    let mut filelist = String::new();
+    for source in generated_sources(project, builder)? {
+        let name = format!("rtl/{}", source.file_name().unwrap().to_string_lossy());
+        if files.iter().any(|(file, _)| *file == name) {
+            continue;
+        }
+        filelist.push_str(&format!("{}\n", name));
+        files.push((name, fs::read(&source)?));
+    }
+    files.push((format!("{}.f", package.name), filelist.into_bytes()));
+    files.push((
+        "package.toml".to_string(),
+        metadata(package, builder.top_modules(), builder.options().emit).into_bytes(),
+    ));
+    for file in license_files(project) {
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        files.push((name, fs::read(file)?));
+    }
+    for file in script::artifacts() {
+        let name = format!("artifacts/{}", file.file_name().unwrap().to_string_lossy());
+        files.push((name, fs::read(file)?));
+    }
+
+    files.sort();
+    let mut sums = String::new();
+    for (name, contents) in &files {
+        fs::write(root.join(name), contents)?;
+        sums.push_str(&format!("{}  {}\n", checksum::sha256(contents), name));
+    }
+    fs::write(root.join("SHA256SUMS"), sums)?;
+
+    let archive = package_dir.join(format!("{}.tar.gz", stem));
+    let status = process::Command::new(config::tool("tar"))
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&package_dir)
+        .arg(&stem)
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                codes::error("D0102", "Unable to locate 'tar' program.")
+            }
+            _ => e,
+        })?;
+    if !status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "tar failed to write {}",
+            archive.to_string_lossy()
+        ))));
+    }
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn package_metadata() {
+        let package: Package = toml::from_str(
+            "name = \"Core\"\nversion = \"1.2.0\"\nlicense = \"Apache-2.0\"\n\
+             authors = [\"Ada <ada@example.com>\"]\n",
+        )
+        .unwrap();
+        let metadata = metadata(&package, &["mkCore".to_string()], Emit::Sv);
+        assert!(metadata.starts_with("[package]\nname = \"Core\"\n"));
+        let table: toml::Table = toml::from_str(&metadata).unwrap();
+        assert_eq!(table["package"]["name"].as_str(), Some("Core"));
+        assert_eq!(table["package"]["license"].as_str(), Some("Apache-2.0"));
+        assert_eq!(
+            table["package"]["authors"][0].as_str(),
+            Some("Ada <ada@example.com>")
+        );
+        assert!(table["package"].get("description").is_none());
+        assert_eq!(table["contents"]["top-modules"][0].as_str(), Some("mkCore"));
+        assert_eq!(table["contents"]["hdl"].as_str(), Some("systemverilog"));
+    }
+}
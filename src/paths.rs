@@ -0,0 +1,181 @@
+//! The long-paths lint: combinational paths from the inputs of a module's methods (their
+//! arguments and enables) to the outputs of its methods (their results and ready signals) in
+//! the Verilog of the last build.  Such a path is chained with the logic of the modules calling
+//! the methods, so a long one is a likely critical path.  Its depth is counted in the wires bsc
+//! assigns along it (CAN_FIRE_, WILL_FIRE_, MUX_... and the output itself), not in logic
+//! levels: one `assign` may hold a whole adder, and a chain of renamings adds depth without
+//! adding logic, so the depth is a heuristic for where to look rather than a timing estimate.
+//! Paths deeper than `[verilog] max-path-depth` are reported.
+use super::diagnostics::{Diagnostic, Severity};
+use super::project::Project;
+use super::verilog;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+/// A combinational path between method ports.
+#[derive(Debug, PartialEq)]
+struct Path {
+    input: String,
+    output: String,
+    depth: usize,
+    offset: usize, // Of the assignment of the output, in the module's Verilog
+}
+
+/// The inputs a signal depends on combinationally, each with the depth of its longest path.
+fn cone<'a>(
+    token_re: &Regex,
+    signal: &'a str,
+    assigns: &BTreeMap<&'a str, (&'a str, usize)>,
+    inputs: &BTreeSet<&'a str>,
+    cones: &mut BTreeMap<&'a str, BTreeMap<&'a str, usize>>,
+) -> BTreeMap<&'a str, usize> {
+    if let Some(cone) = cones.get(signal) {
+        return cone.clone();
+    }
+    // Marks it as visited (with no inputs), which ends any combinational loop.
+    cones.insert(signal, BTreeMap::new());
+    let mut found = BTreeMap::<&str, usize>::new();
+    if let Some((expression, _)) = assigns.get(signal) {
+        for token in token_re.find_iter(expression).map(|token| token.as_str()) {
+            let reached = if inputs.contains(token) {
+                BTreeMap::from([(token, 0)])
+            } else {
+                cone(token_re, token, assigns, inputs, cones)
+            };
+            for (input, depth) in reached {
+                let depth = found.get(input).copied().unwrap_or(0).max(depth + 1);
+                found.insert(input, depth);
+            }
+        }
+    }
+    cones.insert(signal, found.clone());
+    found
+}
+
+/// The paths from the method inputs to the method outputs of a bsc Verilog module.  Clocks
+/// and resets aren't method inputs.
+fn paths(source: &str) -> Vec<Path> {
+    let port_re =
+        Regex::new(r"(?m)^\s*(input|output)\s+(?:wire\s+)?(?:\[[^\]]*\]\s*)?(\w+)\s*;").unwrap();
+    let assign_re = Regex::new(r"(?m)^\s*assign\s+([\w$]+)\s*=([^;]*);").unwrap();
+    // Sized literals (8'd255) are matched so that their digits aren't taken as names.
+    let token_re = Regex::new(r"\d*'[sS]?[bodhBODH]\w+|[A-Za-z_][\w$]*").unwrap();
+
+    let mut inputs = BTreeSet::<&str>::new();
+    let mut outputs = Vec::<&str>::new();
+    for capture in port_re.captures_iter(source) {
+        let port = capture.get(2).unwrap().as_str();
+        if &capture[1] == "output" {
+            outputs.push(port);
+        } else if !port.starts_with("CLK") && !port.starts_with("RST") {
+            inputs.insert(port);
+        }
+    }
+    let assigns: BTreeMap<&str, (&str, usize)> = assign_re
+        .captures_iter(source)
+        .map(|capture| {
+            (
+                capture.get(1).unwrap().as_str(),
+                (
+                    capture.get(2).unwrap().as_str(),
+                    capture.get(0).unwrap().start(),
+                ),
+            )
+        })
+        .collect();
+
+    let mut cones = BTreeMap::new();
+    let mut paths = Vec::new();
+    for output in outputs {
+        let Some((_, offset)) = assigns.get(output) else {
+            continue;
+        };
+        for (input, depth) in cone(&token_re, output, &assigns, &inputs, &mut cones) {
+            paths.push(Path {
+                input: input.to_string(),
+                output: output.to_string(),
+                depth,
+                offset: *offset,
+            });
+        }
+    }
+    paths
+}
+
+/// The paths deeper than `[verilog] max-path-depth` in the Verilog of the last build, each
+/// module checked once.
+pub fn lints(project: &Project) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    let max_depth = project.verilog.max_path_depth;
+    let mut checked = BTreeSet::<String>::new();
+    let mut diagnostics = Vec::new();
+    for file in verilog::generated_files(&project.target_dir()) {
+        let source = fs::read_to_string(&file)?;
+        let Some(module) = verilog::defined_modules(&source).into_iter().next() else {
+            continue;
+        };
+        if !checked.insert(module.clone()) {
+            continue;
+        }
+        for path in paths(&source)
+            .into_iter()
+            .filter(|path| path.depth > max_depth)
+        {
+            let line = source[..path.offset].matches('\n').count() + 1;
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "long-paths",
+                    format!(
+                        "{}: a combinational path through {} assigned wires (over {}) from the \
+                         input {} to the output {}",
+                        module, path.depth, max_depth, path.input, path.output
+                    ),
+                )
+                .at(&file, Some(line)),
+            );
+        }
+    }
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn method_paths() {
+        let bypass = "module mkBypass(CLK, RST_N, enq_1, EN_enq, RDY_enq, first, RDY_first);
+  input  CLK;
+  input  RST_N;
+  input  [7 : 0] enq_1;
+  input  EN_enq;
+  output RDY_enq;
+  output [7 : 0] first;
+  output RDY_first;
+  reg [7 : 0] data;
+  reg full;
+  wire WILL_FIRE_enq;
+  wire [7 : 0] MUX_first;
+  assign RDY_enq = !full ;
+  assign WILL_FIRE_enq = EN_enq && !full ;
+  assign MUX_first = WILL_FIRE_enq ? enq_1 : data ;
+  assign first = MUX_first ;
+  assign RDY_first = full || EN_enq ;
+endmodule
+";
+        let found = paths(bypass);
+        let found: Vec<(&str, &str, usize)> = found
+            .iter()
+            .map(|path| (path.input.as_str(), path.output.as_str(), path.depth))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("EN_enq", "first", 3),
+                ("enq_1", "first", 2),
+                ("EN_enq", "RDY_first", 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! External subcommands: as with cargo, `dolly <name>` runs `dolly-<name>` from the PATH when
+//! dolly has no command of that name, so that a team can add its own flows (`dolly tapeout`)
+//! without changing dolly.  The plugin gets the project's metadata in its environment, and
+//! as JSON in the file DOLLY_METADATA names (stdin stays the terminal's).
+use super::codes;
+use super::history::quote;
+use super::project::Project;
+use super::suggest;
+use std::{ffi::OsString, fs, path, process};
+
+const PREFIX: &str = "dolly-";
+
+/// Whether a file is a program that can be run.
+fn executable(file: &path::Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    file.is_file()
+}
+
+/// The plugins in the directories of a PATH: their names, and the first program of each.
+fn plugins(search_path: &std::ffi::OsStr) -> Vec<(String, path::PathBuf)> {
+    let mut plugins: Vec<(String, path::PathBuf)> = Vec::new();
+    for dir in std::env::split_paths(search_path) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, path::PathBuf)> = entries
+            .filter_map(|entry| {
+                let file = entry.ok()?.path();
+                let name = file.file_name()?.to_str()?.strip_prefix(PREFIX)?;
+                let name = name.strip_suffix(std::env::consts::EXE_SUFFIX)?.to_string();
+                (!name.is_empty()
+                    && executable(&file)
+                    && !plugins.iter().any(|(plugin, _)| *plugin == name))
+                .then_some((name, file))
+            })
+            .collect();
+        found.sort();
+        plugins.extend(found);
+    }
+    plugins
+}
+
+/// The project's metadata, as the JSON object a plugin reads.
+pub fn metadata_json(project: &Project) -> String {
+    let dependencies: Vec<String> = project
+        .dependencies
+        .keys()
+        .map(|name| quote(name))
+        .collect();
+    format!(
+        "{{\"name\":{},\"version\":{},\"root\":{},\"target_dir\":{},\"src_dirs\":[{}],\
+         \"dependencies\":[{}],\"dolly_version\":{}}}\n",
+        quote(&project.package.name),
+        quote(&project.package.version),
+        quote(&project.root_path().to_string_lossy()),
+        quote(&project.target_dir().to_string_lossy()),
+        project
+            .package
+            .src_dirs
+            .iter()
+            .map(|dir| quote(&dir.to_string_lossy()))
+            .collect::<Vec<String>>()
+            .join(","),
+        dependencies.join(","),
+        quote(env!("CARGO_PKG_VERSION"))
+    )
+}
+
+/// Writes the project's metadata for plugins and hooks, returning the file's path.
+pub fn write_metadata(project: &Project) -> std::io::Result<path::PathBuf> {
+    let target_dir = project.target_dir();
+    fs::create_dir_all(&target_dir)?;
+    let metadata = target_dir.join("dolly-metadata.json");
+    fs::write(&metadata, metadata_json(project))?;
+    Ok(metadata)
+}
+
+/// Runs the plugin for an unknown command (`args` being its name and arguments), exiting
+/// with its exit status if it fails.  `commands` are dolly's own, for suggestions.
+pub fn run(
+    project: Option<&Project>,
+    args: &[OsString],
+    commands: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = args
+        .first()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let plugins = plugins(&std::env::var_os("PATH").unwrap_or_default());
+    let Some((_, program)) = plugins.iter().find(|(plugin, _)| *plugin == name) else {
+        let candidates = commands
+            .iter()
+            .copied()
+            .chain(plugins.iter().map(|(plugin, _)| plugin.as_str()));
+        return Err(Box::new(std::io::Error::other(format!(
+            "No such command: {} (nor {}{} on the PATH){}",
+            name,
+            PREFIX,
+            name,
+            suggest::did_you_mean(&suggest::similar(&name, candidates))
+        ))));
+    };
+
+    let mut cmd = process::Command::new(program);
+    cmd.args(&args[1..]);
+    if let Ok(dolly) = std::env::current_exe() {
+        cmd.env("DOLLY", dolly);
+    }
+    if let Some(project) = project {
+        let target_dir = project.target_dir();
+        cmd.envs(project.env()?)
+            .env("DOLLY_METADATA", write_metadata(project)?)
+            .env("DOLLY_PACKAGE", &project.package.name)
+            .env("DOLLY_PACKAGE_VERSION", &project.package.version)
+            .env("DOLLY_PROJECT_ROOT", project.root_path())
+            .env("DOLLY_TARGET_DIR", &target_dir);
+    }
+    let status = cmd.status().map_err(|e| {
+        std::io::Error::other(format!(
+            "Unable to run {}: {}",
+            program.to_string_lossy(),
+            e
+        ))
+    })?;
+    if !status.success() {
+        // dolly exits with the plugin's status, once it has finished as for its own commands.
+        return Err(Box::new(codes::ExitStatus {
+            status: status
+                .code()
+                .and_then(|code| u8::try_from(code).ok())
+                .unwrap_or(1),
+            message: format!("{} failed ({})", program.to_string_lossy(), status),
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plugins_on_path() -> std::io::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let (first, second) = (dir.join("bin"), dir.join("local"));
+        fs::create_dir_all(&first)?;
+        fs::create_dir_all(&second)?;
+        for (dir, file) in [
+            (&first, "dolly-tapeout"),
+            (&second, "dolly-tapeout"),
+            (&second, "dolly-lec"),
+            (&second, "dolly-"),
+        ] {
+            fs::write(dir.join(file), "#!/bin/sh\n")?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(dir.join(file), fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        fs::write(second.join("dolly-notes"), "not a program")?;
+
+        let search_path = std::env::join_paths([&first, &second]).unwrap();
+        let found = plugins(&search_path);
+        assert_eq!(
+            found,
+            vec![
+                ("tapeout".to_string(), first.join("dolly-tapeout")),
+                ("lec".to_string(), second.join("dolly-lec")),
+            ]
+        );
+
+        // Missing directories on the PATH, directories and dangling links aren't plugins.
+        fs::create_dir_all(second.join("dolly-dir"))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("gone"), second.join("dolly-gone"))?;
+        let search_path = std::env::join_paths([&dir.join("missing"), &second]).unwrap();
+        let found: Vec<String> = plugins(&search_path)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(found, ["lec", "tapeout"]);
+        assert!(plugins(std::ffi::OsStr::new("")).is_empty());
+
+        // An unknown command is an error suggesting a similar one.
+        let error = run(None, &["buld".into()], &["build", "test"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No such command: buld (nor dolly-buld on the PATH) (did you mean build?)"
+        );
+        Ok(())
+    }
+}
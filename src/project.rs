@@ -1,20 +1,740 @@
+use super::bsv;
+use super::changes;
+use super::checksum;
+use super::codes;
+use super::config::Config;
+use super::harness::HarnessConfig;
+use super::linters::Linter;
+use super::manifest;
+use super::net;
+use super::templates;
+use super::verilog;
+use super::version::{Version, VersionReq};
+use super::workspace::WorkspaceConfig;
 use convert_case::{Case, Casing};
-use log::{error, trace};
+use regex::Regex;
 use serde::Deserialize;
-use std::{fs, io::Write, path};
+use std::{fs, path};
+use tracing::{error, trace, warn};
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Project {
     pub package: Package,
 
+    #[serde(default)]
+    pub build: BuildConfig,
+
+    #[serde(default)]
+    pub test: TestConfig,
+
+    #[serde(default)]
+    pub verilog: VerilogConfig,
+
+    #[serde(default)]
+    pub formal: FormalConfig,
+
+    #[serde(default, rename = "isa-test")]
+    pub isa_test: IsaTestConfig,
+
+    #[serde(default)]
+    pub riscof: RiscofConfig,
+
+    #[serde(default)]
+    pub cosim: CosimConfig,
+
+    #[serde(default)]
+    pub toolchain: Toolchains,
+
+    #[serde(rename = "memory-map")]
+    pub memory_map: Option<MemoryMapConfig>,
+
+    pub soc: Option<SocConfig>,
+
+    #[serde(default)]
+    pub dependencies: std::collections::BTreeMap<String, Dependency>,
+
+    #[serde(default)]
+    pub patch: std::collections::BTreeMap<String, Patch>, // Dependency name -> replacement
+
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>, // Set for bsc and the simulations
+
+    #[serde(default)]
+    pub hooks: HooksConfig,
+
+    #[serde(default)]
+    pub generators: std::collections::BTreeMap<String, String>, // Name -> command writing BSV
+
+    #[serde(default)]
+    pub lints: std::collections::BTreeMap<String, LintLevel>, // Lint name -> level (see lint.rs)
+
+    #[serde(default)]
+    pub clocks: std::collections::BTreeMap<String, Vec<String>>, // Clock domain -> its clock ports
+
+    pub workspace: Option<WorkspaceConfig>, // The members, when this package is a workspace root
+
     #[serde(skip)]
     root_path: path::PathBuf,
+
+    #[serde(skip)]
+    target_dir: Option<path::PathBuf>, // Overrides <root>/target (see `target_dir`)
+
+    #[serde(skip)]
+    pub config: Config, // User- and project-level settings (see config.rs)
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Package {
     pub name: String,
     pub version: String,
+    #[serde(default = "Package::default_src_dirs")]
+    pub src_dirs: Vec<path::PathBuf>, // Source roots, relative to the project root
+    pub top_file: Option<path::PathBuf>, // Root of the module tree (default src/<Name>.bsv)
+    #[serde(default)]
+    pub authors: Vec<String>, // "Name" or "Name <email>"
+    pub license: Option<String>,         // An SPDX license expression, e.g. "Apache-2.0 OR MIT"
+    pub description: Option<String>,
+    #[allow(dead_code)]
+    pub edition: Option<String>,
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+impl Package {
+    fn default_src_dirs() -> Vec<path::PathBuf> {
+        vec![path::PathBuf::from("src")]
+    }
+
+    /// Checks the metadata fields, which packaging and the Verilog headers copy as they are.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let invalid = |field: &str, value: &str, expected: &str| {
+            Err(Box::new(codes::error(
+                "D0006",
+                format!(
+                    "Invalid [package] {} {:?} in {}: expected {}",
+                    field, value, self.name, expected
+                ),
+            )) as Box<dyn std::error::Error>)
+        };
+        let author_re = Regex::new(r"^[^<>]*[^<>\s](?:\s+<[^<>\s]+@[^<>\s]+>)?$").unwrap();
+        for author in &self.authors {
+            if !author_re.is_match(author) {
+                return invalid("author", author, "\"Name\" or \"Name <email>\"");
+            }
+        }
+        if let Some(license) = &self.license {
+            let id_re = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9.+-]*$").unwrap();
+            let terms: Vec<&str> = license
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .filter(|term| !term.is_empty())
+                .collect();
+            let valid = !terms.is_empty()
+                && terms.iter().enumerate().all(|(index, term)| {
+                    let operator = matches!(*term, "AND" | "OR" | "WITH");
+                    operator == (index % 2 == 1) && id_re.is_match(term)
+                })
+                && terms.len() % 2 == 1;
+            if !valid {
+                return invalid("license", license, "an SPDX expression such as \"MIT\"");
+            }
+        }
+        if let Some(description) = &self.description {
+            if description.trim().is_empty() || description.contains('\n') {
+                return invalid("description", description, "a single line");
+            }
+        }
+        if let Some(repository) = &self.repository {
+            let url_re = Regex::new(r"^(?:[a-z][a-z0-9+.-]*://\S+|[\w.-]+@[\w.-]+:\S+)$").unwrap();
+            if !url_re.is_match(repository) {
+                return invalid("repository", repository, "a URL");
+            }
+        }
+        let keyword_re = Regex::new(r"^[a-z0-9][a-z0-9_-]{0,19}$").unwrap();
+        if self.keywords.len() > 5 {
+            return invalid("keywords", &self.keywords.join(", "), "at most 5 keywords");
+        }
+        for keyword in &self.keywords {
+            if !keyword_re.is_match(keyword) {
+                return invalid(
+                    "keyword",
+                    keyword,
+                    "up to 20 lowercase letters, digits, '-' or '_'",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The comment at the top of the generated Verilog, when there is metadata to put there.
+    pub fn verilog_header(&self) -> Option<String> {
+        if self.description.is_none()
+            && self.authors.is_empty()
+            && self.license.is_none()
+            && self.repository.is_none()
+        {
+            return None;
+        }
+        let mut header = format!("// {} {}\n", self.name, self.version);
+        if let Some(description) = &self.description {
+            header.push_str(&format!("// {}\n", description));
+        }
+        if !self.authors.is_empty() {
+            header.push_str(&format!("// Authors: {}\n", self.authors.join(", ")));
+        }
+        if let Some(repository) = &self.repository {
+            header.push_str(&format!("// Repository: {}\n", repository));
+        }
+        if let Some(license) = &self.license {
+            header.push_str(&format!("// SPDX-License-Identifier: {}\n", license));
+        }
+        Some(header)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildConfig {
+    pub library_paths: Vec<String>, // Appended to the bsc search path; may use $VAR or ${VAR}
+    pub deny_warnings: bool,        // Fail the build or test run if bsc reports any warnings
+    pub suppress_warnings: Vec<String>, // bsc warning tags to suppress (e.g. G0010)
+    pub promote_warnings: Vec<String>, // bsc warning tags to treat as errors
+    pub gc_max_age_days: u64,       // `dolly clean --gc` removes tool outputs older than this
+    pub build_info: bool,           // Generate the BuildInfo package before each build
+    pub build_time: bool,           // Include the build time in BuildInfo
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            library_paths: Vec::new(),
+            deny_warnings: false,
+            suppress_warnings: Vec::new(),
+            promote_warnings: Vec::new(),
+            gc_max_age_days: 30,
+            build_info: false,
+            build_time: false,
+        }
+    }
+}
+
+/// Shell commands run (in the project root) at points in the build and test pipelines.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HooksConfig {
+    pub pre_build: Option<String>,
+    pub post_build: Option<String>,
+    pub pre_test: Option<String>,
+    pub post_test: Option<String>,
+    pub script: Option<path::PathBuf>, // A Rhai script registering stages at these points
+}
+
+/// Options for the Verilog generated by `dolly build`.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VerilogConfig {
+    pub bsc_flags: Vec<String>, // Extra bsc flags for every Verilog build
+    pub remove_dollar: bool,    // With `--emit sv`: replace '$' in generated names
+    pub unspecified_to: String, // With `--emit sv`: value of don't-care bits (X, 0, 1, Z or A)
+    pub module_prefix: Option<String>, // Prefix for the names of generated modules
+    pub single_file: bool,      // Concatenate the tops and their primitives into target/<name>.v
+    pub lint_flags: Vec<String>, // Extra verilator flags for `--lint-verilog` (e.g. -Wno-UNUSED)
+    pub linters: Vec<Linter>,   // Run by `--lint-verilog`
+    pub lint_waivers: Option<path::PathBuf>, // The findings accepted (see linters.rs)
+    pub slang_flags: Vec<String>, // Extra slang flags for `--lint-verilog`
+    pub verible_flags: Vec<String>, // Extra verible-verilog-lint flags (e.g. --rules=-line-length)
+    pub max_path_depth: usize, // Most assigned wires on a method-to-method path before the long-paths lint warns
+}
+
+impl Default for VerilogConfig {
+    fn default() -> Self {
+        Self {
+            bsc_flags: Vec::new(),
+            remove_dollar: true,
+            unspecified_to: "0".to_string(),
+            module_prefix: None,
+            single_file: false,
+            lint_flags: Vec::new(),
+            linters: vec![Linter::Verilator],
+            lint_waivers: None,
+            slang_flags: Vec::new(),
+            verible_flags: Vec::new(),
+            max_path_depth: 4,
+        }
+    }
+}
+
+/// Expands `$VAR` and `${VAR}` environment variable references.
+pub fn expand_env(text: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let variable_re = Regex::new(r"\$(?:\{(\w+)\}|(\w+))").unwrap();
+    let mut expanded = String::new();
+    let mut last = 0;
+    for capture in variable_re.captures_iter(text) {
+        let whole = capture.get(0).unwrap();
+        let name = capture.get(1).or(capture.get(2)).unwrap().as_str();
+        let value = std::env::var(name).map_err(|_| {
+            codes::error(
+                "D0002",
+                format!(
+                    "Environment variable {} used in {:?} is not set",
+                    name, text
+                ),
+            )
+        })?;
+        expanded.push_str(&text[last..whole.start()]);
+        expanded.push_str(&value);
+        last = whole.end();
+    }
+    expanded.push_str(&text[last..]);
+    Ok(expanded)
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TestConfig {
+    pub simulator: Simulator,
+    pub wave_format: WaveFormat,
+    pub coverage_threshold: Option<f64>, // Minimum total coverage percentage for `--coverage`
+    pub default_top: Option<String>,     // Top module of testbenches without //!topmodule
+    pub unit_test_pattern: Option<String>, // File names of unit testbenches (default *_tb)
+    pub unit_test_exclude: Vec<path::PathBuf>, // Directories without unit testbenches
+    pub support_dirs: Option<Vec<path::PathBuf>>, // Packages shared by testbenches (tests/common)
+    pub harness: HarnessConfig,          // The clock and reset of Verilator testbenches
+    pub shard_durations: Option<path::PathBuf>, // Committed test durations balancing --shard
+}
+
+/// The simulator used to build and run testbenches.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Simulator {
+    #[default]
+    Bluesim,
+    Verilator,
+}
+
+/// How `dolly lint` and `dolly check` treat a lint's findings.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    Allow, // Not reported
+    Warn,
+    Deny, // Reported as an error, failing the command
+}
+
+/// The file format of dumped waveforms.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WaveFormat {
+    #[default]
+    Vcd,
+    Fst,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FormalConfig {
+    pub mode: String, // SymbiYosys mode: bmc, prove, cover or live
+    pub depth: u32,
+    pub engines: Vec<String>,
+    pub rvfi: Option<RvfiConfig>,
+}
+
+impl Default for FormalConfig {
+    fn default() -> Self {
+        Self {
+            mode: "bmc".to_string(),
+            depth: 20,
+            engines: vec!["smtbmc".to_string()],
+            rvfi: None,
+        }
+    }
+}
+
+/// Runs the riscv-formal checks against a core exposing an RVFI port.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RvfiConfig {
+    pub riscv_formal: path::PathBuf, // riscv-formal checkout, relative to the project root
+    pub core: String,                // Module exposing the RVFI port
+    pub isa: String,
+    pub nret: u32,
+    pub port_prefix: String, // Prefix of the core's RVFI output ports
+    pub depth: std::collections::BTreeMap<String, String>, // Per-check depth overrides
+}
+
+impl Default for RvfiConfig {
+    fn default() -> Self {
+        Self {
+            riscv_formal: path::PathBuf::from("riscv-formal"),
+            core: String::new(),
+            isa: "rv32i".to_string(),
+            nret: 1,
+            port_prefix: "rvfi_".to_string(),
+            depth: std::collections::BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Toolchains {
+    pub riscv: RiscvToolchain,
+}
+
+/// The RISC-V GCC toolchain used to build firmware.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RiscvToolchain {
+    pub prefix: String, // e.g. riscv64-unknown-elf- for riscv64-unknown-elf-gcc
+    pub march: String,
+    pub mabi: String,
+    pub cflags: Vec<String>,
+    pub ldflags: Vec<String>,
+    pub linker_script: Option<path::PathBuf>,
+    pub image: String, // File name the testbench loads with $readmemh
+    pub base_address: u64,
+    pub word_width: u32, // Bits per image word
+    pub banks: usize,    // Images interleaving the memory's words
+}
+
+impl Default for RiscvToolchain {
+    fn default() -> Self {
+        Self {
+            prefix: "riscv64-unknown-elf-".to_string(),
+            march: "rv32i".to_string(),
+            mabi: "ilp32".to_string(),
+            cflags: Vec::new(),
+            ldflags: Vec::new(),
+            linker_script: None,
+            image: "program.hex".to_string(),
+            base_address: 0x8000_0000,
+            word_width: 32,
+            banks: 1,
+        }
+    }
+}
+
+/// Runs the riscv-tests ISA suites against a testbench wrapping the core.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct IsaTestConfig {
+    pub top: Option<String>, // Testbench module that loads `image` and runs the core
+    pub riscv_tests: path::PathBuf, // riscv-tests checkout, cloned if missing
+    pub suites: Vec<String>,
+    pub image: String, // File name the testbench loads with $readmemh
+    pub base_address: u64,
+    pub xlen: u32,
+}
+
+impl Default for IsaTestConfig {
+    fn default() -> Self {
+        Self {
+            top: None,
+            riscv_tests: path::PathBuf::from("riscv-tests"),
+            suites: vec!["rv32ui-p".to_string()],
+            image: "program.hex".to_string(),
+            base_address: 0x8000_0000,
+            xlen: 32,
+        }
+    }
+}
+
+/// Runs the RISC-V architectural tests through RISCOF.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RiscofConfig {
+    pub top: Option<String>, // Testbench module that loads `image` and runs the core
+    pub isa: String,         // ISA string for the ISA YAML, e.g. RV32IMC_Zicsr
+    pub user_spec: String,
+    pub privilege_spec: Option<String>,
+    pub arch_test: path::PathBuf,        // riscv-arch-test checkout
+    pub reference_plugin: path::PathBuf, // e.g. a riscof-plugins spike or sail_cSim directory
+    pub image: String,
+    pub base_address: u64,
+    pub signature_address: u64, // Address the halt code streams signature words to
+}
+
+impl Default for RiscofConfig {
+    fn default() -> Self {
+        Self {
+            top: None,
+            isa: "RV32I".to_string(),
+            user_spec: "2.3".to_string(),
+            privilege_spec: None,
+            arch_test: path::PathBuf::from("riscv-arch-test"),
+            reference_plugin: path::PathBuf::from("riscof/spike"),
+            image: "program.hex".to_string(),
+            base_address: 0x8000_0000,
+            signature_address: 0x1000_0000,
+        }
+    }
+}
+
+/// Compares the core's commit log against Spike in lock step.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CosimConfig {
+    pub top: Option<String>, // Testbench module that loads `image` and logs commits
+    pub isa: String,
+    pub image: String,
+    pub base_address: u64,
+    pub commit_fifo: Option<String>, // Read the commit log from this FIFO instead of stdout
+    pub spike: String,
+    pub spike_args: Vec<String>,
+}
+
+impl Default for CosimConfig {
+    fn default() -> Self {
+        Self {
+            top: None,
+            isa: "rv32i".to_string(),
+            image: "program.hex".to_string(),
+            base_address: 0x8000_0000,
+            commit_fifo: None,
+            spike: "spike".to_string(),
+            spike_args: Vec::new(),
+        }
+    }
+}
+
+/// Address regions shared by the hardware and its firmware.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MemoryMapConfig {
+    pub address_width: u32,
+    pub regions: Vec<Region>,
+    pub bsv: path::PathBuf, // Generated files, relative to the project root
+    pub c_header: path::PathBuf,
+    pub linker_script: path::PathBuf,
+}
+
+impl Default for MemoryMapConfig {
+    fn default() -> Self {
+        Self {
+            address_width: 32,
+            regions: Vec::new(),
+            bsv: path::PathBuf::from("src/MemoryMap.bsv"),
+            c_header: path::PathBuf::from("sw/include/memory_map.h"),
+            linker_script: path::PathBuf::from("sw/memory_map.ld"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    #[serde(default = "Region::default_attributes")]
+    pub attributes: String, // Linker MEMORY attributes
+}
+
+impl Region {
+    fn default_attributes() -> String {
+        "rwx".to_string()
+    }
+}
+
+/// A top-level wrapper instantiating and connecting tops from this and dependency packages.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SocConfig {
+    pub output: path::PathBuf, // Generated wrapper, relative to the project root
+    pub instances: Vec<SocInstance>,
+    pub connections: Vec<SocConnection>,
+    pub exports: Vec<String>, // instance.member paths exposed by the wrapper's interface
+}
+
+impl Default for SocConfig {
+    fn default() -> Self {
+        Self {
+            output: path::PathBuf::from("src/Soc.bsv"),
+            instances: Vec::new(),
+            connections: Vec::new(),
+            exports: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocInstance {
+    pub name: String,
+    pub module: String,
+    #[serde(default)]
+    pub parameters: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocConnection {
+    pub from: String, // instance.member
+    pub to: String,
+    #[serde(default = "SocConnection::default_via")]
+    pub via: String, // Connection module, e.g. mkConnection
+}
+
+impl SocConnection {
+    fn default_via() -> String {
+        "mkConnection".to_string()
+    }
+}
+
+/// Another dolly package whose modules this package uses.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    pub path: path::PathBuf, // Relative to the project root
+    #[serde(default)]
+    pub precompiled: bool, // `path` holds compiled .bo/.ba packages rather than a dolly package
+    pub version: Option<String>, // A requirement on its [package] version, e.g. "^1.2"
+}
+
+/// A replacement for a dependency, wherever it appears in the dependency graph.  Only the
+/// [patch] table of the project being built is used.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patch {
+    pub path: Option<path::PathBuf>, // A checkout of the package, relative to the project root
+    pub git: Option<String>,         // A repository to clone instead
+    pub rev: Option<String>,         // The branch, tag or commit of `git` (default: its HEAD)
+}
+
+impl Patch {
+    /// Where a git patch is checked out, in the cache.
+    fn checkout_dir(
+        project: &Project,
+        name: &str,
+        git: &str,
+        rev: Option<&str>,
+    ) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+        let source = format!("{} {}", git, rev.unwrap_or_default());
+        Ok(project.cache_dir()?.join("patches").join(format!(
+            "{}-{}",
+            name,
+            &checksum::sha256(source.as_bytes())[..12]
+        )))
+    }
+
+    /// The directory of the replacement for the named dependency, cloning a git patch into
+    /// the project's cache if it isn't there yet.
+    fn checkout(
+        &self,
+        project: &Project,
+        name: &str,
+    ) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+        let git = match (&self.path, &self.git, &self.rev) {
+            (Some(path), None, None) => return Ok(project.root_path.join(path)),
+            (None, Some(git), _) => git,
+            _ => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Invalid [patch] {}: expected either a path, or a git repository with an \
+                     optional rev",
+                    name
+                ))))
+            }
+        };
+        let checkout = Self::checkout_dir(project, name, git, self.rev.as_deref())?;
+        if !checkout.exists() {
+            // Local repositories are relative to the project root.
+            let local = project.root_path.join(git);
+            let url = if local.exists() {
+                local.to_string_lossy().to_string()
+            } else {
+                // Offline, list every patch still to be cloned, not just the first needed.
+                let mut fetches = Vec::new();
+                for (name, patch) in &project.patch {
+                    let Some(git) = &patch.git else { continue };
+                    let dir = Self::checkout_dir(project, name, git, patch.rev.as_deref())?;
+                    if !dir.exists() && !project.root_path.join(git).exists() {
+                        fetches.push(format!("[patch] {} from {}", name, git));
+                    }
+                }
+                net::forbid_fetching(&fetches)?;
+                git.clone()
+            };
+            println!("Cloning {} for [patch] {}", git, name);
+            fs::create_dir_all(checkout.parent().unwrap())?;
+            let clone = changes::git(
+                checkout.parent().unwrap(),
+                &["clone", "--quiet", &url, &checkout.to_string_lossy()],
+            )
+            .and_then(|_| match &self.rev {
+                Some(rev) => changes::git(&checkout, &["checkout", "--quiet", rev]),
+                None => Ok(Vec::new()),
+            });
+            if let Err(e) = clone {
+                let _ = fs::remove_dir_all(&checkout);
+                return Err(e);
+            }
+        }
+        Ok(checkout)
+    }
+}
+
+/// Whether a file name matches a pattern in which * stands for any characters and ? for one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.chars().next() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            name.char_indices()
+                .map(|(index, _)| index)
+                .chain(std::iter::once(name.len()))
+                .any(|index| glob_match(rest, &name[index..]))
+        }
+        Some(first) => {
+            let mut chars = name.chars();
+            chars.next().is_some_and(|c| first == '?' || c == first)
+                && glob_match(&pattern[first.len_utf8()..], chars.as_str())
+        }
+    }
+}
+
+/// The environment variable overriding the target directory.
+pub const TARGET_DIR_VARIABLE: &str = "DOLLY_TARGET_DIR";
+
+/// How `dolly gc` treats a directory of target/ that isn't a top module's or a test's.
+#[derive(Clone, Copy, PartialEq)]
+enum Retention {
+    Kept, // The current outputs, replaced by each build
+    Aged, // One entry per run, removed once older than gc-max-age-days
+}
+
+/// The directories of target/ holding the outputs of commands other than building a top module
+/// and running a test, which each get theirs from `Project::output_dir`.
+const OUTPUT_DIRS: [(&str, Retention); 16] = [
+    ("artifacts", Retention::Aged),
+    ("bluesim", Retention::Aged),
+    ("bo", Retention::Kept),
+    ("cosim", Retention::Aged),
+    ("coverage", Retention::Kept),
+    ("doc", Retention::Aged),
+    ("examples", Retention::Aged),
+    ("expand", Retention::Aged),
+    ("formal", Retention::Aged),
+    ("fuzz", Retention::Aged),
+    ("generated", Retention::Kept),
+    ("hooks", Retention::Aged),
+    ("isa-test", Retention::Aged),
+    ("mutate", Retention::Aged),
+    ("package", Retention::Aged),
+    ("riscof", Retention::Aged),
+];
+
+/// Marks (inside its .git directory) a checkout that dolly cloned, and may remove.
+pub const CLONED_MARKER: &str = "dolly-cloned";
+
+/// What `dolly clean` removes.  With nothing selected, the whole target directory is removed.
+#[derive(Debug, Default)]
+pub struct CleanOptions {
+    pub targets: Vec<String>, // Build directories of the named tests or top modules
+    pub tests: bool,          // Build directories of every test, and the coverage report
+    pub deps: bool,           // Compiled packages and modules of the dependencies
+    pub all: bool,            // Also the cache and the checkouts dolly cloned
 }
 
 impl Project {
@@ -22,9 +742,565 @@ impl Project {
         &self.root_path
     }
 
-    pub fn clean(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Ignore any errors from remove_dir_all()
-        let _ = fs::remove_dir_all(self.root_path.join("target"));
+    /// The `[env]` variables, with references to the environment ($VAR or ${VAR}) expanded.
+    pub fn env(
+        &self,
+    ) -> Result<std::collections::BTreeMap<String, String>, Box<dyn std::error::Error>> {
+        self.env
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), expand_env(value)?)))
+            .collect()
+    }
+
+    /// Where build products go: $DOLLY_TARGET_DIR (or --target-dir) if set, else target/ in
+    /// the project root.
+    pub fn target_dir(&self) -> path::PathBuf {
+        self.target_dir
+            .clone()
+            .unwrap_or_else(|| self.root_path.join("target"))
+    }
+
+    pub fn set_target_dir(&mut self, target_dir: path::PathBuf) {
+        self.target_dir = Some(target_dir);
+    }
+
+    /// The directory of target/ a command writes its outputs to, one of `OUTPUT_DIRS`.
+    pub fn output_dir(&self, name: &str) -> path::PathBuf {
+        debug_assert!(OUTPUT_DIRS.iter().any(|(known, _)| *known == name));
+        self.target_dir().join(name)
+    }
+
+    /// Where cached downloads and build state go: the configured cache-dir, else target/cache.
+    pub fn cache_dir(&self) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+        match &self.config.cache_dir {
+            Some(cache_dir) => Ok(self.root_path.join(expand_env(cache_dir)?)),
+            None => Ok(self.target_dir().join("cache")),
+        }
+    }
+
+    /// The source directories, including the output directory of the `[generators]` (and of
+    /// the BuildInfo package).
+    pub fn src_dirs(&self) -> Vec<path::PathBuf> {
+        let mut src_dirs: Vec<path::PathBuf> = self
+            .package
+            .src_dirs
+            .iter()
+            .map(|src_dir| self.root_path.join(src_dir))
+            .collect();
+        if !self.generators.is_empty() || self.build.build_info {
+            src_dirs.push(self.generated_dir());
+        }
+        src_dirs
+    }
+
+    /// Where the `[generators]` write their BSV packages.
+    pub fn generated_dir(&self) -> path::PathBuf {
+        self.output_dir("generated").join("src")
+    }
+
+    /// The `[build] library-paths`, with environment variables expanded and relative paths
+    /// resolved against the project root.
+    pub fn library_paths(&self) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+        self.build
+            .library_paths
+            .iter()
+            .map(|library_path| Ok(self.root_path.join(expand_env(library_path)?)))
+            .collect()
+    }
+
+    /// The directories the `-p` flags of `[verilog] bsc-flags` add to the bsc search path, with
+    /// `%` standing for the Bluespec directory and the `+` of the default path left out.
+    pub fn flag_search_paths(&self) -> Vec<path::PathBuf> {
+        let flags = &self.verilog.bsc_flags;
+        flags
+            .iter()
+            .zip(flags.iter().skip(1))
+            .filter(|(flag, _)| *flag == "-p")
+            .flat_map(|(_, paths)| paths.split(':'))
+            .filter(|path| !path.is_empty() && *path != "+")
+            .filter_map(|path| match path.strip_prefix('%') {
+                Some(rest) => {
+                    verilog::bluespec_dir().map(|dir| dir.join(rest.trim_start_matches('/')))
+                }
+                None => Some(self.root_path.join(path)),
+            })
+            .collect()
+    }
+
+    /// The file at the root of the module tree: `top-file`, or `<Name>.bsv` (or `<Name>.bs`) in
+    /// the first source root.
+    pub fn top_file(&self) -> path::PathBuf {
+        if let Some(top_file) = &self.package.top_file {
+            return self.root_path.join(top_file);
+        }
+        let src_dir = self
+            .src_dirs()
+            .first()
+            .cloned()
+            .unwrap_or_else(|| self.root_path.join("src"));
+        let name = self.package.name.to_case(Case::Pascal);
+        let classic = src_dir.join(format!("{}.bs", name));
+        if classic.exists() {
+            classic
+        } else {
+            src_dir.join(format!("{}.bsv", name))
+        }
+    }
+
+    /// The project's and its path dependencies' source files, by package (see
+    /// `bsv::find_packages`).
+    pub fn packages(
+        &self,
+    ) -> Result<std::collections::HashMap<String, Vec<path::PathBuf>>, Box<dyn std::error::Error>>
+    {
+        let mut src_dirs = self.src_dirs();
+        for dependency in self.load_dependencies()? {
+            src_dirs.extend(dependency.src_dirs());
+        }
+        let mut packages = std::collections::HashMap::<String, Vec<path::PathBuf>>::new();
+        for src_dir in src_dirs {
+            for (package, package_paths) in bsv::find_packages(&src_dir) {
+                packages.entry(package).or_default().extend(package_paths);
+            }
+        }
+        Ok(packages)
+    }
+
+    /// Loads the path dependencies of this project and, transitively, of its dependencies.
+    /// Packages reached by more than one path are resolved to the highest version that meets
+    /// every requirement on them; it is an error if none does.
+    pub fn load_dependencies(&self) -> Result<Vec<Project>, Box<dyn std::error::Error>> {
+        // Each requirement is recorded with the chain of packages leading to it.
+        struct Requirement {
+            chain: Vec<String>,
+            version: Option<VersionReq>,
+        }
+        let mut loaded = Vec::<Project>::new();
+        let mut requirements = std::collections::BTreeMap::<String, Vec<Requirement>>::new();
+        let mut seen = std::collections::HashSet::<path::PathBuf>::new();
+        seen.insert(self.root_path.clone());
+
+        let requirement = |project: &Project, name: &str, dependency: &Dependency| {
+            let version = match &dependency.version {
+                Some(text) => Some(VersionReq::parse(text).ok_or_else(|| {
+                    codes::error(
+                        "D0008",
+                        format!(
+                            "Invalid version requirement {:?} for {} in {}: expected one such as \
+                             \"^1.2\", \"~1.2.3\" or \"=1.2.0\"",
+                            text, name, project.package.name
+                        ),
+                    )
+                })?),
+                None => None,
+            };
+            let path = match self.patch.get(name) {
+                Some(patch) => {
+                    trace!("Patching {} of {}", name, project.package.name);
+                    patch.checkout(self, name)?
+                }
+                None => project.root_path.join(&dependency.path),
+            };
+            Ok::<_, Box<dyn std::error::Error>>((path, version))
+        };
+        let mut remaining = Vec::<(path::PathBuf, Vec<String>, Option<VersionReq>)>::new();
+        for (name, dependency) in &self.dependencies {
+            if !dependency.precompiled {
+                let (path, version) = requirement(self, name, dependency)?;
+                remaining.push((path, vec![self.package.name.clone()], version));
+            }
+        }
+        remaining.reverse();
+        while let Some((dependency_path, chain, version)) = remaining.pop() {
+            let project_file = dependency_path.join("dolly.toml");
+            let project_file = project_file.canonicalize().map_err(|e| {
+                codes::error(
+                    "D0003",
+                    format!("Unable to load dependency {:?}: {}", project_file, e),
+                )
+            })?;
+            let mut dependency = Project::load(project_file)?;
+            // Only the project being built describes itself, so BuildInfo is defined once.
+            dependency.build.build_info = false;
+            requirements
+                .entry(dependency.package.name.clone())
+                .or_default()
+                .push(Requirement {
+                    chain: chain.clone(),
+                    version,
+                });
+            if !seen.insert(dependency.root_path.clone()) {
+                continue;
+            }
+            trace!("Found dependency {}", dependency.package.name);
+            let mut chain = chain;
+            chain.push(dependency.package.name.clone());
+            for (name, nested) in dependency.dependencies.iter().rev() {
+                if !nested.precompiled {
+                    let (path, version) = requirement(&dependency, name, nested)?;
+                    remaining.push((path, chain.clone(), version));
+                }
+            }
+            loaded.push(dependency);
+        }
+
+        for name in self.patch.keys() {
+            let used = loaded.iter().any(|dependency| {
+                dependency
+                    .dependencies
+                    .get(name)
+                    .is_some_and(|nested| !nested.precompiled)
+            }) || self.dependencies.contains_key(name);
+            if !used {
+                warn!("[patch] {} doesn't replace any dependency", name);
+            }
+        }
+
+        // The requirements of every candidate count, including those not chosen.
+        let mut chosen = std::collections::HashSet::<path::PathBuf>::new();
+        for (name, requirements) in &requirements {
+            let candidates: Vec<&Project> = loaded
+                .iter()
+                .filter(|dependency| dependency.package.name == *name)
+                .collect();
+            if candidates.is_empty() {
+                continue; // A dependency on the project itself
+            }
+            let constrained = requirements.iter().any(|r| r.version.is_some());
+            let mut versions = Vec::<(Option<Version>, &Project)>::new();
+            for candidate in &candidates {
+                let version = Version::parse(&candidate.package.version);
+                if version.is_none() && constrained {
+                    return Err(Box::new(codes::error(
+                        "D0006",
+                        format!(
+                            "Invalid [package] version {:?} in {}: expected a semantic version \
+                             such as 1.2.0",
+                            candidate.package.version, name
+                        ),
+                    )));
+                }
+                versions.push((version, candidate));
+            }
+            let best = versions
+                .iter()
+                .filter(|(version, _)| {
+                    requirements
+                        .iter()
+                        .all(|requirement| match (&requirement.version, version) {
+                            (Some(requirement), Some(version)) => requirement.matches(version),
+                            _ => true,
+                        })
+                })
+                .max_by(|(a, _), (b, _)| a.cmp(b));
+            match best {
+                Some((_, project)) => {
+                    chosen.insert(project.root_path.clone());
+                }
+                None => {
+                    let mut message = format!("No version of {} meets every requirement:", name);
+                    for requirement in requirements {
+                        let Some(version) = &requirement.version else {
+                            continue;
+                        };
+                        message.push_str(&format!(
+                            "\n  {} -> {} {} ({})",
+                            requirement.chain.join(" -> "),
+                            name,
+                            version,
+                            version.range()
+                        ));
+                    }
+                    for (_, candidate) in &versions {
+                        message.push_str(&format!(
+                            "\n  found {} {} at {}",
+                            name,
+                            candidate.package.version,
+                            candidate.root_path.to_string_lossy()
+                        ));
+                    }
+                    return Err(Box::new(codes::error("D0008", message)));
+                }
+            }
+        }
+        loaded.retain(|dependency| chosen.contains(&dependency.root_path));
+        Ok(loaded)
+    }
+
+    /// The directories of this project's precompiled dependencies, each of which must hold at
+    /// least one .bo file.
+    pub fn precompiled_dependencies(
+        &self,
+    ) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+        let mut directories = Vec::<path::PathBuf>::new();
+        for (name, dependency) in &self.dependencies {
+            if !dependency.precompiled {
+                continue;
+            }
+            let directory = self.root_path.join(&dependency.path);
+            let has_packages = directory.read_dir().is_ok_and(|mut entries| {
+                entries.any(|entry| {
+                    entry.is_ok_and(|entry| entry.path().extension().is_some_and(|ext| ext == "bo"))
+                })
+            });
+            if !has_packages {
+                return Err(Box::new(codes::error(
+                    "D0004",
+                    format!(
+                        "Precompiled dependency '{}' has no .bo files in {:?}",
+                        name, directory
+                    ),
+                )));
+            }
+            directories.push(directory);
+        }
+        Ok(directories)
+    }
+
+    /// The names of the unit and integration testbenches (which are also the names of their
+    /// build directories).
+    fn test_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .src_dirs()
+            .iter()
+            .flat_map(|src_dir| bsv::find_packages(src_dir).into_values().flatten())
+            .filter(|path| self.is_unit_test(path))
+            .map(|path| path.file_stem().unwrap().to_string_lossy().to_string())
+            .collect();
+        names.extend(self.integration_tests().into_iter().map(|(suite, path)| {
+            let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+            match suite {
+                Some(suite) => format!("{}.{}", suite, stem),
+                None => stem,
+            }
+        }));
+        names
+    }
+
+    /// Whether a source is a unit testbench: one whose file name matches [test]
+    /// unit-test-pattern (by default, whose stem ends in _tb) outside the directories of
+    /// [test] unit-test-exclude.
+    pub fn is_unit_test(&self, path: &path::Path) -> bool {
+        let excluded = self
+            .test
+            .unit_test_exclude
+            .iter()
+            .any(|dir| path.starts_with(self.root_path.join(dir)));
+        let Some(file_name) = path.file_name().map(|name| name.to_string_lossy()) else {
+            return false;
+        };
+        let matched = match &self.test.unit_test_pattern {
+            Some(pattern) => glob_match(pattern, &file_name),
+            None => path
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().ends_with("_tb")),
+        };
+        bsv::is_source(path) && matched && !excluded
+    }
+
+    /// The directories of packages shared by the testbenches, on the module path of every
+    /// test compile: [test] support-dirs, or tests/common if it exists.
+    pub fn support_dirs(&self) -> Vec<path::PathBuf> {
+        match &self.test.support_dirs {
+            Some(support_dirs) => support_dirs
+                .iter()
+                .map(|support_dir| self.root_path.join(support_dir))
+                .collect(),
+            None => Some(self.root_path.join("tests").join("common"))
+                .filter(|common| common.is_dir())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// The integration testbenches in tests/ and its subdirectories, with the dotted path of
+    /// the subdirectory holding each (e.g. integration.cache for
+    /// tests/integration/cache/Evict_tb.bsv).  The snapshots, fixture data (.data) and
+    /// support directories are skipped.
+    pub fn integration_tests(&self) -> Vec<(Option<String>, path::PathBuf)> {
+        let mut tests = Vec::new();
+        let support_dirs = self.support_dirs();
+        let mut remaining = vec![(None::<String>, self.root_path.join("tests"))];
+        while let Some((suite, dir)) = remaining.pop() {
+            let Ok(entries) = dir.read_dir() else {
+                continue;
+            };
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                if path.is_dir() {
+                    let skipped = name.starts_with('.')
+                        || name.ends_with(".data")
+                        || (suite.is_none() && name == "snapshots")
+                        || support_dirs.contains(&path);
+                    if !skipped {
+                        let suite = match &suite {
+                            Some(suite) => format!("{}.{}", suite, name),
+                            None => name,
+                        };
+                        remaining.push((Some(suite), path));
+                    }
+                } else if bsv::is_source(&path) {
+                    tests.push((suite.clone(), path));
+                }
+            }
+        }
+        tests.sort_by(|(_, a), (_, b)| a.cmp(b));
+        tests
+    }
+
+    /// The files bsc writes for the packages (.bo) and modules (.ba) of the dependencies.
+    fn dependency_outputs(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut outputs = Vec::<String>::new();
+        for dependency in self.load_dependencies()? {
+            for src_dir in dependency.src_dirs() {
+                for (package, files) in bsv::find_packages(&src_dir) {
+                    outputs.push(format!("{}.bo", package));
+                    for file in files {
+                        let source = fs::read_to_string(&file)?;
+                        outputs.extend(
+                            bsv::parse_source_modules(&file, &source)
+                                .into_iter()
+                                .map(|module| format!("{}.ba", module.name)),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Removes the build directories in target/ that `referenced` (the tests and top modules
+    /// of the current build) doesn't name, and the outputs of the other commands (fuzzing,
+    /// formal verification and so on) older than `[build] gc-max-age-days`.
+    pub fn collect_garbage(&self, referenced: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let target = self.target_dir();
+        let Ok(entries) = target.read_dir() else {
+            return Ok(());
+        };
+        let max_age = std::time::Duration::from_secs(self.build.gc_max_age_days * 24 * 60 * 60);
+        let remove = |path: &path::Path| {
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            if removed.is_ok() {
+                println!("Removed {}", path.to_string_lossy());
+            }
+        };
+
+        let remove_old = |directory: &path::Path| -> std::io::Result<()> {
+            for output in directory.read_dir()?.filter_map(|entry| entry.ok()) {
+                let age = output
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .map(|modified| modified.elapsed().unwrap_or_default());
+                if age.is_ok_and(|age| age > max_age) {
+                    remove(&output.path());
+                }
+            }
+            Ok(())
+        };
+
+        let cache_dir = self.cache_dir()?;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            match OUTPUT_DIRS.iter().find(|(known, _)| *known == name) {
+                Some((_, Retention::Aged)) => remove_old(&path)?,
+                Some((_, Retention::Kept)) => {}
+                None if path.is_dir() && path != cache_dir && !referenced.contains(&name) => {
+                    remove(&path)
+                }
+                None => {}
+            }
+        }
+        if cache_dir.is_dir() {
+            remove_old(&cache_dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn clean(&self, options: &CleanOptions) -> Result<(), Box<dyn std::error::Error>> {
+        let target = self.target_dir();
+        let selective = !options.targets.is_empty() || options.tests || options.deps;
+        if options.all {
+            // Ignore any errors from remove_dir_all()
+            let _ = fs::remove_dir_all(&target);
+            let _ = fs::remove_dir_all(self.cache_dir()?);
+            let riscv_tests = self.root_path.join(&self.isa_test.riscv_tests);
+            if riscv_tests.join(".git").join(CLONED_MARKER).exists() {
+                let _ = fs::remove_dir_all(&riscv_tests);
+            }
+            return Ok(());
+        }
+        if !selective {
+            // Everything but the cache.
+            let cache_dir = self.cache_dir()?;
+            let Ok(entries) = target.read_dir() else {
+                return Ok(());
+            };
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                if path == cache_dir {
+                    continue;
+                }
+                let _ = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+            }
+            return Ok(());
+        }
+
+        // A build directory is named after its test or top module, never a path out of target/.
+        if let Some(target_name) = options.targets.iter().find(|name| {
+            let mut components = path::Path::new(name.as_str()).components();
+            !matches!(
+                (components.next(), components.next()),
+                (Some(path::Component::Normal(_)), None)
+            )
+        }) {
+            return Err(Box::new(std::io::Error::other(format!(
+                "'{}' isn't the name of a test or top module",
+                target_name
+            ))));
+        }
+        let mut directories = options.targets.clone();
+        if options.tests {
+            let test_names = self.test_names();
+            // The build directories of //!matrix configurations are named <test>-<defines>.
+            if let Ok(entries) = target.read_dir() {
+                directories.extend(
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .filter(|name| {
+                            test_names.iter().any(|test| {
+                                name.strip_prefix(test.as_str())
+                                    .is_some_and(|defines| defines.starts_with('-'))
+                            })
+                        }),
+                );
+            }
+            directories.extend(test_names);
+            directories.push("coverage".to_string());
+        }
+        for directory in directories {
+            let _ = fs::remove_dir_all(target.join(directory));
+        }
+
+        if options.deps {
+            let outputs = self.dependency_outputs()?;
+            let Ok(entries) = target.read_dir() else {
+                return Ok(());
+            };
+            for directory in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                for output in &outputs {
+                    let _ = fs::remove_file(directory.join(output));
+                }
+            }
+        }
         Ok(())
     }
 
@@ -33,97 +1309,643 @@ impl Project {
         let contents = fs::read_to_string(&project_file_name)?;
 
         trace!("Parsing project file...");
-        let mut project: Project = toml::from_str(&contents)?;
+        let mut manifest: toml::Table = manifest::parse(&project_file_name, &contents)?;
+        let root_path = project_file_name
+            .parent()
+            .expect("Project path has no parent?  Bug.");
+        // The configuration files supply defaults for settings dolly.toml leaves out.
+        let config = Config::load(Some(root_path))?;
+        if let Some(simulator) = config.simulator {
+            let test = manifest
+                .entry("test")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let Some(test) = test.as_table_mut() {
+                test.entry("simulator").or_insert_with(|| {
+                    toml::Value::String(format!("{:?}", simulator).to_lowercase())
+                });
+            }
+        }
+        // Errors are described from the file itself, which has the lines they are on.
+        let mut project: Project = toml::Value::Table(manifest).try_into().map_err(|e| {
+            match manifest::parse::<Project>(&project_file_name, &contents) {
+                Err(described) => described,
+                Ok(_) => std::io::Error::other(e.to_string()),
+            }
+        })?;
+        project.package.validate()?;
+        project.config = config;
 
-        project.root_path = path::PathBuf::from(
-            project_file_name
-                .parent()
-                .expect("Project path has no parent?  Bug."),
-        );
+        project.root_path = path::PathBuf::from(root_path);
+        if let Some(target_dir) =
+            std::env::var_os(TARGET_DIR_VARIABLE).filter(|dir| !dir.is_empty())
+        {
+            project.target_dir = Some(std::env::current_dir()?.join(target_dir));
+        }
 
         Ok(project)
     }
 
-    pub fn init(new_project_path: &path::PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    /// Creates a new project from a template (see templates.rs).
+    pub fn init(
+        new_project_path: &path::PathBuf,
+        template: &templates::Template,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         // first, see if the path exists
         if new_project_path.exists() {
             error!(
                 "Unable to initialize new project. s{:?} already exists",
                 new_project_path
             );
-            Err(Box::new(std::io::Error::from(
+            return Err(Box::new(std::io::Error::from(
                 std::io::ErrorKind::AlreadyExists,
-            )))
-        } else {
-            std::fs::create_dir_all(new_project_path.as_path().join("src"))?;
-            std::fs::create_dir_all(new_project_path.as_path().join("tests"))?;
+            )));
+        }
+        std::fs::create_dir_all(new_project_path)?;
 
-            let module_name = new_project_path
-                .file_stem()
+        let module_name = new_project_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .to_case(Case::UpperCamel);
+        let variables = templates::Variables {
+            name: module_name,
+            project: new_project_path
+                .file_name()
                 .unwrap()
                 .to_string_lossy()
-                .to_case(Case::UpperCamel);
-
-            // Create dolly.toml
-            write!(
-                std::fs::File::create(new_project_path.as_path().join("dolly.toml"))?,
-                r#"[package]
-name = {:?}
-version = "0.1.0"
-"#,
-                module_name
-            )?;
-
-            // Create simple .gitignore
-            writeln!(
-                std::fs::File::create(new_project_path.as_path().join(".gitignore"))?,
-                r"**/target"
-            )?;
-
-            // Create a simple module
-            let filename = format!("src/{}.bsv", module_name);
-            write!(
-                std::fs::File::create(new_project_path.as_path().join(filename))?,
-                r#"interface {};
-    method Bool isWorking;
-endinterface
-
-module mk{}({});
-    method Bool isWorking;
-        return True;
-    endmethod
-endmodule
-"#,
-                module_name,
-                module_name,
-                module_name
-            )?;
-
-            // Create a simple test
-            let filename = format!("tests/{}_tb.bsv", module_name);
-            write!(
-                std::fs::File::create(new_project_path.as_path().join(filename))?,
-                r#"//!topmodule mk{}_tb
-import {}::*;
-
-module mk{}_tb(Empty);
-    {} my_module <- mk{};
-
-    rule run_it;
-        // Required for test to pass.
-        $display(">>>PASS");
-        $finish();
-    endrule
-endmodule
-"#,
-                module_name,
-                module_name,
-                module_name,
-                module_name,
-                module_name
-            )?;
+                .to_string(),
+            author: templates::default_author(),
+        };
+        let instantiated = templates::instantiate(template, new_project_path, &variables);
+        if instantiated.is_err() {
+            let _ = std::fs::remove_dir_all(new_project_path);
+        }
+        instantiated
+    }
 
-            Ok(())
+    /// Implements `dolly new`: a project from a template, with its [package] metadata filled
+    /// in, in a new git repository with an initial commit.
+    pub fn create(
+        new_project_path: &path::PathBuf,
+        template: &templates::Template,
+        metadata: &templates::Metadata,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::init(new_project_path, template)?;
+        let manifest = new_project_path.join("dolly.toml");
+        fs::write(
+            &manifest,
+            templates::add_metadata(&fs::read_to_string(&manifest)?, metadata),
+        )?;
+        templates::commit_initial(new_project_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn environment_expansion() {
+        std::env::set_var("DOLLY_TEST_IP", "/opt/ip");
+        assert_eq!(
+            expand_env("$DOLLY_TEST_IP/bsv:${DOLLY_TEST_IP}x").unwrap(),
+            "/opt/ip/bsv:/opt/ipx"
+        );
+        assert!(expand_env("$DOLLY_TEST_UNSET_VARIABLE/lib").is_err());
+    }
+
+    #[test]
+    fn bsc_flag_search_paths() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[verilog]\nbsc-flags = [\"-keep-fires\", \"-p\", \"vendor/ip:+\"]\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        assert_eq!(project.flag_search_paths(), [root.join("vendor/ip")]);
+    }
+
+    #[test]
+    fn unit_test_pattern() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[test]\nunit-test-pattern = \"*_test.bsv\"\nunit-test-exclude = [\"src/gen\"]\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+
+        let src = root.join("src");
+        assert!(project.is_unit_test(&src.join("Fifo_test.bsv")));
+        assert!(!project.is_unit_test(&src.join("Fifo_tb.bsv")));
+        assert!(!project.is_unit_test(&src.join("gen").join("Regs_test.bsv")));
+        assert!(glob_match("Fifo?_*.bs", "Fifo2_frame.bs"));
+        assert!(!glob_match("*_tb.bsv", "Fifo_tb.bs"));
+
+        // Excluded directories are whole path components, and only BSV sources are unit
+        // testbenches, whatever the pattern matches.
+        assert!(project.is_unit_test(&src.join("generated").join("Regs_test.bsv")));
+        assert!(!project.is_unit_test(&src.join("Fifo_test.bsv.orig")));
+        assert!(!project.is_unit_test(&src.join("..")));
+        assert!(!glob_match("", "Fifo_tb.bsv") && glob_match("", ""));
+        assert!(!glob_match("?*_tb.bsv", "_tb.bsv"));
+
+        // Without a pattern, unit testbenches are the *_tb sources.
+        let manifest = |test: &str| {
+            fs::write(
+                root.join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[test]\n{}\n",
+                    test
+                ),
+            )
+            .unwrap();
+            Project::load(root.join("dolly.toml"))
+        };
+        let project = manifest("").unwrap();
+        assert!(project.is_unit_test(&src.join("Fifo_tb.bs")));
+        assert!(!project.is_unit_test(&src.join("Fifo_tb.v")));
+        assert!(!project.is_unit_test(&src.join("Fifo_test.bsv")));
+
+        // A pattern that isn't a string, or a misspelled key, is rejected.
+        assert!(manifest("unit-test-pattern = 3").is_err());
+        assert!(manifest("unit-test-patern = \"*_test.bsv\"").is_err());
+        assert!(manifest("unit-test-exclude = \"src/gen\"").is_err());
+    }
+
+    #[test]
+    fn precompiled_dependency_directories() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("vendor").join("ip")).unwrap();
+        fs::write(root.join("vendor").join("ip").join("Ip.bo"), "").unwrap();
+        fs::create_dir_all(root.join("vendor").join("empty")).unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[dependencies]\nip = { path = \"vendor/ip\", precompiled = true }\n",
+        )
+        .unwrap();
+
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        assert!(project.load_dependencies().unwrap().is_empty());
+        assert_eq!(
+            project.precompiled_dependencies().unwrap(),
+            vec![root.join("vendor").join("ip")]
+        );
+
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[dependencies]\nip = { path = \"vendor/empty\", precompiled = true }\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let error = project.precompiled_dependencies().unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0004"));
+        assert!(error.to_string().contains("'ip' has no .bo files"));
+
+        // A missing directory, or one with only elaborated modules (.ba), has no packages.
+        fs::write(root.join("vendor").join("empty").join("mkIp.ba"), "").unwrap();
+        assert!(project.precompiled_dependencies().is_err());
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[dependencies]\nip = { path = \"vendor/missing\", precompiled = true }\n",
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        assert!(project.precompiled_dependencies().is_err());
+    }
+
+    #[test]
+    fn resolved_dependency_versions() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let manifest = |dir: &str, name: &str, version: &str, dependencies: &str| {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(
+                root.join(dir).join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"{}\"\n\n[dependencies]\n{}",
+                    name, version, dependencies
+                ),
+            )
+            .unwrap();
+        };
+        manifest("fifo1", "fifo", "1.4.0", "");
+        manifest("fifo2", "fifo", "2.1.0", "");
+        manifest(
+            "soc",
+            "soc",
+            "0.3.0",
+            "fifo = { path = \"../fifo2\", version = \"^2\" }\n",
+        );
+        manifest(
+            "core",
+            "core",
+            "0.1.0",
+            "fifo = { path = \"../fifo1\" }\nsoc = { path = \"../soc\", version = \"~0.3\" }\n",
+        );
+        let load = || {
+            Project::load(root.join("core").join("dolly.toml"))
+                .unwrap()
+                .load_dependencies()
+        };
+        let mut loaded: Vec<String> = load()
+            .unwrap()
+            .iter()
+            .map(|dependency| format!("{} {}", dependency.package.name, dependency.package.version))
+            .collect();
+        loaded.sort();
+        assert_eq!(loaded, ["fifo 2.1.0", "soc 0.3.0"]);
+
+        manifest(
+            "core",
+            "core",
+            "0.1.0",
+            "fifo = { path = \"../fifo1\", version = \"^1.2\" }\nsoc = { path = \"../soc\" }\n",
+        );
+        let error = load().unwrap_err().to_string();
+        assert!(error.starts_with(
+            "D0008: No version of fifo meets every requirement:\n  \
+             core -> fifo ^1.2 (>=1.2.0, <2.0.0)\n  core -> soc -> fifo ^2 (>=2.0.0, <3.0.0)\n  \
+             found fifo 1.4.0 at "
+        ));
+
+        manifest(
+            "core",
+            "core",
+            "0.1.0",
+            "soc = { path = \"../soc\", version = \"0.4\" }\n",
+        );
+        assert!(load()
+            .unwrap_err()
+            .to_string()
+            .contains("core -> soc 0.4 (>=0.4.0, <0.5.0)"));
+
+        // Requirements and (constrained) versions must parse.
+        manifest(
+            "core",
+            "core",
+            "0.1.0",
+            "fifo = { path = \"../fifo1\", version = \">=1.2\" }\n",
+        );
+        let error = load().unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0008"));
+        assert!(error
+            .to_string()
+            .contains("Invalid version requirement \">=1.2\" for fifo in core"));
+        manifest("fifo1", "fifo", "one", "");
+        manifest(
+            "core",
+            "core",
+            "0.1.0",
+            "fifo = { path = \"../fifo1\", version = \"^1\" }\n",
+        );
+        let error = load().unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0006"));
+        assert!(error
+            .to_string()
+            .contains("Invalid [package] version \"one\" in fifo"));
+        manifest("core", "core", "0.1.0", "fifo = { path = \"../fifo1\" }\n");
+        assert_eq!(load().unwrap()[0].package.version, "one");
+    }
+
+    /// The environment is the process's, so $DOLLY_TARGET_DIR is only set in a copy of the
+    /// test binary run for this test alone.
+    #[test]
+    fn relocated_target_dir() {
+        const ROOT_VARIABLE: &str = "DOLLY_TEST_TARGET_ROOT";
+        if let Some(root) = std::env::var_os(ROOT_VARIABLE) {
+            let root = path::Path::new(&root);
+            let project = Project::load(root.join("dolly.toml")).unwrap();
+            let target = std::env::current_dir().unwrap().join("out");
+            assert_eq!(project.target_dir(), target);
+            assert_eq!(project.output_dir("bo"), target.join("bo"));
+            assert_eq!(project.cache_dir().unwrap(), target.join("cache"));
+            return;
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("core");
+        fs::create_dir(&root).unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        // A relative directory is taken from where dolly runs, not the project root.
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "project::test::relocated_target_dir"])
+            .env(ROOT_VARIABLE, &root)
+            .env(TARGET_DIR_VARIABLE, "out")
+            .current_dir(temp.path())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut project = Project::load(root.join("dolly.toml")).unwrap();
+        assert_eq!(project.target_dir(), root.join("target"));
+        let target = temp.path().join("out");
+        project.set_target_dir(target.clone());
+        for directory in ["mkTop", "cache"] {
+            fs::create_dir_all(target.join(directory)).unwrap();
+            fs::create_dir_all(root.join("target").join(directory)).unwrap();
+        }
+        // Cleaning empties the relocated directory, and leaves target/ in the project alone.
+        project.clean(&CleanOptions::default()).unwrap();
+        assert!(!target.join("mkTop").exists() && target.join("cache").exists());
+        assert!(root.join("target").join("mkTop").exists());
+    }
+
+    #[test]
+    fn selective_clean() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::create_dir_all(root.join("dep").join("src")).unwrap();
+        fs::write(root.join("src").join("Fifo_tb.bsv"), "").unwrap();
+        fs::write(root.join("tests").join("TopTest.bsv"), "").unwrap();
+        let suite = root.join("tests").join("integration").join("cache");
+        fs::create_dir_all(&suite).unwrap();
+        fs::write(suite.join("Evict_tb.bsv"), "").unwrap();
+        fs::create_dir_all(root.join("tests").join("snapshots")).unwrap();
+        fs::write(root.join("tests").join("snapshots").join("Old.bsv"), "").unwrap();
+        fs::create_dir_all(root.join("tests").join("common")).unwrap();
+        fs::write(root.join("tests").join("common").join("Scoreboard.bsv"), "").unwrap();
+        fs::write(
+            root.join("dep").join("src").join("Uart.bsv"),
+            "package Uart;\nmodule mkUart(Uart);\nendmodule\nendpackage\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("dep").join("dolly.toml"),
+            "[package]\nname = \"uart\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n\n[dependencies]\nuart = { path = \"dep\" }\n",
+        )
+        .unwrap();
+        let target = root.join("target");
+        for directory in [
+            "Fifo_tb",
+            "Fifo_tb-DEPTH=4",
+            "TopTest",
+            "integration.cache.Evict_tb",
+            "mkTop",
+            "mkSoc",
+            "coverage",
+        ] {
+            fs::create_dir_all(target.join(directory)).unwrap();
+        }
+        for file in ["Uart.bo", "mkUart.ba", "Top.bo"] {
+            fs::write(target.join("mkSoc").join(file), "").unwrap();
+        }
+
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        assert_eq!(
+            project.support_dirs(),
+            vec![root.join("tests").join("common")]
+        );
+        assert_eq!(
+            project.integration_tests(),
+            vec![
+                (None, root.join("tests").join("TopTest.bsv")),
+                (
+                    Some("integration.cache".to_string()),
+                    suite.join("Evict_tb.bsv")
+                ),
+            ]
+        );
+        let clean = |options: CleanOptions| project.clean(&options).unwrap();
+        clean(CleanOptions {
+            tests: true,
+            ..CleanOptions::default()
+        });
+        assert!(!target.join("Fifo_tb").exists() && !target.join("TopTest").exists());
+        assert!(!target.join("integration.cache.Evict_tb").exists());
+        assert!(!target.join("Fifo_tb-DEPTH=4").exists());
+        assert!(!target.join("coverage").exists() && target.join("mkTop").exists());
+
+        clean(CleanOptions {
+            targets: vec!["mkTop".to_string()],
+            deps: true,
+            ..CleanOptions::default()
+        });
+        assert!(!target.join("mkTop").exists());
+        assert!(!target.join("mkSoc").join("Uart.bo").exists());
+        assert!(!target.join("mkSoc").join("mkUart.ba").exists());
+        assert!(target.join("mkSoc").join("Top.bo").exists());
+
+        // Names that aren't build directories, or that lead out of target/, are refused; a
+        // name with no build directory is nothing to do.
+        for name in ["..", "../src", "/tmp", "mkSoc/Top.bo", ""] {
+            let options = CleanOptions {
+                targets: vec![name.to_string()],
+                ..CleanOptions::default()
+            };
+            assert!(project.clean(&options).is_err(), "{}", name);
+        }
+        assert!(root.join("src").exists() && target.join("mkSoc").join("Top.bo").exists());
+        clean(CleanOptions {
+            targets: vec!["Removed_tb".to_string()],
+            ..CleanOptions::default()
+        });
+        // The outputs of a dependency that can't be loaded aren't known.
+        fs::rename(root.join("dep"), root.join("moved")).unwrap();
+        let deps = CleanOptions {
+            deps: true,
+            ..CleanOptions::default()
+        };
+        assert!(project.clean(&deps).is_err());
+        fs::rename(root.join("moved"), root.join("dep")).unwrap();
+
+        fs::create_dir_all(target.join("cache")).unwrap();
+        clean(CleanOptions::default());
+        assert!(!target.join("mkSoc").exists() && target.join("cache").exists());
+        clean(CleanOptions {
+            all: true,
+            ..CleanOptions::default()
+        });
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn patched_dependencies() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let manifest = |dir: &str, name: &str, version: &str, rest: &str| {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(
+                root.join(dir).join("dolly.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"{}\"\n\n{}",
+                    name, version, rest
+                ),
+            )
+            .unwrap();
+        };
+        manifest("fifo", "fifo", "1.0.0", "");
+        manifest("fifo-fix", "fifo", "1.0.1", "");
+        manifest("uart-fork", "uart", "0.2.1", "");
+        manifest(
+            "uart",
+            "uart",
+            "0.2.0",
+            "[dependencies]\nfifo = { path = \"../fifo\" }\n",
+        );
+        let fork = root.join("uart-fork");
+        changes::git(&fork, &["init", "--quiet"]).unwrap();
+        changes::git(&fork, &["add", "dolly.toml"]).unwrap();
+        changes::git(
+            &fork,
+            &[
+                "-c",
+                "user.name=dolly",
+                "-c",
+                "user.email=dolly@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "Fork",
+            ],
+        )
+        .unwrap();
+        let load = |patch: &str| {
+            manifest(
+                "core",
+                "core",
+                "0.1.0",
+                &format!(
+                    "[dependencies]\nuart = {{ path = \"../uart\" }}\n\n[patch]\n{}",
+                    patch
+                ),
+            );
+            Project::load(root.join("core").join("dolly.toml"))
+                .and_then(|project| project.load_dependencies())
+        };
+        let versions = |patch: &str| -> Vec<String> {
+            let mut loaded: Vec<String> = load(patch)
+                .unwrap()
+                .iter()
+                .map(|dependency| {
+                    format!("{} {}", dependency.package.name, dependency.package.version)
+                })
+                .collect();
+            loaded.sort();
+            loaded
+        };
+
+        // A patch replaces a dependency wherever it is, including a dependency's.
+        assert_eq!(versions(""), ["fifo 1.0.0", "uart 0.2.0"]);
+        assert_eq!(
+            versions("fifo = { path = \"../fifo-fix\" }\n"),
+            ["fifo 1.0.1", "uart 0.2.0"]
+        );
+        assert_eq!(
+            versions("uart = { git = \"../uart-fork\" }\n"),
+            ["uart 0.2.1"]
+        );
+        let patches = root
+            .join("core")
+            .join("target")
+            .join("cache")
+            .join("patches");
+        assert_eq!(patches.read_dir().unwrap().count(), 1);
+        // An unused patch is only warned about.
+        assert_eq!(versions("spi = { path = \"../spi\" }\n").len(), 2);
+        assert!(load("fifo = { path = \"../fifo-fix\", git = \"../uart-fork\" }\n").is_err());
+        assert!(load("fifo = { path = \"../fifo-fix\", branch = \"main\" }\n").is_err());
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let target = root.join("target");
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        // Nothing has been built yet.
+        project.collect_garbage(&[]).unwrap();
+
+        for directory in ["mkTop", "RenamedTest", "coverage", "fuzz", "doc", "bo"] {
+            fs::create_dir_all(target.join(directory)).unwrap();
+        }
+        let month_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(31 * 86400);
+        let old = |file: path::PathBuf| {
+            fs::File::create(file)
+                .unwrap()
+                .set_modified(month_ago)
+                .unwrap()
+        };
+        old(target.join("fuzz").join("old.log"));
+        fs::write(target.join("fuzz").join("new.log"), "").unwrap();
+        old(target.join("doc").join("index.html"));
+        // Old compiled packages are kept, as are files (not build directories) in target/.
+        old(target.join("bo").join("Fifo.bo"));
+        old(target.join("dolly-metadata.json"));
+
+        project.collect_garbage(&["mkTop".to_string()]).unwrap();
+        assert!(target.join("bo").join("Fifo.bo").exists());
+        assert!(target.join("dolly-metadata.json").exists());
+        assert!(target.join("mkTop").exists() && target.join("coverage").exists());
+        assert!(!target.join("RenamedTest").exists());
+        assert!(!target.join("fuzz").join("old.log").exists());
+        assert!(target.join("fuzz").join("new.log").exists());
+        assert!(target.join("doc").exists() && !target.join("doc").join("index.html").exists());
+    }
+
+    #[test]
+    fn package_metadata() {
+        let package = |metadata: &str| -> Package {
+            toml::from_str(&format!(
+                "name = \"Uart\"\nversion = \"1.2.0\"\n{}",
+                metadata
+            ))
+            .unwrap()
+        };
+        let uart = package(
+            r#"authors = ["Ada Lovelace <ada@example.com>", "Charles Babbage"]
+license = "Apache-2.0 OR MIT"
+description = "A 16550-compatible UART"
+repository = "https://github.com/example/uart"
+keywords = ["uart", "serial-io"]"#,
+        );
+        assert!(uart.validate().is_ok());
+        assert_eq!(
+            uart.verilog_header().unwrap(),
+            "// Uart 1.2.0\n// A 16550-compatible UART\n\
+             // Authors: Ada Lovelace <ada@example.com>, Charles Babbage\n\
+             // Repository: https://github.com/example/uart\n\
+             // SPDX-License-Identifier: Apache-2.0 OR MIT\n"
+        );
+        assert!(package("").verilog_header().is_none());
+
+        for invalid in [
+            r#"authors = ["Ada <not an email>"]"#,
+            r#"license = "Apache 2.0""#,
+            r#"license = "MIT OR""#,
+            "description = \"\"\"\nTwo\nlines\"\"\"",
+            r#"repository = "github.com/example/uart""#,
+            r#"keywords = ["UART"]"#,
+            r#"keywords = ["a", "b", "c", "d", "e", "f"]"#,
+        ] {
+            let error = package(invalid).validate().unwrap_err().to_string();
+            assert!(
+                error.starts_with("D0006: Invalid [package] "),
+                "{}",
+                invalid
+            );
         }
     }
 }
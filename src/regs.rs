@@ -0,0 +1,509 @@
+use convert_case::{Case, Casing};
+use serde::Deserialize;
+use std::{fmt::Write, fs, path};
+
+/// A register block description, read from a TOML file.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegisterBlock {
+    pub name: String,
+    #[serde(default = "RegisterBlock::default_width")]
+    pub data_width: u32,
+    #[serde(default = "RegisterBlock::default_width")]
+    pub address_width: u32,
+    #[serde(default)]
+    pub registers: Vec<Register>,
+}
+
+impl RegisterBlock {
+    fn default_width() -> u32 {
+        32
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Register {
+    pub name: String,
+    pub offset: u64,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub access: Access, // Default for fields without their own access
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Field {
+    pub name: String,
+    pub lsb: u32,
+    #[serde(default = "Field::default_width")]
+    pub width: u32,
+    pub access: Option<Access>,
+    #[serde(default)]
+    pub reset: u64,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Field {
+    fn default_width() -> u32 {
+        1
+    }
+
+    fn mask(&self) -> u64 {
+        (((1u128 << self.width) - 1) as u64) << self.lsb
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    #[default]
+    Rw,
+    Ro,
+    Wo,
+    W1c, // Write one to clear
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Access::Rw => "rw",
+            Access::Ro => "ro",
+            Access::Wo => "wo",
+            Access::W1c => "w1c",
+        })
+    }
+}
+
+impl Register {
+    /// The register's fields, with a single read-write field covering a register without any.
+    fn fields(&self, data_width: u32) -> Vec<Field> {
+        if !self.fields.is_empty() {
+            return self
+                .fields
+                .iter()
+                .map(|field| Field {
+                    access: Some(field.access.unwrap_or(self.access)),
+                    ..field.clone()
+                })
+                .collect();
+        }
+        vec![Field {
+            name: "value".to_string(),
+            lsb: 0,
+            width: data_width,
+            access: Some(self.access),
+            reset: 0,
+            description: String::new(),
+        }]
+    }
+
+    fn mask(&self, data_width: u32, access: &[Access]) -> u64 {
+        self.fields(data_width)
+            .iter()
+            .filter(|field| access.contains(&field.access.unwrap()))
+            .fold(0, |mask, field| mask | field.mask())
+    }
+
+    fn reset(&self, data_width: u32) -> u64 {
+        self.fields(data_width).iter().fold(0, |reset, field| {
+            reset | ((field.reset << field.lsb) & field.mask())
+        })
+    }
+}
+
+/// Checks field placement and register offsets.
+pub fn validate(block: &RegisterBlock) -> Result<(), Box<dyn std::error::Error>> {
+    let error = |message: String| -> Result<(), Box<dyn std::error::Error>> {
+        Err(Box::new(std::io::Error::other(message)))
+    };
+    if block.data_width == 0 || block.data_width > 64 {
+        return error("data-width must be between 1 and 64 bits".to_string());
+    }
+    for (index, register) in block.registers.iter().enumerate() {
+        if block
+            .registers
+            .iter()
+            .skip(index + 1)
+            .any(|other| other.offset == register.offset)
+        {
+            return error(format!(
+                "Register '{}' shares offset {:#x} with another register",
+                register.name, register.offset
+            ));
+        }
+        let mut used = 0u64;
+        for field in &register.fields {
+            if field.width == 0
+                || field
+                    .lsb
+                    .checked_add(field.width)
+                    .is_none_or(|top| top > block.data_width)
+            {
+                return error(format!(
+                    "Field '{}.{}' does not fit in {} bits",
+                    register.name, field.name, block.data_width
+                ));
+            }
+            if used & field.mask() != 0 {
+                return error(format!(
+                    "Field '{}.{}' overlaps another field",
+                    register.name, field.name
+                ));
+            }
+            used |= field.mask();
+        }
+    }
+    Ok(())
+}
+
+fn type_name(block: &RegisterBlock, register: &Register) -> String {
+    format!(
+        "{}{}",
+        block.name.to_case(Case::Pascal),
+        register.name.to_case(Case::Pascal)
+    )
+}
+
+/// Generates the BSV package: a struct per register and a register-file module skeleton.
+pub fn generate_bsv(block: &RegisterBlock, package: &str) -> String {
+    let prefix = block.name.to_case(Case::Pascal);
+    let mut bsv = String::new();
+    writeln!(bsv, "// Generated by dolly gen regs -- do not edit.").unwrap();
+    writeln!(bsv, "package {};\n", package).unwrap();
+    writeln!(
+        bsv,
+        "typedef Bit#({}) {}Address;",
+        block.address_width, prefix
+    )
+    .unwrap();
+    writeln!(bsv, "typedef Bit#({}) {}Data;", block.data_width, prefix).unwrap();
+
+    for register in &block.registers {
+        let mut fields = register.fields(block.data_width);
+        // The first struct member is packed into the most significant bits.
+        fields.sort_by_key(|field| std::cmp::Reverse(field.lsb));
+
+        writeln!(bsv).unwrap();
+        if !register.description.is_empty() {
+            writeln!(bsv, "// {}", register.description).unwrap();
+        }
+        writeln!(bsv, "typedef struct {{").unwrap();
+        let mut next = block.data_width;
+        for field in &fields {
+            let top = field.lsb + field.width;
+            if top < next {
+                writeln!(bsv, "    Bit#({}) reserved{};", next - top, top).unwrap();
+            }
+            writeln!(
+                bsv,
+                "    Bit#({}) {};",
+                field.width,
+                field.name.to_case(Case::Camel)
+            )
+            .unwrap();
+            next = field.lsb;
+        }
+        if next > 0 {
+            writeln!(bsv, "    Bit#({}) reserved0;", next).unwrap();
+        }
+        writeln!(
+            bsv,
+            "}} {} deriving (Bits, Eq, FShow);",
+            type_name(block, register)
+        )
+        .unwrap();
+    }
+
+    writeln!(bsv, "\ninterface {};", package).unwrap();
+    writeln!(
+        bsv,
+        "    method Action write({}Address address, {}Data data);",
+        prefix, prefix
+    )
+    .unwrap();
+    writeln!(
+        bsv,
+        "    method {}Data read({}Address address);",
+        prefix, prefix
+    )
+    .unwrap();
+    for register in &block.registers {
+        let name = register.name.to_case(Case::Camel);
+        let type_name = type_name(block, register);
+        writeln!(bsv, "    method {} {};", type_name, name).unwrap();
+        if register.mask(block.data_width, &[Access::Ro, Access::W1c]) != 0 {
+            writeln!(
+                bsv,
+                "    method Action set{}({} value);",
+                register.name.to_case(Case::Pascal),
+                type_name
+            )
+            .unwrap();
+        }
+    }
+    writeln!(bsv, "endinterface").unwrap();
+
+    writeln!(bsv, "\nmodule mk{}({});", package, package).unwrap();
+    for register in &block.registers {
+        writeln!(
+            bsv,
+            "    Reg#({}) {}Reg <- mkReg(unpack('h{:x}));",
+            type_name(block, register),
+            register.name.to_case(Case::Camel),
+            register.reset(block.data_width)
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        bsv,
+        "\n    method Action write({}Address address, {}Data data);",
+        prefix, prefix
+    )
+    .unwrap();
+    writeln!(bsv, "        case (address)").unwrap();
+    for register in &block.registers {
+        let name = format!("{}Reg", register.name.to_case(Case::Camel));
+        let write = register.mask(block.data_width, &[Access::Rw, Access::Wo]);
+        let clear = register.mask(block.data_width, &[Access::W1c]);
+        if write == 0 && clear == 0 {
+            continue;
+        }
+        writeln!(
+            bsv,
+            "            'h{:x}: {} <= unpack((pack({}) & ~'h{:x} & ~(data & 'h{:x})) | (data & 'h{:x}));",
+            register.offset, name, name, write, clear, write
+        )
+        .unwrap();
+    }
+    writeln!(bsv, "        endcase").unwrap();
+    writeln!(bsv, "    endmethod").unwrap();
+
+    writeln!(
+        bsv,
+        "\n    method {}Data read({}Address address);",
+        prefix, prefix
+    )
+    .unwrap();
+    writeln!(bsv, "        case (address)").unwrap();
+    for register in &block.registers {
+        let readable = register.mask(block.data_width, &[Access::Rw, Access::Ro, Access::W1c]);
+        writeln!(
+            bsv,
+            "            'h{:x}: return pack({}Reg) & 'h{:x};",
+            register.offset,
+            register.name.to_case(Case::Camel),
+            readable
+        )
+        .unwrap();
+    }
+    writeln!(bsv, "            default: return 0;").unwrap();
+    writeln!(bsv, "        endcase").unwrap();
+    writeln!(bsv, "    endmethod").unwrap();
+
+    for register in &block.registers {
+        let name = register.name.to_case(Case::Camel);
+        writeln!(bsv, "\n    method {} = {}Reg;", name, name).unwrap();
+        let hardware = register.mask(block.data_width, &[Access::Ro, Access::W1c]);
+        if hardware != 0 {
+            // Hardware updates only its fields; it conflicts with a bus write in the same cycle.
+            writeln!(
+                bsv,
+                "    method Action set{}({} value);",
+                register.name.to_case(Case::Pascal),
+                type_name(block, register)
+            )
+            .unwrap();
+            writeln!(
+                bsv,
+                "        {}Reg <= unpack((pack({}Reg) & ~'h{:x}) | (pack(value) & 'h{:x}));",
+                name, name, hardware, hardware
+            )
+            .unwrap();
+            writeln!(bsv, "    endmethod").unwrap();
+        }
+    }
+    writeln!(bsv, "endmodule").unwrap();
+
+    writeln!(bsv, "\nendpackage").unwrap();
+    bsv
+}
+
+/// Generates a C header of register offsets and field shifts and masks.
+pub fn generate_c_header(block: &RegisterBlock, guard: &str) -> String {
+    let prefix = block.name.to_case(Case::UpperSnake);
+    let mut header = String::new();
+    writeln!(header, "// Generated by dolly gen regs -- do not edit.").unwrap();
+    writeln!(header, "#ifndef {}", guard).unwrap();
+    writeln!(header, "#define {}", guard).unwrap();
+    for register in &block.registers {
+        let name = format!("{}_{}", prefix, register.name.to_case(Case::UpperSnake));
+        writeln!(header).unwrap();
+        writeln!(header, "#define {}_OFFSET 0x{:x}u", name, register.offset).unwrap();
+        for field in &register.fields {
+            let field_name = format!("{}_{}", name, field.name.to_case(Case::UpperSnake));
+            writeln!(header, "#define {}_SHIFT {}", field_name, field.lsb).unwrap();
+            writeln!(header, "#define {}_MASK 0x{:x}u", field_name, field.mask()).unwrap();
+        }
+    }
+    writeln!(header, "\n#endif").unwrap();
+    header
+}
+
+/// Generates Markdown documentation of the register block.
+pub fn generate_markdown(block: &RegisterBlock) -> String {
+    let mut markdown = String::new();
+    writeln!(markdown, "# {} registers", block.name).unwrap();
+    for register in &block.registers {
+        writeln!(
+            markdown,
+            "\n## {} (offset {:#x})\n",
+            register.name, register.offset
+        )
+        .unwrap();
+        if !register.description.is_empty() {
+            writeln!(markdown, "{}\n", register.description).unwrap();
+        }
+        writeln!(markdown, "| Bits | Field | Access | Reset | Description |").unwrap();
+        writeln!(markdown, "|------|-------|--------|-------|-------------|").unwrap();
+        let mut fields = register.fields(block.data_width);
+        fields.sort_by_key(|field| std::cmp::Reverse(field.lsb));
+        for field in fields {
+            let bits = if field.width == 1 {
+                field.lsb.to_string()
+            } else {
+                format!("{}:{}", field.lsb + field.width - 1, field.lsb)
+            };
+            writeln!(
+                markdown,
+                "| {} | {} | {} | {:#x} | {} |",
+                bits,
+                field.name,
+                field.access.unwrap(),
+                field.reset,
+                field.description
+            )
+            .unwrap();
+        }
+    }
+    markdown
+}
+
+fn write(path: &path::Path, contents: String) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    println!("Wrote {}", path.to_string_lossy());
+    Ok(())
+}
+
+/// Reads a register description and writes the BSV package, plus the optional C header and
+/// Markdown.  The BSV package defaults to `<Name>Regs.bsv` next to the description.
+pub fn generate(
+    description: &path::Path,
+    output: Option<&path::Path>,
+    c_header: Option<&path::Path>,
+    markdown: Option<&path::Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let block: RegisterBlock = toml::from_str(&fs::read_to_string(description)?)?;
+    validate(&block)?;
+
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => description.with_file_name(format!("{}Regs.bsv", block.name.to_case(Case::Pascal))),
+    };
+    // The package is named after the file, as bsc requires.
+    let package = output.file_stem().unwrap().to_string_lossy().to_string();
+    write(&output, generate_bsv(&block, &package))?;
+
+    if let Some(c_header) = c_header {
+        let guard = format!(
+            "{}_H",
+            c_header
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_case(Case::UpperSnake)
+        );
+        write(c_header, generate_c_header(&block, &guard))?;
+    }
+    if let Some(markdown) = markdown {
+        write(markdown, generate_markdown(&block))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_block_outputs() {
+        let block: RegisterBlock = toml::from_str(
+            r#"
+            name = "uart"
+            data-width = 32
+            address-width = 8
+
+            [[registers]]
+            name = "ctrl"
+            offset = 0x0
+            fields = [
+                { name = "enable", lsb = 0, reset = 1 },
+                { name = "baud-div", lsb = 4, width = 12, reset = 0x10 },
+            ]
+
+            [[registers]]
+            name = "status"
+            offset = 0x4
+            access = "ro"
+            fields = [
+                { name = "rx-ready", lsb = 0 },
+                { name = "overrun", lsb = 1, access = "w1c" },
+            ]
+            "#,
+        )
+        .unwrap();
+        validate(&block).unwrap();
+
+        let bsv = generate_bsv(&block, "UartRegs");
+        assert!(bsv.contains(
+            "typedef struct {\n    Bit#(16) reserved16;\n    Bit#(12) baudDiv;\n    Bit#(3) reserved1;\n    Bit#(1) enable;\n} UartCtrl deriving (Bits, Eq, FShow);\n"
+        ));
+        assert!(bsv.contains("    Reg#(UartCtrl) ctrlReg <- mkReg(unpack('h101));\n"));
+        assert!(bsv.contains(
+            "            'h4: statusReg <= unpack((pack(statusReg) & ~'h0 & ~(data & 'h2)) | (data & 'h0));\n"
+        ));
+        assert!(bsv.contains("    method Action setStatus(UartStatus value);\n"));
+        assert!(!bsv.contains("setCtrl"));
+
+        let header = generate_c_header(&block, "UART_REGS_H");
+        assert!(header.contains("#define UART_CTRL_BAUD_DIV_SHIFT 4\n"));
+        assert!(header.contains("#define UART_CTRL_BAUD_DIV_MASK 0xfff0u\n"));
+        assert!(generate_markdown(&block).contains("| 15:4 | baud-div | rw | 0x10 |  |\n"));
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let block = |fields: &str| -> RegisterBlock {
+            toml::from_str(&format!(
+                "name = \"uart\"\n[[registers]]\nname = \"ctrl\"\noffset = 0\nfields = [{}]\n",
+                fields
+            ))
+            .unwrap()
+        };
+        let error = |fields: &str| validate(&block(fields)).unwrap_err().to_string();
+        assert_eq!(
+            error("{ name = \"top\", lsb = 4294967295, width = 2 }"),
+            "Field 'ctrl.top' does not fit in 32 bits"
+        );
+        assert_eq!(
+            error("{ name = \"a\", lsb = 0, width = 4 }, { name = \"b\", lsb = 3 }"),
+            "Field 'ctrl.b' overlaps another field"
+        );
+    }
+}
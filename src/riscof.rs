@@ -0,0 +1,491 @@
+use super::bsv;
+use super::builder::Builder;
+use super::codes;
+use super::config;
+use super::project::{Project, RiscofConfig};
+use colored::Colorize;
+use convert_case::{Case, Casing};
+use regex::Regex;
+use std::{fmt::Write, fs, path, process};
+use tracing::{error, trace};
+
+/// Returns the XLEN named by an ISA string such as RV32IMC.
+fn xlen(isa: &str) -> u32 {
+    if isa.to_uppercase().starts_with("RV64") {
+        64
+    } else {
+        32
+    }
+}
+
+/// Computes the misa reset value for an ISA string: its MXL plus one bit per single-letter
+/// extension.
+pub fn misa(isa: &str) -> std::io::Result<u64> {
+    let upper = isa.to_uppercase();
+    let Some(letters) = upper
+        .get(4..)
+        .filter(|_| upper.starts_with("RV32") || upper.starts_with("RV64"))
+    else {
+        return Err(codes::error(
+            "D0007",
+            format!(
+                "[riscof] isa = \"{}\" isn't an ISA string such as RV32IMC_Zicsr",
+                isa
+            ),
+        ));
+    };
+    let extensions = letters
+        .chars()
+        .take_while(|c| c.is_ascii_uppercase() && *c != 'Z' && *c != 'X')
+        .fold(0u64, |bits, c| bits | 1 << (c as u8 - b'A'));
+    let mxl = if xlen(&upper) == 64 {
+        2u64 << 62
+    } else {
+        1u64 << 30
+    };
+    Ok(mxl | extensions)
+}
+
+/// Generates the riscv-config ISA YAML for the core.
+pub fn isa_yaml(config: &RiscofConfig) -> std::io::Result<String> {
+    let misa = misa(&config.isa)?;
+    let xlen = xlen(&config.isa);
+    let mxl = if xlen == 64 { 2 } else { 1 };
+    let mut yaml = String::new();
+    writeln!(yaml, "hart_ids: [0]").unwrap();
+    writeln!(yaml, "hart0:").unwrap();
+    writeln!(yaml, "  ISA: {}", config.isa).unwrap();
+    writeln!(yaml, "  physical_addr_sz: {}", xlen).unwrap();
+    writeln!(yaml, "  User_Spec_Version: '{}'", config.user_spec).unwrap();
+    if let Some(privilege_spec) = &config.privilege_spec {
+        writeln!(yaml, "  Privilege_Spec_Version: '{}'", privilege_spec).unwrap();
+    }
+    writeln!(yaml, "  supported_xlen: [{}]", xlen).unwrap();
+    write!(
+        yaml,
+        r#"  misa:
+    reset-val: {:#x}
+    rv{}:
+      accessible: true
+      mxl:
+        implemented: true
+        type:
+          warl:
+            dependency_fields: []
+            legal:
+              - mxl[1:0] in [{:#x}]
+            wr_illegal:
+              - Unchanged
+      extensions:
+        implemented: true
+        type:
+          warl:
+            dependency_fields: []
+            legal:
+              - extensions[25:0] bitmask [{:#09x}, 0x0000000]
+            wr_illegal:
+              - Unchanged
+"#,
+        misa,
+        xlen,
+        mxl,
+        misa & 0x3ff_ffff
+    )
+    .unwrap();
+    Ok(yaml)
+}
+
+/// The riscv-config platform YAML; the architectural tests only need the reset and NMI labels.
+const PLATFORM_YAML: &str = r#"nmi:
+  label: nmi_vector
+reset:
+  label: reset_vector
+"#;
+
+/// Streams the signature to the testbench (which prints each word as "signature: <hex>"), then
+/// signals completion through tohost.
+const MODEL_TEST_H: &str = r#"// Generated by dolly riscof; customize as needed.
+#ifndef _COMPLIANCE_MODEL_H
+#define _COMPLIANCE_MODEL_H
+
+#define RVMODEL_DATA_SECTION                                            \
+  .pushsection .tohost,"aw",@progbits;                                  \
+  .align 8; .global tohost; tohost: .dword 0;                           \
+  .align 8; .global fromhost; fromhost: .dword 0;                       \
+  .popsection;
+
+#define RVMODEL_HALT                                                    \
+  la t0, begin_signature;                                               \
+  la t1, end_signature;                                                 \
+  li t2, SIGNATURE_ADDRESS;                                             \
+1:                                                                      \
+  bgeu t0, t1, 2f;                                                      \
+  lw t3, 0(t0);                                                         \
+  sw t3, 0(t2);                                                         \
+  addi t0, t0, 4;                                                       \
+  j 1b;                                                                 \
+2:                                                                      \
+  li t0, 1;                                                             \
+  la t1, tohost;                                                        \
+  sw t0, 0(t1);                                                         \
+3:                                                                      \
+  j 3b;
+
+#define RVMODEL_BOOT
+
+#define RVMODEL_DATA_BEGIN                                              \
+  RVMODEL_DATA_SECTION                                                  \
+  .align 4; .global begin_signature; begin_signature:
+
+#define RVMODEL_DATA_END                                                \
+  .align 4; .global end_signature; end_signature:
+
+#define RVMODEL_IO_INIT
+#define RVMODEL_IO_WRITE_STR(_R, _STR)
+#define RVMODEL_IO_CHECK()
+#define RVMODEL_IO_ASSERT_GPR_EQ(_S, _R, _I)
+#define RVMODEL_IO_ASSERT_SFPR_EQ(_F, _R, _I)
+#define RVMODEL_IO_ASSERT_DFPR_EQ(_D, _R, _I)
+#define RVMODEL_SET_MSW_INT
+#define RVMODEL_CLEAR_MSW_INT
+#define RVMODEL_CLEAR_MTIMER_INT
+#define RVMODEL_CLEAR_MEXT_INT
+
+#endif
+"#;
+
+fn link_ld(base_address: u64) -> String {
+    format!(
+        r#"OUTPUT_ARCH( "riscv" )
+ENTRY(rvtest_entry_point)
+
+SECTIONS
+{{
+  . = {:#x};
+  .text.init : {{ *(.text.init) }}
+  . = ALIGN(0x1000);
+  .tohost : {{ *(.tohost) }}
+  . = ALIGN(0x1000);
+  .text : {{ *(.text) }}
+  . = ALIGN(0x1000);
+  .data : {{ *(.data) }}
+  .data.string : {{ *(.data.string) }}
+  .bss : {{ *(.bss) }}
+  _end = .;
+}}
+"#,
+        base_address
+    )
+}
+
+/// The DUT plugin: compiles each test, converts it to the testbench's memory image, runs the
+/// simulation and collects the signature it prints.
+fn plugin(model: &str) -> String {
+    format!(
+        r#"# Generated by dolly riscof; customize as needed.
+import os
+import subprocess
+
+import riscof.utils as utils
+from riscof.pluginTemplate import pluginTemplate
+
+
+def write_hex(binary, image):
+    with open(binary, "rb") as f:
+        data = f.read()
+    data += bytes(-len(data) % 4)
+    with open(image, "w") as f:
+        for offset in range(0, len(data), 4):
+            f.write("{{:08x}}\n".format(int.from_bytes(data[offset:offset + 4], "little")))
+
+
+class {model}(pluginTemplate):
+    __model__ = "{model}"
+    __version__ = "0.1.0"
+
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+        config = kwargs.get("config")
+        self.pluginpath = os.path.abspath(config["pluginpath"])
+        self.simulator = config["simulator"]
+        self.prefix = config["riscv_prefix"]
+        self.image = config["image"]
+        self.signature_address = config["signature_address"]
+        self.target_run = config.get("target_run", "1") != "0"
+
+    def initialise(self, suite, work_dir, archtest_env):
+        self.work_dir = work_dir
+        env = os.path.join(self.pluginpath, "env")
+        self.compile_cmd = (
+            self.prefix + "gcc -static -mcmodel=medany -fvisibility=hidden -nostdlib -nostartfiles"
+            " -T " + os.path.join(env, "link.ld") + " -I " + env + " -I " + archtest_env
+            + " -DSIGNATURE_ADDRESS=" + self.signature_address
+        )
+
+    def build(self, isa_yaml, platform_yaml):
+        ispec = utils.load_yaml(isa_yaml)["hart0"]
+        self.mabi = "lp64" if 64 in ispec["supported_xlen"] else "ilp32"
+
+    def runTests(self, testList):
+        for testname in testList:
+            testentry = testList[testname]
+            test_dir = testentry["work_dir"]
+            elf = os.path.join(test_dir, "dut.elf")
+            binary = os.path.join(test_dir, "dut.bin")
+            sig_file = os.path.join(test_dir, self.name[:-1] + ".signature")
+            macros = " ".join("-D" + macro for macro in testentry["macros"])
+
+            utils.shellCommand("{{}} -march={{}} -mabi={{}} {{}} {{}} -o {{}}".format(
+                self.compile_cmd, testentry["isa"].lower(), self.mabi, macros,
+                testentry["test_path"], elf)).run(cwd=test_dir)
+            if not self.target_run:
+                continue
+
+            utils.shellCommand("{{}}objcopy -O binary {{}} {{}}".format(
+                self.prefix, elf, binary)).run(cwd=test_dir)
+            write_hex(binary, os.path.join(test_dir, self.image))
+
+            result = subprocess.run([self.simulator], cwd=test_dir, capture_output=True, text=True)
+            with open(sig_file, "w") as signature:
+                for line in result.stdout.splitlines():
+                    if line.startswith("signature: "):
+                        signature.write(line[len("signature: "):].strip() + "\n")
+
+        if not self.target_run:
+            raise SystemExit(0)
+"#
+    )
+}
+
+/// Writes the plugin scaffolding unless it already exists, so it can be customized.
+fn scaffold_plugin(
+    plugin_dir: &path::Path,
+    model: &str,
+    config: &RiscofConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if plugin_dir.exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(plugin_dir.join("env"))?;
+    fs::write(
+        plugin_dir.join(format!("riscof_{}.py", model)),
+        plugin(model),
+    )?;
+    fs::write(plugin_dir.join("env").join("model_test.h"), MODEL_TEST_H)?;
+    fs::write(
+        plugin_dir.join("env").join("link.ld"),
+        link_ld(config.base_address),
+    )?;
+    fs::write(plugin_dir.join("__init__.py"), "")?;
+    println!(
+        "Created RISCOF plugin scaffolding in {}",
+        plugin_dir.to_string_lossy()
+    );
+    Ok(())
+}
+
+fn config_ini(
+    model: &str,
+    plugin_dir: &path::Path,
+    reference_dir: &path::Path,
+    work_dir: &path::Path,
+    simulator: &path::Path,
+    riscv_prefix: &str,
+    config: &RiscofConfig,
+) -> String {
+    let reference = reference_dir.file_name().unwrap().to_string_lossy();
+    let isa_yaml = work_dir.join(format!("{}_isa.yaml", model));
+    let platform_yaml = work_dir.join(format!("{}_platform.yaml", model));
+
+    let mut ini = String::new();
+    writeln!(ini, "[RISCOF]").unwrap();
+    writeln!(ini, "ReferencePlugin={}", reference).unwrap();
+    writeln!(
+        ini,
+        "ReferencePluginPath={}",
+        reference_dir.to_string_lossy()
+    )
+    .unwrap();
+    writeln!(ini, "DUTPlugin={}", model).unwrap();
+    writeln!(ini, "DUTPluginPath={}", plugin_dir.to_string_lossy()).unwrap();
+
+    writeln!(ini, "\n[{}]", model).unwrap();
+    writeln!(ini, "pluginpath={}", plugin_dir.to_string_lossy()).unwrap();
+    writeln!(ini, "ispec={}", isa_yaml.to_string_lossy()).unwrap();
+    writeln!(ini, "pspec={}", platform_yaml.to_string_lossy()).unwrap();
+    writeln!(ini, "target_run=1").unwrap();
+    writeln!(ini, "simulator={}", simulator.to_string_lossy()).unwrap();
+    writeln!(ini, "riscv_prefix={}", riscv_prefix).unwrap();
+    writeln!(ini, "image={}", config.image).unwrap();
+    writeln!(ini, "signature_address={:#x}", config.signature_address).unwrap();
+
+    writeln!(ini, "\n[{}]", reference).unwrap();
+    writeln!(ini, "pluginpath={}", reference_dir.to_string_lossy()).unwrap();
+    writeln!(ini, "ispec={}", isa_yaml.to_string_lossy()).unwrap();
+    writeln!(ini, "pspec={}", platform_yaml.to_string_lossy()).unwrap();
+
+    ini
+}
+
+/// Extracts the (test, passed) results from RISCOF's report.
+pub fn parse_results(output: &str) -> Vec<(String, bool)> {
+    let result_re = Regex::new(r"(\S+\.S)\s*:\s*(?:\S+\s*:\s*)?(Passed|Failed)\b").unwrap();
+    output
+        .lines()
+        .filter_map(|line| result_re.captures(line))
+        .map(|capture| (capture[1].to_string(), &capture[2] == "Passed"))
+        .collect()
+}
+
+/// Runs the architectural test suite on the core through RISCOF and reports signature
+/// mismatches as test failures.
+pub fn run(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let config = &project.riscof;
+    let Some(top) = &config.top else {
+        return Err(Box::new(std::io::Error::other(
+            "No testbench configured: set 'top' in the [riscof] section of dolly.toml",
+        )));
+    };
+
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let tests_dir = project.root_path().join("tests");
+    let Some(location) = bsv::find_module(
+        builder.module_paths().chain(std::iter::once(&tests_dir)),
+        top,
+    ) else {
+        error!("RISCOF testbench {} not found", top);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+
+    let work_dir = project.output_dir("riscof");
+    fs::create_dir_all(&work_dir)?;
+    let simulator = Builder::build_executable(
+        project,
+        &builder,
+        &work_dir,
+        &location.path,
+        &location.module.name,
+    )?;
+
+    let model = project.package.name.to_case(Case::Snake);
+    let plugin_dir = project.root_path().join("riscof").join(&model);
+    scaffold_plugin(&plugin_dir, &model, config)?;
+
+    let reference_dir = project
+        .root_path()
+        .join(&config.reference_plugin)
+        .canonicalize()?;
+    fs::write(
+        work_dir.join(format!("{}_isa.yaml", model)),
+        isa_yaml(config)?,
+    )?;
+    fs::write(
+        work_dir.join(format!("{}_platform.yaml", model)),
+        PLATFORM_YAML,
+    )?;
+    let config_file = work_dir.join("config.ini");
+    fs::write(
+        &config_file,
+        config_ini(
+            &model,
+            &plugin_dir,
+            &reference_dir,
+            &work_dir,
+            &simulator,
+            &project.toolchain.riscv.prefix,
+            config,
+        ),
+    )?;
+
+    let suite = project
+        .root_path()
+        .join(&config.arch_test)
+        .join("riscv-test-suite");
+    trace!("Running RISCOF with {:?}", config_file);
+    let output = process::Command::new(config::tool("riscof"))
+        .arg("run")
+        .arg(format!("--config={}", config_file.to_string_lossy()))
+        .arg(format!("--suite={}", suite.to_string_lossy()))
+        .arg(format!("--env={}", suite.join("env").to_string_lossy()))
+        .arg("--no-browser")
+        .current_dir(&work_dir)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Box::new(codes::error(
+                "D0102",
+                "Unable to locate 'riscof' program.",
+            )))
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    // RISCOF logs to stderr.
+    let log = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let results = parse_results(&log);
+    if results.is_empty() {
+        println!("{}", log);
+        return Err(Box::new(std::io::Error::other("RISCOF ran no tests")));
+    }
+
+    let mut failed = 0;
+    for (test, passed) in &results {
+        if *passed {
+            println!("Test: {} -- {}.", test, "PASSED".green());
+        } else {
+            failed += 1;
+            println!(
+                "Test: {} -- {} (signature mismatch).",
+                test,
+                "FAILED".red().bold()
+            );
+        }
+    }
+    println!(
+        "RISCOF: {} passed, {} failed (report: {})",
+        results.len() - failed,
+        failed,
+        work_dir
+            .join("riscof_work")
+            .join("report.html")
+            .to_string_lossy()
+    );
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(Box::new(std::io::Error::other(
+            "Not all architectural tests passed",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn misa_and_results() {
+        assert_eq!(misa("RV32IMC_Zicsr").unwrap(), 0x4000_1104);
+        assert_eq!(misa("rv64i").unwrap(), 0x8000_0000_0000_0100);
+        assert!(misa("rv3").unwrap_err().to_string().starts_with("D0007: "));
+        assert!(misa("i386").is_err());
+
+        let log = "    INFO | TEST NAME : COMMIT ID : STATUS\n    INFO | /suite/rv32i_m/I/src/add-01.S : 2e7fa32 : Passed\n    INFO | /suite/rv32i_m/I/src/sub-01.S : 2e7fa32 : Failed\n";
+        assert_eq!(
+            parse_results(log),
+            vec![
+                ("/suite/rv32i_m/I/src/add-01.S".to_string(), true),
+                ("/suite/rv32i_m/I/src/sub-01.S".to_string(), false)
+            ]
+        );
+    }
+}
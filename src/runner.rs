@@ -0,0 +1,308 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path, process,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Mutex,
+    thread, time,
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Installs a Ctrl-C (SIGINT/SIGTERM) handler so that the running child is killed and its
+/// partial outputs removed (see `run`), and no further stage started (see
+/// `check_interrupted`), instead of dolly exiting and leaving it orphaned.  dolly takes no
+/// lock on the target directory, so there is none to release.
+pub fn handle_interrupts() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Interrupts dolly as Ctrl-C does, for a terminal in raw mode, which doesn't send SIGINT.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether dolly has been interrupted, for the loops that don't run commands.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+fn interrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Interrupted")
+}
+
+/// Fails with an `Interrupted` error if dolly has been interrupted, for the stages between
+/// commands.
+pub fn check_interrupted() -> io::Result<()> {
+    match is_interrupted() {
+        true => Err(interrupted()),
+        false => Ok(()),
+    }
+}
+
+/// Removes the files in `dir` written since `start`, other than `keep`.
+fn remove_partial_outputs(dir: &path::Path, start: time::SystemTime, keep: &path::Path) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let modified = path.metadata().and_then(|metadata| metadata.modified());
+        if path != keep && path.is_file() && modified.is_ok_and(|modified| modified >= start) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Quotes an argument for a POSIX shell, when it needs it.
+pub fn quote(arg: &std::ffi::OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:%@".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// The command line of a command, quoted so that it can be pasted into a shell.
+pub fn command_line(cmd: &process::Command) -> String {
+    let mut words = Vec::<String>::new();
+    if let Some(dir) = cmd
+        .get_current_dir()
+        .filter(|dir| *dir != path::Path::new("."))
+    {
+        words.push(format!("cd {} &&", quote(dir.as_os_str())));
+    }
+    for (name, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            words.push(format!("{}={}", name.to_string_lossy(), quote(value)));
+        }
+    }
+    words.push(quote(cmd.get_program()));
+    words.extend(cmd.get_args().map(quote));
+    words.join(" ")
+}
+
+/// Runs a command, streaming each line of its stdout and stderr prefixed with `[name]` while
+/// capturing both (in order) to `log_file`.  Returns the output as `Command::output` would.
+///
+/// If dolly is interrupted, the command is killed, the files it wrote next to the log are
+/// removed and an `Interrupted` error is returned.
+pub fn run(
+    cmd: &mut process::Command,
+    name: &str,
+    log_file: &path::Path,
+) -> io::Result<process::Output> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(interrupted());
+    }
+    // Created first, so that a child is never left running when the log can't be written.  A
+    // failure isn't reported as NotFound, which callers take to mean the program is missing.
+    let log = fs::File::create(log_file).map_err(|e| {
+        io::Error::other(format!(
+            "Unable to create {}: {}",
+            log_file.to_string_lossy(),
+            e
+        ))
+    })?;
+    // Files are dated by the file system's clock, which may lag the system time: the files
+    // the command writes are those as new as its log.
+    let start = log
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| time::SystemTime::now());
+    let log = Mutex::new(io::BufWriter::new(log));
+    // The command gets its own process group so that everything it starts (such as the
+    // simulator run by a shell, or the C++ compiler run by bsc) can be killed together.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+    let mut child = cmd
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let prefix = format!("[{}]", name);
+
+    let (status, stdout, stderr) = thread::scope(|scope| {
+        let stdout = scope.spawn(|| stream(stdout, &prefix, &log, false));
+        let stderr = scope.spawn(|| stream(stderr, &prefix, &log, true));
+        let status = loop {
+            // The child is killed if it can't be waited for, or its pipes would never close.
+            let error = match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if INTERRUPTED.load(Ordering::SeqCst) => interrupted(),
+                Ok(None) => {
+                    thread::sleep(time::Duration::from_millis(10));
+                    continue;
+                }
+                Err(e) => e,
+            };
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            break Err(error);
+        };
+        (status, stdout.join().unwrap(), stderr.join().unwrap())
+    });
+    log.into_inner().unwrap().flush()?;
+
+    let status = match status {
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+            if let Some(dir) = log_file.parent() {
+                remove_partial_outputs(dir, start, log_file);
+            }
+            return Err(e);
+        }
+        status => status?,
+    };
+    Ok(process::Output {
+        status,
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
+fn stream(
+    pipe: impl io::Read,
+    prefix: &str,
+    log: &Mutex<impl Write>,
+    is_stderr: bool,
+) -> io::Result<Vec<u8>> {
+    let mut captured = Vec::<u8>::new();
+    let mut reader = io::BufReader::new(pipe);
+    let mut line = Vec::<u8>::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        if is_stderr {
+            eprintln!("{} {}", prefix, text);
+        } else {
+            println!("{} {}", prefix, text);
+        }
+        log.lock().unwrap().write_all(&line)?;
+        captured.append(&mut line);
+    }
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn captured_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_file = temp.path().join("run.log");
+        let output = run(
+            process::Command::new("sh")
+                .arg("-c")
+                .arg("echo one; echo two >&2; echo three; exit 3"),
+            "test",
+            &log_file,
+        )
+        .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"one\nthree\n");
+        assert_eq!(output.stderr, b"two\n");
+        let log = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(log.lines().count(), 3);
+        assert!(log.contains("two\n"));
+
+        // Nothing is run without a log.
+        let missing = temp.path().join("missing").join("run.log");
+        let ran = temp.path().join("ran");
+        let mut touch = process::Command::new("touch");
+        let error = run(touch.arg(&ran), "test", &missing).unwrap_err();
+        assert_ne!(error.kind(), io::ErrorKind::NotFound);
+        assert!(!ran.exists());
+        let mut missing_program = process::Command::new("dolly-no-such-program");
+        let error = run(&mut missing_program, "test", &log_file).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    /// Interrupts a sleeping child.  The interrupt is sent to a copy of the test process, run
+    /// with the directory to work in, so that it doesn't reach the other tests.
+    #[cfg(unix)]
+    #[test]
+    fn interrupted_child() {
+        const DIR_VARIABLE: &str = "DOLLY_TEST_INTERRUPT_DIR";
+        if let Some(dir) = std::env::var_os(DIR_VARIABLE) {
+            let dir = path::Path::new(&dir);
+            handle_interrupts();
+            let mut cmd = process::Command::new("sh");
+            cmd.arg("-c")
+                .arg("touch partial.vcd && echo $$ > ../pid && exec sleep 30")
+                .current_dir(dir.join("build"));
+            let error = run(&mut cmd, "test", &dir.join("build").join("run.log")).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+            return;
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let build = temp.path().join("build");
+        fs::create_dir(&build).unwrap();
+        let earlier = fs::File::create(build.join("earlier.vcd")).unwrap();
+        let hour_ago = time::SystemTime::now() - time::Duration::from_secs(3600);
+        earlier.set_modified(hour_ago).unwrap();
+        let mut test = process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "runner::test::interrupted_child"])
+            .env(DIR_VARIABLE, temp.path())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid_file = temp.path().join("pid");
+        let start = time::Instant::now();
+        let pid = loop {
+            let pid = fs::read_to_string(&pid_file).unwrap_or_default();
+            if pid.ends_with('\n') {
+                break pid.trim().parse::<libc::pid_t>().unwrap();
+            }
+            assert!(start.elapsed() < time::Duration::from_secs(10));
+            thread::sleep(time::Duration::from_millis(10));
+        };
+        unsafe {
+            libc::kill(test.id() as libc::pid_t, libc::SIGINT);
+        }
+        assert!(test.wait().unwrap().success());
+        assert!(start.elapsed() < time::Duration::from_secs(10));
+
+        // The sleep is gone, and so is what it wrote, but not the log or older files.
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+        assert!(!build.join("partial.vcd").exists());
+        assert!(build.join("run.log").exists());
+        assert!(build.join("earlier.vcd").exists());
+    }
+
+    #[test]
+    fn quoted_command_line() {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(r#""$0" "$@""#)
+            .arg("target/My Test/mkTest")
+            .arg("-p")
+            .arg("%/Libraries:src")
+            .current_dir("target/firmware")
+            .env("LM_LICENSE_FILE", "27000@license");
+        assert_eq!(
+            command_line(&cmd),
+            r#"cd target/firmware && LM_LICENSE_FILE=27000@license sh -c '"$0" "$@"' 'target/My Test/mkTest' -p %/Libraries:src"#
+        );
+        assert_eq!(quote("it's".as_ref()), r"'it'\''s'");
+    }
+}
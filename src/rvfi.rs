@@ -0,0 +1,272 @@
+use super::bsv;
+use super::builder::Builder;
+use super::jobserver;
+use super::project::{Project, RvfiConfig};
+use super::verilog;
+use colored::Colorize;
+use regex::Regex;
+use std::{collections::BTreeMap, fmt::Write, fs, path, process};
+use tracing::{error, trace};
+
+/// Default check depths, from the riscv-formal example cores.
+const DEFAULT_DEPTHS: [(&str, &str); 8] = [
+    ("insn", "20"),
+    ("reg", "15 25"),
+    ("pc_fwd", "10 30"),
+    ("pc_bwd", "10 30"),
+    ("liveness", "1 10 30"),
+    ("unique", "1 10 30"),
+    ("causal", "10 30"),
+    ("cover", "1 15"),
+];
+
+/// Returns the input ports of a bsc-generated Verilog module as (name, width) pairs.
+pub fn input_ports(verilog: &str) -> Vec<(String, usize)> {
+    let input_re = Regex::new(r"(?m)^\s*input\s+(?:\[\s*(\d+)\s*:\s*0\s*\]\s*)?(\w+)\s*;").unwrap();
+    input_re
+        .captures_iter(verilog)
+        .map(|capture| {
+            let width = capture
+                .get(1)
+                .map_or(1, |msb| msb.as_str().parse::<usize>().unwrap_or(0) + 1);
+            (capture[2].to_string(), width)
+        })
+        .collect()
+}
+
+/// Generates the riscv-formal wrapper instantiating `core`, driving its inputs (other than
+/// clock and reset) with unconstrained values.
+pub fn generate_wrapper(config: &RvfiConfig, inputs: &[(String, usize)]) -> String {
+    let mut wrapper = String::new();
+    writeln!(wrapper, "// Generated by dolly prove -- do not edit.").unwrap();
+    writeln!(wrapper, "module rvfi_wrapper (").unwrap();
+    writeln!(wrapper, "\tinput clock,").unwrap();
+    writeln!(wrapper, "\tinput reset,").unwrap();
+    writeln!(wrapper, "\t`RVFI_OUTPUTS").unwrap();
+    writeln!(wrapper, ");").unwrap();
+
+    let free_inputs: Vec<&(String, usize)> = inputs
+        .iter()
+        .filter(|(name, _)| name != "CLK" && name != "RST_N")
+        .collect();
+    for (name, width) in &free_inputs {
+        writeln!(
+            wrapper,
+            "\t(* keep *) `rvformal_rand_reg [{}:0] {};",
+            width - 1,
+            name
+        )
+        .unwrap();
+    }
+
+    writeln!(wrapper, "\n\t{} uut (", config.core).unwrap();
+    writeln!(wrapper, "\t\t.CLK(clock),").unwrap();
+    writeln!(wrapper, "\t\t.RST_N(!reset),").unwrap();
+    for (name, _) in &free_inputs {
+        writeln!(wrapper, "\t\t.{}({}),", name, name).unwrap();
+    }
+    if config.port_prefix == "rvfi_" {
+        writeln!(wrapper, "\t\t`RVFI_CONN").unwrap();
+    } else {
+        let connections: Vec<String> = RVFI_SIGNALS
+            .iter()
+            .map(|signal| format!("\t\t.{}{}(rvfi_{})", config.port_prefix, signal, signal))
+            .collect();
+        writeln!(wrapper, "{}", connections.join(",\n")).unwrap();
+    }
+    writeln!(wrapper, "\t);").unwrap();
+    writeln!(wrapper, "endmodule").unwrap();
+
+    wrapper
+}
+
+/// The RVFI signals (without their rvfi_ prefix) connected when the core uses another prefix.
+const RVFI_SIGNALS: [&str; 21] = [
+    "valid",
+    "order",
+    "insn",
+    "trap",
+    "halt",
+    "intr",
+    "mode",
+    "ixl",
+    "rs1_addr",
+    "rs2_addr",
+    "rs1_rdata",
+    "rs2_rdata",
+    "rd_addr",
+    "rd_wdata",
+    "pc_rdata",
+    "pc_wdata",
+    "mem_addr",
+    "mem_rmask",
+    "mem_wmask",
+    "mem_rdata",
+    "mem_wdata",
+];
+
+/// Generates the riscv-formal checks.cfg for the core.
+pub fn generate_checks_cfg(config: &RvfiConfig, verilog_files: &[path::PathBuf]) -> String {
+    let mut depths: BTreeMap<String, String> = DEFAULT_DEPTHS
+        .iter()
+        .map(|(check, depth)| (check.to_string(), depth.to_string()))
+        .collect();
+    depths.extend(config.depth.clone());
+
+    let mut cfg = String::new();
+    writeln!(cfg, "[options]").unwrap();
+    writeln!(cfg, "isa {}", config.isa).unwrap();
+    writeln!(cfg, "nret {}", config.nret).unwrap();
+
+    writeln!(cfg, "\n[depth]").unwrap();
+    for (check, depth) in &depths {
+        writeln!(cfg, "{:15} {}", check, depth).unwrap();
+    }
+
+    writeln!(cfg, "\n[verilog-files]").unwrap();
+    writeln!(cfg, "@basedir@/cores/@core@/wrapper.sv").unwrap();
+    for file in verilog_files {
+        writeln!(cfg, "{}", file.to_string_lossy()).unwrap();
+    }
+
+    cfg
+}
+
+#[cfg(unix)]
+fn link_dir(original: &path::Path, link: &path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn link_dir(original: &path::Path, link: &path::Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+/// Generates and runs the riscv-formal checks for the core configured in `[formal.rvfi]`.
+pub fn prove(
+    project: &Project,
+    builder: &Builder,
+    config: &RvfiConfig,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let riscv_formal = project
+        .root_path()
+        .join(&config.riscv_formal)
+        .canonicalize()?;
+
+    let Some(location) = bsv::find_module(builder.module_paths(), &config.core) else {
+        error!("RVFI core {} not found", config.core);
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    };
+
+    // Mirror the riscv-formal tree so genchecks.py finds its checks and instructions relative
+    // to the generated core directory.
+    let rvfi_root = project.output_dir("formal").join("rvfi");
+    let _ = fs::remove_dir_all(&rvfi_root);
+    fs::create_dir_all(&rvfi_root)?;
+    for entry in riscv_formal.read_dir()? {
+        let entry = entry?;
+        if entry.file_name() != "cores" && entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &rvfi_root.join(entry.file_name()))?;
+        }
+    }
+
+    let core_dir = rvfi_root.join("cores").join(&project.package.name);
+    builder.generate_verilog(
+        project,
+        &[],
+        &location.path,
+        &location.module.name,
+        &core_dir,
+    )?;
+
+    let core_verilog = core_dir.join(format!("{}.v", location.module.name));
+    let mut verilog_files = vec![core_verilog.clone()];
+    verilog_files.extend(verilog::primitives(&verilog_files)?);
+
+    let inputs = input_ports(&fs::read_to_string(&core_verilog)?);
+    fs::write(
+        core_dir.join("wrapper.sv"),
+        generate_wrapper(config, &inputs),
+    )?;
+    fs::write(
+        core_dir.join("checks.cfg"),
+        generate_checks_cfg(config, &verilog_files),
+    )?;
+
+    trace!("Generating riscv-formal checks in {:?}", core_dir);
+    let output = process::Command::new("python3")
+        .arg(rvfi_root.join("checks").join("genchecks.py"))
+        .current_dir(&core_dir)
+        .output()?;
+    if !output.status.success() {
+        error!(
+            "genchecks.py failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(Box::new(std::io::Error::other(
+            "Generating riscv-formal checks failed",
+        )));
+    }
+
+    let checks_dir = core_dir.join("checks");
+    trace!("Running riscv-formal checks in {:?}", checks_dir);
+    jobserver::make()
+        .arg("-C")
+        .arg(&checks_dir)
+        .arg("-k")
+        .output()?;
+
+    // Each check leaves a PASS or FAIL file in its directory.
+    let mut checks: Vec<path::PathBuf> = checks_dir
+        .read_dir()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    checks.sort();
+
+    let mut all_passed = true;
+    for check in checks {
+        let name = check.file_name().unwrap().to_string_lossy().to_string();
+        if check.join("PASS").exists() {
+            println!("Prove: rvfi/{} -- {}.", name, "PASSED".green());
+        } else {
+            all_passed = false;
+            println!("Prove: rvfi/{} -- {}.", name, "FAILED".red().bold());
+            let trace = check.join("engine_0").join("trace.vcd");
+            if trace.exists() {
+                println!("Counterexample: {}", trace.to_string_lossy());
+            }
+        }
+    }
+
+    Ok(all_passed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrapper_drives_free_inputs() {
+        let verilog = "module mkCore(CLK, RST_N, imem_data, EN_step);\n  input  CLK;\n  input  RST_N;\n  input  [31 : 0] imem_data;\n  input  EN_step;\n";
+        let inputs = input_ports(verilog);
+        assert_eq!(
+            inputs,
+            vec![
+                ("CLK".to_string(), 1),
+                ("RST_N".to_string(), 1),
+                ("imem_data".to_string(), 32),
+                ("EN_step".to_string(), 1)
+            ]
+        );
+
+        let config = RvfiConfig {
+            core: "mkCore".to_string(),
+            ..RvfiConfig::default()
+        };
+        let wrapper = generate_wrapper(&config, &inputs);
+        assert!(wrapper.contains("\t(* keep *) `rvformal_rand_reg [31:0] imem_data;\n"));
+        assert!(wrapper.contains("\t\t.RST_N(!reset),\n"));
+        assert!(wrapper.contains("\t\t.EN_step(EN_step),\n\t\t`RVFI_CONN\n"));
+    }
+}
@@ -0,0 +1,364 @@
+//! `dolly gen module` and `dolly gen test`: skeletons of new modules, wired into the package
+//! that includes them, and of testbenches for existing modules.
+use super::bsv;
+use super::graph::Graph;
+use super::project::Project;
+use convert_case::{Case, Casing};
+use std::{collections::BTreeSet, fmt::Write, fs, path};
+
+const MODULE: &str = r#"package {{name}};
+
+export {{name}}(..);
+export mk{{name}};
+
+interface {{name}};
+    method Action start;
+    method Bool done;
+endinterface
+
+module mk{{name}}({{name}});
+    Reg#(Bool) busy <- mkReg(False);
+
+    // TODO: the module's work.
+    rule work (busy);
+        busy <= False;
+    endrule
+
+    method Action start if (!busy);
+        busy <= True;
+    endmethod
+
+    method Bool done = !busy;
+endmodule
+
+endpackage
+"#;
+
+const TEST: &str = r#"//!topmodule mk{{name}}_tb
+import {{name}}::*;
+
+module mk{{name}}_tb(Empty);
+    {{name}} dut <- mk{{name}};
+    Reg#(Bool) started <- mkReg(False);
+
+    rule start (!started);
+        dut.start;
+        started <= True;
+    endrule
+
+    rule finish (started && dut.done);
+        // TODO: check the results, and only pass if they are right.
+        $display(">>>PASS");
+        $finish();
+    endrule
+endmodule
+"#;
+
+/// Adds a line to a source: a //!submodule directive after its other directives, or an
+/// import after its other imports (or its package declaration).  Sources that already have
+/// the line are returned unchanged.
+pub fn wire(source: &str, line: &str) -> String {
+    let mut lines: Vec<&str> = source.lines().collect();
+    if lines.iter().any(|existing| existing.trim() == line) {
+        return source.to_string();
+    }
+    let directives = lines
+        .iter()
+        .take_while(|existing| existing.starts_with("//!"))
+        .count();
+    let position = if line.starts_with("import ") {
+        let last_import = lines
+            .iter()
+            .rposition(|existing| existing.trim_start().starts_with("import "));
+        let package = lines
+            .iter()
+            .position(|existing| existing.trim_start().starts_with("package "));
+        last_import
+            .or(package)
+            .map_or(directives, |index| index + 1)
+    } else {
+        directives
+    };
+    lines.insert(position, line);
+    lines.join("\n") + "\n"
+}
+
+/// Implements `dolly gen module`: writes `<dir>/<name>/<Name>.bsv` and its `_tb`, where
+/// `<dir>` is the parent module directory (the top file's by default), and adds a
+/// //!submodule directive (or with `import`, an import) to the parent's package.
+pub fn module(
+    project: &Project,
+    name: &str,
+    parent: Option<&path::Path>,
+    import: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let package = name.to_case(Case::Pascal);
+    let directory_name = name.to_case(Case::Snake);
+    let parent_file = match parent {
+        Some(parent) => {
+            let parent_name = parent
+                .canonicalize()?
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_case(Case::Pascal);
+            ["bsv", "bs"]
+                .iter()
+                .map(|ext| parent.join(format!("{}.{}", parent_name, ext)))
+                .find(|file| file.exists())
+                .unwrap_or_else(|| parent.join(format!("{}.bsv", parent_name)))
+        }
+        None => project.top_file(),
+    };
+    if !parent_file.exists() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "The parent package {} doesn't exist",
+            parent_file.to_string_lossy()
+        ))));
+    }
+
+    let directory = parent_file.parent().unwrap().join(&directory_name);
+    if directory.exists() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} already exists",
+            directory.to_string_lossy()
+        ))));
+    }
+    let relative = |path: &path::Path| {
+        path.strip_prefix(project.root_path())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    };
+    fs::create_dir_all(&directory)?;
+    for (file, contents) in [
+        (format!("{}.bsv", package), MODULE),
+        (format!("{}_tb.bsv", package), TEST),
+    ] {
+        let file = directory.join(file);
+        fs::write(&file, contents.replace("{{name}}", &package))?;
+        println!("Wrote {}", relative(&file));
+    }
+
+    let line = if import {
+        format!("import {}::*;", package)
+    } else {
+        format!("//!submodule {}", directory_name)
+    };
+    fs::write(
+        &parent_file,
+        wire(&fs::read_to_string(&parent_file)?, &line),
+    )?;
+    println!("Added '{}' to {}", line, relative(&parent_file));
+    Ok(())
+}
+
+/// The cycle a testbench skeleton gives up at, after calling each method in turn.
+const TIMEOUT: u32 = 1000;
+
+/// A testbench skeleton for a module: it instantiates the module, counts cycles of the default
+/// clock (the simulation resets the testbench and its submodules), calls each method in a rule
+/// of its own, and passes at a timeout.
+pub fn testbench(
+    module: &bsv::Module,
+    package: &str,
+    interface: Option<&bsv::Interface>,
+) -> String {
+    let name = module.name.strip_prefix("mk").unwrap_or(&module.name);
+    let ty = match interface.and_then(|interface| interface.parameters.as_ref()) {
+        Some(parameters) => format!("{}#(/* TODO: {} */)", module.interface, parameters),
+        None => module.interface.clone(),
+    };
+    let instance = match &module.parameters {
+        Some(parameters) => format!("{}(/* TODO: {} */)", module.name, parameters),
+        None => module.name.clone(),
+    };
+
+    let mut tb = String::new();
+    writeln!(tb, "//!topmodule mk{}_tb", name).unwrap();
+    writeln!(tb, "import {}::*;\n", package).unwrap();
+    writeln!(tb, "module mk{}_tb(Empty);", name).unwrap();
+    writeln!(
+        tb,
+        "    // Driven by the testbench's default clock and reset."
+    )
+    .unwrap();
+    writeln!(tb, "    {} dut <- {};", ty, instance).unwrap();
+    writeln!(tb, "    Reg#(UInt#(32)) cycle <- mkReg(0);\n").unwrap();
+    writeln!(
+        tb,
+        "    rule count;\n        cycle <= cycle + 1;\n    endrule"
+    )
+    .unwrap();
+
+    let methods = interface.map_or(&[][..], |interface| &interface.methods[..]);
+    for (index, method) in methods.iter().enumerate() {
+        let arguments = if method.arguments.is_empty() {
+            String::new()
+        } else {
+            let arguments: Vec<&str> = method.arguments.iter().map(|_| "?").collect();
+            format!("({})", arguments.join(", "))
+        };
+        let names: Vec<&str> = method
+            .arguments
+            .iter()
+            .map(|argument| argument.name.as_str())
+            .collect();
+        let pass = if names.is_empty() {
+            String::new()
+        } else {
+            format!("pass {}, and ", names.join(", "))
+        };
+        let call = format!("dut.{}{}", method.name, arguments);
+        writeln!(tb).unwrap();
+        match method.kind {
+            bsv::MethodKind::Action => {
+                writeln!(
+                    tb,
+                    "    rule call_{} (cycle == {});",
+                    method.name,
+                    index + 1
+                )
+                .unwrap();
+                writeln!(tb, "        {}; // TODO: {}check the effect.", call, pass).unwrap();
+            }
+            bsv::MethodKind::ActionValue => {
+                writeln!(
+                    tb,
+                    "    rule call_{} (cycle == {});",
+                    method.name,
+                    index + 1
+                )
+                .unwrap();
+                writeln!(tb, "        let result <- {};", call).unwrap();
+                writeln!(tb, "        // TODO: {}check the result.", pass).unwrap();
+            }
+            bsv::MethodKind::Value => {
+                writeln!(tb, "    rule check_{};", method.name).unwrap();
+                writeln!(tb, "        let value = {};", call).unwrap();
+                writeln!(tb, "        // TODO: {}check the value.", pass).unwrap();
+            }
+        }
+        writeln!(tb, "    endrule").unwrap();
+    }
+    for subinterface in interface.map_or(&[][..], |interface| &interface.subinterfaces[..]) {
+        writeln!(
+            tb,
+            "\n    // TODO: drive or check dut.{} (a {}).",
+            subinterface.name, subinterface.ty
+        )
+        .unwrap();
+    }
+
+    writeln!(tb, "\n    rule finish (cycle == {});", TIMEOUT).unwrap();
+    writeln!(tb, "        // TODO: only pass if the checks succeeded.").unwrap();
+    writeln!(tb, "        $display(\">>>PASS\");\n        $finish();").unwrap();
+    writeln!(tb, "    endrule\nendmodule").unwrap();
+    tb
+}
+
+/// Implements `dolly gen test`: writes a testbench skeleton for a module (`mkName` or `Name`),
+/// by default as the unit testbench `<Name>_tb.bsv` next to the module's source.
+pub fn test(
+    project: &Project,
+    module: &str,
+    output: Option<&path::Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let graph = Graph::load(project)?;
+    let directories: BTreeSet<path::PathBuf> = graph
+        .edges
+        .keys()
+        .filter_map(|source| source.parent().map(path::Path::to_path_buf))
+        .collect();
+    let Some(location) = bsv::find_module(directories.iter(), module) else {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Module '{}' not found",
+            module
+        ))));
+    };
+    let interface = bsv::find_interface(directories.iter(), &location.module.interface);
+    let name = location
+        .module
+        .name
+        .strip_prefix("mk")
+        .unwrap_or(&location.module.name);
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => location
+            .path
+            .parent()
+            .unwrap()
+            .join(format!("{}_tb.bsv", name)),
+    };
+    if output.exists() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} already exists",
+            output.to_string_lossy()
+        ))));
+    }
+    fs::write(
+        &output,
+        testbench(&location.module, &location.package(), interface.as_ref()),
+    )?;
+    println!(
+        "Wrote {}",
+        output
+            .strip_prefix(project.root_path())
+            .unwrap_or(&output)
+            .to_string_lossy()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wired_parent() {
+        let source = "//!topmodule mkTop\npackage Top;\n\nimport FIFO::*;\n\nendpackage\n";
+        assert_eq!(
+            wire(source, "//!submodule fetch_unit"),
+            "//!topmodule mkTop\n//!submodule fetch_unit\npackage Top;\n\nimport FIFO::*;\n\n\
+             endpackage\n"
+        );
+        assert_eq!(
+            wire(source, "import FetchUnit::*;"),
+            "//!topmodule mkTop\npackage Top;\n\nimport FIFO::*;\nimport FetchUnit::*;\n\n\
+             endpackage\n"
+        );
+        assert_eq!(
+            wire("//!topmodule mkTop\n", "import FetchUnit::*;"),
+            "//!topmodule mkTop\nimport FetchUnit::*;\n"
+        );
+        assert_eq!(wire(source, "import FIFO::*;"), source);
+    }
+
+    #[test]
+    fn testbench_skeleton() {
+        let source = r#"
+interface Queue;
+    method Action push(Bit#(8) data);
+    method ActionValue#(Bit#(8)) pop;
+    method Bool empty;
+    interface Put#(Bit#(8)) input;
+endinterface
+"#;
+        let interface = &bsv::parse_interfaces(source)[0];
+        let module = bsv::Module {
+            name: "mkQueue".to_string(),
+            interface: "Queue".to_string(),
+            parameters: None,
+        };
+        let tb = testbench(&module, "Queue", Some(interface));
+        assert!(tb.starts_with("//!topmodule mkQueue_tb\nimport Queue::*;\n"));
+        assert!(tb.contains("    Queue dut <- mkQueue;\n"));
+        assert!(tb.contains(
+            "    rule call_push (cycle == 1);\n        dut.push(?); // TODO: pass data, and check the effect.\n"
+        ));
+        assert!(tb.contains("        let result <- dut.pop;\n"));
+        assert!(tb.contains("    rule check_empty;\n        let value = dut.empty;\n"));
+        assert!(tb.contains("// TODO: drive or check dut.input (a Put#(Bit#(8)))."));
+        assert!(tb.ends_with("$finish();\n    endrule\nendmodule\n"));
+    }
+}
@@ -0,0 +1,361 @@
+use super::project::Project;
+use regex::Regex;
+use std::{collections::BTreeMap, fs, path};
+
+/// A rule in a bsc schedule.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub predicate: String,
+    pub blocking: Vec<String>, // Rules that can prevent this rule from firing
+}
+
+/// A method in a bsc schedule.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub ready: String, // The ready signal ("True" for always-ready methods)
+    pub conflicts: Vec<String>,
+}
+
+/// The schedule bsc writes for a module with -show-schedule (<module>.sched).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schedule {
+    pub module: String,
+    pub methods: Vec<Method>,
+    pub rules: Vec<Rule>,
+    pub order: Vec<String>, // Logical execution order of the methods and rules
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    match value.trim() {
+        "" | "(none)" => Vec::new(),
+        value => value
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect(),
+    }
+}
+
+/// Parses a .sched file.  Long values that bsc wraps onto indented lines are rejoined.
+pub fn parse(text: &str) -> Option<Schedule> {
+    let header_re = Regex::new(r"^=== Generated schedule for (\S+) ===").unwrap();
+    let mut fields = Vec::<(String, String)>::new();
+    let mut module = None;
+    for line in text.lines() {
+        if let Some(capture) = header_re.captures(line) {
+            module = Some(capture[1].to_string());
+        } else if line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((key, value)) = line.split_once(": ") {
+            fields.push((key.to_string(), value.trim().to_string()));
+        } else if let Some(key) = line.strip_suffix(':') {
+            fields.push((key.to_string(), String::new()));
+        }
+    }
+
+    let mut schedule = Schedule {
+        module: module?,
+        ..Schedule::default()
+    };
+    for (key, value) in fields {
+        match key.as_str() {
+            "Method" => schedule.methods.push(Method {
+                name: value,
+                ..Method::default()
+            }),
+            "Ready signal" => {
+                if let Some(method) = schedule.methods.last_mut() {
+                    method.ready = value;
+                }
+            }
+            "Conflicts" => {
+                if let Some(method) = schedule.methods.last_mut() {
+                    method.conflicts = split_list(&value);
+                }
+            }
+            "Rule" => schedule.rules.push(Rule {
+                name: value,
+                ..Rule::default()
+            }),
+            "Predicate" => {
+                if let Some(rule) = schedule.rules.last_mut() {
+                    rule.predicate = value;
+                }
+            }
+            "Blocking rules" => {
+                if let Some(rule) = schedule.rules.last_mut() {
+                    rule.blocking = split_list(&value);
+                }
+            }
+            "Logical execution order" => schedule.order = split_list(&value),
+            _ => {}
+        }
+    }
+    Some(schedule)
+}
+
+/// The rule pairs of the G0010 warnings ("Rule `a' was treated as more urgent than `b'") in
+/// a bsc log: conflicts that bsc resolved without a descending_urgency attribute.
+pub fn urgency_warnings(log: &str) -> Vec<(String, String)> {
+    let re =
+        Regex::new(r"\(G0010\)\s+Rule `(\w+)' was treated as more urgent than\s+`(\w+)'").unwrap();
+    re.captures_iter(log)
+        .map(|capture| (capture[1].to_string(), capture[2].to_string()))
+        .collect()
+}
+
+/// The schedules under a target directory, by path relative to it (e.g. Fifo_tb/mkTb.sched).
+pub fn find(target_dir: &path::Path) -> BTreeMap<path::PathBuf, Schedule> {
+    let mut schedules = BTreeMap::<path::PathBuf, Schedule>::new();
+    let mut remaining = vec![target_dir.to_path_buf()];
+    while let Some(directory) = remaining.pop() {
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.is_dir() {
+                remaining.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "sched") {
+                let schedule = fs::read_to_string(&path).ok().and_then(|text| parse(&text));
+                if let Some(schedule) = schedule {
+                    let relative = path.strip_prefix(target_dir).unwrap().to_path_buf();
+                    schedules.insert(relative, schedule);
+                }
+            }
+        }
+    }
+    schedules
+}
+
+/// A summary of a schedule: its execution order, the rules that other rules can block and
+/// the urgency warnings bsc reported for it.
+pub fn summary(schedule: &Schedule, urgency: &[(String, String)]) -> String {
+    let mut summary = format!(
+        "{}: {} methods, {} rules\n",
+        schedule.module,
+        schedule.methods.len(),
+        schedule.rules.len()
+    );
+    if !schedule.order.is_empty() {
+        summary.push_str(&format!("  order: {}\n", schedule.order.join(", ")));
+    }
+    for rule in schedule
+        .rules
+        .iter()
+        .filter(|rule| !rule.blocking.is_empty())
+    {
+        summary.push_str(&format!(
+            "  blocked: {} (by {})\n",
+            rule.name,
+            rule.blocking.join(", ")
+        ));
+    }
+    let rules: Vec<&str> = schedule
+        .rules
+        .iter()
+        .map(|rule| rule.name.as_str())
+        .collect();
+    for (more, less) in urgency
+        .iter()
+        .filter(|(more, less)| rules.contains(&more.as_str()) && rules.contains(&less.as_str()))
+    {
+        summary.push_str(&format!("  urgency: {} over {} (G0010)\n", more, less));
+    }
+    summary
+}
+
+/// The differences between two schedules of a module, one line each.
+pub fn diff(old: &Schedule, new: &Schedule) -> Vec<String> {
+    let mut differences = Vec::<String>::new();
+    if old.order != new.order {
+        differences.push(format!(
+            "order: {} -> {}",
+            old.order.join(", "),
+            new.order.join(", ")
+        ));
+    }
+    let old_rules: BTreeMap<&str, &Rule> = old.rules.iter().map(|r| (r.name.as_str(), r)).collect();
+    let new_rules: BTreeMap<&str, &Rule> = new.rules.iter().map(|r| (r.name.as_str(), r)).collect();
+    for (name, old_rule) in &old_rules {
+        match new_rules.get(name) {
+            None => differences.push(format!("rule {} removed", name)),
+            Some(new_rule) => {
+                if old_rule.predicate != new_rule.predicate {
+                    differences.push(format!(
+                        "rule {} predicate: {} -> {}",
+                        name, old_rule.predicate, new_rule.predicate
+                    ));
+                }
+                if old_rule.blocking != new_rule.blocking {
+                    differences.push(format!(
+                        "rule {} blocked by: [{}] -> [{}]",
+                        name,
+                        old_rule.blocking.join(", "),
+                        new_rule.blocking.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+    for name in new_rules
+        .keys()
+        .filter(|name| !old_rules.contains_key(*name))
+    {
+        differences.push(format!("rule {} added", name));
+    }
+    let ready = |schedule: &Schedule| -> BTreeMap<String, String> {
+        schedule
+            .methods
+            .iter()
+            .map(|method| (method.name.clone(), method.ready.clone()))
+            .collect()
+    };
+    if ready(old) != ready(new) {
+        differences.push("method ready signals changed".to_string());
+    }
+    differences
+}
+
+/// Implements `dolly schedule`: summarizes the schedules of the last build (of one target, if
+/// given) or, with a baseline target directory, reports how they changed.  Returns whether
+/// the schedules match the baseline.
+pub fn report(
+    project: &Project,
+    target: Option<&str>,
+    baseline: Option<&path::Path>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let target_dir = project.target_dir();
+    let in_target =
+        |relative: &path::Path| target.is_none_or(|target| relative.starts_with(target));
+    let schedules: BTreeMap<path::PathBuf, Schedule> = find(&target_dir)
+        .into_iter()
+        .filter(|(relative, _)| in_target(relative))
+        .collect();
+    if schedules.is_empty() {
+        return Err(Box::new(std::io::Error::other(
+            "No schedules found (run 'dolly build' or 'dolly test' first)",
+        )));
+    }
+
+    let Some(baseline) = baseline else {
+        for (relative, schedule) in &schedules {
+            let log = relative
+                .parent()
+                .map(|directory| target_dir.join(directory).join("compile.log"))
+                .and_then(|log| fs::read_to_string(log).ok())
+                .unwrap_or_default();
+            println!(
+                "[{}]",
+                relative.parent().unwrap_or(relative).to_string_lossy()
+            );
+            print!("{}", summary(schedule, &urgency_warnings(&log)));
+        }
+        return Ok(true);
+    };
+
+    let baseline_schedules: BTreeMap<path::PathBuf, Schedule> = find(baseline)
+        .into_iter()
+        .filter(|(relative, _)| in_target(relative))
+        .collect();
+    let mut matched = true;
+    for (relative, schedule) in &schedules {
+        let name = relative.to_string_lossy();
+        match baseline_schedules.get(relative) {
+            None => {
+                println!("{}: new", name);
+                matched = false;
+            }
+            Some(old) => {
+                for difference in diff(old, schedule) {
+                    println!("{}: {}", name, difference);
+                    matched = false;
+                }
+            }
+        }
+    }
+    for relative in baseline_schedules
+        .keys()
+        .filter(|relative| !schedules.contains_key(*relative))
+    {
+        println!("{}: removed", relative.to_string_lossy());
+        matched = false;
+    }
+    if matched {
+        println!("Schedules match {}", baseline.to_string_lossy());
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCHEDULE: &str = r#"=== Generated schedule for mkGCD ===
+
+Method schedule
+---------------
+Method: start
+Ready signal: y == 0
+Conflict-free: result
+Conflicts: start
+
+Method: result
+Ready signal: y == 0
+Conflict-free: start, result
+
+Rule schedule
+-------------
+Rule: swap
+Predicate: (x > y) && (! (y == 0))
+Blocking rules: (none)
+
+Rule: subtract
+Predicate: (x <= y) &&
+           (! (y == 0))
+Blocking rules: swap
+
+Logical execution order: start, result, swap, subtract
+
+=====================================
+"#;
+
+    #[test]
+    fn parsed_schedule() {
+        let schedule = parse(SCHEDULE).unwrap();
+        assert_eq!(schedule.module, "mkGCD");
+        assert_eq!(schedule.methods.len(), 2);
+        assert_eq!(schedule.methods[0].conflicts, ["start"]);
+        assert_eq!(schedule.rules[1].predicate, "(x <= y) && (! (y == 0))");
+        assert_eq!(schedule.rules[1].blocking, ["swap"]);
+        assert_eq!(schedule.order, ["start", "result", "swap", "subtract"]);
+
+        let log = "Warning: \"src/GCD.bsv\", line 12, column 8: (G0010)\n  Rule `swap' was treated as more urgent than `subtract'. Conflicts:\n";
+        let urgency = urgency_warnings(log);
+        assert_eq!(urgency, [("swap".to_string(), "subtract".to_string())]);
+        let summary = summary(&schedule, &urgency);
+        assert!(summary.contains("  blocked: subtract (by swap)\n"));
+        assert!(summary.contains("  urgency: swap over subtract (G0010)\n"));
+
+        let mut changed = schedule.clone();
+        changed.order.swap(2, 3);
+        changed.rules[1].blocking.clear();
+        changed.rules.push(Rule {
+            name: "reset".to_string(),
+            ..Rule::default()
+        });
+        assert_eq!(
+            diff(&schedule, &changed),
+            [
+                "order: start, result, swap, subtract -> start, result, subtract, swap",
+                "rule subtract blocked by: [swap] -> []",
+                "rule reset added"
+            ]
+        );
+        assert!(diff(&schedule, &schedule).is_empty());
+    }
+}
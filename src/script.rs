@@ -0,0 +1,282 @@
+//! Scripted pipeline stages: the Rhai script `[hooks] script` names (conventionally dolly.rhai)
+//! registers stages to run at the hook points, for flows too involved for a shell command but
+//! not worth a plugin:
+//!
+//! ```rhai
+//! stage("post-build", "bitstream", |project| {
+//!     if run(`vivado -mode batch -source fpga/build.tcl -tclargs ${project.target_dir}`) != 0 {
+//!         throw "vivado failed";
+//!     }
+//!     artifact("fpga/out/top.bit");
+//! });
+//! ```
+//!
+//! A stage gets the project's metadata (as plugins do) as a map, and can `run` shell commands
+//! in the project root and add files as `artifact`s, which `dolly package` ships.  Built
+//! without the `scripting` feature, dolly refuses a project with a script.
+use super::builder::Options;
+use super::codes;
+use super::hooks::Hook;
+use super::project::Project;
+use std::{path, sync::Mutex};
+
+/// The artifacts the stages run so far added, in target/artifacts.
+static ARTIFACTS: Mutex<Vec<path::PathBuf>> = Mutex::new(Vec::new());
+
+/// The artifacts the stages run so far added.
+pub fn artifacts() -> Vec<path::PathBuf> {
+    ARTIFACTS.lock().unwrap().clone()
+}
+
+/// Runs the stages the project's script registers for `hook`, in the order it registers
+/// them.
+#[cfg(feature = "scripting")]
+pub fn run(
+    project: &Project,
+    hook: Hook,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(script) = &project.hooks.script else {
+        return Ok(());
+    };
+    rhai_stages::run(project, &project.root_path().join(script), hook, options)
+}
+
+/// Refuses a project with a script, which this dolly can't run.
+#[cfg(not(feature = "scripting"))]
+pub fn run(
+    project: &Project,
+    _hook: Hook,
+    _options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(script) = &project.hooks.script else {
+        return Ok(());
+    };
+    Err(Box::new(codes::error(
+        "D0010",
+        format!(
+            "dolly.toml names the script {}, but this dolly was built without the 'scripting' \
+             feature",
+            script.to_string_lossy()
+        ),
+    )))
+}
+
+#[cfg(feature = "scripting")]
+mod rhai_stages {
+    use super::super::{plugin, runner};
+    use super::*;
+    use rhai::{Engine, EvalAltResult, FnPtr, Map};
+    use std::{cell::RefCell, fs, process, rc::Rc};
+
+    /// A stage registered by the script.
+    struct Stage {
+        hook: Hook,
+        name: String,
+        function: FnPtr,
+    }
+
+    /// The error of a function called by a script.
+    fn script_error(message: String) -> Box<EvalAltResult> {
+        message.into()
+    }
+
+    /// An engine with dolly's API for the project, registering stages in `stages`.
+    fn engine(
+        project: &Project,
+        stages: Rc<RefCell<Vec<Stage>>>,
+        options: &Options,
+    ) -> Result<Engine, Box<dyn std::error::Error>> {
+        let mut engine = Engine::new();
+        engine.register_fn(
+            "stage",
+            move |hook: &str, name: &str, function: FnPtr| -> Result<(), Box<EvalAltResult>> {
+                let Some(hook) = Hook::ALL.into_iter().find(|known| known.name() == hook) else {
+                    let names: Vec<&str> = Hook::ALL.iter().map(Hook::name).collect();
+                    return Err(script_error(format!(
+                        "No hook point is named '{}' (expected one of {})",
+                        hook,
+                        names.join(", ")
+                    )));
+                };
+                stages.borrow_mut().push(Stage {
+                    hook,
+                    name: name.to_string(),
+                    function,
+                });
+                Ok(())
+            },
+        );
+
+        let (root, target_dir) = (project.root_path().clone(), project.target_dir());
+        let log_dir = project.output_dir("hooks");
+        let env = project.env()?;
+        let show_commands = options.show_commands;
+        let package = project.package.name.clone();
+        engine.register_fn(
+            "run",
+            move |command: &str| -> Result<i64, Box<EvalAltResult>> {
+                let mut cmd = process::Command::new("sh");
+                cmd.arg("-c")
+                    .arg(command)
+                    .current_dir(&root)
+                    .envs(&env)
+                    .env("DOLLY_PACKAGE", &package)
+                    .env("DOLLY_PROJECT_ROOT", &root)
+                    .env("DOLLY_TARGET_DIR", &target_dir);
+                if show_commands {
+                    println!("{}", runner::command_line(&cmd));
+                }
+                let output = fs::create_dir_all(&log_dir)
+                    .and_then(|()| runner::run(&mut cmd, "script", &log_dir.join("script.log")))
+                    .map_err(|e| script_error(format!("Unable to run '{}': {}", command, e)))?;
+                Ok(output.status.code().unwrap_or(1).into())
+            },
+        );
+
+        let (root, artifacts_dir) = (project.root_path().clone(), project.output_dir("artifacts"));
+        engine.register_fn(
+            "artifact",
+            move |file: &str| -> Result<(), Box<EvalAltResult>> {
+                let source = root.join(file);
+                let copy = source.file_name().map(|name| artifacts_dir.join(name));
+                let copied = match &copy {
+                    Some(copy) if source.is_file() => fs::create_dir_all(&artifacts_dir)
+                        .and_then(|()| fs::copy(&source, copy))
+                        .map_err(|e| e.to_string()),
+                    _ => Err("not a file".to_string()),
+                };
+                if let Err(e) = copied {
+                    return Err(script_error(format!(
+                        "Unable to add the artifact {}: {}",
+                        file, e
+                    )));
+                }
+                let mut artifacts = ARTIFACTS.lock().unwrap();
+                let copy = copy.unwrap();
+                if !artifacts.contains(&copy) {
+                    artifacts.push(copy);
+                }
+                Ok(())
+            },
+        );
+        Ok(engine)
+    }
+
+    /// Evaluates the script, which registers its stages, then runs those for `hook`, each
+    /// given the project's metadata.
+    pub fn run(
+        project: &Project,
+        script: &path::Path,
+        hook: Hook,
+        options: &Options,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            codes::error(
+                "D0010",
+                format!("Invalid script {}: {}", script.to_string_lossy(), e),
+            )
+        };
+        let stages = Rc::new(RefCell::new(Vec::new()));
+        let engine = engine(project, stages.clone(), options)?;
+        let source = fs::read_to_string(script).map_err(|e| invalid(&e))?;
+        let ast = engine.compile(source).map_err(|e| invalid(&e))?;
+        engine.run_ast(&ast).map_err(|e| invalid(&e))?;
+
+        let metadata: Map = engine
+            .parse_json(plugin::metadata_json(project), true)
+            .map_err(|e| invalid(&e))?;
+        for stage in stages.borrow().iter().filter(|stage| stage.hook == hook) {
+            if options.dry_run || options.show_commands {
+                println!("{} stage {}", hook.name(), stage.name);
+            }
+            if options.dry_run {
+                continue;
+            }
+            let _returned: rhai::Dynamic = stage
+                .function
+                .call(&engine, &ast, (metadata.clone(),))
+                .map_err(|e| {
+                    codes::error(
+                        "D0305",
+                        format!("The {} stage '{}' failed: {}", hook.name(), stage.name, e),
+                    )
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn scripted_stages() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::write(
+            root.join("dolly.toml"),
+            r#"
+            [package]
+            name = "core"
+            version = "0.1.0"
+
+            [hooks]
+            script = "dolly.rhai"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            root.join("dolly.rhai"),
+            r#"
+            stage("post-build", "report", |project| {
+                if run(`echo ${project.name} ${project.version} > report.txt`) != 0 {
+                    throw "echo failed";
+                }
+                artifact("report.txt");
+            });
+            stage("post-test", "fails", |project| {
+                if run("exit 3") != 0 {
+                    throw "the check failed";
+                }
+            });
+            "#,
+        )
+        .unwrap();
+        let project = Project::load(root.join("dolly.toml")).unwrap();
+        let options = Options::default();
+
+        run(&project, Hook::PreBuild, &options).unwrap();
+        assert!(!root.join("report.txt").exists());
+        run(&project, Hook::PostBuild, &options).unwrap();
+        let artifact = project.output_dir("artifacts").join("report.txt");
+        assert_eq!(fs::read_to_string(&artifact).unwrap(), "core 0.1.0\n");
+        assert!(artifacts().contains(&artifact));
+        let error = run(&project, Hook::PostTest, &options).unwrap_err();
+        assert!(error.to_string().contains("post-test stage 'fails' failed"));
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0305"));
+
+        // Unknown hook points, syntax errors and missing artifacts are errors.
+        fs::write(
+            root.join("dolly.rhai"),
+            r#"stage("post-bulid", "typo", |project| {});"#,
+        )
+        .unwrap();
+        let error = run(&project, Hook::PreBuild, &options).unwrap_err();
+        assert!(codes::coded(error.as_ref()).is_some_and(|coded| coded.code() == "D0010"));
+        assert!(error.to_string().contains("'post-bulid'"));
+        fs::write(root.join("dolly.rhai"), "stage(").unwrap();
+        assert!(run(&project, Hook::PreBuild, &options).is_err());
+        fs::write(
+            root.join("dolly.rhai"),
+            r#"stage("pre-build", "missing", |project| { artifact("none.bit"); });"#,
+        )
+        .unwrap();
+        let error = run(&project, Hook::PreBuild, &options).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Unable to add the artifact none.bit"));
+    }
+}
@@ -0,0 +1,305 @@
+//! `dolly self update`: replaces the running dolly with the latest release of a channel.
+//! Each channel is described by <feed>/<channel>.toml, naming its version and, for each
+//! platform, the binary's URL and SHA-256 checksum:
+//!
+//! ```toml
+//! version = "0.2.0"
+//!
+//! [binaries.x86_64-linux]
+//! url = "https://example.com/dolly-0.2.0-x86_64-linux"
+//! sha256 = "9f86d08..."
+//! ```
+//!
+//! The checksum only catches a broken download, as it comes from the same feed: a binary is
+//! only installed if its detached minisign signature (<url>.minisig, unless the binary names
+//! its `signature`) verifies with the release key pinned in dolly when it was built, or the
+//! [update] public-key of the configuration.
+use super::checksum;
+use super::config::{self, Config};
+use super::net;
+use super::version::Version;
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path, process};
+
+/// A release channel.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Stable,
+    Nightly, // Built from the main branch every night
+}
+
+impl Channel {
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Nightly => "nightly",
+        }
+    }
+}
+
+/// The minisign public key releases are signed with, pinned when dolly is built (the release
+/// builds set DOLLY_RELEASE_KEY).
+const RELEASE_KEY: Option<&str> = option_env!("DOLLY_RELEASE_KEY");
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Binary {
+    url: String,
+    sha256: String,
+    signature: Option<String>, // URL of the minisign signature, by default <url>.minisig
+}
+
+impl Binary {
+    fn signature_url(&self) -> String {
+        self.signature
+            .clone()
+            .unwrap_or_else(|| format!("{}.minisig", self.url))
+    }
+}
+
+/// A channel's latest release.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Release {
+    version: String,
+    #[serde(default)]
+    binaries: BTreeMap<String, Binary>, // Platform (see `platform`) -> binary
+}
+
+/// The platform releases are built for, such as x86_64-linux or aarch64-macos.
+fn platform() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Whether to install a release: if it is newer, or any other version when switching
+/// channels (a stable release being older than the nightly builds after it).
+fn wanted(current: &Version, release: &Version, switching: bool) -> bool {
+    release > current || (switching && release != current)
+}
+
+/// Downloads a URL with curl.
+fn download(url: &str, file: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let output = net::curl().arg("-o").arg(file).arg(url).output()?;
+    if !output.status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to download {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+/// Checks a download against its detached minisign signature, made with the secret key of
+/// `public_key` (its base64 line, as in a minisign .pub file).
+fn verify(contents: &[u8], signature: &str, public_key: &str) -> Result<(), String> {
+    let public_key = PublicKey::from_base64(public_key.trim())
+        .map_err(|e| format!("the public key {} is invalid: {}", public_key.trim(), e))?;
+    let signature =
+        Signature::decode(signature).map_err(|e| format!("the signature is invalid: {}", e))?;
+    public_key
+        .verify(contents, &signature, false)
+        .map_err(|e| e.to_string())
+}
+
+/// Fetches a channel's latest release from the feed.
+fn latest(feed: &str, channel: Channel) -> Result<Release, Box<dyn std::error::Error>> {
+    let url = format!("{}/{}.toml", feed.trim_end_matches('/'), channel.name());
+    net::forbid_fetching(&[format!("the {} release feed from {}", channel.name(), url)])?;
+    let output = net::curl().arg(&url).output()?;
+    if !output.status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to fetch the release feed {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    toml::from_str(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| std::io::Error::other(format!("Invalid release feed {}: {}", url, e)).into())
+}
+
+/// Replaces the running executable with a download of `binary` that matches its checksum and
+/// its signature by `public_key`.
+fn install(binary: &Binary, public_key: &str) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    let executable = std::env::current_exe()?.canonicalize()?;
+    // Downloaded next to the executable, so that the rename replacing it stays on one file
+    // system.
+    let download_file = executable.with_file_name(format!(".dolly-update-{}", process::id()));
+    let installed = download(&binary.url, &download_file).and_then(|_| {
+        let contents = fs::read(&download_file)?;
+        let sha256 = checksum::sha256(&contents);
+        if !sha256.eq_ignore_ascii_case(&binary.sha256) {
+            return Err(Box::new(std::io::Error::other(format!(
+                "The download of {} doesn't match its checksum (SHA-256 {}, expected {})",
+                binary.url, sha256, binary.sha256
+            ))) as Box<dyn std::error::Error>);
+        }
+        let signature_url = binary.signature_url();
+        let output = net::curl().arg(&signature_url).output()?;
+        if !output.status.success() {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Unable to download the signature {}: {}",
+                signature_url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))));
+        }
+        verify(
+            &contents,
+            &String::from_utf8_lossy(&output.stdout),
+            public_key,
+        )
+        .map_err(|e| {
+            std::io::Error::other(format!(
+                "The download of {} isn't signed with the release key ({})",
+                binary.url, e
+            ))
+        })?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&download_file, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&download_file, &executable)?;
+        Ok(())
+    });
+    if let Err(e) = installed {
+        let _ = fs::remove_file(&download_file);
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to update {}: {}",
+            executable.to_string_lossy(),
+            e
+        ))));
+    }
+    Ok(executable)
+}
+
+/// Updates dolly to the latest release of `channel` (the configured one by default, which a
+/// new channel replaces), or with `check`, only reports whether there is one.
+pub fn update(
+    config: &Config,
+    channel: Option<Channel>,
+    check: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let switching = channel.is_some_and(|channel| channel != config.update.channel);
+    let channel = channel.unwrap_or(config.update.channel);
+    let current_version = env!("CARGO_PKG_VERSION");
+    let current = Version::parse(current_version).ok_or("dolly's own version is invalid")?;
+    let release = latest(&config.update.feed, channel)?;
+    let version = Version::parse(&release.version).ok_or_else(|| {
+        std::io::Error::other(format!(
+            "The {} release feed has an invalid version: {}",
+            channel.name(),
+            release.version
+        ))
+    })?;
+
+    if !wanted(&current, &version, switching) {
+        println!(
+            "dolly {} is up to date ({} channel)",
+            current_version,
+            channel.name()
+        );
+        return Ok(());
+    }
+    if check {
+        println!(
+            "dolly {} is available on the {} channel (this is {})",
+            release.version,
+            channel.name(),
+            current_version
+        );
+        return Ok(());
+    }
+    let binary = release.binaries.get(&platform()).ok_or_else(|| {
+        std::io::Error::other(format!(
+            "dolly {} has no binary for {} (only {})",
+            release.version,
+            platform(),
+            release
+                .binaries
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })?;
+    let public_key = config.update.public_key.as_deref().or(RELEASE_KEY).ok_or(
+        "This dolly was built without the release key (DOLLY_RELEASE_KEY), so it can't \
+                check the signatures of releases; set [update] public-key to the minisign key \
+                the feed's binaries are signed with",
+    )?;
+    let executable = install(binary, public_key)?;
+    if switching {
+        let user_file = config::user_file().ok_or("Unable to locate the user configuration")?;
+        config::set(&user_file, "update.channel", channel.name(), true)?;
+    }
+    println!(
+        "Updated {} from {} to {} ({} channel)",
+        executable.to_string_lossy(),
+        current_version,
+        release.version,
+        channel.name()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn release_feed() {
+        let release: Release = toml::from_str(
+            "version = \"0.3.0-nightly.20261014\"\n\
+             [binaries.x86_64-linux]\n\
+             url = \"https://example.com/dolly-x86_64-linux\"\n\
+             sha256 = \"9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            release.binaries["x86_64-linux"].url,
+            "https://example.com/dolly-x86_64-linux"
+        );
+
+        let version = |text| Version::parse(text).unwrap();
+        let current = version("0.2.0");
+        assert!(wanted(&current, &version("0.2.1"), false));
+        assert!(!wanted(&current, &version("0.2.0"), false));
+        assert!(wanted(&current, &version(&release.version), false));
+        // Going back to stable from a nightly build.
+        assert!(!wanted(&version("0.3.0-nightly.1"), &current, false));
+        assert!(wanted(&version("0.3.0-nightly.1"), &current, true));
+    }
+
+    #[test]
+    fn signed_binaries() {
+        let binary: Binary =
+            toml::from_str("url = \"https://example.com/dolly\"\nsha256 = \"00\"\n").unwrap();
+        assert_eq!(binary.signature_url(), "https://example.com/dolly.minisig");
+
+        let keys = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        let sign = |contents: &[u8]| {
+            minisign::sign(None, &keys.sk, contents, Some("dolly 0.2.0"), None)
+                .unwrap()
+                .into_string()
+        };
+        let public_key = keys.pk.to_base64();
+        let contents = b"\x7fELF dolly 0.2.0";
+        assert_eq!(verify(contents, &sign(contents), &public_key), Ok(()));
+
+        // A binary changed after it was signed, a signature by another key, and a key or
+        // signature that doesn't decode are refused.
+        assert!(verify(b"\x7fELF dolly 0.2.1", &sign(contents), &public_key).is_err());
+        let other = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        assert!(verify(contents, &sign(contents), &other.pk.to_base64()).is_err());
+        assert!(verify(contents, &sign(contents), "RWQ")
+            .unwrap_err()
+            .contains("public key"));
+        assert!(verify(contents, "untrusted comment: none\n", &public_key)
+            .unwrap_err()
+            .contains("signature is invalid"));
+    }
+}
@@ -0,0 +1,343 @@
+//! `dolly serve`: a small HTTP server showing the latest results of a project's tests, with
+//! links to the coverage and timing reports and the build directories under target/, for the
+//! lab machines that run regressions with no one at their terminal.
+use super::coverage::escape_html;
+use super::history::{self, TestRun};
+use super::project::Project;
+use super::runner;
+use super::timings;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    net, path, thread, time,
+};
+use tracing::{trace, warn};
+
+/// The HTML page of a directory's entries, directories first.
+fn listing(dir: &path::Path, url: &str) -> io::Result<String> {
+    let mut entries: Vec<(bool, String)> = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            Some((!entry.path().is_dir(), name))
+        })
+        .collect();
+    entries.sort();
+    let mut html = page(&format!("dolly: {}", url));
+    html.push_str(&format!(
+        "</head>\n<body>\n<h1>{}</h1>\n<ul>\n",
+        escape_html(url)
+    ));
+    html.push_str("<li><a href=\"../\">../</a></li>\n");
+    for (file, name) in entries {
+        let name = if file { name } else { format!("{}/", name) };
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape_html(&name),
+            escape_html(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// The start of an HTML page.
+fn page(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\nbody {{ font-family: sans-serif; }}\ntd, th {{ padding: 2px 8px; text-align: left; }}\n\
+         .pass {{ color: #38761d; }}\n.fail {{ color: #cc0000; }}\n</style>\n",
+        escape_html(title)
+    )
+}
+
+/// How long ago a run was, as people read it.
+fn ago(timestamp: u64, now: u64) -> String {
+    match now.saturating_sub(timestamp) {
+        seconds @ 0..=119 => format!("{}s ago", seconds),
+        seconds @ 120..=7199 => format!("{} min ago", seconds / 60),
+        seconds @ 7200..=172_799 => format!("{} h ago", seconds / 3600),
+        seconds => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// The dashboard: the latest run of each test, links to the reports, and the history report.
+fn dashboard(project: &Project, runs: &[TestRun], now: u64) -> String {
+    let target_dir = project.target_dir();
+    let mut latest = BTreeMap::<&str, &TestRun>::new();
+    for run in runs {
+        latest.insert(&run.test, run);
+    }
+    let failed = latest.values().filter(|run| !run.passed).count();
+
+    // Reloaded every minute, for a screen in the lab.
+    let mut html = page(&format!("dolly: {}", project.package.name));
+    html.push_str("<meta http-equiv=\"refresh\" content=\"60\">\n</head>\n<body>\n");
+    html.push_str(&format!(
+        "<h1>{}</h1>\n<p>{} tests: <span class=\"pass\">{} passed</span>, \
+         <span class=\"fail\">{} failed</span> in their latest runs.</p>\n",
+        escape_html(&project.package.name),
+        latest.len(),
+        latest.len() - failed,
+        failed
+    ));
+
+    html.push_str("<p>");
+    let reports = [
+        (target_dir.join("coverage").join("index.html"), "Coverage"),
+        (timings::timings_file(&target_dir), "Timings"),
+    ];
+    for (file, title) in reports {
+        if let Some(file) = file
+            .strip_prefix(&target_dir)
+            .ok()
+            .filter(|_| file.is_file())
+        {
+            html.push_str(&format!(
+                "<a href=\"/target/{}\">{}</a> | ",
+                escape_html(&file.to_string_lossy()),
+                title
+            ));
+        }
+    }
+    html.push_str("<a href=\"/target/\">Build directories</a></p>\n");
+
+    html.push_str(
+        "<table>\n<tr><th>Test</th><th>Result</th><th>Duration</th><th>Run</th><th></th></tr>\n",
+    );
+    for run in latest.values() {
+        let (class, result) = if run.passed {
+            ("pass", "passed")
+        } else {
+            ("fail", "failed")
+        };
+        let dir = target_dir.join(&run.test);
+        let logs = if dir.is_dir() {
+            format!("<a href=\"/target/{}/\">logs</a>", escape_html(&run.test))
+        } else {
+            String::new()
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td>{:.1}s</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&run.test),
+            class,
+            result,
+            run.duration,
+            ago(run.timestamp, now),
+            logs
+        ));
+    }
+    html.push_str("</table>\n");
+    if !runs.is_empty() {
+        html.push_str(&format!(
+            "<h2>History</h2>\n<pre>{}</pre>\n",
+            escape_html(&history::report(runs, 10))
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// The file below the target directory a /target/ URL path names, refusing paths that
+/// would leave it.
+fn resolve(target_dir: &path::Path, url: &str) -> Option<path::PathBuf> {
+    let mut file = target_dir.to_path_buf();
+    for part in url
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+    {
+        let part = decode(part)?;
+        if part == ".." || part.contains(['/', '\\']) {
+            return None;
+        }
+        file.push(part);
+    }
+    Some(file)
+}
+
+/// A URL path segment without its %-escapes.
+fn decode(part: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = part.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The Content-Type of a file, by its extension.  Logs, waveforms and sources are shown as
+/// text.
+fn content_type(file: &path::Path) -> &'static str {
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("fst" | "bo" | "ba" | "so" | "o") => "application/octet-stream",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+/// Writes an HTTP response, without the body for a HEAD request.
+fn respond(
+    stream: &mut net::TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    head: bool,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if !head {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+/// Answers one request.
+fn handle(project: &Project, mut stream: net::TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(10)))?;
+    let mut reader = io::BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers don't matter.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    trace!("{}", request.trim_end());
+
+    let mut words = request.split_whitespace();
+    let (method, url) = (words.next().unwrap_or(""), words.next().unwrap_or("/"));
+    let url = url.split(['?', '#']).next().unwrap_or("/");
+    let head = method == "HEAD";
+    if method != "GET" && !head {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"",
+            head,
+        );
+    }
+    let html = "text/html; charset=utf-8";
+    if url == "/" {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let runs = history::load(&history::history_file(project.root_path()));
+        let body = dashboard(project, &runs, now);
+        return respond(&mut stream, "200 OK", html, body.as_bytes(), head);
+    }
+    let file = url
+        .strip_prefix("/target")
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .and_then(|rest| resolve(&project.target_dir(), rest));
+    match file {
+        Some(dir) if dir.is_dir() && !url.ends_with('/') => {
+            let location = format!("{}/", url);
+            write!(
+                stream,
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\n\
+                 Connection: close\r\n\r\n",
+                location
+            )
+        }
+        Some(dir) if dir.is_dir() => {
+            let body = listing(&dir, url)?;
+            respond(&mut stream, "200 OK", html, body.as_bytes(), head)
+        }
+        Some(file) if file.is_file() => {
+            let body = fs::read(&file)?;
+            respond(&mut stream, "200 OK", content_type(&file), &body, head)
+        }
+        _ => respond(
+            &mut stream,
+            "404 Not Found",
+            "text/plain",
+            b"Not found\n",
+            head,
+        ),
+    }
+}
+
+/// Serves the project's dashboard at `address` until dolly is interrupted.
+pub fn run(project: &Project, address: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = net::TcpListener::bind(address)
+        .map_err(|e| io::Error::other(format!("Unable to listen on {}: {}", address, e)))?;
+    // Polled, so that Ctrl-C (which dolly handles itself) stops the server.
+    listener.set_nonblocking(true)?;
+    println!(
+        "Serving the results of {} at http://{}/ (Ctrl-C to stop)",
+        project.package.name,
+        listener.local_addr()?
+    );
+    let served: io::Result<()> = thread::scope(|scope| loop {
+        if runner::is_interrupted() {
+            return Ok(());
+        }
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                stream.set_nonblocking(false)?;
+                scope.spawn(move || {
+                    if let Err(e) = handle(project, stream) {
+                        warn!("Unable to answer {}: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(time::Duration::from_millis(100))
+            }
+            Err(e) => return Err(e),
+        }
+    });
+    Ok(served?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serve_paths() {
+        let target = path::Path::new("/work/uart/target");
+        assert_eq!(
+            resolve(target, "/Uart_tb/run/"),
+            Some(target.join("Uart_tb").join("run"))
+        );
+        assert_eq!(
+            resolve(target, "/My%20Test/log.txt"),
+            Some(target.join("My Test").join("log.txt"))
+        );
+        assert_eq!(resolve(target, ""), Some(target.to_path_buf()));
+        assert_eq!(resolve(target, "/Uart_tb/../../dolly.toml"), None);
+        assert_eq!(resolve(target, "/%2e%2e/dolly.toml"), None);
+        assert_eq!(resolve(target, "/a%2Fb"), None);
+
+        assert_eq!(
+            content_type(path::Path::new("coverage/index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(path::Path::new("Uart_tb/sim.log")),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(ago(1000, 1090), "90s ago");
+        assert_eq!(ago(0, 3 * 86400), "3 days ago");
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, path,
+};
+
+/// One of `count` shards of the test suite, numbered from 1 (as in `--shard 2/8`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// Parses a shard given as `<index>/<count>`.
+pub fn parse(text: &str) -> Result<Shard, String> {
+    let invalid = || {
+        format!(
+            "expected <index>/<count> with 1 <= index <= count, got '{}'",
+            text
+        )
+    };
+    let (index, count) = text.split_once('/').ok_or_else(invalid)?;
+    let index: usize = index.trim().parse().map_err(|_| invalid())?;
+    let count: usize = count.trim().parse().map_err(|_| invalid())?;
+    if index == 0 || index > count {
+        return Err(invalid());
+    }
+    Ok(Shard { index, count })
+}
+
+/// Loads a file recording how long each test last took to build and run, in seconds.
+pub fn load_durations(file: &path::Path) -> BTreeMap<String, f64> {
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Merges `durations` into the durations file.
+pub fn record_durations(
+    file: &path::Path,
+    durations: &BTreeMap<String, f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if durations.is_empty() {
+        return Ok(());
+    }
+    let mut recorded = load_durations(file);
+    recorded.extend(
+        durations
+            .iter()
+            .map(|(name, seconds)| (name.clone(), *seconds)),
+    );
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file, toml::to_string(&recorded)?)?;
+    Ok(())
+}
+
+impl Shard {
+    /// The tests (by name) in this shard.  Every shard sees the same partition of `names` as
+    /// long as they share the durations (which is why they come from a committed file, not
+    /// each machine's own runs): without durations the tests are dealt out in name order, and
+    /// with them the longest tests are placed first, each on the shard with the least total
+    /// duration so far.  Tests without a recorded duration count as the average.
+    pub fn select(&self, names: &[String], durations: &BTreeMap<String, f64>) -> HashSet<String> {
+        let mut names: Vec<&String> = names.iter().collect();
+        names.sort();
+        names.dedup();
+
+        let known: Vec<f64> = names
+            .iter()
+            .filter_map(|name| durations.get(*name).copied())
+            .collect();
+        if known.is_empty() {
+            return names
+                .into_iter()
+                .enumerate()
+                .filter(|(position, _)| position % self.count == self.index - 1)
+                .map(|(_, name)| name.clone())
+                .collect();
+        }
+
+        let average = known.iter().sum::<f64>() / known.len() as f64;
+        let duration = |name: &String| durations.get(name).copied().unwrap_or(average);
+        // Stable sort, so equal durations stay in name order.
+        names.sort_by(|a, b| duration(b).total_cmp(&duration(a)));
+        let mut totals = vec![0.0f64; self.count];
+        let mut selected = HashSet::<String>::new();
+        for name in names {
+            let shard = (0..self.count)
+                .min_by(|a, b| totals[*a].total_cmp(&totals[*b]))
+                .unwrap();
+            totals[shard] += duration(name);
+            if shard == self.index - 1 {
+                selected.insert(name.clone());
+            }
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partitions() {
+        assert_eq!(parse("2/8"), Ok(Shard { index: 2, count: 8 }));
+        assert!(parse("0/8").is_err() && parse("9/8").is_err() && parse("2").is_err());
+
+        let names: Vec<String> = ["Alu_tb", "Cache_tb", "Core_tb", "Fifo_tb", "Uart_tb"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let shards = |durations: &BTreeMap<String, f64>| -> Vec<HashSet<String>> {
+            (1..=2)
+                .map(|index| Shard { index, count: 2 }.select(&names, durations))
+                .collect()
+        };
+
+        let dealt = shards(&BTreeMap::new());
+        assert_eq!(dealt[0].len() + dealt[1].len(), names.len());
+        assert!(dealt[0].contains("Alu_tb") && dealt[1].contains("Cache_tb"));
+
+        let durations = BTreeMap::from([
+            ("Core_tb".to_string(), 60.0),
+            ("Cache_tb".to_string(), 30.0),
+            ("Alu_tb".to_string(), 20.0),
+            ("Fifo_tb".to_string(), 10.0),
+        ]);
+        let weighted = shards(&durations);
+        assert_eq!(
+            weighted[0],
+            HashSet::from(["Core_tb".to_string(), "Alu_tb".to_string()])
+        );
+        assert_eq!(weighted[1].len(), 3);
+    }
+}
@@ -0,0 +1,311 @@
+//! `dolly size`: how big the design is.  The counts of rules (from the schedules bsc
+//! writes), registers, FIFOs and RAMs (from the generated Verilog) of each top module, summed
+//! over its instance hierarchy, and optionally the cells Yosys synthesizes it to.
+use super::codes;
+use super::config;
+use super::project::Project;
+use super::schedule;
+use super::verilog;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, path, process,
+};
+
+/// The contents of one generated module, not counting those of its submodules.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleSize {
+    pub rules: usize,
+    pub registers: usize,
+    pub register_bits: usize,
+    pub fifos: usize,
+    pub rams: usize,
+    pub instances: Vec<String>, // The modules of the other instances
+}
+
+impl ModuleSize {
+    fn add(&mut self, other: &ModuleSize) {
+        self.rules += other.rules;
+        self.registers += other.registers;
+        self.register_bits += other.register_bits;
+        self.fifos += other.fifos;
+        self.rams += other.rams;
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} rules, {} registers ({} bits), {} FIFOs, {} RAMs",
+            self.rules, self.registers, self.register_bits, self.fifos, self.rams
+        )
+    }
+}
+
+/// Words that start lines of the form `word name(` without being instances.
+pub const KEYWORDS: [&str; 8] = [
+    "module", "always", "initial", "assign", "function", "task", "if", "case",
+];
+
+/// Measures the module a bsc Verilog file defines.  Registers are those bsc inlined (a reg
+/// with a $D_IN) and Reg primitives, whose widths aren't counted; FIFOs and RAMs are the
+/// instances of the Bluespec primitives by name, so that a generated mkRxFIFO is a submodule.
+/// The Verilog is only matched with regular expressions, as bsc writes it.
+pub fn measure(source: &str) -> ModuleSize {
+    let reg_re = Regex::new(r"(?m)^\s*reg\s*(?:\[\s*(\d+)\s*:\s*(\d+)\s*\])?\s*(\w+)\s*;").unwrap();
+    let instance_re = Regex::new(r"(?m)^[ \t]*(\w+)[ \t]+(?:#[ \t]*\(|\w+[ \t]*\()").unwrap();
+    let rule_re = Regex::new(r"\bWILL_FIRE_RL_(\w+)").unwrap();
+
+    let rules: BTreeSet<&str> = rule_re
+        .captures_iter(source)
+        .map(|capture| capture.get(1).unwrap().as_str())
+        .collect();
+    let mut size = ModuleSize {
+        rules: rules.len(),
+        ..ModuleSize::default()
+    };
+    for capture in reg_re.captures_iter(source) {
+        if !source.contains(&format!("{}$D_IN", &capture[3])) {
+            continue;
+        }
+        let bits = match (capture.get(1), capture.get(2)) {
+            (Some(high), Some(low)) => {
+                let high: usize = high.as_str().parse().unwrap_or(0);
+                let low: usize = low.as_str().parse().unwrap_or(0);
+                high.abs_diff(low) + 1
+            }
+            _ => 1,
+        };
+        size.registers += 1;
+        size.register_bits += bits;
+    }
+    let primitive_reg_re = Regex::new(r"^(?:Config)?C?Reg(?:N|UN|A)\d*$").unwrap();
+    let primitive_fifo_re =
+        Regex::new(r"^(?:FIFOL?|SizedFIFOL?|SyncFIFOL?|SyncFIFOLevel|SyncBypassFIFO)\d*$").unwrap();
+    let primitive_ram_re =
+        Regex::new(r"^(?:BRAM[12](?:BE)?(?:Load)?|SyncBRAM[12]|RegFile(?:Load)?)$").unwrap();
+    for capture in instance_re.captures_iter(source) {
+        let module = &capture[1];
+        if KEYWORDS.contains(&module) {
+            continue;
+        }
+        if primitive_reg_re.is_match(module) {
+            size.registers += 1;
+        } else if primitive_fifo_re.is_match(module) {
+            size.fifos += 1;
+        } else if primitive_ram_re.is_match(module) {
+            size.rams += 1;
+        } else {
+            size.instances.push(module.to_string());
+        }
+    }
+    size
+}
+
+/// The size of a module with those of the generated modules it instantiates, recursively.
+fn hierarchy_size(modules: &BTreeMap<String, ModuleSize>, module: &str) -> ModuleSize {
+    let mut total = ModuleSize::default();
+    let mut remaining = vec![(module.to_string(), 0)];
+    while let Some((module, depth)) = remaining.pop() {
+        // Modules can't instantiate themselves, so a deeper hierarchy is a loop.
+        let Some(size) = modules.get(&module).filter(|_| depth <= modules.len()) else {
+            continue;
+        };
+        total.add(size);
+        remaining.extend(
+            size.instances
+                .iter()
+                .map(|instance| (instance.clone(), depth + 1)),
+        );
+    }
+    total
+}
+
+/// The generated modules in a top module's build directory, by (Verilog) name, with their
+/// files.  Rule counts come from the schedules when bsc wrote them.
+fn modules(
+    project: &Project,
+    build_path: &path::Path,
+) -> std::io::Result<BTreeMap<String, (path::PathBuf, ModuleSize)>> {
+    let prefix = project.verilog.module_prefix.as_deref().unwrap_or_default();
+    let mut modules = BTreeMap::new();
+    let mut files: Vec<path::PathBuf> = build_path
+        .read_dir()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "v" || ext == "sv")
+        })
+        .collect();
+    files.sort();
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let Some(module) = verilog::defined_modules(&source).into_iter().next() else {
+            continue;
+        };
+        let mut size = measure(&source);
+        let sched_file = build_path.join(format!(
+            "{}.sched",
+            module.strip_prefix(prefix).unwrap_or(&module)
+        ));
+        if let Some(schedule) = fs::read_to_string(sched_file)
+            .ok()
+            .and_then(|text| schedule::parse(&text))
+        {
+            size.rules = schedule.rules.len();
+        }
+        modules.insert(module, (file, size));
+    }
+    Ok(modules)
+}
+
+/// Runs `yosys stat` on a top module's Verilog (with the primitives it uses) after a generic
+/// synthesis, returning its report.
+fn yosys_stat(
+    build_path: &path::Path,
+    top_module: &str,
+    files: &[path::PathBuf],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let primitives = verilog::primitives(files)?;
+    let stat_file = build_path.join("yosys-stat.txt");
+    let sources: Vec<String> = files
+        .iter()
+        .chain(&primitives)
+        .map(|file| format!("\"{}\"", file.to_string_lossy()))
+        .collect();
+    let script = format!(
+        "read_verilog -sv {}; synth -flatten -top {}; tee -q -o \"{}\" stat",
+        sources.join(" "),
+        top_module,
+        stat_file.to_string_lossy()
+    );
+    let output = process::Command::new(config::tool("yosys"))
+        .arg("-q")
+        .arg("-p")
+        .arg(&script)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                codes::error("D0102", "Unable to locate 'yosys' program.")
+            }
+            _ => e,
+        })?;
+    if !output.status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "yosys failed on {}: {}",
+            top_module,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(fs::read_to_string(stat_file)?)
+}
+
+/// Implements `dolly size`: reports the size of each built top module, with `by_module` each
+/// of its generated modules too, and with `yosys` the gate-level statistics.
+pub fn report(
+    project: &Project,
+    top_modules: &[String],
+    by_module: bool,
+    yosys: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let prefix = project.verilog.module_prefix.as_deref().unwrap_or_default();
+    for top_module in top_modules {
+        let build_path = project.target_dir().join(top_module);
+        let modules = modules(project, &build_path)?;
+        let sizes: BTreeMap<String, ModuleSize> = modules
+            .iter()
+            .map(|(module, (_, size))| (module.clone(), size.clone()))
+            .collect();
+        let verilog_top = format!("{}{}", prefix, top_module);
+        println!(
+            "{}: {}",
+            top_module,
+            hierarchy_size(&sizes, &verilog_top).describe()
+        );
+
+        if by_module {
+            let width = sizes.keys().map(String::len).max().unwrap_or(0).max(6);
+            println!(
+                "  {:width$}  {:>5}  {:>9}  {:>6}  {:>5}  {:>4}  {:>9}",
+                "Module",
+                "Rules",
+                "Registers",
+                "Bits",
+                "FIFOs",
+                "RAMs",
+                "Instances",
+                width = width
+            );
+            for (module, size) in &sizes {
+                println!(
+                    "  {:width$}  {:>5}  {:>9}  {:>6}  {:>5}  {:>4}  {:>9}",
+                    module,
+                    size.rules,
+                    size.registers,
+                    size.register_bits,
+                    size.fifos,
+                    size.rams,
+                    size.instances.len(),
+                    width = width
+                );
+            }
+        }
+
+        if yosys {
+            let files: Vec<path::PathBuf> = modules.into_values().map(|(file, _)| file).collect();
+            print!("{}", yosys_stat(&build_path, &verilog_top, &files)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TOP: &str = "module mkTop(CLK, RST_N, out);
+  input  CLK;
+  input  RST_N;
+  reg [7 : 0] count;
+  wire [7 : 0] count$D_IN;
+  wire count$EN;
+  reg busy;
+  wire busy$D_IN, busy$EN;
+  reg [31 : 0] unused_display_temp;
+  wire CAN_FIRE_RL_tick, WILL_FIRE_RL_tick;
+  wire WILL_FIRE_RL_drain;
+  assign WILL_FIRE_RL_drain = count$EN && !WILL_FIRE_RL_tick;
+  FIFO2 #(.width(32'd8), .guarded(1'd1)) queue(.RST(RST_N), .CLK(CLK));
+  BRAM2 #(.PIPELINED(1'd0)) memory(.CLKA(CLK), .CLKB(CLK));
+  mkCore core(.CLK(CLK), .RST_N(RST_N));
+  mkCore core2(.CLK(CLK), .RST_N(RST_N));
+  mkRxFIFO rx(.CLK(CLK), .RST_N(RST_N));
+  SizedFIFO #(.p1width(32'd8), .p2depth(32'd4)) pending(.RST(RST_N), .CLK(CLK));
+  RegFileLoad #(.addr_width(32'd5)) regs(.CLK(CLK));
+  always @(posedge CLK)
+  begin
+    if (count$EN) count <= count$D_IN;
+  end
+endmodule
+";
+
+    #[test]
+    fn module_sizes() {
+        let top = measure(TOP);
+        assert_eq!(top.rules, 2);
+        assert_eq!((top.registers, top.register_bits), (2, 9));
+        assert_eq!((top.fifos, top.rams), (2, 2));
+        assert_eq!(top.instances, vec!["mkCore", "mkCore", "mkRxFIFO"]);
+
+        let core = ModuleSize {
+            rules: 3,
+            registers: 1,
+            register_bits: 32,
+            ..ModuleSize::default()
+        };
+        let modules = BTreeMap::from([("mkTop".to_string(), top), ("mkCore".to_string(), core)]);
+        let total = hierarchy_size(&modules, "mkTop");
+        assert_eq!(
+            (total.rules, total.registers, total.register_bits),
+            (8, 4, 73)
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fs, path};
+
+/// Where the output compared against a golden snapshot comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotSource {
+    Stdout,
+    File(path::PathBuf),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotResult {
+    Matched,
+    Updated,
+    Missing,
+    Mismatched(String),
+}
+
+/// Maximum number of differing lines reported for a mismatched snapshot.
+const MAX_DIFF_LINES: usize = 20;
+
+/// Returns the path of the golden file for the given test and snapshot source.
+pub fn golden_path(
+    snapshot_dir: &path::Path,
+    test_name: &str,
+    source: &SnapshotSource,
+) -> path::PathBuf {
+    match source {
+        SnapshotSource::Stdout => snapshot_dir.join(format!("{}.snap", test_name)),
+        SnapshotSource::File(file) => snapshot_dir.join(format!(
+            "{}.{}.snap",
+            test_name,
+            file.file_name().unwrap_or_default().to_string_lossy()
+        )),
+    }
+}
+
+/// Compares `actual` against the golden file, (re)writing the golden file instead when `update`
+/// is set.
+pub fn check(
+    golden: &path::Path,
+    actual: &str,
+    update: bool,
+) -> Result<SnapshotResult, Box<dyn std::error::Error>> {
+    if update {
+        if let Some(parent) = golden.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(golden, actual)?;
+        return Ok(SnapshotResult::Updated);
+    }
+
+    if !golden.exists() {
+        return Ok(SnapshotResult::Missing);
+    }
+
+    let expected = fs::read_to_string(golden)?;
+    if expected == actual {
+        Ok(SnapshotResult::Matched)
+    } else {
+        Ok(SnapshotResult::Mismatched(diff(&expected, actual)))
+    }
+}
+
+/// Produces a simple line-by-line difference report between the expected and actual output.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let mut report = String::new();
+    let mut reported = 0;
+    for line in 0..expected.len().max(actual.len()) {
+        let expected_line = expected.get(line);
+        let actual_line = actual.get(line);
+        if expected_line == actual_line {
+            continue;
+        }
+
+        if reported == MAX_DIFF_LINES {
+            report.push_str("...\n");
+            break;
+        }
+
+        if let Some(expected_line) = expected_line {
+            report.push_str(&format!("{:>5} - {}\n", line + 1, expected_line));
+        }
+        if let Some(actual_line) = actual_line {
+            report.push_str(&format!("{:>5} + {}\n", line + 1, actual_line));
+        }
+        reported += 1;
+    }
+
+    if report.is_empty() {
+        // Only line endings differ.
+        report.push_str("(outputs differ only in line endings)\n");
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_update_then_compare() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let snapshot_dir = temp.path();
+
+        let golden = golden_path(snapshot_dir, "trace_tb", &SnapshotSource::Stdout);
+        assert_eq!(check(&golden, "a\nb\n", false)?, SnapshotResult::Missing);
+        assert_eq!(check(&golden, "a\nb\n", true)?, SnapshotResult::Updated);
+        assert_eq!(check(&golden, "a\nb\n", false)?, SnapshotResult::Matched);
+        assert_eq!(
+            check(&golden, "a\nc\n", false)?,
+            SnapshotResult::Mismatched("    2 - b\n    2 + c\n".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_file_golden_path() {
+        let golden = golden_path(
+            path::Path::new("tests/snapshots"),
+            "trace_tb",
+            &SnapshotSource::File(path::PathBuf::from("out/trace.log")),
+        );
+        assert_eq!(
+            golden,
+            path::PathBuf::from("tests/snapshots/trace_tb.trace.log.snap")
+        );
+    }
+}
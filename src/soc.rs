@@ -0,0 +1,289 @@
+use super::bsv::{self, Interface, ModuleLocation};
+use super::builder::Builder;
+use super::project::{Project, SocConfig, SocInstance};
+use std::{collections::BTreeSet, fmt::Write, fs};
+
+/// An instance along with the module it instantiates and that module's interface.
+pub struct ResolvedInstance<'a> {
+    pub instance: &'a SocInstance,
+    pub location: ModuleLocation,
+    pub interface: Option<Interface>,
+}
+
+fn error(message: String) -> Box<dyn std::error::Error> {
+    Box::new(std::io::Error::other(message))
+}
+
+/// Splits an `instance.member` path, checking that the instance exists.
+fn split_path<'a>(
+    instances: &[ResolvedInstance],
+    path: &'a str,
+) -> Result<(&'a str, &'a str), Box<dyn std::error::Error>> {
+    let Some((instance, member)) = path.split_once('.') else {
+        return Err(error(format!("'{}' is not an instance.member path", path)));
+    };
+    if !instances
+        .iter()
+        .any(|resolved| resolved.instance.name == instance)
+    {
+        return Err(error(format!(
+            "Unknown instance '{}' in '{}'",
+            instance, path
+        )));
+    }
+    Ok((instance, member))
+}
+
+/// Generates the wrapper package: instances, their connections and the exported interface.
+pub fn generate_wrapper(
+    config: &SocConfig,
+    package: &str,
+    instances: &[ResolvedInstance],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut declarations = Vec::<String>::new();
+    let mut definitions = Vec::<String>::new();
+    let mut exported = BTreeSet::<String>::new();
+    for export in &config.exports {
+        let (instance, member) = split_path(instances, export)?;
+        if !exported.insert(member.to_string()) {
+            return Err(error(format!("'{}' is exported more than once", member)));
+        }
+        let resolved = instances
+            .iter()
+            .find(|resolved| resolved.instance.name == instance)
+            .unwrap();
+        let Some(interface) = &resolved.interface else {
+            return Err(error(format!(
+                "Interface {} of '{}' not found",
+                resolved.location.module.interface, instance
+            )));
+        };
+
+        if let Some(subinterface) = interface
+            .subinterfaces
+            .iter()
+            .find(|subinterface| subinterface.name == member)
+        {
+            declarations.push(format!("    interface {} {};", subinterface.ty, member));
+            definitions.push(format!("    interface {} = {};", member, export));
+        } else if let Some(method) = interface
+            .methods
+            .iter()
+            .find(|method| method.name == member)
+        {
+            let arguments: Vec<String> = method
+                .arguments
+                .iter()
+                .map(|argument| format!("{} {}", argument.ty, argument.name))
+                .collect();
+            let (prototype, call) = if arguments.is_empty() {
+                (format!("{} {}", method.result, member), export.clone())
+            } else {
+                let names: Vec<&str> = method
+                    .arguments
+                    .iter()
+                    .map(|argument| argument.name.as_str())
+                    .collect();
+                (
+                    format!("{} {}({})", method.result, member, arguments.join(", ")),
+                    format!("{}({})", export, names.join(", ")),
+                )
+            };
+            declarations.push(format!("    method {};", prototype));
+            definitions.push(format!("    method {} = {};", prototype, call));
+        } else {
+            return Err(error(format!(
+                "'{}' is not a method or subinterface of {}",
+                member, interface.name
+            )));
+        }
+    }
+
+    let mut wrapper = String::new();
+    writeln!(wrapper, "// Generated by dolly gen soc -- do not edit.").unwrap();
+    writeln!(wrapper, "package {};\n", package).unwrap();
+
+    let mut imports = BTreeSet::<String>::new();
+    if !config.connections.is_empty() {
+        imports.insert("Connectable".to_string());
+    }
+    imports.extend(instances.iter().map(|resolved| resolved.location.package()));
+    for import in imports {
+        writeln!(wrapper, "import {}::*;", import).unwrap();
+    }
+
+    let interface_name = if declarations.is_empty() {
+        "Empty".to_string()
+    } else {
+        writeln!(wrapper, "\ninterface {};", package).unwrap();
+        for declaration in &declarations {
+            writeln!(wrapper, "{}", declaration).unwrap();
+        }
+        writeln!(wrapper, "endinterface").unwrap();
+        package.to_string()
+    };
+
+    writeln!(wrapper, "\nmodule mk{}({});", package, interface_name).unwrap();
+    for resolved in instances {
+        let parameters = if resolved.instance.parameters.is_empty() {
+            String::new()
+        } else {
+            format!("({})", resolved.instance.parameters.join(", "))
+        };
+        writeln!(
+            wrapper,
+            "    {} {} <- {}{};",
+            resolved.location.module.interface,
+            resolved.instance.name,
+            resolved.location.module.name,
+            parameters
+        )
+        .unwrap();
+    }
+
+    if !config.connections.is_empty() {
+        writeln!(wrapper).unwrap();
+    }
+    for connection in &config.connections {
+        split_path(instances, &connection.from)?;
+        split_path(instances, &connection.to)?;
+        writeln!(
+            wrapper,
+            "    {}({}, {});",
+            connection.via, connection.from, connection.to
+        )
+        .unwrap();
+    }
+
+    if !definitions.is_empty() {
+        writeln!(wrapper).unwrap();
+    }
+    for definition in &definitions {
+        writeln!(wrapper, "{}", definition).unwrap();
+    }
+    writeln!(wrapper, "endmodule").unwrap();
+    writeln!(wrapper, "\nendpackage").unwrap();
+    Ok(wrapper)
+}
+
+/// Writes the `[soc]` wrapper, finding each instance's module in this package or its
+/// dependencies.
+pub fn generate(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(config) = &project.soc else {
+        return Err(error("No [soc] section in dolly.toml".to_string()));
+    };
+
+    let builder = Builder::find_dependencies(project, Builder::new())
+        .and_then(|builder| Builder::find_modules(project, builder))?;
+
+    let output = project.root_path().join(&config.output);
+    let mut instances = Vec::<ResolvedInstance>::new();
+    for instance in &config.instances {
+        if instances
+            .iter()
+            .any(|resolved| resolved.instance.name == instance.name)
+        {
+            return Err(error(format!("Duplicate instance '{}'", instance.name)));
+        }
+        let Some(location) = bsv::find_module(builder.module_paths(), &instance.module) else {
+            return Err(error(format!(
+                "Module {} of instance '{}' not found",
+                instance.module, instance.name
+            )));
+        };
+        if location.path == output {
+            return Err(error(format!(
+                "Instance '{}' refers to the generated wrapper",
+                instance.name
+            )));
+        }
+        let interface = bsv::find_interface(builder.module_paths(), &location.module.interface);
+        instances.push(ResolvedInstance {
+            instance,
+            location,
+            interface,
+        });
+    }
+
+    // The package is named after the file, as bsc requires.
+    let package = output.file_stem().unwrap().to_string_lossy().to_string();
+    let wrapper = generate_wrapper(config, &package, &instances)?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output, wrapper)?;
+    println!("Wrote {}", output.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path;
+
+    #[test]
+    fn soc_wrapper() {
+        let config: SocConfig = toml::from_str(
+            r#"
+            exports = ["uart.pins", "uart.baud"]
+            instances = [
+                { name = "cpu", module = "mkCore" },
+                { name = "uart", module = "mkUart", parameters = ["16"] },
+            ]
+            connections = [{ from = "cpu.bus", to = "uart.bus" }]
+            "#,
+        )
+        .unwrap();
+        let uart = bsv::parse_interfaces(
+            "interface Uart;\n    interface UartPins pins;\n    method Action baud(Bit#(16) divisor);\nendinterface\n",
+        );
+        let location = |name: &str, interface: &str, file: &str| ModuleLocation {
+            module: bsv::Module {
+                name: name.to_string(),
+                interface: interface.to_string(),
+                parameters: None,
+            },
+            path: path::PathBuf::from(file),
+        };
+        let instances = [
+            ResolvedInstance {
+                instance: &config.instances[0],
+                location: location("mkCore", "Core", "/deps/core/src/Core.bsv"),
+                interface: None,
+            },
+            ResolvedInstance {
+                instance: &config.instances[1],
+                location: location("mkUart", "Uart", "/p/src/Uart.bsv"),
+                interface: uart.into_iter().next(),
+            },
+        ];
+
+        assert_eq!(
+            generate_wrapper(&config, "Soc", &instances).unwrap(),
+            r#"// Generated by dolly gen soc -- do not edit.
+package Soc;
+
+import Connectable::*;
+import Core::*;
+import Uart::*;
+
+interface Soc;
+    interface UartPins pins;
+    method Action baud(Bit#(16) divisor);
+endinterface
+
+module mkSoc(Soc);
+    Core cpu <- mkCore;
+    Uart uart <- mkUart(16);
+
+    mkConnection(cpu.bus, uart.bus);
+
+    interface pins = uart.pins;
+    method Action baud(Bit#(16) divisor) = uart.baud(divisor);
+endmodule
+
+endpackage
+"#
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! "Did you mean" suggestions for names (of packages, directories, ...) that don't resolve.
+
+/// The edit (Levenshtein) distance between two names, ignoring case.
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The candidates close to a name, closest first: those differing only in case, or by at most
+/// one edit per three characters.
+pub fn similar<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut similar: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .collect();
+    similar.sort();
+    similar.dedup();
+    similar
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// The suggestions to add to an error message (" (did you mean a or b?)"), if there are any.
+pub fn did_you_mean(suggestions: &[&str]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [suggestion] => format!(" (did you mean {}?)", suggestion),
+        [suggestions @ .., last] => {
+            format!(" (did you mean {} or {}?)", suggestions.join(", "), last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("Fifo", "FIFO"), 0);
+        let candidates = ["FIFO", "FIFOF", "Vector", "fetch_unit", "fetch"];
+        assert_eq!(similar("Fifo", candidates), ["FIFO", "FIFOF"]);
+        assert_eq!(similar("fetch_unti", candidates), ["fetch_unit"]);
+        assert!(similar("Decoder", candidates).is_empty());
+        assert_eq!(did_you_mean(&[]), "");
+        assert_eq!(did_you_mean(&["FIFO"]), " (did you mean FIFO?)");
+        assert_eq!(did_you_mean(&["a", "b", "c"]), " (did you mean a, b or c?)");
+    }
+}
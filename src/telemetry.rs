@@ -0,0 +1,343 @@
+//! Tracing of where dolly's time goes, as `tracing` spans: one for the run of dolly, with a
+//! span for each pipeline stage (finding modules, building Verilog, running tests), each test,
+//! and each compile, link and run.  dolly's log messages are `tracing` events, shown on stderr
+//! as RUST_LOG selects them.  When an OTLP collector is configured ([telemetry] otlp-endpoint,
+//! or OTEL_EXPORTER_OTLP_ENDPOINT), the spans are exported to it over OTLP/HTTP (http:// or
+//! https://) by opentelemetry-otlp, and sent when dolly exits.
+use super::config::Telemetry;
+use opentelemetry::{trace::TracerProvider, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+    Resource,
+};
+use std::{
+    cell::RefCell,
+    io::IsTerminal,
+    sync::{Mutex, OnceLock},
+    time,
+};
+use tracing::{trace, warn};
+use tracing_subscriber::{
+    filter::LevelFilter, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer,
+    Registry,
+};
+
+type OtelLayer =
+    tracing_opentelemetry::OpenTelemetryLayer<Registry, opentelemetry_sdk::trace::Tracer>;
+
+/// Where `install` puts the layer exporting the spans.
+static OTEL: OnceLock<reload::Handle<Option<OtelLayer>, Registry>> = OnceLock::new();
+
+/// The exporting provider and the URL it sends to, while tracing.
+static PROVIDER: Mutex<Option<(SdkTracerProvider, String)>> = Mutex::new(None);
+
+/// The root span of the trace, which covers the whole run of dolly.
+static ROOT: Mutex<Option<tracing::Span>> = Mutex::new(None);
+
+/// The span of the current pipeline stage, if any.
+static STAGE: Mutex<Option<tracing::Span>> = Mutex::new(None);
+
+/// Why the export of spans failed, if it did.
+static FAILURE: Mutex<Option<String>> = Mutex::new(None);
+
+thread_local! {
+    /// The span of the target this thread is working on, if any.
+    static TARGET: RefCell<Option<tracing::Span>> = const { RefCell::new(None) };
+}
+
+/// Shows the log messages RUST_LOG selects (only errors by default) on stderr, and readies
+/// the export of spans for `install`.
+pub fn init() {
+    let (otel, handle) = reload::Layer::new(None::<OtelLayer>);
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+    let log = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .without_time()
+        .with_filter(filter);
+    if tracing_subscriber::registry()
+        .with(otel.with_filter(LevelFilter::INFO))
+        .with(log)
+        .try_init()
+        .is_ok()
+    {
+        let _ = OTEL.set(handle);
+    }
+}
+
+/// The OTLP exporter, keeping why an export failed (the SDK drops the error) for `finish`.
+#[derive(Debug)]
+struct Exporter(opentelemetry_otlp::SpanExporter);
+
+impl SpanExporter for Exporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let result = self.0.export(batch).await;
+        if let Err(e) = &result {
+            FAILURE.lock().unwrap().get_or_insert_with(|| e.to_string());
+        }
+        result
+    }
+
+    fn shutdown_with_timeout(&self, timeout: time::Duration) -> OTelSdkResult {
+        self.0.shutdown_with_timeout(timeout)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.0.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.0.set_resource(resource);
+    }
+}
+
+/// The traces URL of an OTLP/HTTP endpoint: <endpoint>/v1/traces.
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+/// The resource attributes of the trace: the service name and the configured attributes,
+/// under those of OTEL_SERVICE_NAME and OTEL_RESOURCE_ATTRIBUTES (k=v,k=v).
+fn resource(
+    config: &Telemetry,
+    variable: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let mut resource = vec![(
+        "service.name".to_string(),
+        variable("OTEL_SERVICE_NAME").unwrap_or_else(|| config.service_name.clone()),
+    )];
+    resource.extend(config.attributes.clone());
+    if let Some(attributes) = variable("OTEL_RESOURCE_ATTRIBUTES") {
+        resource.extend(attributes.split(',').filter_map(|attribute| {
+            let (key, value) = attribute.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        }));
+    }
+    resource
+}
+
+/// Starts tracing, when a collector is configured.  OTEL_EXPORTER_OTLP_ENDPOINT,
+/// OTEL_SERVICE_NAME and OTEL_RESOURCE_ATTRIBUTES take precedence over the configuration, as
+/// they do for other OpenTelemetry programs.
+pub fn install(config: &Telemetry) {
+    let variable = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let Some(endpoint) = variable("OTEL_EXPORTER_OTLP_ENDPOINT").or(config.otlp_endpoint.clone())
+    else {
+        return;
+    };
+    let (Some(handle), mut provider) = (OTEL.get(), PROVIDER.lock().unwrap()) else {
+        return;
+    };
+    if provider.is_some() {
+        return;
+    }
+    let url = traces_url(&endpoint);
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&url)
+        .with_timeout(time::Duration::from_secs(5))
+        .build()
+    {
+        Ok(exporter) => Exporter(exporter),
+        Err(e) => {
+            warn!("Unable to send traces to {}: {}", url, e);
+            return;
+        }
+    };
+    let attributes = resource(config, variable)
+        .into_iter()
+        .map(|(key, value)| KeyValue::new(key, value));
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder_empty()
+                .with_attributes(attributes)
+                .build(),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("dolly"));
+    if handle.reload(Some(layer)).is_err() {
+        return;
+    }
+    let args: Vec<String> = std::env::args().collect();
+    *ROOT.lock().unwrap() = Some(tracing::info_span!(
+        parent: None,
+        "dolly",
+        process.command_args = %args.join(" "),
+        otel.status_code = tracing::field::Empty,
+        otel.status_description = tracing::field::Empty,
+    ));
+    *provider = Some((tracer_provider, url));
+}
+
+/// Whether spans are being exported.
+pub fn enabled() -> bool {
+    PROVIDER.lock().unwrap().is_some()
+}
+
+/// The span new stages and targets are in: the current stage, else the root.
+fn current_stage() -> Option<tracing::Id> {
+    STAGE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .or(ROOT.lock().unwrap().as_ref())
+        .and_then(tracing::Span::id)
+}
+
+/// A span in progress, ended when dropped.
+pub struct Span {
+    _entered: Option<tracing::span::EnteredSpan>, // None when not tracing
+    stage: Option<Option<tracing::Span>>,         // For a stage, the stage it is nested in
+    target: bool,
+}
+
+impl Span {
+    fn new(span: tracing::Span) -> Self {
+        Self {
+            _entered: Some(span.entered()),
+            stage: None,
+            target: false,
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            _entered: None,
+            stage: None,
+            target: false,
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(outer) = self.stage.take() {
+            *STAGE.lock().unwrap() = outer;
+        }
+        if self.target {
+            TARGET.with(|target| target.borrow_mut().take());
+        }
+    }
+}
+
+/// Starts the span of a pipeline stage, nested in the stage running, if any.
+pub fn stage(name: &str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    let span = tracing::info_span!(
+        parent: current_stage(),
+        "stage",
+        otel.name = name,
+        dolly.stage = name,
+    );
+    let outer = STAGE.lock().unwrap().replace(span.clone());
+    let mut guard = Span::new(span);
+    guard.stage = Some(outer);
+    guard
+}
+
+/// Starts the span of work on one target (such as a test) on this thread, in the current
+/// stage.
+pub fn target(kind: &str, name: &str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    let span = tracing::info_span!(
+        parent: current_stage(),
+        "target",
+        otel.name = format!("{} {}", kind, name),
+        dolly.target = name,
+    );
+    TARGET.with(|target| *target.borrow_mut() = Some(span.clone()));
+    let mut guard = Span::new(span);
+    guard.target = true;
+    guard
+}
+
+/// Starts the span of a step (a compile, link, run...) of a target, in the target this thread
+/// is working on or else the current stage.
+pub fn step(phase: &str, target: &str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    let parent = TARGET
+        .with(|target| target.borrow().as_ref().and_then(tracing::Span::id))
+        .or_else(current_stage);
+    Span::new(tracing::info_span!(
+        parent: parent,
+        "step",
+        otel.name = format!("{} {}", phase, target),
+        dolly.phase = phase,
+        dolly.target = target,
+    ))
+}
+
+/// Ends the trace, failing its root span with the error dolly exits with, if any, and sends
+/// it to the collector.  A collector that can't be reached is only warned about.
+pub fn finish(error: Option<&str>) {
+    let Some((provider, url)) = PROVIDER.lock().unwrap().take() else {
+        return;
+    };
+    STAGE.lock().unwrap().take();
+    if let Some(root) = ROOT.lock().unwrap().take() {
+        match error {
+            Some(message) => {
+                root.record("otel.status_code", "error");
+                root.record("otel.status_description", message);
+            }
+            None => {
+                root.record("otel.status_code", "ok");
+            }
+        }
+    }
+    trace!("Sending the trace to {}", url);
+    let failure = provider.shutdown().err().map(|e| e.to_string());
+    if let Some(failure) = FAILURE.lock().unwrap().take().or(failure) {
+        warn!("Unable to send the trace to {}: {}", url, failure);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn otlp_export() {
+        assert_eq!(
+            traces_url("https://localhost:4318/"),
+            "https://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("http://collector/v1/traces"),
+            "http://collector/v1/traces"
+        );
+
+        let config = Telemetry {
+            attributes: [("ci.job".to_string(), "lint".to_string())].into(),
+            ..Telemetry::default()
+        };
+        let variable = |name: &str| {
+            (name == "OTEL_RESOURCE_ATTRIBUTES").then(|| "ci.job = test, host=build1".to_string())
+        };
+        assert_eq!(
+            resource(&config, variable),
+            [
+                ("service.name".to_string(), "dolly".to_string()),
+                ("ci.job".to_string(), "lint".to_string()),
+                ("ci.job".to_string(), "test".to_string()),
+                ("host".to_string(), "build1".to_string()),
+            ]
+        );
+    }
+}
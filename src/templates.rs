@@ -0,0 +1,562 @@
+//! The project templates `dolly init` instantiates: built-in skeletons, or a git repository.
+use super::codes;
+use super::config;
+use super::manifest;
+use super::net;
+use std::{fs, path, process};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Template {
+    #[default]
+    Default, // A top module and its testbench
+    Lib,         // Packages for other projects, with a unit test
+    TbOnly,      // Testbenches for a design kept elsewhere
+    Soc,         // An SoC wrapper generated from [soc] and [memory-map]
+    RiscvCore,   // A core with an ISA test harness
+    Git(String), // A template repository (URL or path)
+}
+
+/// The values of the `{{name}}`, `{{project}}` and `{{author}}` placeholders in template paths
+/// and files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variables {
+    pub name: String,    // The package name (UpperCamelCase)
+    pub project: String, // The project directory's name
+    pub author: String,
+}
+
+impl Variables {
+    pub fn substitute(&self, text: &str) -> String {
+        text.replace("{{name}}", &self.name)
+            .replace("{{project}}", &self.project)
+            .replace("{{author}}", &self.author)
+    }
+}
+
+/// The [package] metadata of a new project; unset fields are left out of dolly.toml.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub description: Option<String>,
+    pub edition: Option<String>,
+}
+
+fn git_config(key: &str) -> Option<String> {
+    process::Command::new(config::tool("git"))
+        .args(["config", key])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The author for new projects: git's user.name, or else the login name.
+pub fn default_author() -> String {
+    git_config("user.name")
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_default()
+}
+
+/// git's user.name and user.email, as an author for [package] authors.
+pub fn git_identity() -> Option<String> {
+    let name = git_config("user.name")?;
+    Some(match git_config("user.email") {
+        Some(email) => format!("{} <{}>", name, email),
+        None => name,
+    })
+}
+
+/// Sets the metadata fields of a manifest's [package] table, replacing those it already has
+/// and keeping the rest of the file (and its comments) as it is.
+pub fn add_metadata(manifest: &str, metadata: &Metadata) -> String {
+    let mut fields = Vec::<(&str, toml::Value)>::new();
+    if !metadata.authors.is_empty() {
+        let authors = metadata.authors.iter().cloned().map(toml::Value::String);
+        fields.push(("authors", toml::Value::Array(authors.collect())));
+    }
+    for (key, value) in [
+        ("license", &metadata.license),
+        ("description", &metadata.description),
+        ("edition", &metadata.edition),
+    ] {
+        if let Some(value) = value {
+            fields.push((key, toml::Value::String(value.clone())));
+        }
+    }
+
+    manifest::set_package_fields(manifest, &fields)
+}
+
+/// Makes a new project a git repository, with the project's files as its first commit.
+pub fn commit_initial(project_path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let commands: [&[&str]; 3] = [
+        &["init", "--quiet"],
+        &["add", "--all"],
+        &["commit", "--quiet", "--message", "Initial commit"],
+    ];
+    for arguments in commands {
+        let status = process::Command::new(config::tool("git"))
+            .args(arguments)
+            .current_dir(project_path)
+            .status()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    codes::error("D0102", "Unable to locate 'git' program.")
+                }
+                _ => e,
+            })?;
+        if !status.success() {
+            return Err(Box::new(std::io::Error::other(format!(
+                "'git {}' failed in {}",
+                arguments.join(" "),
+                project_path.to_string_lossy()
+            ))));
+        }
+    }
+    Ok(())
+}
+
+const MANIFEST: &str = r#"[package]
+name = "{{name}}"
+version = "0.1.0"
+"#;
+
+const GITIGNORE: &str = "**/target\n";
+
+const MODULE: &str = r#"//!topmodule mk{{name}}
+
+interface {{name}};
+    method Bool isWorking;
+endinterface
+
+module mk{{name}}({{name}});
+    method Bool isWorking;
+        return True;
+    endmethod
+endmodule
+"#;
+
+const TEST: &str = r#"//!topmodule mk{{name}}_tb
+import {{name}}::*;
+
+module mk{{name}}_tb(Empty);
+    {{name}} my_module <- mk{{name}};
+
+    rule run_it;
+        // Required for test to pass.
+        $display(">>>PASS");
+        $finish();
+    endrule
+endmodule
+"#;
+
+const LIB_PACKAGE: &str = r#"package {{name}};
+
+export {{name}}(..);
+export mk{{name}};
+
+interface {{name}};
+    method Action increment;
+    method Bit#(32) value;
+endinterface
+
+module mk{{name}}({{name}});
+    Reg#(Bit#(32)) count <- mkReg(0);
+
+    method Action increment;
+        count <= count + 1;
+    endmethod
+
+    method Bit#(32) value = count;
+endmodule
+
+endpackage
+"#;
+
+const LIB_TEST: &str = r#"//!topmodule mk{{name}}_tb
+import {{name}}::*;
+
+module mk{{name}}_tb(Empty);
+    {{name}} dut <- mk{{name}};
+    Reg#(Bit#(32)) cycle <- mkReg(0);
+
+    rule step (cycle < 3);
+        dut.increment;
+        cycle <= cycle + 1;
+    endrule
+
+    rule check (cycle == 3);
+        if (dut.value == 3) $display(">>>PASS");
+        $finish();
+    endrule
+endmodule
+"#;
+
+const TB_ONLY_MANIFEST: &str = r#"[package]
+name = "{{name}}"
+version = "0.1.0"
+
+# The design under test, as a dolly package (or precompiled = true for .bo files).
+[dependencies]
+# dut = { path = "../dut" }
+"#;
+
+const TB_ONLY_TEST: &str = r#"//!topmodule mk{{name}}_tb
+
+// Import the design under test's packages here.
+
+module mk{{name}}_tb(Empty);
+    rule run_it;
+        // TODO: instantiate and drive the design under test.
+        $display(">>>PASS");
+        $finish();
+    endrule
+endmodule
+"#;
+
+const SOC_MANIFEST: &str = r#"[package]
+name = "{{name}}"
+version = "0.1.0"
+
+# 'dolly gen memory-map' writes the address constants, 'dolly gen soc' the wrapper.
+[memory-map]
+address-width = 32
+bsv = "src/MemoryMap.bsv"
+c-header = "sw/include/memory_map.h"
+linker-script = "sw/memory_map.ld"
+regions = [
+    { name = "ram", base = 0x80000000, size = 0x10000 },
+    { name = "timer", base = 0x02000000, size = 0x1000 },
+]
+
+[soc]
+output = "src/Soc.bsv"
+instances = [
+    { name = "timer", module = "mkTimer" },
+]
+exports = ["timer.count"]
+"#;
+
+const SOC_TOP: &str = r#"//!topmodule mk{{name}}
+// The SoC wrapper (src/Soc.bsv) is generated by 'dolly gen soc' from the [soc] section.
+import Soc::*;
+
+module mk{{name}}(Empty);
+    Soc soc <- mkSoc;
+endmodule
+"#;
+
+const SOC_TIMER: &str = r#"package Timer;
+
+interface Timer;
+    method Bit#(64) count;
+endinterface
+
+module mkTimer(Timer);
+    Reg#(Bit#(64)) cycles <- mkReg(0);
+
+    rule tick;
+        cycles <= cycles + 1;
+    endrule
+
+    method Bit#(64) count = cycles;
+endmodule
+
+endpackage
+"#;
+
+const RISCV_MANIFEST: &str = r#"[package]
+name = "{{name}}"
+version = "0.1.0"
+
+[isa-test]
+top = "mkCoreTb"
+suites = ["rv32ui-p"]
+
+[toolchain.riscv]
+prefix = "riscv64-unknown-elf-"
+march = "rv32i"
+mabi = "ilp32"
+"#;
+
+const RISCV_CORE: &str = r#"//!topmodule mk{{name}}
+package {{name}};
+
+typedef Bit#(32) Word;
+
+interface {{name}};
+    // Instruction and data memory port (TODO: a request/response bus).
+    method Word address;
+    method Action response(Word data);
+    method Bool halted;
+endinterface
+
+module mk{{name}}({{name}});
+    Reg#(Word) pc <- mkReg('h80000000);
+    Reg#(Bool) done <- mkReg(False);
+
+    method Word address = pc;
+
+    method Action response(Word instruction);
+        // TODO: decode and execute the instruction.
+        pc <= pc + 4;
+    endmethod
+
+    method Bool halted = done;
+endmodule
+
+endpackage
+"#;
+
+const RISCV_TB: &str = r#"//!topmodule mkCoreTb
+// Runs the core on program.hex (written by 'dolly isa-test'); a test passes when it writes 1
+// to tohost.
+import RegFile::*;
+import {{name}}::*;
+
+module mkCoreTb(Empty);
+    {{name}} core <- mk{{name}};
+    RegFile#(Bit#(16), Word) memory <- mkRegFileLoad("program.hex", 0, 'hffff);
+    Reg#(Bit#(32)) cycle <- mkReg(0);
+
+    rule fetch (!core.halted);
+        core.response(memory.sub(truncate((core.address - 'h80000000) >> 2)));
+        cycle <= cycle + 1;
+    endrule
+
+    rule timeout (core.halted || cycle == 100000);
+        // TODO: check tohost and print >>>PASS on success.
+        $finish();
+    endrule
+endmodule
+"#;
+
+/// The files of a built-in template, by path (which may use placeholders too).
+fn files(template: &Template) -> Vec<(&'static str, &'static str)> {
+    let mut files = vec![(".gitignore", GITIGNORE)];
+    files.extend(match template {
+        Template::Default | Template::Git(_) => vec![
+            ("dolly.toml", MANIFEST),
+            ("src/{{name}}.bsv", MODULE),
+            ("tests/{{name}}_tb.bsv", TEST),
+        ],
+        Template::Lib => vec![
+            ("dolly.toml", MANIFEST),
+            ("src/{{name}}.bsv", LIB_PACKAGE),
+            ("src/{{name}}_tb.bsv", LIB_TEST),
+        ],
+        Template::TbOnly => vec![
+            ("dolly.toml", TB_ONLY_MANIFEST),
+            ("src/.gitkeep", ""),
+            ("tests/{{name}}_tb.bsv", TB_ONLY_TEST),
+        ],
+        Template::Soc => vec![
+            ("dolly.toml", SOC_MANIFEST),
+            ("src/{{name}}.bsv", SOC_TOP),
+            ("src/Timer.bsv", SOC_TIMER),
+        ],
+        Template::RiscvCore => vec![
+            ("dolly.toml", RISCV_MANIFEST),
+            ("src/{{name}}.bsv", RISCV_CORE),
+            ("tests/CoreTb.bsv", RISCV_TB),
+        ],
+    });
+    files
+}
+
+/// Copies a template checkout into the new project, substituting the placeholders in the
+/// paths and in the text files.
+fn copy_tree(
+    from: &path::Path,
+    to: &path::Path,
+    variables: &Variables,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in from.read_dir()? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+        let destination = to.join(variables.substitute(&name));
+        if path.is_dir() {
+            fs::create_dir_all(&destination)?;
+            copy_tree(&path, &destination, variables)?;
+        } else {
+            match fs::read_to_string(&path) {
+                Ok(text) => fs::write(&destination, variables.substitute(&text))?,
+                Err(_) => {
+                    fs::copy(&path, &destination)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a template into a new (empty) project directory.
+pub fn instantiate(
+    template: &Template,
+    project_path: &path::Path,
+    variables: &Variables,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // dolly expects both, even where a template leaves one empty.
+    fs::create_dir_all(project_path.join("src"))?;
+    fs::create_dir_all(project_path.join("tests"))?;
+    let Template::Git(url) = template else {
+        for (file, contents) in files(template) {
+            let file = project_path.join(variables.substitute(file));
+            fs::create_dir_all(file.parent().unwrap())?;
+            fs::write(file, variables.substitute(contents))?;
+        }
+        return Ok(());
+    };
+
+    let checkout = std::env::temp_dir().join(format!("dolly-template-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&checkout);
+    let mut command = net::git();
+    command.args(["clone", "--quiet"]);
+    if !path::Path::new(url).exists() {
+        net::forbid_fetching(&[format!("the template from {}", url)])?;
+        command.args(["--depth", "1"]); // Ignored (with a warning) by local clones
+    }
+    let status = command
+        .arg(url)
+        .arg(&checkout)
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                codes::error("D0102", "Unable to locate 'git' program.")
+            }
+            _ => e,
+        })?;
+    if !status.success() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Unable to clone template {}",
+            url
+        ))));
+    }
+    let copied = copy_tree(&checkout, project_path, variables);
+    let _ = fs::remove_dir_all(&checkout);
+    copied?;
+    if !project_path.join("dolly.toml").exists() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Template {} has no dolly.toml",
+            url
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn template_files() {
+        let variables = Variables {
+            name: "Uart".to_string(),
+            project: "uart".to_string(),
+            author: "Ada".to_string(),
+        };
+        assert_eq!(
+            variables.substitute("// {{name}} ({{project}}) by {{author}}"),
+            "// Uart (uart) by Ada"
+        );
+
+        let root = std::env::temp_dir().join(format!("dolly-templates-{}", std::process::id()));
+        for template in [
+            Template::Default,
+            Template::Lib,
+            Template::TbOnly,
+            Template::Soc,
+            Template::RiscvCore,
+        ] {
+            let project_path = root.join(format!("{:?}", template));
+            instantiate(&template, &project_path, &variables).unwrap();
+            let manifest = fs::read_to_string(project_path.join("dolly.toml")).unwrap();
+            assert!(manifest.starts_with("[package]\nname = \"Uart\"\n"));
+            assert!(toml::from_str::<toml::Value>(&manifest).is_ok());
+        }
+        let module = fs::read_to_string(root.join("Default/src/Uart.bsv")).unwrap();
+        assert!(module.starts_with("//!topmodule mkUart\n"));
+        assert!(root.join("Lib/src/Uart_tb.bsv").exists());
+        assert!(root.join("RiscvCore/tests/CoreTb.bsv").exists());
+
+        let template = root.join("template");
+        fs::create_dir_all(template.join("src")).unwrap();
+        fs::write(template.join("dolly.toml"), MANIFEST).unwrap();
+        fs::write(template.join("src/{{name}}.bsv"), "// {{author}}\n").unwrap();
+        let project_path = root.join("copied");
+        fs::create_dir_all(&project_path).unwrap();
+        copy_tree(&template, &project_path, &variables).unwrap();
+        assert_eq!(
+            fs::read_to_string(project_path.join("src/Uart.bsv")).unwrap(),
+            "// Ada\n"
+        );
+
+        // Unknown placeholders are left alone, binary files are copied as they are, and the
+        // template's git directory isn't copied.
+        fs::create_dir_all(template.join(".git")).unwrap();
+        fs::write(template.join("src/rom.bin"), [0xff, 0xfe, 0x7b, 0x7b]).unwrap();
+        fs::write(template.join("README"), "{{name}} by {{maintainer}}\n").unwrap();
+        copy_tree(&template, &project_path, &variables).unwrap();
+        assert_eq!(
+            fs::read(project_path.join("src/rom.bin")).unwrap(),
+            [0xff, 0xfe, 0x7b, 0x7b]
+        );
+        assert_eq!(
+            fs::read_to_string(project_path.join("README")).unwrap(),
+            "Uart by {{maintainer}}\n"
+        );
+        assert!(!project_path.join(".git").exists());
+        assert!(copy_tree(&root.join("none"), &project_path, &variables).is_err());
+
+        // A template that isn't a repository, or has no dolly.toml, is an error.
+        let git_template = Template::Git(template.to_string_lossy().to_string());
+        let error = instantiate(&git_template, &root.join("unclonable"), &variables).unwrap_err();
+        assert!(error.to_string().starts_with("Unable to clone template"));
+        fs::remove_dir_all(template.join(".git")).unwrap();
+        fs::remove_file(template.join("dolly.toml")).unwrap();
+        let git = |args: &[&str]| {
+            assert!(net::git()
+                .args(["-c", "user.name=Ada", "-c", "user.email=ada@example.com"])
+                .args(args)
+                .current_dir(&template)
+                .output()
+                .unwrap()
+                .status
+                .success())
+        };
+        git(&["init", "--quiet"]);
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "Template"]);
+        let error = instantiate(&git_template, &root.join("unmanifested"), &variables).unwrap_err();
+        assert!(error.to_string().ends_with("has no dolly.toml"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn package_metadata() {
+        let metadata = Metadata {
+            authors: vec!["Ada <ada@example.com>".to_string()],
+            license: Some("Apache-2.0".to_string()),
+            description: None,
+            edition: Some("2024".to_string()),
+        };
+        let manifest = "[package]\nname = \"Uart\"\nlicense = \"MIT\"\n\n[dependencies]\n";
+        assert_eq!(
+            add_metadata(manifest, &metadata),
+            r#"[package]
+name = "Uart"
+license = "Apache-2.0"
+authors = ["Ada <ada@example.com>"]
+edition = "2024"
+
+[dependencies]
+"#
+        );
+        assert_eq!(add_metadata("", &metadata), "");
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, fs, path};
+
+/// How long one step of a build took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+    pub phase: &'static str, // verilog, lint, compile, link or run
+    pub target: String,
+    pub start: f64,    // Seconds from the start of the build
+    pub duration: f64, // Seconds
+}
+
+pub fn timings_file(target_dir: &path::Path) -> path::PathBuf {
+    target_dir.join("dolly-timings.html")
+}
+
+/// The total time of each phase, longest first.
+fn phase_totals(timings: &[Timing]) -> Vec<(&'static str, f64, usize)> {
+    let mut totals = BTreeMap::<&'static str, (f64, usize)>::new();
+    for timing in timings {
+        let total = totals.entry(timing.phase).or_default();
+        total.0 += timing.duration;
+        total.1 += 1;
+    }
+    let mut totals: Vec<(&'static str, f64, usize)> = totals
+        .into_iter()
+        .map(|(phase, (duration, count))| (phase, duration, count))
+        .collect();
+    totals.sort_by(|a, b| b.1.total_cmp(&a.1));
+    totals
+}
+
+fn slowest(timings: &[Timing]) -> Vec<&Timing> {
+    let mut slowest: Vec<&Timing> = timings.iter().collect();
+    slowest.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+    slowest
+}
+
+/// The terminal summary: the time per phase and the ten slowest steps.
+pub fn summary(timings: &[Timing]) -> String {
+    let mut summary = String::from("Timings:\n");
+    for (phase, duration, count) in phase_totals(timings) {
+        summary.push_str(&format!(
+            "  {:8} {:8.1}s  ({} steps)\n",
+            phase, duration, count
+        ));
+    }
+    summary.push_str("Slowest steps:\n");
+    for timing in slowest(timings).into_iter().take(10) {
+        summary.push_str(&format!(
+            "  {:8} {:8.1}s  {}\n",
+            timing.phase, timing.duration, timing.target
+        ));
+    }
+    summary
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Writes an HTML report with the phase totals and a timeline of every step.
+pub fn write_html(file: &path::Path, timings: &[Timing]) -> std::io::Result<()> {
+    let end = timings
+        .iter()
+        .map(|timing| timing.start + timing.duration)
+        .fold(0.0f64, f64::max)
+        .max(f64::EPSILON);
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>dolly timings</title>\n\
+         <style>\nbody { font-family: sans-serif; }\ntd, th { padding: 2px 8px; text-align: left; }\n\
+         .bar { height: 12px; background: #4a7ebb; }\n.run { background: #6aa84f; }\n\
+         .link { background: #e69138; }\n.verilog { background: #8e7cc3; }\n</style>\n</head>\n<body>\n",
+    );
+    html.push_str(&format!(
+        "<h1>dolly timings</h1>\n<p>{} steps, {:.1}s in all.</p>\n",
+        timings.len(),
+        end
+    ));
+
+    html.push_str(
+        "<h2>Phases</h2>\n<table>\n<tr><th>Phase</th><th>Steps</th><th>Total</th></tr>\n",
+    );
+    for (phase, duration, count) in phase_totals(timings) {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{:.2}s</td></tr>\n",
+            phase, count, duration
+        ));
+    }
+    html.push_str("</table>\n");
+
+    html.push_str(
+        "<h2>Steps</h2>\n<table>\n<tr><th>Phase</th><th>Target</th><th>Start</th><th>Duration</th><th></th></tr>\n",
+    );
+    for timing in timings {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{:.2}s</td><td>{:.2}s</td>\
+             <td style=\"width: 600px\"><div class=\"bar {}\" style=\"margin-left: {:.1}%; width: {:.1}%\"></div></td></tr>\n",
+            timing.phase,
+            escape(&timing.target),
+            timing.start,
+            timing.duration,
+            timing.phase,
+            100.0 * timing.start / end,
+            (100.0 * timing.duration / end).max(0.2)
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file, html)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timing_report() {
+        let timing = |phase, target: &str, start, duration| Timing {
+            phase,
+            target: target.to_string(),
+            start,
+            duration,
+        };
+        let timings = vec![
+            timing("compile", "Core_tb", 0.0, 40.0),
+            timing("link", "Core_tb", 40.0, 15.0),
+            timing("run", "Core_tb", 55.0, 5.0),
+            timing("compile", "<Alu_tb>", 60.0, 20.0),
+        ];
+        assert_eq!(
+            phase_totals(&timings),
+            vec![("compile", 60.0, 2), ("link", 15.0, 1), ("run", 5.0, 1)]
+        );
+        let summary = summary(&timings);
+        assert!(summary.contains("  compile      60.0s  (2 steps)\n"));
+        assert!(summary.contains("  link         15.0s  Core_tb\n"));
+
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("dolly-timings.html");
+        write_html(&file, &timings).unwrap();
+        let html = fs::read_to_string(&file).unwrap();
+        assert!(html.contains("&lt;Alu_tb&gt;"));
+        assert!(html.contains("margin-left: 75.0%; width: 25.0%"));
+
+        // No steps, or only instant ones, make an empty report rather than dividing by zero.
+        assert_eq!(super::summary(&[]), "Timings:\nSlowest steps:\n");
+        write_html(&file, &[]).unwrap();
+        assert!(fs::read_to_string(&file)
+            .unwrap()
+            .contains("0 steps, 0.0s in all."));
+        write_html(&file, &[timing("verilog", "mkCore", 0.0, 0.0)]).unwrap();
+        let html = fs::read_to_string(&file).unwrap();
+        assert!(!html.contains("NaN") && !html.contains("inf"));
+        assert!(html.contains("margin-left: 0.0%; width: 0.2%"));
+
+        // A report that can't be written is an error.
+        assert!(write_html(&file.join("dolly-timings.html"), &timings).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use regex::Regex;
+use std::{collections::BTreeSet, env, fs, path};
+
+/// Locates the Bluespec installation: `$BLUESPECDIR`, or the `lib` directory next to the `bsc`
+/// found on the `PATH`.
+pub fn bluespec_dir() -> Option<path::PathBuf> {
+    if let Some(dir) = env::var_os("BLUESPECDIR") {
+        return Some(path::PathBuf::from(dir));
+    }
+
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join("bsc"))
+        .find(|bsc| bsc.is_file())
+        .and_then(|bsc| bsc.canonicalize().ok())
+        .and_then(|bsc| Some(bsc.parent()?.parent()?.join("lib")))
+}
+
+/// Returns the Verilog sources of the Bluespec primitives (FIFO2, RegN, ...) instantiated by the
+/// given Verilog files, including primitives instantiated by other primitives.
+pub fn primitives(verilog_files: &[path::PathBuf]) -> std::io::Result<Vec<path::PathBuf>> {
+    let Some(library) = bluespec_dir().map(|dir| dir.join("Verilog")) else {
+        return Ok(Vec::new());
+    };
+    let instance_re = Regex::new(r"(?m)^\s*(\w+)\s*(?:#\s*\(|\w+\s*\()").unwrap();
+
+    let mut found = BTreeSet::<path::PathBuf>::new();
+    let mut pending: Vec<path::PathBuf> = verilog_files.to_vec();
+    while let Some(file) = pending.pop() {
+        let contents = fs::read_to_string(&file)?;
+        for capture in instance_re.captures_iter(&contents) {
+            let primitive = library.join(format!("{}.v", &capture[1]));
+            if primitive.is_file() && found.insert(primitive.clone()) {
+                pending.push(primitive);
+            }
+        }
+    }
+
+    Ok(found.into_iter().collect())
+}
+
+/// The Verilog files of the last build, in the directories of the targets under a target
+/// directory.
+pub fn generated_files(target_dir: &path::Path) -> Vec<path::PathBuf> {
+    let mut directories: Vec<path::PathBuf> = target_dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    directories.sort();
+    let mut files: Vec<path::PathBuf> = directories
+        .iter()
+        .flat_map(|directory| directory.read_dir().into_iter().flatten())
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "v" || ext == "sv")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Matches strings and comments (which are kept as they are) and identifiers.
+const TOKEN_PATTERN: &str = r#""(?:[^"\\\n]|\\.)*"|//[^\n]*|(?s:/\*.*?\*/)|[A-Za-z_][\w$]*"#;
+
+/// Returns the names of the modules a Verilog source defines.
+pub fn defined_modules(source: &str) -> Vec<String> {
+    let module_re = Regex::new(r"(?m)^\s*module\s+(\w+)").unwrap();
+    module_re
+        .captures_iter(source)
+        .map(|capture| capture[1].to_string())
+        .collect()
+}
+
+/// Prefixes the definitions and instantiations of the named modules.
+pub fn prefix_modules(source: &str, modules: &BTreeSet<String>, prefix: &str) -> String {
+    let token_re = Regex::new(TOKEN_PATTERN).unwrap();
+    token_re
+        .replace_all(source, |capture: &regex::Captures| {
+            let token = &capture[0];
+            if modules.contains(token) {
+                format!("{}{}", prefix, token)
+            } else {
+                token.to_string()
+            }
+        })
+        .into_owned()
+}
+
+/// SystemVerilog keywords that aren't Verilog-2005 keywords, and so may appear as identifiers
+/// in bsc's output.
+const SYSTEM_VERILOG_KEYWORDS: &[&str] = &[
+    "accept_on",
+    "alias",
+    "always_comb",
+    "always_ff",
+    "always_latch",
+    "assert",
+    "assume",
+    "before",
+    "bind",
+    "bins",
+    "binsof",
+    "bit",
+    "break",
+    "byte",
+    "chandle",
+    "checker",
+    "class",
+    "clocking",
+    "const",
+    "constraint",
+    "context",
+    "continue",
+    "cover",
+    "covergroup",
+    "coverpoint",
+    "cross",
+    "dist",
+    "do",
+    "endchecker",
+    "endclass",
+    "endclocking",
+    "endgroup",
+    "endinterface",
+    "endpackage",
+    "endprogram",
+    "endproperty",
+    "endsequence",
+    "enum",
+    "eventually",
+    "expect",
+    "export",
+    "extends",
+    "extern",
+    "final",
+    "first_match",
+    "foreach",
+    "forkjoin",
+    "global",
+    "iff",
+    "ignore_bins",
+    "illegal_bins",
+    "implements",
+    "implies",
+    "import",
+    "inside",
+    "int",
+    "interconnect",
+    "interface",
+    "intersect",
+    "join_any",
+    "join_none",
+    "let",
+    "local",
+    "logic",
+    "longint",
+    "matches",
+    "modport",
+    "nettype",
+    "new",
+    "nexttime",
+    "null",
+    "package",
+    "packed",
+    "priority",
+    "program",
+    "property",
+    "protected",
+    "pure",
+    "rand",
+    "randc",
+    "randcase",
+    "randsequence",
+    "ref",
+    "reject_on",
+    "restrict",
+    "return",
+    "s_always",
+    "s_eventually",
+    "s_nexttime",
+    "s_until",
+    "s_until_with",
+    "sequence",
+    "shortint",
+    "shortreal",
+    "soft",
+    "solve",
+    "static",
+    "string",
+    "strong",
+    "struct",
+    "super",
+    "sync_accept_on",
+    "sync_reject_on",
+    "tagged",
+    "this",
+    "throughout",
+    "timeprecision",
+    "timeunit",
+    "type",
+    "typedef",
+    "union",
+    "unique",
+    "unique0",
+    "until",
+    "until_with",
+    "untyped",
+    "var",
+    "virtual",
+    "void",
+    "wait_order",
+    "weak",
+    "wildcard",
+    "with",
+    "within",
+];
+
+/// Post-processes bsc's Verilog for strict SystemVerilog lint: identifiers that are
+/// SystemVerilog keywords get a trailing underscore and implicit nets are disallowed.  Ports
+/// aren't renamed, which would change the module's interface, so a port that is a keyword is
+/// an error; nor are `import "DPI-C"` declarations (and the functions they import), which
+/// must keep the names and types of C.
+pub fn to_system_verilog(source: &str) -> Result<String, String> {
+    let port_re =
+        Regex::new(r"(?m)^\s*(?:input|output|inout)\b[^;]*?([A-Za-z_][\w$]*)\s*;").unwrap();
+    let ports: Vec<&str> = port_re
+        .captures_iter(source)
+        .map(|capture| capture.get(1).unwrap().as_str())
+        .filter(|port| SYSTEM_VERILOG_KEYWORDS.contains(port))
+        .collect();
+    if !ports.is_empty() {
+        return Err(format!(
+            "the port{} {} {} SystemVerilog keyword{}; rename the method or argument (or use a \
+             port attribute) so that the module can be instantiated from SystemVerilog",
+            if ports.len() == 1 { "" } else { "s" },
+            ports.join(", "),
+            if ports.len() == 1 { "is a" } else { "are" },
+            if ports.len() == 1 { "" } else { "s" },
+        ));
+    }
+
+    let import_re = Regex::new(r#"(?m)^[ \t]*import[ \t]+"DPI(?:-C)?"[^\n]*"#).unwrap();
+    let function_re = Regex::new(r"([A-Za-z_][\w$]*)\s*\(").unwrap();
+    let imports: Vec<(usize, usize)> = import_re
+        .find_iter(source)
+        .map(|import| (import.start(), import.end()))
+        .collect();
+    let imported: Vec<&str> = import_re
+        .find_iter(source)
+        .filter_map(|import| function_re.captures(import.as_str()))
+        .map(|capture| capture.get(1).unwrap().as_str())
+        .collect();
+
+    let token_re = Regex::new(TOKEN_PATTERN).unwrap();
+    let renamed = token_re.replace_all(source, |capture: &regex::Captures| {
+        let token = capture.get(0).unwrap();
+        let in_import = imports
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&token.start()));
+        let name = token.as_str();
+        if SYSTEM_VERILOG_KEYWORDS.contains(&name) && !in_import && !imported.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_string()
+        }
+    });
+
+    Ok(format!(
+        "`default_nettype none\n{}`default_nettype wire\n",
+        renamed
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefixed_modules() {
+        let verilog = "module mkTop(CLK);\n  mkSub sub(.CLK(CLK)); // mkSub\n  FIFO2 #(.width(8)) fifo(.CLK(CLK));\nendmodule\n";
+        let modules: BTreeSet<String> =
+            defined_modules("module mkTop(CLK);\nendmodule\n\nmodule mkSub(CLK);\nendmodule\n")
+                .into_iter()
+                .collect();
+        assert_eq!(
+            prefix_modules(verilog, &modules, "uart_"),
+            "module uart_mkTop(CLK);\n  uart_mkSub sub(.CLK(CLK)); // mkSub\n  FIFO2 #(.width(8)) fifo(.CLK(CLK));\nendmodule\n"
+        );
+    }
+
+    #[test]
+    fn system_verilog_keywords() {
+        let verilog = "module mkTop(CLK, value);\n  input value; // logic\n  initial $display(\"final\");\n  wire final$EN, logic;\n  import \"DPI-C\" function string alias(input int bit);\n  assign logic = alias(32'd1);\nendmodule\n";
+        assert_eq!(
+            to_system_verilog(verilog).unwrap(),
+            "`default_nettype none\nmodule mkTop(CLK, value);\n  input value; // logic\n  initial $display(\"final\");\n  wire final$EN, logic_;\n  import \"DPI-C\" function string alias(input int bit);\n  assign logic_ = alias(32'd1);\nendmodule\n`default_nettype wire\n"
+        );
+
+        let verilog = "module mkTop(CLK, logic, RDY_logic);\n  input CLK;\n  input [7 : 0] logic;\n  output RDY_logic;\nendmodule\n";
+        assert!(to_system_verilog(verilog)
+            .unwrap_err()
+            .starts_with("the port logic is a SystemVerilog keyword;"));
+    }
+}
@@ -0,0 +1,466 @@
+//! Semantic versions of packages and requirements on them, and `dolly bump`.
+use super::changes;
+use super::codes;
+use super::manifest;
+use super::project::Project;
+use super::workspace::Workspace;
+use std::{cmp::Ordering, fmt, fs};
+
+/// A semantic version (major.minor.patch, with an optional pre-release).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Option<String>, // "rc.1" of 1.2.0-rc.1
+}
+
+/// How a requirement bounds the versions it accepts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Caret, // ^1.2.3 (or 1.2.3): compatible versions, up to the next change of the first non-zero part
+    Tilde, // ~1.2.3: patch releases (or minor ones for ~1)
+    Exact, // =1.2.3: that version (or, for =1.2, any of its patches)
+    Any,   // *
+}
+
+/// A requirement on the version of a dependency, as in Cargo: "^1.2", "~1.2.3", "=1.2.0" or
+/// "*".
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionReq {
+    operator: Operator,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Option<String>,
+    text: String, // As written
+}
+
+/// The part of a version `dolly bump` increments.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Part {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) if !pre.is_empty() => (release, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (version, None),
+        };
+        let numbers: Vec<u64> = release
+            .split('.')
+            .map(|number| {
+                let valid = !number.is_empty()
+                    && number.chars().all(|c| c.is_ascii_digit())
+                    && (number == "0" || !number.starts_with('0'));
+                valid.then(|| number.parse().ok()).flatten()
+            })
+            .collect::<Option<_>>()?;
+        match numbers[..] {
+            [major, minor, patch] => Some(Self {
+                major,
+                minor,
+                patch,
+                pre,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The next version: a release of a pre-release of it, or the part incremented and the
+    /// parts after it reset.
+    pub fn bump(&self, part: Part) -> Self {
+        let released = match part {
+            Part::Major => self.minor == 0 && self.patch == 0,
+            Part::Minor => self.patch == 0,
+            Part::Patch => true,
+        };
+        if self.pre.is_some() && released {
+            return Self {
+                pre: None,
+                ..self.clone()
+            };
+        }
+        match part {
+            Part::Major => Self {
+                major: self.major + 1,
+                minor: 0,
+                patch: 0,
+                pre: None,
+            },
+            Part::Minor => Self {
+                minor: self.minor + 1,
+                patch: 0,
+                pre: None,
+                ..self.clone()
+            },
+            Part::Patch => Self {
+                patch: self.patch + 1,
+                pre: None,
+                ..self.clone()
+            },
+        }
+    }
+}
+
+/// Orders pre-releases by their dot-separated identifiers: numeric ones numerically, and
+/// before alphanumeric ones.
+fn compare_pre(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => compare_pre(a, b),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl VersionReq {
+    pub fn parse(text: &str) -> Option<Self> {
+        let trimmed = text.trim();
+        let (operator, version) = if trimmed == "*" {
+            (Operator::Any, "0")
+        } else if let Some(version) = trimmed.strip_prefix('^') {
+            (Operator::Caret, version)
+        } else if let Some(version) = trimmed.strip_prefix('~') {
+            (Operator::Tilde, version)
+        } else if let Some(version) = trimmed.strip_prefix('=') {
+            (Operator::Exact, version)
+        } else {
+            (Operator::Caret, trimmed)
+        };
+        // Partial versions (1 or 1.2) leave the later parts open; only full ones take a
+        // pre-release.
+        let (release, pre) = match version.trim().split_once('-') {
+            Some((release, pre)) => (release, format!("-{}", pre)),
+            None => (version.trim(), String::new()),
+        };
+        let parts = release.split('.').count();
+        if parts < 3 && !pre.is_empty() {
+            return None;
+        }
+        let padding = ".0".repeat(3usize.saturating_sub(parts));
+        let parsed = Version::parse(&format!("{}{}{}", release, padding, pre))?;
+        Some(Self {
+            operator,
+            major: parsed.major,
+            minor: (parts >= 2).then_some(parsed.minor),
+            patch: (parts >= 3).then_some(parsed.patch),
+            pre: parsed.pre,
+            text: trimmed.to_string(),
+        })
+    }
+
+    /// The lowest version accepted, and the (exclusive) release bounding them above.
+    fn bounds(&self) -> (Version, Option<Version>) {
+        let release = |major, minor, patch| Version {
+            major,
+            minor,
+            patch,
+            pre: None,
+        };
+        let lowest = Version {
+            pre: self.pre.clone(),
+            ..release(self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0))
+        };
+        let (major, patch) = (self.major, self.patch);
+        let Some(minor) = self.minor else {
+            let upper = (self.operator != Operator::Any).then(|| release(major + 1, 0, 0));
+            return (lowest, upper);
+        };
+        let upper = match self.operator {
+            Operator::Any => None,
+            Operator::Caret if major > 0 => Some(release(major + 1, 0, 0)),
+            Operator::Caret if minor > 0 || patch.is_none() => Some(release(0, minor + 1, 0)),
+            Operator::Tilde => Some(release(major, minor + 1, 0)),
+            Operator::Exact if patch.is_none() => Some(release(major, minor + 1, 0)),
+            Operator::Caret | Operator::Exact => {
+                Some(release(major, minor, patch.unwrap_or(0) + 1))
+            }
+        };
+        (lowest, upper)
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        // Pre-releases are only accepted by requirements naming a pre-release of the same
+        // version.
+        if let Some(pre) = &version.pre {
+            let same_release = self.major == version.major
+                && self.minor == Some(version.minor)
+                && self.patch == Some(version.patch);
+            if !same_release || self.pre.is_none() {
+                return false;
+            }
+            if self.operator == Operator::Exact {
+                return self.pre.as_ref() == Some(pre);
+            }
+        }
+        if self.operator == Operator::Exact && self.patch.is_some() {
+            return *version == self.bounds().0;
+        }
+        let (lowest, upper) = self.bounds();
+        *version >= lowest
+            && upper.is_none_or(|upper| {
+                (version.major, version.minor, version.patch)
+                    < (upper.major, upper.minor, upper.patch)
+            })
+    }
+
+    /// The versions accepted, as a range (">=1.2.0, <2.0.0").
+    pub fn range(&self) -> String {
+        match self.bounds() {
+            _ if self.operator == Operator::Exact && self.patch.is_some() => {
+                format!("={}", self.bounds().0)
+            }
+            (lowest, Some(upper))
+                if lowest.major == 0 && lowest.minor == 0 && lowest.patch == 0 =>
+            {
+                format!("<{}", upper)
+            }
+            (lowest, Some(upper)) => format!(">={}, <{}", lowest, upper),
+            (_, None) => "any version".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+/// The members of the workspace `project` is in whose path dependencies on it require a
+/// version other than `next`, each with its requirement.
+fn broken_dependents(
+    project: &Project,
+    next: &Version,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let Some(workspace) = Workspace::find(project.root_path())? else {
+        return Ok(Vec::new());
+    };
+    let root = project.root_path().canonicalize()?;
+    let mut broken = Vec::new();
+    for member in workspace.load_members()? {
+        for (name, dependency) in &member.dependencies {
+            let Some(text) = &dependency.version else {
+                continue;
+            };
+            let path = member.root_path().join(&dependency.path).canonicalize();
+            if dependency.precompiled || path.ok() != Some(root.clone()) {
+                continue;
+            }
+            if VersionReq::parse(text).is_some_and(|requirement| !requirement.matches(next)) {
+                broken.push(format!("{} ({} = \"{}\")", member.package.name, name, text));
+            }
+        }
+    }
+    Ok(broken)
+}
+
+/// Implements `dolly bump`: writes the next version into dolly.toml and, with `tag`, commits
+/// it and tags the commit `v<version>`.  It fails if the version no longer meets the
+/// requirement of a workspace member depending on the package, unless `force` is given.
+pub fn bump(
+    project: &Project,
+    part: Part,
+    tag: bool,
+    force: bool,
+) -> Result<Version, Box<dyn std::error::Error>> {
+    let package = &project.package;
+    let Some(current) = Version::parse(&package.version) else {
+        return Err(Box::new(codes::error(
+            "D0006",
+            format!(
+                "Invalid [package] version {:?} in {}: expected a semantic version such as 1.2.0",
+                package.version, package.name
+            ),
+        )));
+    };
+    let next = current.bump(part);
+    let broken = broken_dependents(project, &next)?;
+    if !broken.is_empty() && !force {
+        return Err(Box::new(codes::error(
+            "D0008",
+            format!(
+                "{} {} wouldn't meet the requirements of {}; update them first, or bump with \
+                 --force",
+                package.name,
+                next,
+                broken.join(", ")
+            ),
+        )));
+    }
+    for dependent in &broken {
+        println!("{} no longer meets the requirement of {}", next, dependent);
+    }
+    let root = project.root_path();
+    let tag_name = format!("v{}", next);
+    if tag && !changes::git(root, &["tag", "--list", &tag_name])?.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "The tag {} already exists",
+            tag_name
+        ))));
+    }
+
+    let manifest_file = root.join("dolly.toml");
+    let contents = fs::read_to_string(&manifest_file)?;
+    fs::write(
+        &manifest_file,
+        manifest::set_package_fields(&contents, &[("version", next.to_string().into())]),
+    )?;
+    println!("Bumped {} from {} to {}", package.name, current, next);
+
+    if tag {
+        let message = format!("{} {}", package.name, next);
+        changes::git(
+            root,
+            &[
+                "commit",
+                "--quiet",
+                "--message",
+                &message,
+                "--",
+                "dolly.toml",
+            ],
+        )?;
+        changes::git(
+            root,
+            &["tag", "--annotate", &tag_name, "--message", &message],
+        )?;
+        println!("Tagged {}", tag_name);
+    }
+    Ok(next)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bumped_versions() {
+        let bump = |version: &str, part| Version::parse(version).unwrap().bump(part).to_string();
+        assert_eq!(bump("1.2.3", Part::Patch), "1.2.4");
+        assert_eq!(bump("1.2.3", Part::Minor), "1.3.0");
+        assert_eq!(bump("1.2.3", Part::Major), "2.0.0");
+        assert_eq!(bump("1.3.0-rc.1", Part::Patch), "1.3.0");
+        assert_eq!(bump("1.3.0-rc.1", Part::Minor), "1.3.0");
+        assert_eq!(bump("1.3.0-rc.1", Part::Major), "2.0.0");
+        assert_eq!(Version::parse("1.2"), None);
+        assert_eq!(Version::parse("1.02.0"), None);
+        assert_eq!(Version::parse("1.2.0-"), None);
+    }
+
+    #[test]
+    fn workspace_dependents() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        fs::create_dir_all(root.join("lib"))?;
+        fs::create_dir_all(root.join("app"))?;
+        fs::write(
+            root.join("dolly.toml"),
+            "[workspace]\nmembers = [\"lib\", \"app\"]\n",
+        )?;
+        fs::write(
+            root.join("lib").join("dolly.toml"),
+            "[package]\nname = \"lib\"\nversion = \"1.2.0\"\n",
+        )?;
+        fs::write(
+            root.join("app").join("dolly.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nlib = { path = \"../lib\", version = \"^1.2\" }\n",
+        )?;
+
+        let lib = Project::load(root.join("lib").join("dolly.toml"))?;
+        let error = bump(&lib, Part::Major, false, false).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "D0008: lib 2.0.0 wouldn't meet the requirements of app (lib = \"^1.2\")"
+        ));
+        assert_eq!(bump(&lib, Part::Minor, false, false)?.to_string(), "1.3.0");
+        let lib = Project::load(root.join("lib").join("dolly.toml"))?;
+        assert_eq!(bump(&lib, Part::Major, false, true)?.to_string(), "2.0.0");
+        Ok(())
+    }
+
+    #[test]
+    fn version_requirements() {
+        let matches = |requirement: &str, version: &str| {
+            VersionReq::parse(requirement)
+                .unwrap()
+                .matches(&Version::parse(version).unwrap())
+        };
+        assert!(matches("^1.2", "1.9.0") && !matches("^1.2", "2.0.0") && !matches("^1.2", "1.1.9"));
+        assert!(matches("1.2.3", "1.2.3") && !matches("1.2.3", "1.2.2"));
+        assert!(matches("^0.2.3", "0.2.9") && !matches("^0.2.3", "0.3.0"));
+        assert!(matches("^0.0.3", "0.0.3") && !matches("^0.0.3", "0.0.4"));
+        assert!(matches("~1.2.3", "1.2.9") && !matches("~1.2.3", "1.3.0"));
+        assert!(matches("~1", "1.9.0") && !matches("~1", "2.0.0"));
+        assert!(matches("=1.2.0", "1.2.0") && !matches("=1.2.0", "1.2.1"));
+        assert!(matches("=1.2", "1.2.7") && !matches("=1.2", "1.3.0"));
+        assert!(matches("*", "3.1.4") && !matches("*", "3.1.4-rc.1"));
+        assert!(!matches("^1.2", "1.3.0-rc.1"));
+        assert!(matches("^1.3.0-rc.1", "1.3.0-rc.2") && matches("^1.3.0-rc.1", "1.3.0"));
+        assert!(!matches("^1.3.0-rc.2", "1.3.0-rc.1"));
+        assert!(Version::parse("1.3.0-rc.2").unwrap() < Version::parse("1.3.0-rc.10").unwrap());
+        assert_eq!(
+            VersionReq::parse("^1.2").unwrap().range(),
+            ">=1.2.0, <2.0.0"
+        );
+        assert_eq!(VersionReq::parse("^0").unwrap().range(), "<1.0.0");
+        assert_eq!(VersionReq::parse("=1.2.3").unwrap().range(), "=1.2.3");
+        assert_eq!(VersionReq::parse("1.x"), None);
+
+        // Malformed versions and requirements don't parse.
+        for version in [
+            "", "1.2", "1.2.3.4", "01.2.3", "1.2.3-", "v1.2.3", "1.-2.3", "1..3",
+        ] {
+            assert_eq!(Version::parse(version), None, "{:?}", version);
+        }
+        for requirement in ["", "^", ">=1.2", "^1.2-rc.1", "~~1", "=1.2.3.4", "1.2.x"] {
+            assert_eq!(VersionReq::parse(requirement), None, "{:?}", requirement);
+        }
+        assert!(Version::parse("18446744073709551616.0.0").is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use super::project::BuildConfig;
+use colored::Colorize;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// The bsc flags implementing the `[build]` warning policy.
+pub fn bsc_flags(config: &BuildConfig) -> Vec<std::ffi::OsString> {
+    let mut flags = Vec::<std::ffi::OsString>::new();
+    if !config.suppress_warnings.is_empty() {
+        flags.push("-suppress-warnings".into());
+        flags.push(config.suppress_warnings.join(":").into());
+    }
+    if !config.promote_warnings.is_empty() {
+        flags.push("-promote-warnings".into());
+        flags.push(config.promote_warnings.join(":").into());
+    }
+    flags
+}
+
+/// Warning counts by bsc message tag (e.g. G0010) across a build.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    counts: BTreeMap<String, usize>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the warnings reported in a bsc output, such as
+    /// `Warning: "src/Top.bsv", line 10, column 5: (G0010)`.
+    pub fn add_output(&mut self, output: &str) {
+        let re = Regex::new(r"(?m)^Warning: .*\(([A-Z]\d{4})\)").unwrap();
+        for captures in re.captures_iter(output) {
+            *self.counts.entry(captures[1].to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Prints the per-tag counts, if there were any warnings.
+    pub fn print_summary(&self) {
+        if self.counts.is_empty() {
+            return;
+        }
+        let tags: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(tag, count)| format!("{} x{}", tag, count))
+            .collect();
+        println!(
+            "{} {} ({})",
+            self.total(),
+            "warnings".yellow().bold(),
+            tags.join(", ")
+        );
+    }
+
+    /// Prints the summary, failing if warnings are denied and there were any.
+    pub fn check(&self, deny: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.print_summary();
+        if deny && self.total() > 0 {
+            return Err(Box::new(std::io::Error::other(format!(
+                "{} warnings with deny-warnings set",
+                self.total()
+            ))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn warning_counts() {
+        let mut warnings = Warnings::new();
+        warnings.add_output(
+            r#"Warning: "src/Top.bsv", line 10, column 5: (G0010)
+  Rule `tick' was treated as more urgent than `tock'.
+Warning: "src/Top.bsv", line 22, column 8: (G0010)
+  Rule `tock' was treated as more urgent than `reset'.
+"#,
+        );
+        warnings.add_output("Warning: \"src/Uart.bsv\", line 3, column 1: (S0080)\n");
+        assert_eq!(warnings.total(), 3);
+        assert_eq!(warnings.counts["G0010"], 2);
+        assert!(warnings.check(false).is_ok());
+        assert!(warnings.check(true).is_err());
+
+        let config: BuildConfig = toml::from_str(
+            r#"
+            suppress-warnings = ["G0010", "S0080"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            bsc_flags(&config),
+            vec![
+                std::ffi::OsString::from("-suppress-warnings"),
+                "G0010:S0080".into()
+            ]
+        );
+    }
+}
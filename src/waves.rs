@@ -0,0 +1,418 @@
+use super::codes;
+use super::config;
+use super::project::WaveFormat;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path, process,
+};
+use tracing::{error, trace, warn};
+
+/// Returns the waveform file written for the given test executable: its name (which may
+/// have dots, as a suite's tests do) followed by the format's extension.
+pub fn wave_file(target_executable: &path::Path, format: WaveFormat) -> path::PathBuf {
+    let extension = match format {
+        WaveFormat::Vcd => "vcd",
+        WaveFormat::Fst => "fst",
+    };
+    let name = target_executable.file_name().unwrap_or_default();
+    target_executable.with_file_name(format!("{}.{}", name.to_string_lossy(), extension))
+}
+
+/// Converts a VCD file to FST using GTKWave's `vcd2fst`, removing the VCD on success.
+///
+/// Returns the path of the waveform that remains, which is the original VCD if the conversion
+/// tool isn't available or fails.
+pub fn convert_to_fst(vcd_file: &path::Path, fst_file: &path::Path) -> path::PathBuf {
+    trace!("Converting {:?} to {:?}", vcd_file, fst_file);
+    let output = process::Command::new(config::tool("vcd2fst"))
+        .arg(vcd_file)
+        .arg(fst_file)
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            let _ = fs::remove_file(vcd_file);
+            fst_file.to_path_buf()
+        }
+        Ok(output) => {
+            warn!(
+                "vcd2fst failed, keeping VCD waveform: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            vcd_file.to_path_buf()
+        }
+        Err(_) => {
+            warn!("Unable to locate 'vcd2fst' program, keeping VCD waveform.");
+            vcd_file.to_path_buf()
+        }
+    }
+}
+
+/// A variable declared in the header of a VCD file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcdVar {
+    pub id: String,
+    pub name: String, // Hierarchical name, e.g. main.top.count
+    pub width: usize,
+    depth: usize,
+}
+
+impl VcdVar {
+    /// The signal name as GTKWave expects it in a save file.
+    pub fn gtkwave_name(&self) -> String {
+        if self.width > 1 && !self.name.ends_with(']') {
+            format!("{}[{}:0]", self.name, self.width - 1)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// Parses the declarations section of a VCD file, returning every variable it declares.
+pub fn read_vcd_header(reader: impl io::BufRead) -> io::Result<Vec<VcdVar>> {
+    parse_vcd_header(&mut reader.lines())
+}
+
+fn parse_vcd_header(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+) -> io::Result<Vec<VcdVar>> {
+    let mut vars = Vec::<VcdVar>::new();
+    let mut scopes = Vec::<String>::new();
+    let mut tokens = Vec::<String>::new();
+
+    for line in lines {
+        tokens.extend(line?.split_whitespace().map(str::to_string));
+
+        // Declarations are terminated by $end and may span lines: only consume complete ones.
+        while let Some(end) = tokens.iter().position(|token| token == "$end") {
+            let declaration: Vec<String> = tokens.drain(..=end).collect();
+            match declaration.first().map(String::as_str) {
+                Some("$scope") if declaration.len() >= 3 => scopes.push(declaration[2].clone()),
+                Some("$upscope") => {
+                    scopes.pop();
+                }
+                Some("$var") if declaration.len() >= 5 => {
+                    let mut name = scopes.clone();
+                    name.push(declaration[4..declaration.len() - 1].concat());
+                    vars.push(VcdVar {
+                        id: declaration[3].clone(),
+                        name: name.join("."),
+                        width: declaration[2].parse().unwrap_or(1),
+                        depth: scopes.len(),
+                    });
+                }
+                Some("$enddefinitions") => return Ok(vars),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(vars)
+}
+
+/// The value changes of a signal, as (time, value) pairs.
+pub type ValueChanges = Vec<(u64, String)>;
+
+/// Finds the variable for a signal name, which is either a full hierarchical name or a name
+/// relative to any scope (the shallowest match wins).
+fn find_var<'a>(vars: &'a [VcdVar], signal: &str) -> Option<&'a VcdVar> {
+    let suffix = format!(".{}", signal);
+    vars.iter()
+        .filter(|var| var.name == signal || var.name.ends_with(&suffix))
+        .min_by_key(|var| var.depth)
+}
+
+/// Reads the value changes of the named signals from a VCD file.
+pub fn read_vcd_signals(
+    reader: impl io::BufRead,
+    signals: &[String],
+) -> Result<HashMap<String, ValueChanges>, Box<dyn std::error::Error>> {
+    let mut lines = reader.lines();
+    let vars = parse_vcd_header(&mut lines)?;
+
+    // Map VCD identifier codes to the requested signal names.
+    let mut ids = HashMap::<String, Vec<String>>::new();
+    for signal in signals {
+        match find_var(&vars, signal) {
+            Some(var) => ids.entry(var.id.clone()).or_default().push(signal.clone()),
+            None => {
+                return Err(Box::new(io::Error::other(format!(
+                    "Signal {} not found in waveform",
+                    signal
+                ))))
+            }
+        }
+    }
+
+    let mut changes: HashMap<String, ValueChanges> = signals
+        .iter()
+        .map(|signal| (signal.clone(), ValueChanges::new()))
+        .collect();
+    let mut time = 0u64;
+    let mut pending_vector: Option<String> = None;
+
+    for line in lines {
+        for token in line?.split_whitespace() {
+            let (value, id) = if let Some(value) = pending_vector.take() {
+                // Second half of a vector/real change: "b1010 <id>"
+                (value, token)
+            } else if let Some(timestamp) = token.strip_prefix('#') {
+                time = timestamp.parse()?;
+                continue;
+            } else if token.starts_with('$') {
+                // $dumpvars, $end, etc.
+                continue;
+            } else if token.starts_with(['b', 'B', 'r', 'R']) {
+                pending_vector = Some(token[1..].to_string());
+                continue;
+            } else {
+                // Scalar change: "<value><id>"
+                let (value, id) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+                (value.to_string(), id)
+            };
+
+            for signal in ids.get(id).into_iter().flatten() {
+                changes.get_mut(signal).unwrap().push((time, value.clone()));
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Compares the named signals between two VCD files, returning a description of each mismatch.
+pub fn compare(
+    current: &path::Path,
+    baseline: &path::Path,
+    signals: &[String],
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let current_changes = read_vcd_signals(io::BufReader::new(fs::File::open(current)?), signals)?;
+    let baseline_changes =
+        read_vcd_signals(io::BufReader::new(fs::File::open(baseline)?), signals)?;
+
+    let mut mismatches = Vec::<String>::new();
+    for signal in signals {
+        let current = &current_changes[signal];
+        let baseline = &baseline_changes[signal];
+
+        let first_difference = (0..current.len().max(baseline.len()))
+            .find(|index| current.get(*index) != baseline.get(*index));
+        if let Some(index) = first_difference {
+            let describe = |change: Option<&(u64, String)>| match change {
+                Some((time, value)) => format!("{} at #{}", value, time),
+                None => "no change".to_string(),
+            };
+            mismatches.push(format!(
+                "{}: expected {}, found {}",
+                signal,
+                describe(baseline.get(index)),
+                describe(current.get(index))
+            ));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Returns the variables of the top module: those in the shallowest scope declaring any.
+pub fn top_module_vars(vars: &[VcdVar]) -> Vec<VcdVar> {
+    match vars.iter().map(|var| var.depth).min() {
+        Some(depth) => vars
+            .iter()
+            .filter(|var| var.depth == depth)
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Locates the most recently written waveform for a test.
+pub fn find_latest(build_root: &path::Path, test_name: &str) -> Option<path::PathBuf> {
+    let test_build_path = build_root.join(test_name);
+    [WaveFormat::Vcd, WaveFormat::Fst]
+        .iter()
+        .map(|format| wave_file(&test_build_path.join(test_name), *format))
+        .filter_map(|file| {
+            let modified = file
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()?;
+            Some((modified, file))
+        })
+        .max()
+        .map(|(_, file)| file)
+}
+
+/// Writes a GTKWave save file for `wave_file`, seeded with the top module's signals when the
+/// waveform is a VCD.
+pub fn write_gtkw(wave_file: &path::Path, gtkw_file: &path::Path) -> io::Result<()> {
+    let signals = if wave_file.extension().is_some_and(|ext| ext == "vcd") {
+        let vcd = io::BufReader::new(fs::File::open(wave_file)?);
+        top_module_vars(&read_vcd_header(vcd)?)
+    } else {
+        Vec::new()
+    };
+
+    let mut gtkw = fs::File::create(gtkw_file)?;
+    writeln!(gtkw, "[dumpfile] \"{}\"", wave_file.to_string_lossy())?;
+    writeln!(gtkw, "[savefile] \"{}\"", gtkw_file.to_string_lossy())?;
+    for signal in signals {
+        writeln!(gtkw, "{}", signal.gtkwave_name())?;
+    }
+
+    Ok(())
+}
+
+/// Waveform viewers `dolly waves` can launch.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Viewer {
+    Gtkwave,
+    Surfer,
+}
+
+/// Opens the most recent waveform for a test in a waveform viewer.
+pub fn open(
+    build_root: &path::Path,
+    test_name: &str,
+    viewer: Viewer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(wave_file) = find_latest(build_root, test_name) else {
+        error!(
+            "No waveform found for {} (run 'dolly test --waves' first)",
+            test_name
+        );
+        return Err(Box::new(io::Error::from(io::ErrorKind::NotFound)));
+    };
+    trace!("Latest waveform for {}: {:?}", test_name, wave_file);
+
+    let mut cmd = match viewer {
+        Viewer::Gtkwave => {
+            // Reuse an existing save file so signal selections made in GTKWave are kept.
+            let gtkw_file = wave_file.with_extension("gtkw");
+            if !gtkw_file.exists() {
+                write_gtkw(&wave_file, &gtkw_file)?;
+            }
+
+            let mut cmd = process::Command::new(config::tool("gtkwave"));
+            cmd.arg(&wave_file).arg(&gtkw_file);
+            cmd
+        }
+        Viewer::Surfer => {
+            let mut cmd = process::Command::new(config::tool("surfer"));
+            cmd.arg(&wave_file);
+            cmd
+        }
+    };
+
+    // The viewer is left running after dolly exits.
+    match cmd.spawn() {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Box::new(codes::error(
+            "D0102",
+            format!("Unable to locate '{:?}' program.", cmd.get_program()),
+        ))),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn failed_fst_conversion() {
+        // Whether vcd2fst is missing or fails (here, on a VCD that wasn't dumped), the VCD
+        // is the waveform.
+        let temp = tempfile::tempdir().unwrap();
+        let vcd = temp.path().join("Fifo_tb.vcd");
+        let fst = temp.path().join("Fifo_tb.fst");
+        assert_eq!(convert_to_fst(&vcd, &fst), vcd);
+    }
+
+    #[test]
+    fn dotted_wave_files() {
+        let executable =
+            path::Path::new("target/integration.cache.Evict_tb/integration.cache.Evict_tb");
+        assert_eq!(
+            wave_file(executable, WaveFormat::Vcd),
+            path::Path::new("target/integration.cache.Evict_tb/integration.cache.Evict_tb.vcd")
+        );
+        assert_ne!(
+            wave_file(path::Path::new("target/alu.add/alu.add"), WaveFormat::Fst),
+            wave_file(path::Path::new("target/alu.sub/alu.sub"), WaveFormat::Fst)
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let build_root = temp.path();
+        fs::create_dir_all(build_root.join("alu.add")).unwrap();
+        fs::write(build_root.join("alu.add").join("alu.vcd"), "").unwrap();
+        assert_eq!(find_latest(build_root, "alu.add"), None);
+        fs::write(build_root.join("alu.add").join("alu.add.vcd"), "").unwrap();
+        assert_eq!(
+            find_latest(build_root, "alu.add"),
+            Some(build_root.join("alu.add").join("alu.add.vcd"))
+        );
+    }
+
+    #[test]
+    fn vcd_header_top_module_vars() -> io::Result<()> {
+        let vcd = r#"$timescale 1 ns $end
+$scope module main $end
+$scope module top $end
+$var wire 1 ! CLK $end
+$var reg 32 " count $end
+$scope module fifo $end
+$var reg 1 # full $end
+$upscope $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+"#;
+        let vars = read_vcd_header(vcd.as_bytes())?;
+        assert_eq!(vars.len(), 3);
+
+        let names: Vec<String> = top_module_vars(&vars)
+            .iter()
+            .map(VcdVar::gtkwave_name)
+            .collect();
+        assert_eq!(names, vec!["main.top.CLK", "main.top.count[31:0]"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn vcd_signal_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let vcd = r#"$scope module top $end
+$var wire 1 ! CLK $end
+$var reg 4 " count $end
+$upscope $end
+$enddefinitions $end
+$dumpvars
+0!
+b0000 "
+$end
+#5
+1!
+#10
+0!
+b0001 "
+"#;
+        let changes = read_vcd_signals(vcd.as_bytes(), &["count".to_string()])?;
+        assert_eq!(
+            changes["count"],
+            vec![(0, "0000".to_string()), (10, "0001".to_string())]
+        );
+
+        assert!(read_vcd_signals(vcd.as_bytes(), &["missing".to_string()]).is_err());
+
+        // Malformed scalar changes starting with a multibyte character are skipped.
+        let garbled = format!("{}#15\n\u{e9}!\n", vcd);
+        let changes = read_vcd_signals(garbled.as_bytes(), &["count".to_string()])?;
+        assert_eq!(changes["count"].len(), 2);
+
+        Ok(())
+    }
+}
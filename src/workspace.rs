@@ -0,0 +1,197 @@
+//! Workspaces: a dolly.toml with a [workspace] table groups the packages of a repository
+//! that are developed together, so that they can be tested in one command from its root.
+//! The workspace's dolly.toml may describe a package of its own, which is then a member too.
+//!
+//! ```toml
+//! [workspace]
+//! members = ["fifo", "cores/*"]
+//! ```
+use super::codes;
+use super::manifest;
+use super::project::Project;
+use super::suggest;
+use serde::Deserialize;
+use std::{fs, path};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    pub members: Vec<path::PathBuf>, // Package directories, relative to the root; dir/* for each in dir
+}
+
+/// A workspace found on disk.
+pub struct Workspace {
+    pub root: path::PathBuf,
+    pub members: Vec<path::PathBuf>, // The directories of the member packages
+}
+
+/// The member directories the patterns name: each directory, or with dir/*, the directories
+/// in dir that hold a dolly.toml.
+fn expand(
+    root: &path::Path,
+    patterns: &[path::PathBuf],
+) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    let mut members = Vec::new();
+    for pattern in patterns {
+        if pattern.file_name().is_some_and(|name| name == "*") {
+            let dir = root.join(pattern.parent().unwrap_or(path::Path::new("")));
+            let mut found: Vec<path::PathBuf> = dir
+                .read_dir()
+                .map_err(|e| {
+                    std::io::Error::other(format!(
+                        "Unable to read the workspace members in {}: {}",
+                        dir.to_string_lossy(),
+                        e
+                    ))
+                })?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|member| member.join("dolly.toml").is_file())
+                .collect();
+            found.sort();
+            members.extend(found);
+        } else {
+            let member = root.join(pattern);
+            if !member.join("dolly.toml").is_file() {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "The workspace member {} has no dolly.toml",
+                    member.to_string_lossy()
+                ))));
+            }
+            members.push(member);
+        }
+    }
+    Ok(members)
+}
+
+impl Workspace {
+    /// Finds the workspace `start` is in: the nearest dolly.toml at or above it with a
+    /// [workspace] table.
+    pub fn find(start: &path::Path) -> Result<Option<Workspace>, Box<dyn std::error::Error>> {
+        for dir in start.canonicalize()?.ancestors() {
+            let file = dir.join("dolly.toml");
+            if !file.is_file() {
+                continue;
+            }
+            let manifest: toml::Table = manifest::parse(&file, &fs::read_to_string(&file)?)?;
+            let Some(workspace) = manifest.get("workspace") else {
+                continue;
+            };
+            let mut members = Vec::new();
+            // A root with a package of its own is loaded as one, [workspace] included.
+            let config = if manifest.contains_key("package") {
+                members.push(dir.to_path_buf());
+                Project::load(file)?.workspace.unwrap_or_default()
+            } else {
+                workspace.clone().try_into().map_err(|e| {
+                    codes::error(
+                        "D0007",
+                        format!("Invalid [workspace] in {}: {}", file.to_string_lossy(), e),
+                    )
+                })?
+            };
+            for member in expand(dir, &config.members)? {
+                let member = member.canonicalize()?;
+                if !members.contains(&member) {
+                    members.push(member);
+                }
+            }
+            return Ok(Some(Workspace {
+                root: dir.to_path_buf(),
+                members,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Loads the member packages, in the order of [workspace] members.
+    pub fn load_members(&self) -> Result<Vec<Project>, Box<dyn std::error::Error>> {
+        if self.members.is_empty() {
+            return Err(Box::new(codes::error(
+                "D0012",
+                format!(
+                    "The workspace in {} has no members",
+                    self.root.to_string_lossy()
+                ),
+            )));
+        }
+        self.members
+            .iter()
+            .map(|member| Project::load(member.join("dolly.toml")))
+            .collect()
+    }
+
+    /// Loads the member whose [package] is named `name`.
+    pub fn member(&self, name: &str) -> Result<Project, Box<dyn std::error::Error>> {
+        let mut members = self.load_members()?;
+        match members
+            .iter()
+            .position(|member| member.package.name == name)
+        {
+            Some(position) => Ok(members.swap_remove(position)),
+            None => {
+                let names: Vec<&str> = members
+                    .iter()
+                    .map(|member| member.package.name.as_str())
+                    .collect();
+                Err(Box::new(codes::error(
+                    "D0012",
+                    format!(
+                        "The workspace has no package named '{}'{}; its packages are {}",
+                        name,
+                        suggest::did_you_mean(&suggest::similar(name, names.iter().copied())),
+                        names.join(", ")
+                    ),
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn workspace_members() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path();
+        let root = root.canonicalize()?;
+        for member in ["fifo", "cores/rv32", "cores/rv64", "cores/notes"] {
+            fs::create_dir_all(root.join(member))?;
+        }
+        for member in ["fifo", "cores/rv32", "cores/rv64"] {
+            fs::write(root.join(member).join("dolly.toml"), "")?;
+        }
+        fs::write(
+            root.join("dolly.toml"),
+            "[workspace]\nmembers = [\"fifo\", \"cores/*\"]\n",
+        )?;
+
+        let workspace = Workspace::find(&root.join("cores").join("rv32"))?.unwrap();
+        let missing = expand(&root, &[path::PathBuf::from("uart")]);
+        assert_eq!(workspace.root, root);
+        assert_eq!(
+            workspace.members,
+            vec![
+                root.join("fifo"),
+                root.join("cores").join("rv32"),
+                root.join("cores").join("rv64")
+            ]
+        );
+        assert!(missing.is_err());
+
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n\n[workspace]\nmembers = [\"fifo\"]\n",
+        )?;
+        let workspace = Workspace::find(&root)?.unwrap();
+        assert_eq!(workspace.members, vec![root.clone(), root.join("fifo")]);
+        fs::write(
+            root.join("dolly.toml"),
+            "[package]\nname = \"soc\"\nversion = \"0.1.0\"\n\n[workspace]\nmember = [\"fifo\"]\n",
+        )?;
+        let error = Workspace::find(&root).err().unwrap();
+        assert!(error.to_string().contains("member"));
+        Ok(())
+    }
+}